@@ -1,14 +1,23 @@
-use clap::Parser;
+mod modules;
+
+use clap::{Parser, Subcommand};
+use dotlin_ast::Program;
 use dotlin_codegen::CodeGenerator;
-use dotlin_interpreter::Interpreter;
-use dotlin_parser::Parser as DotlinParser;
+use dotlin_interpreter::{with_stack, Interpreter};
+use dotlin_vm::Vm;
+use modules::Module;
+use std::collections::HashMap;
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::process::Command;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
+#[command(args_conflicts_with_subcommands = true)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Commands>,
+
     /// Input file (.lin)
     #[arg(name = "INPUT")]
     input: Option<PathBuf>,
@@ -25,85 +34,194 @@ struct Cli {
     #[arg(short, long)]
     compile_only: bool,
 
-    /// Run the file using the interpreter instead of compiling
+    /// Run the file on the bytecode VM, or the interpreter, instead of compiling
     #[arg(short, long)]
     run: bool,
+
+    /// Directory holding the packages the input file may import
+    #[arg(long)]
+    source_root: Option<PathBuf>,
+
+    /// Make the executable print a stack trace for an uncaught exception,
+    /// recording every call and line it runs at some cost in speed
+    #[arg(short = 'g', long)]
+    debug: bool,
+
+    /// Time every function call, writing profile.folded (for flame graphs) and
+    /// profile.json; with --run the interpreter does, otherwise the executable
+    #[arg(long)]
+    profile: bool,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Run the input file in the interpreter, counting the runs of its lines
+    /// and branches; writes an LCOV report and prints a summary
+    Coverage {
+        /// Input file (.lin)
+        input: PathBuf,
+
+        /// Directory holding the packages the input file may import
+        #[arg(long)]
+        source_root: Option<PathBuf>,
+
+        /// Where to write the LCOV report
+        #[arg(long, default_value = "lcov.info")]
+        lcov: PathBuf,
+    },
 }
 
 fn main() {
     let cli = Cli::parse();
 
-    if let Some(input_path) = &cli.input {
+    if let Some(Commands::Coverage {
+        input,
+        source_root,
+        lcov,
+    }) = &cli.command
+    {
+        if let Some(modules) = load(input, source_root.as_deref()) {
+            with_stack(|| run_with_coverage(modules, lcov));
+        }
+    } else if let Some(input_path) = &cli.input {
+        let Some(modules) = load(input_path, cli.source_root.as_deref()) else {
+            return;
+        };
+
         if cli.run {
             // Run using interpreter instead of compiling
-            run_with_interpreter(input_path);
+            with_stack(|| run_with_interpreter(modules, cli.profile));
         } else {
-            // Compile using the original method
-            let content = fs::read_to_string(input_path).expect("Failed to read file");
-            let mut parser = DotlinParser::new(&content);
-
-            match parser.parse_program() {
-                Ok(mut ast) => {
-                    let mut typechecker = dotlin_typechecker::TypeChecker::new();
-                    if let Err(e) = typechecker.check_program(&mut ast) {
-                        eprintln!("Type Error: {}", e);
-                        return;
-                    }
-
-                    let generator = CodeGenerator::new();
-                    match generator.compile_program(&ast) {
-                        Ok(bytes) => {
-                            let obj_path = if cli.compile_only {
-                                cli.output.clone().unwrap_or(PathBuf::from("output.o"))
-                            } else {
-                                PathBuf::from("temp_output.o")
-                            };
-
-                            fs::write(&obj_path, bytes).expect("Failed to write object file");
-
-                            if !cli.compile_only {
-                                link_executable(&obj_path, &cli);
-                                if obj_path.to_string_lossy() == "temp_output.o" {
-                                    let _ = fs::remove_file(obj_path);
-                                }
-                            } else {
-                                println!("Compiled to {:?}", obj_path);
-                            }
-                        }
-                        Err(e) => eprintln!("Compilation Error: {}", e),
-                    }
-                }
-                Err(e) => {
-                    eprintln!("Error parsing file: {}", e);
-                }
-            }
+            compile(modules, &cli);
         }
     } else {
         println!("No input file provided.");
     }
 }
 
-fn link_executable(obj_path: &Path, cli: &Cli) {
+/// The modules of the program `input` is the entry file of.
+fn load(input: &Path, source_root: Option<&Path>) -> Option<Vec<Module>> {
+    let modules = match source_root {
+        Some(source_root) => modules::load_module_graph(input, source_root),
+        None => Module::load(input).map(|module| vec![module]),
+    };
+    match modules {
+        Ok(modules) => Some(modules),
+        Err(e) => {
+            eprintln!("{}", e);
+            None
+        }
+    }
+}
+
+/// Compiles every module to an object file and, unless `--compile-only` is
+/// given, links them into an executable.
+fn compile(modules: Vec<Module>, cli: &Cli) {
+    let names: Vec<String> = modules.iter().map(Module::name).collect();
+    let mut programs: Vec<Program> = modules.into_iter().map(|m| m.program).collect();
+
+    let mut typechecker = dotlin_typechecker::TypeChecker::new();
+    if let Err(e) = typechecker.check_modules(&mut programs) {
+        eprintln!("Type Error: {}", e);
+        return;
+    }
+
+    let mut objects = Vec::new();
+    for (index, program) in programs.iter().enumerate() {
+        let dependencies: Vec<&Program> = programs
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| *i != index)
+            .map(|(_, dependency)| dependency)
+            .collect();
+        let mut generator = CodeGenerator::new();
+        if cli.debug {
+            generator = generator.with_stack_traces();
+        }
+        if cli.profile {
+            generator = generator.with_profiling();
+        }
+        let bytes = match generator.compile_module(program, &dependencies) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                eprintln!("Compilation Error: {}", e);
+                return;
+            }
+        };
+
+        let obj_path = match (cli.compile_only, index) {
+            (true, 0) => cli.output.clone().unwrap_or(PathBuf::from("output.o")),
+            (true, _) => {
+                let dir = cli
+                    .output
+                    .as_ref()
+                    .and_then(|output| output.parent())
+                    .unwrap_or(Path::new(""));
+                dir.join(format!("{}.o", names[index]))
+            }
+            (false, 0) => PathBuf::from("temp_output.o"),
+            (false, _) => PathBuf::from(format!("temp_output_{}.o", names[index])),
+        };
+        fs::write(&obj_path, bytes).expect("Failed to write object file");
+        if cli.compile_only {
+            println!("Compiled to {:?}", obj_path);
+        }
+        objects.push(obj_path);
+    }
+
+    if !cli.compile_only {
+        let entry = dotlin_codegen::symbol_name(&programs[0].qualified_name("main"));
+        link_executable(&objects, &entry, cli);
+        for obj_path in objects {
+            let _ = fs::remove_file(obj_path);
+        }
+    }
+}
+
+/// Links the objects with the runtime; `entry` is the symbol of the entry module's `main`.
+fn link_executable(objects: &[PathBuf], entry: &str, cli: &Cli) {
     let exe_path = cli.output.clone().unwrap_or(PathBuf::from("output.exe"));
 
     // Create a temporary wrapper file
     let wrapper_path = PathBuf::from("temp_wrapper.rs");
-    let wrapper_content = r#"
-extern "C" {
+    let wrapper_content = format!(
+        r#"
+extern "C" {{
+    #[link_name = "{}"]
     fn main_lin();
-}
-fn main() {
-    unsafe { main_lin(); }
-}
-"#;
+    fn dotlin_install_panic_hook();
+    fn dotlin_join_threads();{}
+}}
+fn main() {{
+    unsafe {{
+        // Uncaught exceptions print the calls they escaped from
+        dotlin_install_panic_hook();
+        main_lin();
+        // Like Kotlin, the program ends once every thread has
+        dotlin_join_threads();{}
+    }}
+}}
+"#,
+        entry,
+        match cli.profile {
+            true => "\n    fn dotlin_profile_report();",
+            false => "",
+        },
+        match cli.profile {
+            // Once every thread has finished
+            true => "\n        dotlin_profile_report();",
+            false => "",
+        }
+    );
     fs::write(&wrapper_path, wrapper_content).expect("Failed to write wrapper");
 
     let mut cmd = Command::new("rustc");
-    cmd.arg(&wrapper_path)
-        .arg("-C")
-        .arg(format!("link-arg={}", obj_path.display()))
-        .arg("-o")
-        .arg(&exe_path);
+    cmd.arg(&wrapper_path);
+    for obj_path in objects {
+        cmd.arg("-C")
+            .arg(format!("link-arg={}", obj_path.display()));
+    }
+    cmd.arg("-o").arg(&exe_path);
 
     // Determine runtime search dirs (cli override, then absolute workspace lib)
     let mut runtime_dirs: Vec<PathBuf> = Vec::new();
@@ -165,33 +283,109 @@ fn main() {
     }
 }
 
-fn run_with_interpreter(input_path: &PathBuf) {
-    match std::fs::read_to_string(input_path) {
-        Ok(content) => {
-            let mut parser = dotlin_parser::Parser::new(&content);
-            match parser.parse_program() {
-                Ok(mut ast) => {
-                    // Type check
-                    let mut typechecker = dotlin_typechecker::TypeChecker::new();
-                    if let Err(e) = typechecker.check_program(&mut ast) {
-                        eprintln!("Type Error: {}", e);
-                        return;
-                    }
-
-                    // Run with interpreter
-                    let mut interpreter = Interpreter::new();
-                    match interpreter.interpret_program(&ast) {
-                        Ok(()) => println!("Program executed successfully"),
-                        Err(e) => eprintln!("Runtime Error: {}", e),
-                    }
-                }
-                Err(e) => {
-                    eprintln!("Parse Error: {}", e);
-                }
+/// Type-checks and resolves the modules for running them, or reports why
+/// they can't run.
+fn check_for_running(modules: Vec<Module>) -> Option<Vec<Program>> {
+    let mut programs: Vec<Program> = modules.into_iter().map(|m| m.program).collect();
+
+    // Type check
+    let mut typechecker = dotlin_typechecker::TypeChecker::new();
+    if let Err(e) = typechecker.check_modules(&mut programs) {
+        eprintln!("Type Error: {}", e);
+        return None;
+    }
+
+    // Scoping errors stop the program before it runs, on either backend
+    if let Err(e) = programs
+        .iter_mut()
+        .try_for_each(dotlin_interpreter::resolve_program)
+    {
+        eprintln!("Error: {}", e);
+        return None;
+    }
+    Some(programs)
+}
+
+fn run_with_interpreter(modules: Vec<Module>, profile: bool) {
+    let Some(programs) = check_for_running(modules) else {
+        return;
+    };
+
+    // Run on the VM, or with the interpreter if it uses what the VM doesn't
+    // support or the run is profiled
+    let module = match profile {
+        true => None,
+        false => dotlin_vm::compile(&programs).ok(),
+    };
+    let (result, trace) = match module {
+        Some(module) => {
+            let mut vm = Vm::new();
+            let result = vm.run(&module);
+            (result, vm.interpreter().take_stack_trace())
+        }
+        None => {
+            let mut interpreter = Interpreter::new();
+            if profile {
+                interpreter.enable_profiling();
+            }
+            let result = interpreter.interpret_modules(&programs);
+            if profile {
+                write_profile(&interpreter.take_profile());
             }
+            (result, interpreter.take_stack_trace())
         }
+    };
+    match result {
+        Ok(()) => println!("Program executed successfully"),
         Err(e) => {
-            eprintln!("Error reading file: {}", e);
+            eprint!("Runtime Error: {}\n{}", e, trace);
+            // Fail like a native program with an uncaught exception
+            std::process::exit(1);
         }
     }
 }
+
+/// Writes the profile of an interpreted run where a profiled executable
+/// writes its own.
+fn write_profile(profile: &dotlin_interpreter::Profile) {
+    let written = fs::write("profile.folded", profile.folded())
+        .and_then(|()| fs::write("profile.json", profile.json()));
+    match written {
+        Ok(()) => eprintln!("Profile written to profile.folded and profile.json"),
+        Err(e) => eprintln!("Failed to write the profile: {}", e),
+    }
+}
+
+/// Runs the modules in the interpreter, then writes the coverage of their
+/// lines and branches to `lcov` and prints its summary.
+fn run_with_coverage(modules: Vec<Module>, lcov: &Path) {
+    // Coverage is by file name, like stack traces
+    let paths: HashMap<String, PathBuf> = modules
+        .iter()
+        .map(|module| {
+            let name = module.path.file_name().unwrap_or_default();
+            (name.to_string_lossy().into_owned(), module.path.clone())
+        })
+        .collect();
+    let Some(programs) = check_for_running(modules) else {
+        return;
+    };
+
+    let mut interpreter = Interpreter::new();
+    interpreter.enable_coverage(&programs);
+    let result = interpreter.interpret_modules(&programs);
+    let coverage = interpreter.take_coverage();
+    let report = coverage.lcov(|file| match paths.get(file) {
+        Some(path) => path.display().to_string(),
+        None => file.to_string(),
+    });
+    match fs::write(lcov, report) {
+        Ok(()) => eprintln!("Coverage written to {}", lcov.display()),
+        Err(e) => eprintln!("Failed to write {}: {}", lcov.display(), e),
+    }
+    print!("{}", coverage.summary());
+    if let Err(e) = result {
+        eprint!("Runtime Error: {}\n{}", e, interpreter.take_stack_trace());
+        std::process::exit(1);
+    }
+}
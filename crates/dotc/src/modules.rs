@@ -0,0 +1,125 @@
+//! The module graph of a multi-file program: the entry file plus every file
+//! under the source root it reaches through `import`s or by sharing a package.
+
+use dotlin_ast::Program;
+use dotlin_parser::Parser as DotlinParser;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// One parsed `.lin` file.
+pub struct Module {
+    pub path: PathBuf,
+    pub program: Program,
+}
+
+impl Module {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("Error reading file {}: {}", path.display(), e))?;
+        let file = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let program = DotlinParser::with_file(&content, file)
+            .parse_program()
+            .map_err(|e| format!("Error parsing file {}: {}", path.display(), e))?;
+        Ok(Module {
+            path: path.to_path_buf(),
+            program,
+        })
+    }
+
+    /// The package followed by the file stem, e.g. `geometry.shapes`; used to name object files.
+    pub fn name(&self) -> String {
+        let stem = self
+            .path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        self.program.qualified_name(&stem)
+    }
+}
+
+/// Loads the modules `entry` depends on, entry first. Only the package
+/// header of each `.lin` file under `source_root` is read up front; the files
+/// of a package are parsed once the entry reaches it, by sharing its package
+/// or through an `import`.
+pub fn load_module_graph(entry: &Path, source_root: &Path) -> Result<Vec<Module>, String> {
+    let mut paths = Vec::new();
+    collect_sources(source_root, &mut paths)
+        .map_err(|e| format!("Error reading {}: {}", source_root.display(), e))?;
+    paths.sort();
+
+    let canonical = |path: &Path| path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    let entry_path = canonical(entry);
+    let mut modules = vec![Module::load(entry)?];
+    let mut packages: HashMap<Option<String>, Vec<PathBuf>> = HashMap::new();
+    packages.insert(modules[0].program.package.clone(), Vec::new());
+    for path in paths {
+        if canonical(&path) == entry_path {
+            continue;
+        }
+        // A file whose header doesn't parse belongs to no package anyone can reach
+        if let Some(package) = package_of(&path) {
+            packages.entry(package).or_default().push(path);
+        }
+    }
+
+    let mut loaded = HashSet::new();
+    let mut next = 0;
+    while next < modules.len() {
+        let program = &modules[next].program;
+        let mut needed = vec![program.package.clone()];
+        for import in &program.imports {
+            let package = if import.wildcard {
+                import.path.as_str()
+            } else {
+                import
+                    .path
+                    .rsplit_once('.')
+                    .map_or(import.path.as_str(), |(package, _)| package)
+            };
+            let package = package.to_string();
+            if !packages.contains_key(&Some(package.clone())) {
+                return Err(format!(
+                    "Unresolved import {} in {}: no package {} under {}",
+                    import.path,
+                    modules[next].path.display(),
+                    package,
+                    source_root.display()
+                ));
+            }
+            needed.push(Some(package));
+        }
+
+        for package in needed {
+            if !loaded.insert(package.clone()) {
+                continue;
+            }
+            for path in packages.get(&package).into_iter().flatten() {
+                modules.push(Module::load(path)?);
+            }
+        }
+        next += 1;
+    }
+    Ok(modules)
+}
+
+/// The package a file declares, or `None` if its header can't be read.
+fn package_of(path: &Path) -> Option<Option<String>> {
+    let content = fs::read_to_string(path).ok()?;
+    DotlinParser::new(&content).parse_package().ok()
+}
+
+fn collect_sources(dir: &Path, paths: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_sources(&path, paths)?;
+        } else if path.extension().is_some_and(|ext| ext == "lin") {
+            paths.push(path);
+        }
+    }
+    Ok(())
+}
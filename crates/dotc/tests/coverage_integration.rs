@@ -0,0 +1,92 @@
+use std::process::Command;
+
+#[test]
+fn coverage_writes_lcov_and_prints_a_summary() {
+    let dir = std::env::temp_dir().join("dotlin-coverage");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(
+        dir.join("main.lin"),
+        "fun classify(n: Int): String {
+    if (n < 0) {
+        return \"negative\"
+    }
+    return if (n % 2 == 0) \"even\" else \"odd\"
+}
+
+fun main() {
+    var i = 0
+    while (i < 3) {
+        println(classify(i))
+        i += 1
+    }
+}
+",
+    )
+    .unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_dotc"))
+        .args(["coverage", "main.lin", "--lcov", "out/lcov.info"])
+        .current_dir(&dir)
+        .output()
+        .expect("failed to run dotc coverage");
+    // The report's directory doesn't exist
+    assert!(String::from_utf8_lossy(&output.stderr).starts_with("Failed to write out/lcov.info"));
+
+    let output = Command::new(env!("CARGO_BIN_EXE_dotc"))
+        .args(["coverage", "main.lin"])
+        .current_dir(&dir)
+        .output()
+        .expect("failed to run dotc coverage");
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "even\nodd\neven\n\
+         File        Lines  Branches  Missed lines\n\
+         main.lin    85.7%     83.3%  3\n\
+         Total       85.7%     83.3%\n"
+    );
+
+    let lcov = std::fs::read_to_string(dir.join("lcov.info")).unwrap();
+    let records: Vec<&str> = lcov.lines().collect();
+    assert_eq!(records[..2], ["TN:", "SF:main.lin"]);
+    // `n < 0` was never true
+    assert!(records.contains(&"BRDA:2,0,0,0"));
+    assert!(records.contains(&"BRDA:2,0,1,3"));
+    assert!(records.contains(&"DA:3,0"));
+    assert!(records.contains(&"DA:11,3"));
+    assert_eq!(
+        records[records.len() - 3..],
+        ["LF:7", "LH:6", "end_of_record"]
+    );
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn runaway_recursion_fails_instead_of_overflowing_the_stack() {
+    let dir = std::env::temp_dir().join("dotlin-coverage-recursion");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(
+        dir.join("main.lin"),
+        "fun down(n: Int): Int = if (n == 0) 0 else down(n - 1) + 1
+
+fun main() {
+    println(down(1000000))
+}
+",
+    )
+    .unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_dotc"))
+        .args(["coverage", "main.lin"])
+        .current_dir(&dir)
+        .output()
+        .expect("failed to run dotc coverage");
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Runtime Error: Limit exceeded: more than 5000 nested calls\n"),
+        "{}",
+        stderr
+    );
+    let _ = std::fs::remove_dir_all(&dir);
+}
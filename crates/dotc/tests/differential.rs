@@ -0,0 +1,145 @@
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// Output of one backend, normalized for comparison.
+#[derive(Debug, PartialEq)]
+struct Outcome {
+    stdout: String,
+    success: bool,
+    /// The `SomeException: message` part of an uncaught exception, if any
+    exception: Option<String>,
+    /// The `at f(file:line)` lines of its stack trace
+    trace: Vec<String>,
+}
+
+impl Outcome {
+    fn from_output(output: &Output) -> Self {
+        let stdout = String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter(|line| *line != "Program executed successfully")
+            .collect::<Vec<_>>()
+            .join("\n");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let exception = stderr.lines().find_map(|line| {
+            let end = line.find("Exception: ")?;
+            let start = line[..end].rfind(' ').map_or(0, |space| space + 1);
+            Some(line[start..].to_string())
+        });
+        let trace = stderr
+            .lines()
+            .filter_map(|line| line.strip_prefix("\tat "))
+            .map(str::to_string)
+            .collect();
+        Outcome {
+            stdout,
+            success: output.status.success(),
+            exception,
+            trace,
+        }
+    }
+}
+
+fn workspace_root() -> PathBuf {
+    // Workspace root (two levels up from crates/dotc)
+    let manifest = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    manifest.parent().unwrap().parent().unwrap().to_path_buf()
+}
+
+/// Builds the runtime library and returns the directory containing it.
+fn build_runtime(workspace_root: &Path) -> PathBuf {
+    let status = Command::new("cargo")
+        .args(["build", "-p", "dotlin_runtime", "--release"])
+        .current_dir(workspace_root)
+        .status()
+        .expect("failed to run cargo build for dotlin_runtime");
+    assert!(status.success(), "building dotlin_runtime failed");
+    std::env::var("CARGO_TARGET_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| workspace_root.join("target"))
+        .join("release")
+}
+
+fn run_interpreter(program: &Path) -> Outcome {
+    let output = Command::new(env!("CARGO_BIN_EXE_dotc"))
+        .arg("--run")
+        .arg(program)
+        .output()
+        .expect("failed to run dotc --run");
+    Outcome::from_output(&output)
+}
+
+fn run_native(program: &Path, runtime_dir: &Path, work_dir: &Path) -> Outcome {
+    let exe = work_dir.join("program.exe");
+    // dotc writes its object file to the working directory
+    // With the stack traces the interpreter always prints
+    let compiled = Command::new(env!("CARGO_BIN_EXE_dotc"))
+        .arg("--debug")
+        .arg(program)
+        .arg("-o")
+        .arg(&exe)
+        .arg("--runtime-path")
+        .arg(runtime_dir)
+        .current_dir(work_dir)
+        .output()
+        .expect("failed to run dotc");
+    assert!(
+        compiled.status.success() && exe.exists(),
+        "dotc failed to compile {:?}: {}",
+        program,
+        String::from_utf8_lossy(&compiled.stderr)
+    );
+
+    let mut cmd = Command::new(&exe);
+    for var in ["LD_LIBRARY_PATH", "DYLD_LIBRARY_PATH", "PATH"] {
+        let mut paths = vec![runtime_dir.to_path_buf()];
+        if let Some(existing) = std::env::var_os(var) {
+            paths.extend(std::env::split_paths(&existing));
+        }
+        cmd.env(var, std::env::join_paths(paths).unwrap());
+    }
+    let output = cmd.output().expect("failed to run compiled program");
+    Outcome::from_output(&output)
+}
+
+/// Every program under `tests/differential` must print the same output and
+/// fail the same way whether it is interpreted or compiled natively.
+#[test]
+fn interpreter_and_native_backends_agree() {
+    let workspace_root = workspace_root();
+    let programs_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("differential");
+    let mut programs: Vec<PathBuf> = std::fs::read_dir(&programs_dir)
+        .expect("differential programs missing")
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "lin"))
+        .collect();
+    programs.sort();
+    assert!(!programs.is_empty());
+
+    // On CI runners the runtime environment can be fragile; only check the interpreter there.
+    let run_native_programs =
+        std::env::var("GITHUB_ACTIONS").is_err() && std::env::var("CI").is_err();
+    let runtime_dir = run_native_programs.then(|| build_runtime(&workspace_root));
+
+    for program in &programs {
+        let interpreted = run_interpreter(program);
+        assert!(
+            interpreted.success || interpreted.exception.is_some(),
+            "{:?} failed in the interpreter: {:?}",
+            program,
+            interpreted
+        );
+        let Some(runtime_dir) = &runtime_dir else {
+            continue;
+        };
+
+        let name = program.file_stem().unwrap().to_string_lossy();
+        let work_dir = std::env::temp_dir().join(format!("dotlin-differential-{}", name));
+        std::fs::create_dir_all(&work_dir).unwrap();
+        let native = run_native(program, runtime_dir, &work_dir);
+        let _ = std::fs::remove_dir_all(&work_dir);
+
+        assert_eq!(interpreted, native, "backends disagree on {:?}", program);
+    }
+}
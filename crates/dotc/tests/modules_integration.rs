@@ -0,0 +1,204 @@
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+const EXPECTED: [&str; 7] = ["== modules ==", "12", "14", "42", "10", "2", "2"];
+
+fn workspace_root() -> PathBuf {
+    // Workspace root (two levels up from crates/dotc)
+    let manifest = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    manifest.parent().unwrap().parent().unwrap().to_path_buf()
+}
+
+fn program_lines(output: &Output) -> Vec<String> {
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter(|line| *line != "Program executed successfully")
+        .map(str::to_string)
+        .collect()
+}
+
+fn dotc(source_root: &Path) -> Command {
+    let mut cmd = Command::new(env!("CARGO_BIN_EXE_dotc"));
+    cmd.arg(source_root.join("app").join("main.lin"))
+        .arg("--source-root")
+        .arg(source_root);
+    cmd
+}
+
+#[test]
+fn interprets_program_spread_over_packages() {
+    let source_root = workspace_root().join("examples").join("modules");
+    let output = dotc(&source_root)
+        .arg("--run")
+        .output()
+        .expect("failed to run dotc --run");
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(program_lines(&output), EXPECTED);
+}
+
+fn write_sources(source_root: &Path, files: &[(&str, &str)]) {
+    for (file, source) in files {
+        let path = source_root.join(file);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, source).unwrap();
+    }
+}
+
+#[test]
+fn value_classes_of_the_same_name_in_two_packages() {
+    let source_root = std::env::temp_dir().join("dotlin-modules-value-classes");
+    write_sources(
+        &source_root,
+        &[
+        (
+            "a/ids.lin",
+            "package a\n\n@JvmInline\nvalue class Id(val n: Int) {\n    fun next(): Id = Id(n + 1)\n}\n\n\
+             fun first(): Id = Id(1)\n",
+        ),
+        (
+            "b/ids.lin",
+            "package b\n\n@JvmInline\nvalue class Id(val s: String)\n\nfun named(s: String): Id = Id(s)\n",
+        ),
+        (
+            "app/main.lin",
+            "package app\n\nimport a.*\nimport b.named\n\nfun main() {\n    val x = first().next()\n\
+             \x20   println(x)\n    println(x.n)\n    val y = named(\"k\")\n    println(y)\n\
+             \x20   println(y.s.length)\n    println(b.Id(\"z\"))\n    println(a.Id(7))\n}\n",
+        ),
+        ],
+    );
+
+    let output = dotc(&source_root)
+        .arg("--run")
+        .output()
+        .expect("failed to run dotc --run");
+    let _ = std::fs::remove_dir_all(&source_root);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        program_lines(&output),
+        ["Id(n=2)", "2", "Id(s=k)", "1", "Id(s=z)", "Id(n=7)"]
+    );
+}
+
+#[test]
+fn loads_only_the_packages_the_entry_reaches() {
+    let source_root = std::env::temp_dir().join("dotlin-modules-reached");
+    let _ = std::fs::remove_dir_all(&source_root);
+    write_sources(
+        &source_root,
+        &[
+            (
+                "app/main.lin",
+                "package app\n\nimport util.twice\n\nfun main() {\n    println(twice(2))\n}\n",
+            ),
+            (
+                "util/math.lin",
+                "package util\n\nfun twice(n: Int): Int = n * 2\n",
+            ),
+            // Neither the default package nor a file that doesn't parse is reached
+            (
+                "tools/script.lin",
+                "fun main() {\n    println(\"script\")\n}\n",
+            ),
+            ("broken/bad.lin", "package broken\n\nfun (\n"),
+        ],
+    );
+    let run = || {
+        dotc(&source_root)
+            .arg("--run")
+            .output()
+            .expect("failed to run dotc --run")
+    };
+    let output = run();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(program_lines(&output), ["4"]);
+
+    // Two files of one package may not both declare `main`
+    write_sources(
+        &source_root,
+        &[("app/other.lin", "package app\n\nfun main() {\n}\n")],
+    );
+    let output = run();
+    let _ = std::fs::remove_dir_all(&source_root);
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("Conflicting declarations of 'app.main'")
+    );
+}
+
+#[test]
+fn rejects_imports_without_source_root() {
+    let entry = workspace_root()
+        .join("examples")
+        .join("modules")
+        .join("app")
+        .join("main.lin");
+    let output = Command::new(env!("CARGO_BIN_EXE_dotc"))
+        .arg("--run")
+        .arg(entry)
+        .output()
+        .expect("failed to run dotc --run");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Unresolved import"));
+}
+
+#[test]
+fn links_one_object_per_module() {
+    // On CI runners the runtime environment can be fragile; the interpreter test covers it there.
+    if std::env::var("GITHUB_ACTIONS").is_ok() || std::env::var("CI").is_ok() {
+        return;
+    }
+    let workspace_root = workspace_root();
+    let status = Command::new("cargo")
+        .args(["build", "-p", "dotlin_runtime", "--release"])
+        .current_dir(&workspace_root)
+        .status()
+        .expect("failed to run cargo build for dotlin_runtime");
+    assert!(status.success(), "building dotlin_runtime failed");
+    let runtime_dir = std::env::var("CARGO_TARGET_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| workspace_root.join("target"))
+        .join("release");
+
+    let work_dir = std::env::temp_dir().join("dotlin-modules-integration");
+    std::fs::create_dir_all(&work_dir).unwrap();
+    let exe = work_dir.join("modules.exe");
+    // dotc writes its object files to the working directory
+    let compiled = dotc(&workspace_root.join("examples").join("modules"))
+        .arg("-o")
+        .arg(&exe)
+        .arg("--runtime-path")
+        .arg(&runtime_dir)
+        .current_dir(&work_dir)
+        .output()
+        .expect("failed to run dotc");
+    assert!(
+        compiled.status.success() && exe.exists(),
+        "dotc failed: {}",
+        String::from_utf8_lossy(&compiled.stderr)
+    );
+
+    let mut cmd = Command::new(&exe);
+    for var in ["LD_LIBRARY_PATH", "DYLD_LIBRARY_PATH", "PATH"] {
+        let mut paths = vec![runtime_dir.clone()];
+        if let Some(existing) = std::env::var_os(var) {
+            paths.extend(std::env::split_paths(&existing));
+        }
+        cmd.env(var, std::env::join_paths(paths).unwrap());
+    }
+    let output = cmd.output().expect("failed to run compiled program");
+    let _ = std::fs::remove_dir_all(&work_dir);
+
+    assert!(output.status.success());
+    assert_eq!(program_lines(&output), EXPECTED);
+}
@@ -0,0 +1,119 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const PROGRAM: &str = "fun fib(n: Int): Int {
+    if (n < 2) { return n }
+    return fib(n - 1) + fib(n - 2)
+}
+fun main() {
+    println(fib(10))
+}
+";
+
+fn workspace_root() -> PathBuf {
+    // Workspace root (two levels up from crates/dotc)
+    let manifest = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    manifest.parent().unwrap().parent().unwrap().to_path_buf()
+}
+
+/// Builds the runtime library and returns the directory containing it.
+fn build_runtime(workspace_root: &Path) -> PathBuf {
+    let status = Command::new("cargo")
+        .args(["build", "-p", "dotlin_runtime", "--release"])
+        .current_dir(workspace_root)
+        .status()
+        .expect("failed to run cargo build for dotlin_runtime");
+    assert!(status.success(), "building dotlin_runtime failed");
+    std::env::var("CARGO_TARGET_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| workspace_root.join("target"))
+        .join("release")
+}
+
+/// Checks the profile a run of `PROGRAM` wrote to `dir`.
+fn check_profile(dir: &Path) {
+    let folded = std::fs::read_to_string(dir.join("profile.folded")).unwrap();
+    let stacks: Vec<&str> = folded
+        .lines()
+        .map(|line| {
+            let (stack, micros) = line.rsplit_once(' ').unwrap();
+            assert!(micros.parse::<u64>().is_ok(), "bad sample: {}", line);
+            stack
+        })
+        .collect();
+    // fib(10) recurses 9 levels deep below the first call
+    assert_eq!(stacks.len(), 11, "{}", folded);
+    assert_eq!(stacks[0], "main");
+    assert_eq!(stacks[10], format!("main{}", ";fib".repeat(10)));
+
+    let json: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(dir.join("profile.json")).unwrap()).unwrap();
+    let functions = json["functions"].as_array().unwrap();
+    let calls = |name: &str| {
+        functions
+            .iter()
+            .find(|function| function["name"] == name)
+            .map(|function| function["calls"].as_u64().unwrap())
+    };
+    assert_eq!(calls("main"), Some(1));
+    assert_eq!(calls("fib"), Some(177));
+}
+
+#[test]
+fn interpreted_runs_write_a_profile() {
+    let dir = std::env::temp_dir().join("dotlin-profile-interpreted");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("main.lin"), PROGRAM).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_dotc"))
+        .args(["--run", "--profile", "main.lin"])
+        .current_dir(&dir)
+        .output()
+        .expect("failed to run dotc --run --profile");
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).starts_with("55\n"));
+    check_profile(&dir);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn profiled_executables_write_a_profile() {
+    // On CI runners the runtime environment can be fragile; only check the interpreter there.
+    if std::env::var("GITHUB_ACTIONS").is_ok() || std::env::var("CI").is_ok() {
+        return;
+    }
+    let runtime_dir = build_runtime(&workspace_root());
+    let dir = std::env::temp_dir().join("dotlin-profile-native");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("main.lin"), PROGRAM).unwrap();
+
+    // dotc writes its object file to the working directory
+    let exe = dir.join("program.exe");
+    let compiled = Command::new(env!("CARGO_BIN_EXE_dotc"))
+        .args(["--profile", "main.lin", "-o"])
+        .arg(&exe)
+        .arg("--runtime-path")
+        .arg(&runtime_dir)
+        .current_dir(&dir)
+        .output()
+        .expect("failed to run dotc");
+    assert!(
+        compiled.status.success() && exe.exists(),
+        "dotc failed: {}",
+        String::from_utf8_lossy(&compiled.stderr)
+    );
+
+    let mut cmd = Command::new(&exe);
+    for var in ["LD_LIBRARY_PATH", "DYLD_LIBRARY_PATH", "PATH"] {
+        let mut paths = vec![runtime_dir.clone()];
+        if let Some(existing) = std::env::var_os(var) {
+            paths.extend(std::env::split_paths(&existing));
+        }
+        cmd.env(var, std::env::join_paths(paths).unwrap());
+    }
+    let output = cmd.current_dir(&dir).output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "55\n");
+    check_profile(&dir);
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -63,6 +63,20 @@ fn format_code(input: &str) -> Result<String> {
 fn format_program(program: &Program) -> String {
     let mut result = String::new();
 
+    if let Some(package) = &program.package {
+        result.push_str(&format!("package {}\n\n", package));
+    }
+    for import in &program.imports {
+        if import.wildcard {
+            result.push_str(&format!("import {}.*\n", import.path));
+        } else {
+            result.push_str(&format!("import {}\n", import.path));
+        }
+    }
+    if !program.imports.is_empty() {
+        result.push('\n');
+    }
+
     for decl in &program.declarations {
         result.push_str(&format_declaration(decl, 0));
         result.push_str("\n\n");
@@ -76,11 +90,162 @@ fn format_declaration(decl: &Declaration, indent_level: usize) -> String {
 
     match decl {
         Declaration::Function(func) => format_function(func, indent_level),
+        Declaration::Object(object) => format_object(object, indent_level),
+        Declaration::TypeAlias(alias) => format!(
+            "{}{}typealias {} = {}",
+            _indent,
+            format_visibility(alias.visibility),
+            alias.name,
+            format_type(&alias.typ)
+        ),
+        Declaration::ValueClass(class) => format_value_class(class, indent_level),
+    }
+}
+
+fn format_visibility(visibility: Visibility) -> &'static str {
+    match visibility {
+        Visibility::Public => "",
+        Visibility::Internal => "internal ",
+        Visibility::Private => "private ",
+    }
+}
+
+fn format_object(object: &ObjectDecl, indent_level: usize) -> String {
+    format!(
+        "{}{}object {} {}",
+        "    ".repeat(indent_level),
+        format_visibility(object.visibility),
+        object.name,
+        format_object_body(object, indent_level)
+    )
+}
+
+fn format_object_body(object: &ObjectDecl, indent_level: usize) -> String {
+    let indent = "    ".repeat(indent_level);
+
+    let mut members = Vec::new();
+    for property in &object.properties {
+        members.push(format_property(property, indent_level + 1));
+    }
+    for func in &object.functions {
+        members.push(format_function(func, indent_level + 1));
+    }
+
+    format!("{{\n{}\n{}}}", members.join("\n\n"), indent)
+}
+
+fn format_value_class(class: &ValueClassDecl, indent_level: usize) -> String {
+    let indent = "    ".repeat(indent_level);
+    let mut result = format!(
+        "{}@JvmInline\n{}{}value class {}(val {}: {})",
+        indent,
+        indent,
+        format_visibility(class.visibility),
+        class.name,
+        class.property.name,
+        format_type(&class.property.typ)
+    );
+    if !class.functions.is_empty() || class.companion.is_some() {
+        // The parser adds the `this` parameter
+        let mut members: Vec<String> = class
+            .functions
+            .iter()
+            .map(|func| {
+                let mut func = func.clone();
+                func.params.remove(0);
+                format_function(&func, indent_level + 1)
+            })
+            .collect();
+        if let Some(companion) = &class.companion {
+            members.push(format!(
+                "{}{}companion object {}",
+                "    ".repeat(indent_level + 1),
+                format_visibility(companion.visibility),
+                format_object_body(companion, indent_level + 1)
+            ));
+        }
+        result.push_str(&format!(" {{\n{}\n{}}}", members.join("\n\n"), indent));
+    }
+    result
+}
+
+fn format_property(property: &PropertyDecl, indent_level: usize) -> String {
+    let indent = "    ".repeat(indent_level);
+    let type_str = if let Some(t) = property.typ.as_ref() {
+        format!(": {}", format_type(t))
+    } else {
+        String::new()
+    };
+    let mut result = format!(
+        "{}{}{}{} {}{}",
+        indent,
+        format_visibility(property.visibility),
+        if property.lateinit { "lateinit " } else { "" },
+        if property.mutable { "var" } else { "val" },
+        property.name,
+        type_str
+    );
+    if let Some(initializer) = &property.initializer {
+        result.push_str(&format!(
+            " = {}",
+            format_expression(initializer, indent_level)
+        ));
+    }
+    if let Some(delegate) = &property.delegate {
+        result.push_str(&format!(
+            " by {}",
+            format_expression(delegate, indent_level)
+        ));
     }
+    let accessor_indent = "    ".repeat(indent_level + 1);
+    for (accessor, head) in [
+        (&property.getter, "get()".to_string()),
+        (
+            &property.setter,
+            match property.setter.as_ref().map(|setter| &setter.params[0]) {
+                Some(param) if param.typ != Type::Named("_".to_string()) => {
+                    format!("set({}: {})", param.name, format_type(&param.typ))
+                }
+                Some(param) => format!("set({})", param.name),
+                None => String::new(),
+            },
+        ),
+    ] {
+        let Some(accessor) = accessor else {
+            continue;
+        };
+        match accessor.body.statements.first() {
+            Some(Statement::Return(Some(expr)) | Statement::Expression(expr))
+                if accessor.expression_body =>
+            {
+                result.push_str(&format!(
+                    "\n{}{} = {}",
+                    accessor_indent,
+                    head,
+                    format_expression(expr, indent_level + 1)
+                ));
+            }
+            _ => result.push_str(&format!(
+                "\n{}{} {{\n{}\n{}}}",
+                accessor_indent,
+                head,
+                format_block(&accessor.body, indent_level + 2),
+                accessor_indent
+            )),
+        }
+    }
+    result
 }
 
 fn format_function(func: &FunctionDecl, indent_level: usize) -> String {
     let indent = "    ".repeat(indent_level);
+    let mut modifier = format_visibility(func.visibility).to_string();
+    if func.is_operator {
+        modifier.push_str("operator ");
+    }
+    if func.is_suspend {
+        modifier.push_str("suspend ");
+    }
 
     let params_str = func
         .params
@@ -95,11 +260,27 @@ fn format_function(func: &FunctionDecl, indent_level: usize) -> String {
         String::new()
     };
 
+    if func.expression_body {
+        if let Some(Statement::Return(Some(expr)) | Statement::Expression(expr)) =
+            func.body.statements.first()
+        {
+            return format!(
+                "{}{}fun {}({}){} = {}",
+                indent,
+                modifier,
+                func.name,
+                params_str,
+                return_str,
+                format_expression(expr, indent_level)
+            );
+        }
+    }
+
     let body_str = format_block(&func.body, indent_level + 1);
 
     format!(
-        "{}fun {}({}){} {{\n{}\n{}}}",
-        indent, func.name, params_str, return_str, body_str, indent
+        "{}{}fun {}({}){} {{\n{}\n{}}}",
+        indent, modifier, func.name, params_str, return_str, body_str, indent
     )
 }
 
@@ -148,6 +329,11 @@ fn format_statement(stmt: &Statement, indent_level: usize) -> String {
 
             format!("var {}{}{}", name, type_str, init_str)
         }
+        Statement::Destructuring { names, initializer } => format!(
+            "val ({}) = {}",
+            names.join(", "),
+            format_expression(initializer, indent_level)
+        ),
         Statement::Return(expr) => {
             let expr_str = if let Some(e) = expr.as_ref() {
                 format!(" {}", format_expression(e, indent_level))
@@ -180,6 +366,15 @@ fn format_statement(stmt: &Statement, indent_level: usize) -> String {
 
             format!("while ({}) {}", cond_str, body_str)
         }
+        Statement::DoWhile { body, condition } => {
+            let indent = "    ".repeat(indent_level);
+            format!(
+                "do {{\n{}\n{}}} while ({})",
+                format_block(body, indent_level + 1),
+                indent,
+                format_expression(condition, indent_level)
+            )
+        }
         Statement::ForEach {
             variable,
             iterable,
@@ -203,8 +398,18 @@ fn format_expression(expr: &Expression, _indent_level: usize) -> String {
     match &*expr.kind {
         ExpressionKind::Literal(lit) => format_literal(lit),
         ExpressionKind::Variable(name) => name.clone(),
-        ExpressionKind::Assignment { name, value } => {
-            format!("{} = {}", name, format_expression(value, _indent_level))
+        ExpressionKind::Assignment {
+            target,
+            operator,
+            value,
+        } => {
+            let operator = operator.as_ref().map_or(String::new(), format_binary_op);
+            format!(
+                "{} {}= {}",
+                format_expression(target, _indent_level),
+                operator,
+                format_expression(value, _indent_level)
+            )
         }
         ExpressionKind::Call { callee, arguments } => {
             let args: Vec<String> = arguments
@@ -266,16 +471,89 @@ fn format_expression(expr: &Expression, _indent_level: usize) -> String {
                 .collect();
             format!("{{ {} }}", pairs_str.join(", "))
         }
+        ExpressionKind::If {
+            condition,
+            then_branch,
+            else_branch,
+        } => {
+            let cond_str = format_expression(condition, _indent_level);
+            let then_str = format_branch(then_branch, _indent_level);
+            if let Some(els) = else_branch {
+                format!(
+                    "if ({}) {} else {}",
+                    cond_str,
+                    then_str,
+                    format_branch(els, _indent_level)
+                )
+            } else {
+                format!("if ({}) {}", cond_str, then_str)
+            }
+        }
+        ExpressionKind::Lambda { params, body } => {
+            let params: Vec<String> = params
+                .iter()
+                .map(|param| match &param.typ {
+                    Some(typ) => format!("{}: {}", param.name, format_type(typ)),
+                    None => param.name.clone(),
+                })
+                .collect();
+            let header = if params.is_empty() {
+                String::new()
+            } else {
+                format!(" {} ->", params.join(", "))
+            };
+            match body.statements.as_slice() {
+                [Statement::Expression(expr)] => {
+                    format!("{{{} {} }}", header, format_expression(expr, _indent_level))
+                }
+                _ => format!(
+                    "{{{}\n{}\n{}}}",
+                    header,
+                    format_block(body, _indent_level + 1),
+                    "    ".repeat(_indent_level)
+                ),
+            }
+        }
+        ExpressionKind::SafeAccess { object, access } => {
+            let access = format_expression(access, _indent_level);
+            let receiver = format!("{}.", dotlin_ast::SAFE_RECEIVER);
+            format!(
+                "{}?.{}",
+                format_expression(object, _indent_level),
+                access.strip_prefix(&receiver).unwrap_or(&access)
+            )
+        }
+        ExpressionKind::Nullable(value) => format_expression(value, _indent_level),
+    }
+}
+
+fn format_branch(block: &Block, indent_level: usize) -> String {
+    match block.statements.as_slice() {
+        [Statement::Expression(expr)] => format_expression(expr, indent_level),
+        _ => {
+            let indent = "    ".repeat(indent_level);
+            format!(
+                "{{\n{}\n{}}}",
+                format_block(block, indent_level + 1),
+                indent
+            )
+        }
     }
 }
 
 fn format_literal(lit: &Literal) -> String {
     match lit {
         Literal::Integer(i) => i.to_string(),
-        Literal::Float(f) => f.to_string(),
+        Literal::Long(i) => format!("{}L", i),
+        Literal::UInt(u) => format!("{}u", u),
+        Literal::ULong(u) => format!("{}uL", u),
+        // Debug formatting keeps the `.0` that marks a floating-point literal
+        Literal::Float(f) => format!("{:?}f", f),
+        Literal::Double(f) => format!("{:?}", f),
         Literal::String(s) => format!("\"{}\"", s),
         Literal::Boolean(b) => b.to_string(),
         Literal::Char(c) => format!("'{}'", c),
+        Literal::Null => "null".to_string(),
     }
 }
 
@@ -292,6 +570,11 @@ fn format_type(typ: &Type) -> String {
             let param_strs: Vec<String> = params.iter().map(format_type).collect();
             format!("{}<{}>", name, param_strs.join(", "))
         }
+        Type::Function(params, ret) => {
+            let param_strs: Vec<String> = params.iter().map(format_type).collect();
+            format!("({}) -> {}", param_strs.join(", "), format_type(ret))
+        }
+        Type::Nullable(inner) => format!("{}?", format_type(inner)),
     }
 }
 
@@ -301,18 +584,28 @@ fn format_binary_op(op: &BinaryOp) -> String {
         BinaryOp::Sub => "-",
         BinaryOp::Mul => "*",
         BinaryOp::Div => "/",
+        BinaryOp::Rem => "%",
         BinaryOp::PlusEqual => "+=",
         BinaryOp::MinusEqual => "-=",
         BinaryOp::StarEqual => "*=",
         BinaryOp::SlashEqual => "/=",
         BinaryOp::Equal => "==",
         BinaryOp::NotEqual => "!=",
+        BinaryOp::Identical => "===",
+        BinaryOp::NotIdentical => "!==",
         BinaryOp::Less => "<",
         BinaryOp::LessEqual => "<=",
         BinaryOp::Greater => ">",
         BinaryOp::GreaterEqual => ">=",
         BinaryOp::And => "&&",
         BinaryOp::Or => "||",
+        BinaryOp::BitAnd => "and",
+        BinaryOp::BitOr => "or",
+        BinaryOp::BitXor => "xor",
+        BinaryOp::Shl => "shl",
+        BinaryOp::Shr => "shr",
+        BinaryOp::UShr => "ushr",
+        BinaryOp::Elvis => "?:",
     }
     .to_string()
 }
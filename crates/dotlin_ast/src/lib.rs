@@ -1,19 +1,316 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Program {
+    /// The `package a.b` header; `None` for the default package
+    pub package: Option<String>,
+    pub imports: Vec<Import>,
     pub declarations: Vec<Declaration>,
 }
 
+impl Program {
+    /// The fully qualified name of a top-level declaration in this file, e.g. `a.b.foo`.
+    pub fn qualified_name(&self, name: &str) -> String {
+        match &self.package {
+            Some(package) => format!("{}.{}", package, name),
+            None => name.to_string(),
+        }
+    }
+
+    /// Every function of the file, top-level and inside objects, including
+    /// property accessors.
+    pub fn functions(&self) -> Vec<FunctionItem<'_>> {
+        let mut functions = Vec::new();
+        for decl in &self.declarations {
+            match decl {
+                Declaration::Function(func) => functions.push(FunctionItem {
+                    name: self.qualified_name(&func.name),
+                    object: None,
+                    private: func.visibility == Visibility::Private,
+                    decl: func,
+                }),
+                Declaration::Object(object) => {
+                    let object_name = self.qualified_name(&object.name);
+                    for func in object.functions.iter().chain(object.accessors()) {
+                        functions.push(FunctionItem {
+                            name: format!("{}.{}", object_name, func.name),
+                            object: Some(object_name.clone()),
+                            private: object.visibility == Visibility::Private
+                                || func.visibility == Visibility::Private,
+                            decl: func,
+                        });
+                    }
+                }
+                Declaration::ValueClass(class) => {
+                    let class_name = self.qualified_name(&class.name);
+                    for func in &class.functions {
+                        functions.push(FunctionItem {
+                            name: format!("{}.{}", class_name, func.name),
+                            object: None,
+                            private: class.visibility == Visibility::Private
+                                || func.visibility == Visibility::Private,
+                            decl: func,
+                        });
+                    }
+                }
+                Declaration::TypeAlias(_) => {}
+            }
+            if let Some(object) = decl.companion() {
+                let object_name = self.qualified_name(&object.name);
+                for func in object.functions.iter().chain(object.accessors()) {
+                    functions.push(FunctionItem {
+                        name: format!("{}.{}", object_name, func.name),
+                        object: Some(object_name.clone()),
+                        private: object.visibility == Visibility::Private
+                            || func.visibility == Visibility::Private,
+                        decl: func,
+                    });
+                }
+            }
+        }
+        functions
+    }
+
+    /// The file's objects, including the companion objects of its classes.
+    pub fn objects(&self) -> impl Iterator<Item = &ObjectDecl> {
+        self.declarations.iter().filter_map(Declaration::object)
+    }
+
+    /// Calls `visit` on every type written in the file or resolved for one
+    /// of its expressions.
+    pub fn visit_types_mut(&mut self, visit: &mut dyn FnMut(&mut Type)) {
+        for decl in &mut self.declarations {
+            match decl {
+                Declaration::Function(func) => func.visit_types_mut(visit),
+                Declaration::Object(object) => object.visit_types_mut(visit),
+                Declaration::TypeAlias(alias) => visit(&mut alias.typ),
+                Declaration::ValueClass(class) => {
+                    visit(&mut class.property.typ);
+                    for func in &mut class.functions {
+                        func.visit_types_mut(visit);
+                    }
+                    if let Some(companion) = &mut class.companion {
+                        companion.visit_types_mut(visit);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A function of a file as seen from outside it.
+pub struct FunctionItem<'a> {
+    /// Qualified name: `a.b.foo`, or `a.b.Registry.register` for an object member
+    pub name: String,
+    /// Qualified name of the enclosing object
+    pub object: Option<String>,
+    /// Not callable from other files
+    pub private: bool,
+    pub decl: &'a FunctionDecl,
+}
+
+/// `import a.b.foo`, or `import a.b.*` when `wildcard` is set (then `path` is the package).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Import {
+    pub path: String,
+    pub wildcard: bool,
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Declaration {
     Function(FunctionDecl),
+    Object(ObjectDecl),
+    TypeAlias(TypeAliasDecl),
+    ValueClass(ValueClassDecl),
+}
+
+impl Declaration {
+    /// The object this declaration is or, for a class, its companion object.
+    pub fn object(&self) -> Option<&ObjectDecl> {
+        match self {
+            Declaration::Object(object) => Some(object),
+            _ => self.companion(),
+        }
+    }
+
+    pub fn object_mut(&mut self) -> Option<&mut ObjectDecl> {
+        match self {
+            Declaration::Object(object) => Some(object),
+            Declaration::ValueClass(class) => class.companion.as_mut(),
+            _ => None,
+        }
+    }
+
+    /// The companion object of a class.
+    pub fn companion(&self) -> Option<&ObjectDecl> {
+        match self {
+            Declaration::ValueClass(class) => class.companion.as_ref(),
+            _ => None,
+        }
+    }
+}
+
+/// `typealias Name = Type`: another name for a type, which the type checker
+/// replaces with the type itself.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeAliasDecl {
+    pub visibility: Visibility,
+    pub name: String,
+    pub typ: Type,
+}
+
+/// `@JvmInline value class Name(val property: Type) { ... }`: a wrapper
+/// around a single value. The type checker treats it as a type of its own,
+/// then erases it to the type of the property, so at run time an instance
+/// is just its value.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValueClassDecl {
+    pub visibility: Visibility,
+    pub name: String,
+    pub property: Param,
+    /// Member functions, each with the instance as a first parameter named `this`
+    pub functions: Vec<FunctionDecl>,
+    /// `companion object { ... }` in the body: an object named after the
+    /// class, so `Name.create()` calls its `create`
+    pub companion: Option<ObjectDecl>,
+}
+
+/// `object Name { ... }`: a singleton, initialized on first use.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObjectDecl {
+    pub visibility: Visibility,
+    pub name: String,
+    pub properties: Vec<PropertyDecl>,
+    pub functions: Vec<FunctionDecl>,
+}
+
+impl ObjectDecl {
+    /// The getters and setters of the object's properties.
+    pub fn accessors(&self) -> impl Iterator<Item = &FunctionDecl> {
+        self.properties
+            .iter()
+            .flat_map(|property| property.getter.iter().chain(&property.setter))
+    }
+
+    fn visit_types_mut(&mut self, visit: &mut dyn FnMut(&mut Type)) {
+        for property in &mut self.properties {
+            if let Some(typ) = &mut property.typ {
+                visit(typ);
+            }
+            for expr in property
+                .initializer
+                .iter_mut()
+                .chain(&mut property.delegate)
+            {
+                expr.visit_types_mut(visit);
+            }
+            for accessor in property.getter.iter_mut().chain(&mut property.setter) {
+                accessor.visit_types_mut(visit);
+            }
+        }
+        for func in &mut self.functions {
+            func.visit_types_mut(visit);
+        }
+    }
+}
+
+/// `val name: Type = initializer` in an object, optionally with accessors:
+///
+/// ```kotlin
+/// var name: Type = initializer
+///     get() = field
+///     set(value) { field = value }
+/// ```
+///
+/// or `lateinit var name: Type`, or `val name: Type by delegate`. The type
+/// checker fills in an omitted type.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PropertyDecl {
+    pub visibility: Visibility,
+    /// `var` rather than `val`
+    pub mutable: bool,
+    pub name: String,
+    pub typ: Option<Type>,
+    /// Unset for a `lateinit` or delegated property, and for one its getter computes
+    pub initializer: Option<Expression>,
+    /// `lateinit var`: unset until first assigned
+    pub lateinit: bool,
+    /// `by lazy { ... }`, or an object with `operator fun getValue` (and
+    /// `setValue` for a `var`)
+    pub delegate: Option<Expression>,
+    /// `get()`, a function named `getter_name(name)`. Accessors read and
+    /// write the property's value as `field`. The type checker adds the
+    /// accessors of a property delegated to an object.
+    pub getter: Option<FunctionDecl>,
+    /// `set(value)`, a function named `setter_name(name)`. A parameter
+    /// without a type has the type `_` until the type checker fills in the
+    /// property's.
+    pub setter: Option<FunctionDecl>,
+}
+
+impl PropertyDecl {
+    /// The name of property `name`'s getter, like its name on the JVM.
+    pub fn getter_name(name: &str) -> String {
+        format!("<get-{}>", name)
+    }
+
+    pub fn setter_name(name: &str) -> String {
+        format!("<set-{}>", name)
+    }
+
+    /// The initializer of a `by lazy { ... }` property.
+    pub fn lazy_initializer(&self) -> Option<&Block> {
+        let ExpressionKind::Call { callee, arguments } = &*self.delegate.as_ref()?.kind else {
+            return None;
+        };
+        match (&*callee.kind, arguments.as_slice()) {
+            (ExpressionKind::Variable(name), [lambda]) if name == "lazy" => match &*lambda.kind {
+                ExpressionKind::Lambda { params, body } if params.is_empty() => Some(body),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct FunctionDecl {
+    pub visibility: Visibility,
     pub name: String,
     pub params: Vec<Param>,
     pub return_type: Option<Type>,
     pub body: Block,
+    /// True for `fun f() = expr`; the body is desugared to `{ return expr }`.
+    pub expression_body: bool,
+    /// True for `suspend fun`, which may only be called from a coroutine.
+    pub is_suspend: bool,
+    /// True for `operator fun`, such as a delegate's `getValue`.
+    pub is_operator: bool,
+    /// The name of the source file, for stack traces
+    pub file: Option<String>,
+}
+
+impl FunctionDecl {
+    fn visit_types_mut(&mut self, visit: &mut dyn FnMut(&mut Type)) {
+        for param in &mut self.params {
+            visit(&mut param.typ);
+        }
+        if let Some(typ) = &mut self.return_type {
+            visit(typ);
+        }
+        self.body.visit_types_mut(visit);
+    }
+}
+
+/// Declarations without a modifier are public. `internal` ones are visible to
+/// every file compiled together and `private` ones only inside their own file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Visibility {
+    #[default]
+    Public,
+    Internal,
+    Private,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -28,11 +325,58 @@ pub enum Type {
     Array(Box<Type>),
     Map(Box<Type>, Box<Type>),  // Key-value map type
     Generic(String, Vec<Type>), // Generic type with type parameters
+    /// `(A, B) -> R`
+    Function(Vec<Type>, Box<Type>),
+    /// `T?`; the type of `null` is `Nothing?`
+    Nullable(Box<Type>),
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Block {
     pub statements: Vec<Statement>,
+    /// The names of the variables the block's scope holds, in slot order;
+    /// only the interpreter's resolver sets them
+    pub locals: Option<Arc<[String]>>,
+}
+
+impl Block {
+    pub fn new(statements: Vec<Statement>) -> Self {
+        Self {
+            statements,
+            locals: None,
+        }
+    }
+
+    /// Every name the block uses as a variable or callee, whether declared in it or not.
+    pub fn variable_names(&self) -> HashSet<String> {
+        let mut names = HashSet::new();
+        for stmt in &self.statements {
+            stmt.collect_names(&mut names);
+        }
+        names
+    }
+
+    fn visit_types_mut(&mut self, visit: &mut dyn FnMut(&mut Type)) {
+        for stmt in &mut self.statements {
+            stmt.visit_types_mut(visit);
+        }
+    }
+
+    /// Calls `visit` on every statement and expression of the block, each
+    /// before the ones nested in it, including the branches of `if`
+    /// expressions and the bodies of lambdas.
+    pub fn walk(&self, visit: &mut dyn FnMut(Node)) {
+        for stmt in &self.statements {
+            stmt.walk(visit);
+        }
+    }
+}
+
+/// A statement or an expression, as `Block::walk` visits them.
+#[derive(Debug, Clone, Copy)]
+pub enum Node<'a> {
+    Statement(&'a Statement),
+    Expression(&'a Expression),
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -44,6 +388,11 @@ pub enum Statement {
         typ: Option<Type>,
         initializer: Option<Expression>,
     },
+    /// `val (a, _, c) = expr`; a `_` name skips that component.
+    Destructuring {
+        names: Vec<String>,
+        initializer: Expression,
+    },
     Return(Option<Expression>),
     If {
         condition: Expression,
@@ -54,6 +403,11 @@ pub enum Statement {
         condition: Expression,
         body: Box<Statement>,
     },
+    /// `do { ... } while (condition)`; the condition sees the body's variables.
+    DoWhile {
+        body: Block,
+        condition: Expression,
+    },
     ForEach {
         variable: ForEachTarget,
         iterable: Expression,
@@ -61,6 +415,256 @@ pub enum Statement {
     },
 }
 
+impl Statement {
+    /// The line of the statement's first expression, or 0 if it has none.
+    pub fn line(&self) -> u32 {
+        match self {
+            Statement::Expression(expr)
+            | Statement::Destructuring {
+                initializer: expr, ..
+            }
+            | Statement::Return(Some(expr))
+            | Statement::If {
+                condition: expr, ..
+            }
+            | Statement::While {
+                condition: expr, ..
+            }
+            | Statement::ForEach { iterable: expr, .. } => expr.line,
+            Statement::VariableDecl {
+                initializer: Some(expr),
+                ..
+            } => expr.line,
+            Statement::DoWhile { body, .. } | Statement::Block(body) => {
+                body.statements.first().map_or(0, Statement::line)
+            }
+            _ => 0,
+        }
+    }
+
+    /// The argument of a `yield(value)` statement in a `sequence { }` body.
+    pub fn yielded(&self) -> Option<&Expression> {
+        match self {
+            Statement::Expression(expr) => match expr.kind.as_ref() {
+                ExpressionKind::Call { callee, arguments } if arguments.len() == 1 => {
+                    match callee.kind.as_ref() {
+                        ExpressionKind::Variable(name) if name == "yield" => Some(&arguments[0]),
+                        _ => None,
+                    }
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn collect_names(&self, names: &mut HashSet<String>) {
+        match self {
+            Statement::Expression(expr) => expr.collect_names(names),
+            Statement::Block(block) => block.statements.iter().for_each(|s| s.collect_names(names)),
+            Statement::VariableDecl { initializer, .. } => {
+                if let Some(init) = initializer {
+                    init.collect_names(names);
+                }
+            }
+            Statement::Destructuring { initializer, .. } => initializer.collect_names(names),
+            Statement::Return(value) => {
+                if let Some(value) = value {
+                    value.collect_names(names);
+                }
+            }
+            Statement::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                condition.collect_names(names);
+                then_branch.collect_names(names);
+                if let Some(branch) = else_branch {
+                    branch.collect_names(names);
+                }
+            }
+            Statement::While { condition, body } => {
+                condition.collect_names(names);
+                body.collect_names(names);
+            }
+            Statement::DoWhile { body, condition } => {
+                body.statements.iter().for_each(|s| s.collect_names(names));
+                condition.collect_names(names);
+            }
+            Statement::ForEach { iterable, body, .. } => {
+                iterable.collect_names(names);
+                body.collect_names(names);
+            }
+        }
+    }
+
+    fn visit_types_mut(&mut self, visit: &mut dyn FnMut(&mut Type)) {
+        match self {
+            Statement::Expression(expr)
+            | Statement::Destructuring {
+                initializer: expr, ..
+            } => expr.visit_types_mut(visit),
+            Statement::Block(block) => block.visit_types_mut(visit),
+            Statement::VariableDecl {
+                typ, initializer, ..
+            } => {
+                if let Some(typ) = typ {
+                    visit(typ);
+                }
+                if let Some(init) = initializer {
+                    init.visit_types_mut(visit);
+                }
+            }
+            Statement::Return(value) => {
+                if let Some(value) = value {
+                    value.visit_types_mut(visit);
+                }
+            }
+            Statement::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                condition.visit_types_mut(visit);
+                then_branch.visit_types_mut(visit);
+                if let Some(branch) = else_branch {
+                    branch.visit_types_mut(visit);
+                }
+            }
+            Statement::While { condition, body } => {
+                condition.visit_types_mut(visit);
+                body.visit_types_mut(visit);
+            }
+            Statement::DoWhile { body, condition } => {
+                body.visit_types_mut(visit);
+                condition.visit_types_mut(visit);
+            }
+            Statement::ForEach { iterable, body, .. } => {
+                iterable.visit_types_mut(visit);
+                body.visit_types_mut(visit);
+            }
+        }
+    }
+
+    /// See `Block::walk`.
+    pub fn walk(&self, visit: &mut dyn FnMut(Node)) {
+        visit(Node::Statement(self));
+        match self {
+            Statement::Expression(expr)
+            | Statement::Destructuring {
+                initializer: expr, ..
+            } => expr.walk(visit),
+            Statement::Block(block) => block.walk(visit),
+            Statement::VariableDecl { initializer, .. } | Statement::Return(initializer) => {
+                if let Some(expr) = initializer {
+                    expr.walk(visit);
+                }
+            }
+            Statement::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                condition.walk(visit);
+                then_branch.walk(visit);
+                if let Some(branch) = else_branch {
+                    branch.walk(visit);
+                }
+            }
+            Statement::While { condition, body } => {
+                condition.walk(visit);
+                body.walk(visit);
+            }
+            Statement::DoWhile { body, condition } => {
+                body.walk(visit);
+                condition.walk(visit);
+            }
+            Statement::ForEach { iterable, body, .. } => {
+                iterable.walk(visit);
+                body.walk(visit);
+            }
+        }
+    }
+
+    /// Whether the statement makes a call `selects` picks, directly or in a
+    /// nested statement. The bodies of lambdas are not searched.
+    pub fn contains_call(&self, selects: &dyn Fn(&Expression) -> bool) -> bool {
+        let in_block = |block: &Block| block.statements.iter().any(|s| s.contains_call(selects));
+        match self {
+            Statement::Expression(expr) => expr.contains_call(selects),
+            Statement::Block(block) => in_block(block),
+            Statement::VariableDecl { initializer, .. } => initializer
+                .as_ref()
+                .is_some_and(|init| init.contains_call(selects)),
+            Statement::Destructuring { initializer, .. } => initializer.contains_call(selects),
+            Statement::Return(value) => value
+                .as_ref()
+                .is_some_and(|value| value.contains_call(selects)),
+            Statement::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                condition.contains_call(selects)
+                    || then_branch.contains_call(selects)
+                    || else_branch
+                        .as_ref()
+                        .is_some_and(|branch| branch.contains_call(selects))
+            }
+            Statement::While { condition, body } => {
+                condition.contains_call(selects) || body.contains_call(selects)
+            }
+            Statement::DoWhile { body, condition } => {
+                in_block(body) || condition.contains_call(selects)
+            }
+            Statement::ForEach { iterable, body, .. } => {
+                iterable.contains_call(selects) || body.contains_call(selects)
+            }
+        }
+    }
+
+    /// Moves the calls `selects` picks out of the expressions the statement
+    /// evaluates before any nested statement, in evaluation order: each call
+    /// is replaced by a variable `$n`, numbered from `next`. Returns the calls
+    /// with their variables, and what is left of the statement, if anything.
+    pub fn hoist_calls(
+        &self,
+        selects: &dyn Fn(&Expression) -> bool,
+        next: &mut usize,
+    ) -> (Vec<(String, Expression)>, Option<Statement>) {
+        let mut hoisted = Vec::new();
+        let mut stmt = self.clone();
+        let expr = match &mut stmt {
+            Statement::Expression(expr)
+            | Statement::VariableDecl {
+                initializer: Some(expr),
+                ..
+            }
+            | Statement::Destructuring {
+                initializer: expr, ..
+            }
+            | Statement::Return(Some(expr))
+            | Statement::If {
+                condition: expr, ..
+            }
+            | Statement::ForEach { iterable: expr, .. } => Some(expr),
+            _ => None,
+        };
+        if let Some(expr) = expr {
+            expr.hoist_calls(selects, next, &mut hoisted);
+        }
+        // A call made for its effect leaves nothing behind
+        let left = match (&stmt, hoisted.last()) {
+            (Statement::Expression(expr), Some((last, _))) => {
+                !matches!(&*expr.kind, ExpressionKind::Variable(name) if name == last)
+            }
+            _ => true,
+        };
+        (hoisted, left.then_some(stmt))
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum ForEachTarget {
     Ident(String),
@@ -71,13 +675,241 @@ pub enum ForEachTarget {
 pub struct Expression {
     pub kind: Box<ExpressionKind>,
     pub resolved_type: Option<Type>,
+    /// Where a local variable lives; only the interpreter's resolver sets it
+    pub slot: Option<ScopeSlot>,
+    /// The source line the expression starts on, counted from 1; 0 if unknown
+    pub line: u32,
+}
+
+/// A local variable's place at run time: `depth` scopes out from the one it
+/// is used in, at `slot` in the order that scope declares its variables.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScopeSlot {
+    pub depth: u32,
+    pub slot: u32,
 }
 
 impl Expression {
+    /// An expression starting where its first operand does, if it has one.
     pub fn new(kind: ExpressionKind) -> Self {
+        let line = match &kind {
+            ExpressionKind::Assignment { target: first, .. }
+            | ExpressionKind::Call { callee: first, .. }
+            | ExpressionKind::Binary { left: first, .. }
+            | ExpressionKind::Unary { operand: first, .. }
+            | ExpressionKind::MemberAccess { object: first, .. }
+            | ExpressionKind::Index { array: first, .. }
+            | ExpressionKind::If {
+                condition: first, ..
+            }
+            | ExpressionKind::SafeAccess { object: first, .. }
+            | ExpressionKind::Nullable(first) => first.line,
+            _ => 0,
+        };
         Self {
             kind: Box::new(kind),
             resolved_type: None,
+            slot: None,
+            line,
+        }
+    }
+
+    fn collect_names(&self, names: &mut HashSet<String>) {
+        match &*self.kind {
+            ExpressionKind::Literal(_) => {}
+            ExpressionKind::Variable(name) => {
+                names.insert(name.clone());
+            }
+            ExpressionKind::Assignment { target, value, .. } => {
+                target.collect_names(names);
+                value.collect_names(names);
+            }
+            ExpressionKind::Call { callee, arguments } => {
+                callee.collect_names(names);
+                arguments.iter().for_each(|arg| arg.collect_names(names));
+            }
+            ExpressionKind::Binary { left, right, .. } => {
+                left.collect_names(names);
+                right.collect_names(names);
+            }
+            ExpressionKind::Unary { operand, .. } => operand.collect_names(names),
+            ExpressionKind::MemberAccess { object, .. } => object.collect_names(names),
+            ExpressionKind::ArrayLiteral { elements } => {
+                elements.iter().for_each(|e| e.collect_names(names))
+            }
+            ExpressionKind::Index { array, index } => {
+                array.collect_names(names);
+                index.collect_names(names);
+            }
+            ExpressionKind::HashMapLiteral { pairs } => {
+                for (key, value) in pairs {
+                    key.collect_names(names);
+                    value.collect_names(names);
+                }
+            }
+            ExpressionKind::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                condition.collect_names(names);
+                names.extend(then_branch.variable_names());
+                if let Some(branch) = else_branch {
+                    names.extend(branch.variable_names());
+                }
+            }
+            ExpressionKind::Lambda { body, .. } => names.extend(body.variable_names()),
+            ExpressionKind::SafeAccess { object, access } => {
+                object.collect_names(names);
+                access.collect_names(names);
+            }
+            ExpressionKind::Nullable(value) => value.collect_names(names),
+        }
+    }
+
+    /// Whether this is a call that can suspend a coroutine: to `delay`, to
+    /// `await` or `join` on a job, or to one of `suspend_functions`.
+    pub fn is_suspend_call(&self, suspend_functions: &HashSet<String>) -> bool {
+        let ExpressionKind::Call { callee, .. } = &*self.kind else {
+            return false;
+        };
+        match &*callee.kind {
+            ExpressionKind::Variable(name) => name == "delay" || suspend_functions.contains(name),
+            ExpressionKind::MemberAccess { member, .. } => member == "await" || member == "join",
+            _ => false,
+        }
+    }
+
+    /// The subexpressions evaluated as part of this one, in evaluation order.
+    /// The branches of an `if`, the body of a lambda and the access of a safe
+    /// call are not included.
+    fn operands(&self) -> Vec<&Expression> {
+        match &*self.kind {
+            ExpressionKind::Literal(_)
+            | ExpressionKind::Variable(_)
+            | ExpressionKind::Lambda { .. } => Vec::new(),
+            ExpressionKind::Assignment { target, value, .. } => vec![target, value],
+            ExpressionKind::Call { callee, arguments } => {
+                std::iter::once(callee).chain(arguments).collect()
+            }
+            ExpressionKind::Binary { left, right, .. } => vec![left, right],
+            ExpressionKind::Unary { operand, .. } => vec![operand],
+            ExpressionKind::MemberAccess { object, .. } => vec![object],
+            ExpressionKind::ArrayLiteral { elements } => elements.iter().collect(),
+            ExpressionKind::Index { array, index } => vec![array, index],
+            ExpressionKind::HashMapLiteral { pairs } => {
+                pairs.iter().flat_map(|(key, value)| [key, value]).collect()
+            }
+            ExpressionKind::If { condition, .. } => vec![condition],
+            ExpressionKind::SafeAccess { object, .. } => vec![object],
+            ExpressionKind::Nullable(value) => vec![value],
+        }
+    }
+
+    fn operands_mut(&mut self) -> Vec<&mut Expression> {
+        match &mut *self.kind {
+            ExpressionKind::Literal(_)
+            | ExpressionKind::Variable(_)
+            | ExpressionKind::Lambda { .. } => Vec::new(),
+            ExpressionKind::Assignment { target, value, .. } => vec![target, value],
+            ExpressionKind::Call { callee, arguments } => {
+                std::iter::once(callee).chain(arguments).collect()
+            }
+            ExpressionKind::Binary { left, right, .. } => vec![left, right],
+            ExpressionKind::Unary { operand, .. } => vec![operand],
+            ExpressionKind::MemberAccess { object, .. } => vec![object],
+            ExpressionKind::ArrayLiteral { elements } => elements.iter_mut().collect(),
+            ExpressionKind::Index { array, index } => vec![array, index],
+            ExpressionKind::HashMapLiteral { pairs } => pairs
+                .iter_mut()
+                .flat_map(|(key, value)| [key, value])
+                .collect(),
+            ExpressionKind::If { condition, .. } => vec![condition],
+            ExpressionKind::SafeAccess { object, .. } => vec![object],
+            ExpressionKind::Nullable(value) => vec![value],
+        }
+    }
+
+    fn visit_types_mut(&mut self, visit: &mut dyn FnMut(&mut Type)) {
+        if let Some(typ) = &mut self.resolved_type {
+            visit(typ);
+        }
+        match &mut *self.kind {
+            ExpressionKind::If {
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                then_branch.visit_types_mut(visit);
+                if let Some(branch) = else_branch {
+                    branch.visit_types_mut(visit);
+                }
+            }
+            ExpressionKind::Lambda { params, body } => {
+                for typ in params.iter_mut().filter_map(|param| param.typ.as_mut()) {
+                    visit(typ);
+                }
+                body.visit_types_mut(visit);
+            }
+            ExpressionKind::SafeAccess { access, .. } => access.visit_types_mut(visit),
+            _ => {}
+        }
+        for operand in self.operands_mut() {
+            operand.visit_types_mut(visit);
+        }
+    }
+
+    /// See `Block::walk`.
+    pub fn walk(&self, visit: &mut dyn FnMut(Node)) {
+        visit(Node::Expression(self));
+        for operand in self.operands() {
+            operand.walk(visit);
+        }
+        match &*self.kind {
+            ExpressionKind::If {
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                then_branch.walk(visit);
+                if let Some(branch) = else_branch {
+                    branch.walk(visit);
+                }
+            }
+            ExpressionKind::Lambda { body, .. } => body.walk(visit),
+            ExpressionKind::SafeAccess { access, .. } => access.walk(visit),
+            _ => {}
+        }
+    }
+
+    /// Whether the expression makes a call `selects` picks, outside of `if`
+    /// branches and lambdas.
+    pub fn contains_call(&self, selects: &dyn Fn(&Expression) -> bool) -> bool {
+        selects(self)
+            || self
+                .operands()
+                .into_iter()
+                .any(|operand| operand.contains_call(selects))
+    }
+
+    fn hoist_calls(
+        &mut self,
+        selects: &dyn Fn(&Expression) -> bool,
+        next: &mut usize,
+        hoisted: &mut Vec<(String, Expression)>,
+    ) {
+        for operand in self.operands_mut() {
+            operand.hoist_calls(selects, next, hoisted);
+        }
+        if selects(self) {
+            let name = format!("${}", next);
+            *next += 1;
+            let call = std::mem::replace(
+                self,
+                Expression::new(ExpressionKind::Variable(name.clone())),
+            );
+            self.resolved_type = call.resolved_type.clone();
+            hoisted.push((name, call));
         }
     }
 }
@@ -86,8 +918,11 @@ impl Expression {
 pub enum ExpressionKind {
     Literal(Literal),
     Variable(String),
+    /// `target = value`, or `target op= value` when `operator` is set. The
+    /// target is a `Variable`, an `Index` or a `MemberAccess`.
     Assignment {
-        name: String,
+        target: Expression,
+        operator: Option<BinaryOp>,
         value: Expression,
     },
     Call {
@@ -117,15 +952,54 @@ pub enum ExpressionKind {
     HashMapLiteral {
         pairs: Vec<(Expression, Expression)>, // (key, value) pairs
     },
+    /// `if` used as an expression; the value of each branch is its last expression statement.
+    If {
+        condition: Expression,
+        then_branch: Block,
+        else_branch: Option<Block>,
+    },
+    /// `{ a, b -> body }`; the value is the body's last expression statement.
+    /// The type checker names the implicit `it` parameter of `{ body }`.
+    Lambda {
+        params: Vec<LambdaParam>,
+        body: Block,
+    },
+    /// `object?.member` or `object?.member(args)`: `null` if the object is,
+    /// otherwise `access`, the same member access on `SAFE_RECEIVER`, which
+    /// holds the object's value.
+    SafeAccess {
+        object: Expression,
+        access: Expression,
+    },
+    /// A value of type `T` used where `T?` is expected. Only the type checker
+    /// adds these.
+    Nullable(Expression),
+}
+
+/// The variable a safe call's access reads the receiver from.
+pub const SAFE_RECEIVER: &str = "$receiver";
+
+/// A lambda parameter; its type may come from the expected function type.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LambdaParam {
+    pub name: String,
+    pub typ: Option<Type>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Literal {
+    /// An unsuffixed integer literal that fits in 32 bits; `Int` unless the
+    /// expected type is another integer type.
     Integer(i64),
-    Float(f64),
+    Long(i64),
+    UInt(u32),
+    ULong(u64),
+    Float(f32),
+    Double(f64),
     String(String),
     Boolean(bool),
     Char(char),
+    Null,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -134,18 +1008,32 @@ pub enum BinaryOp {
     Sub,
     Mul,
     Div,
+    Rem,
     PlusEqual,
     MinusEqual,
     StarEqual,
     SlashEqual,
     Equal,
     NotEqual,
+    /// `===`: the same array or map, not merely an equal one
+    Identical,
+    /// `!==`
+    NotIdentical,
     Less,
     LessEqual,
     Greater,
     GreaterEqual,
     And,
     Or,
+    // Kotlin's infix bit operations on integers
+    BitAnd,
+    BitOr,
+    BitXor,
+    Shl,
+    Shr,
+    UShr,
+    /// `a ?: b`, which only evaluates `b` if `a` is `null`
+    Elvis,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -0,0 +1,309 @@
+//! The program being debugged, which runs on a thread of its own so the
+//! server keeps reading requests while it does. Its values never leave that
+//! thread: while it is stopped, the server passes it the requests that
+//! inspect it, and it answers them itself.
+
+use crate::protocol::{Client, Output};
+use dotlin_ast::Statement;
+use dotlin_interpreter::{Debugger, Interpreter, Io, Paused, Resume, StopReason, Value};
+use dotlin_parser::{Parser, ReplNode};
+use serde_json::{json, Value as Json};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Mutex};
+
+/// The lines with a breakpoint, by file name.
+pub type Breakpoints = Arc<Mutex<HashMap<String, HashSet<u32>>>>;
+
+/// The only thread the client is told about; interpreter threads run one
+/// at a time on it.
+pub const THREAD_ID: i64 = 1;
+
+pub struct Debuggee {
+    client: Client,
+    program: PathBuf,
+    breakpoints: Breakpoints,
+    /// Set by a `pause` request, and before the start to stop on entry
+    pause: Arc<AtomicBool>,
+    /// Whether the next pause is the stop on entry
+    entry: bool,
+    /// The requests that need the stopped program
+    requests: Receiver<Json>,
+    /// What each `variablesReference` handed out during this stop expands to,
+    /// the first being 1
+    references: Vec<Reference>,
+}
+
+enum Reference {
+    Locals(usize),
+    Value(Value),
+}
+
+impl Debuggee {
+    pub fn new(
+        client: Client,
+        program: PathBuf,
+        breakpoints: Breakpoints,
+        pause: Arc<AtomicBool>,
+        requests: Receiver<Json>,
+    ) -> Self {
+        let entry = pause.load(Ordering::SeqCst);
+        Self {
+            client,
+            program,
+            breakpoints,
+            pause,
+            entry,
+            requests,
+            references: Vec::new(),
+        }
+    }
+
+    /// Type-checks and runs the program, then tells the client it exited.
+    pub fn run(self) {
+        let client = self.client.clone();
+        let code = match self.start() {
+            Ok(()) => 0,
+            Err(message) => {
+                client.event("output", json!({ "category": "stderr", "output": message }));
+                1
+            }
+        };
+        client.event("exited", json!({ "exitCode": code }));
+        client.event("terminated", json!({}));
+    }
+
+    fn start(self) -> Result<(), String> {
+        let source = std::fs::read_to_string(&self.program)
+            .map_err(|e| format!("Error reading file {}: {}\n", self.program.display(), e))?;
+        let file = file_name(&self.program);
+        let mut program = Parser::with_file(&source, file)
+            .parse_program()
+            .map_err(|e| format!("Parse Error: {}\n", e))?;
+        dotlin_typechecker::TypeChecker::new()
+            .check_program(&mut program)
+            .map_err(|e| format!("Type Error: {}\n", e))?;
+        dotlin_interpreter::resolve_program(&mut program).map_err(|e| format!("Error: {}\n", e))?;
+
+        let mut interpreter = Interpreter::new();
+        interpreter.set_io(Io {
+            stdout: Box::new(Output {
+                client: self.client.clone(),
+                category: "stdout",
+            }),
+            stderr: Box::new(Output {
+                client: self.client.clone(),
+                category: "stderr",
+            }),
+            stdin: Box::new(std::io::empty()),
+        });
+        interpreter.set_debugger(self);
+        interpreter
+            .interpret_program(&program)
+            .map_err(|e| format!("Runtime Error: {}\n{}", e, interpreter.take_stack_trace()))
+    }
+
+    /// The path of a file a frame names, which is next to the program.
+    fn source(&self, file: &str) -> Json {
+        let path = match self.program.parent() {
+            Some(dir) => dir.join(file),
+            None => PathBuf::from(file),
+        };
+        json!({ "name": file, "path": path.display().to_string() })
+    }
+
+    fn reference(&mut self, reference: Reference) -> usize {
+        self.references.push(reference);
+        self.references.len()
+    }
+
+    /// A variable as the client shows it; arrays, maps and pairs expand.
+    fn variable(&mut self, name: String, value: Value) -> Json {
+        let expands = match &value {
+            Value::Array(elements) => !elements.borrow().is_empty(),
+            Value::HashMap(map) => !map.borrow().is_empty(),
+            Value::Tuple(_) => true,
+            _ => false,
+        };
+        let text = value.to_string();
+        let reference = if expands {
+            self.reference(Reference::Value(value))
+        } else {
+            0
+        };
+        json!({ "name": name, "value": text, "variablesReference": reference })
+    }
+
+    /// The variables a `variablesReference` expands to.
+    fn variables(&mut self, paused: &Paused, reference: usize) -> Vec<Json> {
+        let children = match reference
+            .checked_sub(1)
+            .and_then(|i| self.references.get(i))
+        {
+            Some(Reference::Locals(frame)) => paused.locals(*frame),
+            Some(Reference::Value(Value::Array(elements))) => elements
+                .borrow()
+                .iter()
+                .enumerate()
+                .map(|(i, element)| (format!("[{}]", i), element.clone()))
+                .collect(),
+            Some(Reference::Value(Value::HashMap(map))) => {
+                let mut entries: Vec<_> = map
+                    .borrow()
+                    .iter()
+                    .map(|(key, value)| (format!("[\"{}\"]", key), value.clone()))
+                    .collect();
+                entries.sort_by(|a, b| a.0.cmp(&b.0));
+                entries
+            }
+            Some(Reference::Value(Value::Tuple(components))) => ["first", "second", "third"]
+                .iter()
+                .map(|name| name.to_string())
+                .zip(components.clone())
+                .collect(),
+            _ => Vec::new(),
+        };
+        children
+            .into_iter()
+            .map(|(name, value)| self.variable(name, value))
+            .collect()
+    }
+
+    fn evaluate(&mut self, paused: &Paused, arguments: &Json) -> Result<Json, String> {
+        let source = arguments["expression"].as_str().unwrap_or_default();
+        let frame = arguments["frameId"].as_u64().unwrap_or(0) as usize;
+        let expr = match Parser::new(source).parse_repl_input() {
+            Ok(ReplNode::Stmt(Statement::Expression(expr))) => expr,
+            Ok(_) => return Err("Only expressions can be evaluated".to_string()),
+            Err(e) => return Err(e.to_string()),
+        };
+        let value = paused.evaluate(frame, &expr).map_err(|e| e.to_string())?;
+        let variable = self.variable(String::new(), value);
+        Ok(json!({
+            "result": variable["value"],
+            "variablesReference": variable["variablesReference"],
+        }))
+    }
+
+    /// Answers a request about the stopped program, returning how it goes on
+    /// if the request resumes it.
+    fn answer(&mut self, paused: &Paused, request: &Json) -> Option<Resume> {
+        let arguments = &request["arguments"];
+        let resume = match request["command"].as_str().unwrap_or_default() {
+            "continue" => Resume::Continue,
+            "next" => Resume::StepOver,
+            "stepIn" => Resume::StepIn,
+            "stepOut" => Resume::StepOut,
+            "stackTrace" => {
+                let frames: Vec<Json> = paused
+                    .stack()
+                    .iter()
+                    .enumerate()
+                    .map(|(id, frame)| {
+                        let mut json = json!({
+                            "id": id,
+                            "name": frame.function,
+                            "line": frame.line.unwrap_or(0),
+                            "column": 1,
+                        });
+                        if let Some(file) = &frame.file {
+                            json["source"] = self.source(file);
+                        }
+                        json
+                    })
+                    .collect();
+                let total = frames.len();
+                self.client.respond(
+                    request,
+                    json!({ "stackFrames": frames, "totalFrames": total }),
+                );
+                return None;
+            }
+            "scopes" => {
+                let frame = arguments["frameId"].as_u64().unwrap_or(0) as usize;
+                let locals = self.reference(Reference::Locals(frame));
+                self.client.respond(
+                    request,
+                    json!({ "scopes": [{
+                        "name": "Locals",
+                        "presentationHint": "locals",
+                        "variablesReference": locals,
+                        "expensive": false,
+                    }] }),
+                );
+                return None;
+            }
+            "variables" => {
+                let reference = arguments["variablesReference"].as_u64().unwrap_or(0) as usize;
+                let variables = self.variables(paused, reference);
+                self.client
+                    .respond(request, json!({ "variables": variables }));
+                return None;
+            }
+            "evaluate" => {
+                match self.evaluate(paused, arguments) {
+                    Ok(body) => self.client.respond(request, body),
+                    Err(message) => self.client.fail(request, &message),
+                }
+                return None;
+            }
+            command => {
+                self.client
+                    .fail(request, &format!("Unsupported request '{}'", command));
+                return None;
+            }
+        };
+        let body = match resume {
+            Resume::Continue => json!({ "allThreadsContinued": true }),
+            _ => json!({}),
+        };
+        self.client.respond(request, body);
+        Some(resume)
+    }
+}
+
+impl Debugger for Debuggee {
+    fn breakpoint(&self, file: Option<&str>, line: u32) -> bool {
+        let Some(file) = file else {
+            return false;
+        };
+        let breakpoints = self.breakpoints.lock().unwrap_or_else(|e| e.into_inner());
+        breakpoints
+            .get(file)
+            .is_some_and(|lines| lines.contains(&line))
+    }
+
+    fn pause_requested(&self) -> bool {
+        self.pause.swap(false, Ordering::SeqCst)
+    }
+
+    fn stopped(&mut self, reason: StopReason, paused: &Paused) -> Resume {
+        let reason = match reason {
+            StopReason::Pause if std::mem::take(&mut self.entry) => "entry",
+            StopReason::Pause => "pause",
+            StopReason::Breakpoint => "breakpoint",
+            StopReason::Step => "step",
+        };
+        self.references.clear();
+        self.client.event(
+            "stopped",
+            json!({ "reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true }),
+        );
+        while let Ok(request) = self.requests.recv() {
+            if let Some(resume) = self.answer(paused, &request) {
+                return resume;
+            }
+        }
+        // The server is gone; let the program finish
+        Resume::Continue
+    }
+}
+
+/// The name frames give a file, e.g. `main.lin`.
+pub fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
@@ -0,0 +1,154 @@
+//! A Debug Adapter Protocol server for Dotlin, speaking over stdin and
+//! stdout. It runs a `.lin` program in the interpreter, stopping it at
+//! breakpoints and steps so an editor can show its calls and variables.
+
+mod debuggee;
+mod protocol;
+
+use debuggee::{Breakpoints, Debuggee, THREAD_ID};
+use protocol::{read_message, Client};
+use serde_json::{json, Value};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+
+struct Server {
+    client: Client,
+    breakpoints: Breakpoints,
+    pause: Arc<AtomicBool>,
+    /// The program `launch` asked for, until the configuration is done
+    launch: Option<PathBuf>,
+    configured: bool,
+    /// Passes the requests that need the stopped program to its thread
+    debuggee: Option<Sender<Value>>,
+}
+
+impl Server {
+    fn new(client: Client) -> Self {
+        Self {
+            client,
+            breakpoints: Breakpoints::default(),
+            pause: Arc::new(AtomicBool::new(false)),
+            launch: None,
+            configured: false,
+            debuggee: None,
+        }
+    }
+
+    fn handle(&mut self, request: Value) {
+        if request["type"] != "request" {
+            return;
+        }
+        let arguments = &request["arguments"];
+        match request["command"].as_str().unwrap_or_default() {
+            "initialize" => {
+                self.client.respond(
+                    &request,
+                    json!({
+                        "supportsConfigurationDoneRequest": true,
+                        "supportsEvaluateForHovers": true,
+                        "supportsTerminateRequest": true,
+                    }),
+                );
+                self.client.event("initialized", json!({}));
+            }
+            "launch" => {
+                let Some(program) = arguments["program"].as_str() else {
+                    self.client
+                        .fail(&request, "The launch configuration needs a 'program'");
+                    return;
+                };
+                if arguments["stopOnEntry"] == true {
+                    self.pause.store(true, Ordering::SeqCst);
+                }
+                self.launch = Some(PathBuf::from(program));
+                self.client.respond(&request, json!({}));
+                self.start();
+            }
+            "setBreakpoints" => {
+                let path = PathBuf::from(arguments["source"]["path"].as_str().unwrap_or_default());
+                let lines: Vec<u32> = arguments["breakpoints"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|breakpoint| breakpoint["line"].as_u64())
+                    .map(|line| line as u32)
+                    .collect();
+                let verified: Vec<Value> = lines
+                    .iter()
+                    .map(|line| json!({ "verified": true, "line": line }))
+                    .collect();
+                self.breakpoints
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .insert(debuggee::file_name(&path), lines.into_iter().collect());
+                self.client
+                    .respond(&request, json!({ "breakpoints": verified }));
+            }
+            "configurationDone" => {
+                self.configured = true;
+                self.client.respond(&request, json!({}));
+                self.start();
+            }
+            "threads" => {
+                self.client.respond(
+                    &request,
+                    json!({ "threads": [{ "id": THREAD_ID, "name": "main" }] }),
+                );
+            }
+            "pause" => {
+                self.pause.store(true, Ordering::SeqCst);
+                self.client.respond(&request, json!({}));
+            }
+            "disconnect" | "terminate" => {
+                self.client.respond(&request, json!({}));
+                std::process::exit(0);
+            }
+            _ => match &self.debuggee {
+                // Answered by the program's thread once it is stopped
+                Some(debuggee) if debuggee.send(request.clone()).is_ok() => {}
+                _ => self.client.fail(&request, "The program isn't running"),
+            },
+        }
+    }
+
+    /// Starts the program once it has been launched and the client has set
+    /// its breakpoints.
+    fn start(&mut self) {
+        if !self.configured || self.debuggee.is_some() {
+            return;
+        }
+        let Some(program) = self.launch.take() else {
+            return;
+        };
+        let (sender, requests) = std::sync::mpsc::channel();
+        self.debuggee = Some(sender);
+        let client = self.client.clone();
+        let breakpoints = self.breakpoints.clone();
+        let pause = self.pause.clone();
+        // The program's values stay on its thread, whose stack holds as many
+        // calls as the interpreter allows
+        std::thread::Builder::new()
+            .stack_size(dotlin_interpreter::STACK_SIZE)
+            .spawn(move || {
+                Debuggee::new(client, program, breakpoints, pause, requests).run();
+            })
+            .expect("failed to start the debuggee's thread");
+    }
+}
+
+fn main() {
+    let mut server = Server::new(Client::new(Box::new(std::io::stdout())));
+    let mut input = std::io::stdin().lock();
+    loop {
+        match read_message(&mut input) {
+            Ok(Some(request)) => server.handle(request),
+            Ok(None) => break,
+            Err(e) => {
+                eprintln!("dotlin_dap: {}", e);
+                break;
+            }
+        }
+    }
+}
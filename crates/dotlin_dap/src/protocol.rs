@@ -0,0 +1,123 @@
+//! The Debug Adapter Protocol's framing: JSON messages, each preceded by a
+//! `Content-Length` header, in both directions.
+
+use serde_json::{json, Value};
+use std::io::{BufRead, Write};
+use std::sync::{Arc, Mutex};
+
+/// Reads the next message, or `None` once the client has closed the stream.
+pub fn read_message(input: &mut impl BufRead) -> std::io::Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+    let Some(length) = length else {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "message without a Content-Length header",
+        ));
+    };
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    serde_json::from_slice(&body).map(Some).map_err(Into::into)
+}
+
+/// Where responses and events go. Cloned for the thread running the
+/// program, which reports its output and where it stopped.
+#[derive(Clone)]
+pub struct Client {
+    inner: Arc<Mutex<Outgoing>>,
+}
+
+struct Outgoing {
+    output: Box<dyn Write + Send>,
+    /// The sequence number of the last message sent
+    seq: i64,
+}
+
+impl Client {
+    pub fn new(output: Box<dyn Write + Send>) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Outgoing { output, seq: 0 })),
+        }
+    }
+
+    /// Answers `request` successfully.
+    pub fn respond(&self, request: &Value, body: Value) {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": true,
+            "body": body,
+        }));
+    }
+
+    /// Answers `request` with an error the client shows the user.
+    pub fn fail(&self, request: &Value, message: &str) {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": false,
+            "message": message,
+        }));
+    }
+
+    pub fn event(&self, event: &str, body: Value) {
+        self.send(json!({
+            "type": "event",
+            "event": event,
+            "body": body,
+        }));
+    }
+
+    fn send(&self, mut message: Value) {
+        let mut outgoing = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        outgoing.seq += 1;
+        message["seq"] = json!(outgoing.seq);
+        let body = message.to_string();
+        // A client that went away can't be told
+        let _ = write!(
+            outgoing.output,
+            "Content-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        let _ = outgoing.output.flush();
+    }
+}
+
+/// Sends what the program prints to the client as `output` events.
+pub struct Output {
+    pub client: Client,
+    /// `stdout` or `stderr`
+    pub category: &'static str,
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.client.event(
+            "output",
+            json!({
+                "category": self.category,
+                "output": String::from_utf8_lossy(buf),
+            }),
+        );
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
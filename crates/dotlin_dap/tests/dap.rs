@@ -0,0 +1,167 @@
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+
+/// A client driving `dotlin_dap` over its stdin and stdout.
+struct Session {
+    server: Child,
+    input: ChildStdin,
+    output: BufReader<ChildStdout>,
+    seq: i64,
+    /// What the program printed so far
+    printed: String,
+}
+
+impl Session {
+    fn start() -> Self {
+        let mut server = Command::new(env!("CARGO_BIN_EXE_dotlin_dap"))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("failed to start dotlin_dap");
+        let input = server.stdin.take().unwrap();
+        let output = BufReader::new(server.stdout.take().unwrap());
+        Session {
+            server,
+            input,
+            output,
+            seq: 0,
+            printed: String::new(),
+        }
+    }
+
+    fn send(&mut self, command: &str, arguments: Value) {
+        self.seq += 1;
+        let body = json!({
+            "seq": self.seq,
+            "type": "request",
+            "command": command,
+            "arguments": arguments,
+        })
+        .to_string();
+        write!(self.input, "Content-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+        self.input.flush().unwrap();
+    }
+
+    fn receive(&mut self) -> Value {
+        let mut length = 0;
+        loop {
+            let mut header = String::new();
+            assert!(
+                self.output.read_line(&mut header).unwrap() > 0,
+                "server exited"
+            );
+            match header.trim_end().strip_prefix("Content-Length: ") {
+                Some(value) => length = value.parse().unwrap(),
+                None if header.trim_end().is_empty() => break,
+                None => {}
+            }
+        }
+        let mut body = vec![0; length];
+        self.output.read_exact(&mut body).unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    /// Reads messages up to the `event` event, collecting program output.
+    fn event(&mut self, event: &str) -> Value {
+        loop {
+            let message = self.receive();
+            if message["event"] == "output" {
+                self.printed += message["body"]["output"].as_str().unwrap();
+            }
+            if message["type"] == "event" && message["event"] == event {
+                return message["body"].clone();
+            }
+        }
+    }
+
+    /// Sends a request and returns the body of its successful response.
+    fn request(&mut self, command: &str, arguments: Value) -> Value {
+        self.send(command, arguments);
+        let seq = self.seq;
+        loop {
+            let message = self.receive();
+            if message["event"] == "output" {
+                self.printed += message["body"]["output"].as_str().unwrap();
+            }
+            if message["type"] == "response" && message["request_seq"] == seq {
+                assert_eq!(message["success"], true, "{} failed: {}", command, message);
+                return message["body"].clone();
+            }
+        }
+    }
+}
+
+#[test]
+fn breakpoints_steps_variables_and_evaluation() {
+    let dir = std::env::temp_dir().join("dotlin-dap-test");
+    std::fs::create_dir_all(&dir).unwrap();
+    let program = dir.join("main.lin");
+    std::fs::write(
+        &program,
+        "fun square(n: Int): Int {\n\
+         \x20   val sq = n * n\n\
+         \x20   return sq\n\
+         }\n\
+         fun main() {\n\
+         \x20   val xs = [1, 2]\n\
+         \x20   var total = square(3)\n\
+         \x20   println(total)\n\
+         }\n",
+    )
+    .unwrap();
+
+    let mut session = Session::start();
+    let capabilities = session.request("initialize", json!({ "adapterID": "dotlin" }));
+    assert_eq!(capabilities["supportsConfigurationDoneRequest"], true);
+    session.event("initialized");
+    session.request("launch", json!({ "program": program }));
+    let breakpoints = session.request(
+        "setBreakpoints",
+        json!({ "source": { "path": program }, "breakpoints": [{ "line": 7 }] }),
+    );
+    assert_eq!(breakpoints["breakpoints"][0]["verified"], true);
+    session.request("configurationDone", json!({}));
+
+    let stopped = session.event("stopped");
+    assert_eq!(stopped["reason"], "breakpoint");
+    let threads = session.request("threads", json!({}));
+    assert_eq!(threads["threads"][0]["id"], 1);
+
+    session.request("stepIn", json!({ "threadId": 1 }));
+    assert_eq!(session.event("stopped")["reason"], "step");
+    let stack = session.request("stackTrace", json!({ "threadId": 1 }));
+    let frames = stack["stackFrames"].as_array().unwrap();
+    let names: Vec<_> = frames
+        .iter()
+        .map(|frame| format!("{}:{}", frame["name"], frame["line"]))
+        .collect();
+    assert_eq!(names, ["\"square\":2", "\"main\":7"]);
+    assert_eq!(frames[0]["source"]["name"], "main.lin");
+
+    // The caller's locals, with the array expanding to its elements
+    let scopes = session.request("scopes", json!({ "frameId": 1 }));
+    let locals = scopes["scopes"][0]["variablesReference"].clone();
+    let variables = session.request("variables", json!({ "variablesReference": locals }));
+    let xs = &variables["variables"][0];
+    assert_eq!(xs["name"], "xs");
+    let elements = session.request(
+        "variables",
+        json!({ "variablesReference": xs["variablesReference"] }),
+    );
+    assert_eq!(elements["variables"][1]["name"], "[1]");
+    assert_eq!(elements["variables"][1]["value"], "2");
+
+    session.request("next", json!({ "threadId": 1 }));
+    session.event("stopped");
+    let result = session.request("evaluate", json!({ "expression": "sq + n", "frameId": 0 }));
+    assert_eq!(result["result"], "12");
+
+    session.request("continue", json!({ "threadId": 1 }));
+    assert_eq!(session.event("exited")["exitCode"], 0);
+    session.event("terminated");
+    assert_eq!(session.printed, "9\n");
+
+    session.request("disconnect", json!({}));
+    session.server.wait().unwrap();
+}
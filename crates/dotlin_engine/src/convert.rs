@@ -0,0 +1,199 @@
+use dotlin_ast::Type;
+use dotlin_interpreter::{RuntimeError, Value};
+use std::any::Any;
+use std::collections::HashMap;
+
+/// A Rust type with a Dotlin counterpart, which the type checker sees in
+/// the signatures of host functions.
+pub trait ScriptType {
+    fn script_type() -> Type;
+}
+
+/// Converts a script value to Rust, as host functions receive their arguments
+/// and `Engine::call` returns its result.
+pub trait FromValue: ScriptType + Sized {
+    fn from_value(value: Value) -> Result<Self, RuntimeError>;
+}
+
+/// Converts a Rust value to a script value, as host functions return theirs.
+pub trait IntoValue: ScriptType {
+    fn into_value(self) -> Value;
+}
+
+/// A Rust type scripts hold as an opaque object of the type `NAME` and call
+/// the methods registered with `Engine::register_method` on. Host functions
+/// take such an argument by value, so it is cloned out of the object.
+pub trait HostType: Any + Clone {
+    const NAME: &'static str;
+}
+
+fn named(name: &str) -> Type {
+    Type::Named(name.to_string())
+}
+
+fn mismatch(expected: &Type, found: &Value) -> RuntimeError {
+    RuntimeError::TypeMismatch(format!("expected {:?}, found {}", expected, found))
+}
+
+/// Converts between a Rust type and the script value holding it.
+macro_rules! scalar {
+    ($rust:ty, $name:literal, $variant:ident) => {
+        impl ScriptType for $rust {
+            fn script_type() -> Type {
+                named($name)
+            }
+        }
+
+        impl FromValue for $rust {
+            fn from_value(value: Value) -> Result<Self, RuntimeError> {
+                match value {
+                    Value::$variant(v) => Ok(v),
+                    other => Err(mismatch(&Self::script_type(), &other)),
+                }
+            }
+        }
+
+        impl IntoValue for $rust {
+            fn into_value(self) -> Value {
+                Value::$variant(self)
+            }
+        }
+    };
+}
+
+scalar!(i32, "Int", Integer);
+scalar!(i64, "Long", Long);
+scalar!(f32, "Float", Float);
+scalar!(f64, "Double", Double);
+scalar!(bool, "Boolean", Boolean);
+scalar!(char, "Char", Char);
+scalar!(String, "String", String);
+
+impl ScriptType for &str {
+    fn script_type() -> Type {
+        named("String")
+    }
+}
+
+impl IntoValue for &str {
+    fn into_value(self) -> Value {
+        Value::String(self.to_string())
+    }
+}
+
+impl ScriptType for () {
+    fn script_type() -> Type {
+        named("Unit")
+    }
+}
+
+impl FromValue for () {
+    fn from_value(_: Value) -> Result<Self, RuntimeError> {
+        Ok(())
+    }
+}
+
+impl IntoValue for () {
+    fn into_value(self) -> Value {
+        Value::Void
+    }
+}
+
+impl<T: ScriptType> ScriptType for Option<T> {
+    fn script_type() -> Type {
+        Type::Nullable(Box::new(T::script_type()))
+    }
+}
+
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(value: Value) -> Result<Self, RuntimeError> {
+        match value {
+            Value::Null => Ok(None),
+            value => T::from_value(value).map(Some),
+        }
+    }
+}
+
+impl<T: IntoValue> IntoValue for Option<T> {
+    fn into_value(self) -> Value {
+        self.map_or(Value::Null, T::into_value)
+    }
+}
+
+impl<T: ScriptType> ScriptType for Vec<T> {
+    fn script_type() -> Type {
+        Type::Array(Box::new(T::script_type()))
+    }
+}
+
+/// Copies the elements, so the script's array and the `Vec` don't share changes.
+impl<T: FromValue> FromValue for Vec<T> {
+    fn from_value(value: Value) -> Result<Self, RuntimeError> {
+        match value {
+            Value::Array(elements) => elements
+                .borrow()
+                .iter()
+                .cloned()
+                .map(T::from_value)
+                .collect(),
+            other => Err(mismatch(&Self::script_type(), &other)),
+        }
+    }
+}
+
+impl<T: IntoValue> IntoValue for Vec<T> {
+    fn into_value(self) -> Value {
+        Value::array(self.into_iter().map(T::into_value).collect())
+    }
+}
+
+impl<T: ScriptType> ScriptType for HashMap<String, T> {
+    fn script_type() -> Type {
+        Type::Map(Box::new(named("String")), Box::new(T::script_type()))
+    }
+}
+
+impl<T: FromValue> FromValue for HashMap<String, T> {
+    fn from_value(value: Value) -> Result<Self, RuntimeError> {
+        match value {
+            Value::HashMap(map) => map
+                .borrow()
+                .iter()
+                .map(|(key, value)| Ok((key.clone(), T::from_value(value.clone())?)))
+                .collect(),
+            other => Err(mismatch(&Self::script_type(), &other)),
+        }
+    }
+}
+
+impl<T: IntoValue> IntoValue for HashMap<String, T> {
+    fn into_value(self) -> Value {
+        Value::map(
+            self.into_iter()
+                .map(|(key, value)| (key, value.into_value()))
+                .collect(),
+        )
+    }
+}
+
+impl<T: HostType> ScriptType for T {
+    fn script_type() -> Type {
+        named(T::NAME)
+    }
+}
+
+impl<T: HostType> FromValue for T {
+    fn from_value(value: Value) -> Result<Self, RuntimeError> {
+        match &value {
+            Value::Host(object) => object.with(|value: &mut T| value.clone()),
+            _ => None,
+        }
+        .ok_or_else(|| mismatch(&Self::script_type(), &value))
+    }
+}
+
+impl<T: HostType> IntoValue for T {
+    fn into_value(self) -> Value {
+        Value::host_object(T::NAME, self)
+    }
+}
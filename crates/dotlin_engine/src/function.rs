@@ -0,0 +1,116 @@
+use crate::convert::{FromValue, HostType, IntoValue};
+use dotlin_ast::Type;
+use dotlin_interpreter::{HostFunction, RuntimeError, Value};
+
+/// A Rust closure scripts can call, taking up to four arguments that convert
+/// from script values and returning one that converts back. `Args` is the
+/// tuple of its argument types.
+pub trait HostFn<Args>: 'static {
+    /// The parameter and return types the type checker sees.
+    fn signature() -> (Vec<Type>, Type);
+
+    fn into_function(self, name: &str) -> HostFunction;
+}
+
+/// Like `HostFn`, but a method of the host type `T`: the closure also gets
+/// the object it is called on.
+pub trait HostMethod<T, Args>: 'static {
+    fn signature() -> (Vec<Type>, Type);
+
+    fn into_function(self, name: &str) -> HostFunction;
+}
+
+/// The arguments of a script function called from Rust, as a tuple.
+pub trait IntoArgs {
+    fn into_args(self) -> Vec<Value>;
+}
+
+/// Takes the arguments of a call, which must be exactly `count`.
+fn arguments(args: Vec<Value>, count: usize) -> Result<std::vec::IntoIter<Value>, RuntimeError> {
+    if args.len() != count {
+        return Err(RuntimeError::ArgumentCount {
+            expected: count,
+            got: args.len(),
+        });
+    }
+    Ok(args.into_iter())
+}
+
+macro_rules! host_fn {
+    ($($arg:ident),*) => {
+        #[allow(non_snake_case)]
+        impl<F, R, $($arg),*> HostFn<($($arg,)*)> for F
+        where
+            F: Fn($($arg),*) -> R + 'static,
+            R: IntoValue,
+            $($arg: FromValue,)*
+        {
+            fn signature() -> (Vec<Type>, Type) {
+                (vec![$($arg::script_type()),*], R::script_type())
+            }
+
+            fn into_function(self, name: &str) -> HostFunction {
+                HostFunction::new(name, move |args| {
+                    let params: &[&str] = &[$(stringify!($arg)),*];
+                    #[allow(unused_mut, unused_variables)]
+                    let mut args = arguments(args, params.len())?;
+                    $(let $arg = $arg::from_value(args.next().unwrap())?;)*
+                    Ok(self($($arg),*).into_value())
+                })
+            }
+        }
+
+        #[allow(non_snake_case)]
+        impl<F, T, R, $($arg),*> HostMethod<T, ($($arg,)*)> for F
+        where
+            F: Fn(&mut T, $($arg),*) -> R + 'static,
+            T: HostType,
+            R: IntoValue,
+            $($arg: FromValue,)*
+        {
+            fn signature() -> (Vec<Type>, Type) {
+                (vec![$($arg::script_type()),*], R::script_type())
+            }
+
+            fn into_function(self, name: &str) -> HostFunction {
+                let method = name.to_string();
+                HostFunction::new(name, move |args| {
+                    let params: &[&str] = &[$(stringify!($arg)),*];
+                    let mut args = arguments(args, params.len() + 1)?;
+                    let Some(Value::Host(receiver)) = args.next() else {
+                        return Err(RuntimeError::TypeMismatch(format!(
+                            "'{}' is a method of {}",
+                            method,
+                            T::NAME
+                        )));
+                    };
+                    $(let $arg = $arg::from_value(args.next().unwrap())?;)*
+                    let result = receiver.with(|receiver: &mut T| self(receiver, $($arg),*));
+                    match result {
+                        Some(result) => Ok(result.into_value()),
+                        None => Err(RuntimeError::TypeMismatch(format!(
+                            "'{}' is a method of {}, not {}",
+                            method,
+                            T::NAME,
+                            receiver.type_name
+                        ))),
+                    }
+                })
+            }
+        }
+
+        #[allow(non_snake_case)]
+        impl<$($arg: IntoValue),*> IntoArgs for ($($arg,)*) {
+            fn into_args(self) -> Vec<Value> {
+                let ($($arg,)*) = self;
+                vec![$($arg.into_value()),*]
+            }
+        }
+    };
+}
+
+host_fn!();
+host_fn!(A);
+host_fn!(A, B);
+host_fn!(A, B, C);
+host_fn!(A, B, C, D);
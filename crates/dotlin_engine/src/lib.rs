@@ -0,0 +1,275 @@
+//! Embeds Dotlin in a Rust application. An `Engine` runs scripts on the
+//! tree-walking interpreter; the application gives them Rust closures to
+//! call and Rust types to hold, and calls their functions in turn:
+//!
+//! ```
+//! use dotlin_engine::Engine;
+//!
+//! let mut engine = Engine::new();
+//! let rate = 3;
+//! engine.register_fn("scale", move |n: i32| n * rate);
+//! engine.run("fun total(n: Int): Int = scale(n) + 1").unwrap();
+//! assert_eq!(engine.call::<i32>("total", (4,)).unwrap(), 13);
+//! ```
+
+mod convert;
+mod function;
+
+pub use convert::{FromValue, HostType, IntoValue, ScriptType};
+pub use dotlin_interpreter::{
+    with_stack, Capabilities, Captured, Io, Limit, Limits, RuntimeError, StackFrame, StackTrace,
+    Value, DEFAULT_CALL_DEPTH, STACK_SIZE,
+};
+pub use function::{HostFn, HostMethod, IntoArgs};
+
+use dotlin_ast::Type;
+use dotlin_interpreter::Interpreter;
+use dotlin_parser::{ParseError, Parser};
+use dotlin_typechecker::{TypeChecker, TypeError};
+
+#[derive(Debug, thiserror::Error)]
+pub enum EngineError {
+    #[error("Parse error: {0}")]
+    Parse(#[from] ParseError),
+    #[error("Type error: {0}")]
+    Type(#[from] TypeError),
+    #[error("Runtime error: {0}")]
+    Runtime(#[from] RuntimeError),
+}
+
+#[derive(Default)]
+pub struct Engine {
+    interpreter: Interpreter,
+    /// Signatures of the host functions, which every script is checked against
+    functions: Vec<(String, Vec<Type>, Type)>,
+    /// Signatures of the host types' methods, with the type's name first
+    methods: Vec<(String, String, Vec<Type>, Type)>,
+    /// What each run or call may use
+    limits: Limits,
+}
+
+impl Engine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Limits every later `run` and `call`, each counted on its own. By
+    /// default calls nest at most `DEFAULT_CALL_DEPTH` deep, which takes a
+    /// thread with a stack of `STACK_SIZE`, such as one `with_stack` runs on;
+    /// on smaller stacks, limit the depth further.
+    pub fn set_limits(&mut self, limits: Limits) -> &mut Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Sends what scripts print to, and reads their input from, `io`.
+    pub fn set_io(&mut self, io: Io) -> &mut Self {
+        self.interpreter.set_io(io);
+        self
+    }
+
+    /// Lets scripts use the files, processes or environment variables the
+    /// capabilities grant; scripts can reach none of them otherwise.
+    pub fn grant(&mut self, capabilities: Capabilities) -> &mut Self {
+        for native in capabilities.natives() {
+            self.functions
+                .push((native.name.to_string(), native.params, native.ret));
+        }
+        self.interpreter.grant(capabilities);
+        self
+    }
+
+    /// Lets scripts call `function` as `name`. Arguments and the result are
+    /// converted with `FromValue` and `IntoValue`, and typed accordingly.
+    pub fn register_fn<Args>(&mut self, name: &str, function: impl HostFn<Args>) -> &mut Self {
+        fn signature<Args, F: HostFn<Args>>(_: &F) -> (Vec<Type>, Type) {
+            F::signature()
+        }
+        let (params, ret) = signature(&function);
+        self.functions.push((name.to_string(), params, ret));
+        self.interpreter
+            .register_function(function.into_function(name));
+        self
+    }
+
+    /// Adds the method `name` to the host type `T`, which scripts call on an
+    /// object of it as `object.name(args)`.
+    pub fn register_method<T: HostType, Args>(
+        &mut self,
+        name: &str,
+        method: impl HostMethod<T, Args>,
+    ) -> &mut Self {
+        fn signature<T, Args, F: HostMethod<T, Args>>(_: &F) -> (Vec<Type>, Type) {
+            F::signature()
+        }
+        let (params, ret) = signature(&method);
+        self.methods
+            .push((T::NAME.to_string(), name.to_string(), params, ret));
+        self.interpreter
+            .register_method(T::NAME, method.into_function(name));
+        self
+    }
+
+    /// Type-checks and runs a script: defines its declarations, then calls
+    /// its `main` if it has one. Functions of earlier scripts stay callable
+    /// from Rust, but a script only sees its own and the host's.
+    pub fn run(&mut self, source: &str) -> Result<(), EngineError> {
+        let mut program = Parser::new(source).parse_program()?;
+        let mut checker = TypeChecker::new();
+        for (name, params, ret) in &self.functions {
+            checker.declare_function(name, params.clone(), ret.clone());
+        }
+        for (typ, name, params, ret) in &self.methods {
+            checker.declare_method(typ, name, params.clone(), ret.clone());
+        }
+        checker.check_program(&mut program)?;
+        dotlin_interpreter::resolve_program(&mut program).map_err(RuntimeError::from)?;
+        self.interpreter.set_limits(self.limits.clone());
+        self.interpreter.interpret_program(&program)?;
+        Ok(())
+    }
+
+    /// Calls the script function `name`, e.g. `engine.call::<i64>("add", (1i64, 2i64))`.
+    pub fn call<R: FromValue>(
+        &mut self,
+        name: &str,
+        args: impl IntoArgs,
+    ) -> Result<R, EngineError> {
+        self.interpreter.set_limits(self.limits.clone());
+        self.interpreter.take_stack_trace();
+        let function = self.interpreter.globals.borrow().get(name)?;
+        let result = self.interpreter.call_value(function, args.into_args())?;
+        Ok(R::from_value(result)?)
+    }
+
+    /// The script calls the last runtime error of `run` or `call` unwound
+    /// through, innermost first.
+    pub fn take_stack_trace(&self) -> StackTrace {
+        self.interpreter.take_stack_trace()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use std::rc::Rc;
+
+    #[derive(Clone)]
+    struct Account {
+        id: i64,
+        balance: i64,
+    }
+
+    impl HostType for Account {
+        const NAME: &'static str = "Account";
+    }
+
+    #[test]
+    fn scripts_call_closures_and_methods_of_host_objects() {
+        let mut engine = Engine::new();
+        let output = Captured::new();
+        engine.set_io(Io::captured(&output));
+        let balances = HashMap::from([(7i64, 100i64), (8, 5)]);
+        engine.register_fn("lookup", move |id: i64| {
+            balances.get(&id).map(|&balance| Account { id, balance })
+        });
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let sink = log.clone();
+        engine.register_fn("audit", move |line: String| sink.borrow_mut().push(line));
+        engine
+            .register_method("deposit", |account: &mut Account, amount: i64| {
+                account.balance += amount;
+                account.balance
+            })
+            .register_method("id", |account: &mut Account| account.id);
+
+        engine
+            .run(
+                "fun pay(id: Long, amount: Long): Long {\n\
+                     val account = lookup(id)\n\
+                     if (account == null) { audit(\"unknown\")\n return -1L }\n\
+                     return account?.deposit(amount) ?: 0L\n\
+                 }\n\
+                 fun open(id: Long): Account? = lookup(id)\n\
+                 fun main() { audit(\"ready\")\n println(lookup(7L)) }",
+            )
+            .unwrap();
+        assert_eq!(engine.call::<i64>("pay", (7i64, 20i64)).unwrap(), 120);
+        assert_eq!(engine.call::<i64>("pay", (9i64, 20i64)).unwrap(), -1);
+        assert_eq!(*log.borrow(), ["ready", "unknown"]);
+        assert_eq!(output.contents(), "Account\n");
+
+        let account = engine.call::<Option<Account>>("open", (8i64,)).unwrap();
+        assert_eq!(account.map(|a| a.balance), Some(5));
+
+        // Host signatures are type-checked like the script's own functions
+        assert!(matches!(
+            engine.run("fun bad(): Long = lookup(\"x\")?.id() ?: 0L"),
+            Err(EngineError::Type(_))
+        ));
+        assert!(matches!(
+            engine.call::<String>("pay", (7i64, 1i64)),
+            Err(EngineError::Runtime(RuntimeError::TypeMismatch(_)))
+        ));
+    }
+
+    #[test]
+    fn untrusted_scripts_run_within_limits_and_granted_capabilities() {
+        let mut engine = Engine::new();
+        engine.set_limits(Limits {
+            instructions: Some(100_000),
+            call_depth: Some(20),
+            ..Limits::default()
+        });
+        // Natives reaching outside the interpreter don't exist until granted
+        assert!(matches!(
+            engine.run("fun main() { println(readFile(\"/etc/passwd\")) }"),
+            Err(EngineError::Type(TypeError::UndefinedVariable(name))) if name == "readFile"
+        ));
+        engine.grant(Capabilities {
+            environment: true,
+            ..Capabilities::default()
+        });
+        engine
+            .run(
+                "fun home(): Boolean = getenv(\"DOTLIN_ENGINE_UNSET\") == null\n\
+                 fun spin(): Int { var i = 0\n while (true) { i += 1 }\n return i }\n\
+                 fun down(n: Int): Int = if (n == 0) 0 else down(n - 1)",
+            )
+            .unwrap();
+        assert!(engine.call::<bool>("home", ()).unwrap());
+        assert!(matches!(
+            engine.call::<i32>("spin", ()),
+            Err(EngineError::Runtime(RuntimeError::LimitExceeded(
+                Limit::Instructions(100_000)
+            )))
+        ));
+        assert!(matches!(
+            engine.call::<i32>("down", (1000,)),
+            Err(EngineError::Runtime(RuntimeError::LimitExceeded(
+                Limit::CallDepth(20)
+            )))
+        ));
+        // Each call gets the whole budget again
+        assert_eq!(engine.call::<i32>("down", (10,)).unwrap(), 0);
+    }
+
+    #[test]
+    fn runaway_recursion_stops_at_the_default_call_depth() {
+        with_stack(|| {
+            let mut engine = Engine::new();
+            engine
+                .run("fun down(n: Int): Int = if (n == 0) 0 else down(n - 1)")
+                .unwrap();
+            assert!(matches!(
+                engine.call::<i32>("down", (1_000_000,)),
+                Err(EngineError::Runtime(RuntimeError::LimitExceeded(
+                    Limit::CallDepth(DEFAULT_CALL_DEPTH)
+                )))
+            ));
+            assert_eq!(engine.call::<i32>("down", (1000,)).unwrap(), 0);
+        });
+    }
+}
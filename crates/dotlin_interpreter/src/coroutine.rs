@@ -0,0 +1,308 @@
+//! Coroutines. `runBlocking` runs an event loop on the current thread, in
+//! which `launch` and `async` start more coroutines. A coroutine is a stack of
+//! activations, one for its body and one for each suspend function it is in,
+//! which suspend like the body of a `sequence { }`.
+//!
+//! The scheduler is deterministic: coroutines run in the order they became
+//! ready, and timers fire in the order of their deadline, then of their
+//! creation. Time is the deadline of the last timer fired, so a coroutine
+//! doing work between two delays doesn't shift the schedule. `runTest` uses
+//! virtual time: it fires timers without sleeping.
+
+use crate::sequence::{Frame, Step, Suspension};
+use crate::{Environment, Interpreter, RuntimeError, Value};
+use dotlin_ast::*;
+use std::cell::RefCell;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, VecDeque};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+type Env = Rc<RefCell<Environment>>;
+
+/// What `launch` and `async` return; completes with the result of the coroutine.
+#[derive(Debug, Default)]
+pub struct Job {
+    result: RefCell<Option<Value>>,
+    /// Coroutines waiting for the job, and whether they wait for its result
+    waiters: RefCell<Vec<(usize, bool)>>,
+}
+
+impl Job {
+    pub fn is_completed(&self) -> bool {
+        self.result.borrow().is_some()
+    }
+}
+
+/// A body or suspend function a coroutine is running.
+struct Activation {
+    frames: Vec<Frame>,
+    /// Where to define the result of the call it is suspended in
+    result: Option<(String, Env)>,
+}
+
+struct Coroutine {
+    /// The innermost call last
+    activations: Vec<Activation>,
+    job: Rc<Job>,
+}
+
+pub(crate) struct EventLoop {
+    virtual_time: bool,
+    started: Instant,
+    /// Milliseconds since the loop started
+    now: i64,
+    /// Coroutines to resume, with the value to resume them with
+    ready: VecDeque<(usize, Value)>,
+    /// Deadline, creation order and coroutine of each pending `delay`
+    timers: BinaryHeap<Reverse<(i64, usize, usize)>>,
+    timers_created: usize,
+    /// Coroutines by id; `None` once completed, or while running
+    coroutines: Vec<Option<Coroutine>>,
+}
+
+impl EventLoop {
+    fn new(virtual_time: bool) -> Self {
+        Self {
+            virtual_time,
+            started: Instant::now(),
+            now: 0,
+            ready: VecDeque::new(),
+            timers: BinaryHeap::new(),
+            timers_created: 0,
+            coroutines: Vec::new(),
+        }
+    }
+}
+
+/// The coroutine builder a call is to, if any.
+pub(crate) fn coroutine_builder(callee: &Expression) -> Option<&str> {
+    match &*callee.kind {
+        ExpressionKind::Variable(name)
+            if matches!(
+                name.as_str(),
+                "runBlocking" | "runTest" | "launch" | "async"
+            ) =>
+        {
+            Some(name)
+        }
+        _ => None,
+    }
+}
+
+impl Interpreter {
+    /// Runs `runBlocking`, `runTest`, `launch` or `async` with the lambda `body`.
+    pub(crate) fn start_coroutine(
+        &self,
+        builder: &str,
+        body: Value,
+    ) -> Result<Value, RuntimeError> {
+        let activation = self.activation(body, Vec::new())?;
+        match builder {
+            "runBlocking" => self.run_blocking(activation, false),
+            "runTest" => self.run_blocking(activation, true).map(|_| Value::Void),
+            _ => {
+                let job = Rc::new(Job::default());
+                self.spawn(activation, job.clone())?;
+                Ok(Value::Job(job))
+            }
+        }
+    }
+
+    /// Runs `main` when it is a suspend function.
+    pub(crate) fn run_suspend_main(&self, main: Value) -> Result<(), RuntimeError> {
+        let activation = self.activation(main, Vec::new())?;
+        self.run_blocking(activation, false).map(|_| ())
+    }
+
+    /// `currentTime`, in the innermost event loop.
+    pub(crate) fn current_time(&self) -> Option<Value> {
+        let loops = self.event_loops.borrow();
+        loops.last().map(|event_loop| Value::Long(event_loop.now))
+    }
+
+    /// Runs a coroutine in a new event loop until it and every coroutine it
+    /// launched have completed, and returns its result.
+    fn run_blocking(&self, body: Activation, virtual_time: bool) -> Result<Value, RuntimeError> {
+        let job = Rc::new(Job::default());
+        self.event_loops
+            .borrow_mut()
+            .push(EventLoop::new(virtual_time));
+        let result = self
+            .spawn(body, job.clone())
+            .and_then(|_| self.run_event_loop());
+        self.event_loops.borrow_mut().pop();
+        result?;
+        let value = job.result.borrow().clone();
+        Ok(value.unwrap_or(Value::Void))
+    }
+
+    fn spawn(&self, body: Activation, job: Rc<Job>) -> Result<(), RuntimeError> {
+        let mut loops = self.event_loops.borrow_mut();
+        let Some(event_loop) = loops.last_mut() else {
+            return Err(RuntimeError::TypeMismatch(
+                "Coroutines can only be launched inside runBlocking".to_string(),
+            ));
+        };
+        let id = event_loop.coroutines.len();
+        event_loop.coroutines.push(Some(Coroutine {
+            activations: vec![body],
+            job,
+        }));
+        event_loop.ready.push_back((id, Value::Void));
+        Ok(())
+    }
+
+    /// An activation of a function, or of the lambda of a coroutine builder.
+    fn activation(&self, function: Value, args: Vec<Value>) -> Result<Activation, RuntimeError> {
+        let Value::Function {
+            declaration,
+            closure,
+        } = function
+        else {
+            return Err(RuntimeError::NotAFunction(format!("{}", function)));
+        };
+        if args.len() != declaration.params.len() {
+            return Err(RuntimeError::ArgumentCount {
+                expected: declaration.params.len(),
+                got: args.len(),
+            });
+        }
+        let env = Rc::new(RefCell::new(Environment::for_block(
+            &declaration.body,
+            closure,
+        )));
+        for (param, arg) in declaration.params.iter().zip(args) {
+            env.borrow_mut().define(&param.name, arg);
+        }
+        let suspend_functions = self.suspend_functions.borrow();
+        let steps = Step::compile(&declaration.body.statements, &|expr| {
+            expr.is_suspend_call(&suspend_functions)
+        });
+        Ok(Activation {
+            frames: vec![Frame::Steps {
+                steps,
+                next: 0,
+                env,
+            }],
+            result: None,
+        })
+    }
+
+    fn run_event_loop(&self) -> Result<(), RuntimeError> {
+        while let Some((id, input)) = self.next_ready()? {
+            let taken = self.event_loops.borrow_mut().last_mut().unwrap().coroutines[id].take();
+            let mut coroutine = taken.expect("only suspended coroutines are resumed");
+            match self.step(&mut coroutine, id, input)? {
+                Some(result) => self.complete(&coroutine.job, result),
+                None => {
+                    self.event_loops.borrow_mut().last_mut().unwrap().coroutines[id] =
+                        Some(coroutine)
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The next coroutine to resume: a ready one, or else the one whose timer
+    /// fires first, once it has. Waiting for the timer counts against the
+    /// time limit.
+    fn next_ready(&self) -> Result<Option<(usize, Value)>, RuntimeError> {
+        let mut loops = self.event_loops.borrow_mut();
+        let Some(event_loop) = loops.last_mut() else {
+            return Ok(None);
+        };
+        if let Some(ready) = event_loop.ready.pop_front() {
+            return Ok(Some(ready));
+        }
+        let Some(Reverse((deadline, _, id))) = event_loop.timers.pop() else {
+            return Ok(None);
+        };
+        event_loop.now = deadline;
+        if !event_loop.virtual_time {
+            let at = event_loop.started + Duration::from_millis(deadline as u64);
+            drop(loops);
+            self.budget.sleep_until(at)?;
+        }
+        Ok(Some((id, Value::Void)))
+    }
+
+    /// Resumes coroutine `id` with `input` until it suspends, returning its
+    /// result if it completes instead.
+    fn step(
+        &self,
+        coroutine: &mut Coroutine,
+        id: usize,
+        mut input: Value,
+    ) -> Result<Option<Value>, RuntimeError> {
+        loop {
+            let Some(activation) = coroutine.activations.last_mut() else {
+                return Ok(Some(input));
+            };
+            if let Some((name, env)) = activation.result.take() {
+                env.borrow_mut().define(name, input);
+            }
+            let suspended = match self.resume(&mut activation.frames) {
+                Ok(Some(suspended)) => suspended,
+                Ok(None) => {
+                    coroutine.activations.pop();
+                    input = Value::Void;
+                    continue;
+                }
+                Err(RuntimeError::Return(value)) => {
+                    coroutine.activations.pop();
+                    input = *value;
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            activation.result = Some(suspended.result);
+            input = Value::Void;
+            let wants_result = matches!(suspended.suspension, Suspension::Await(_));
+            match suspended.suspension {
+                Suspension::Call(function, args) => {
+                    let callee = self.activation(function, args)?;
+                    coroutine.activations.push(callee);
+                }
+                Suspension::Delay(ms) if ms <= 0 => {}
+                Suspension::Delay(ms) => {
+                    let mut loops = self.event_loops.borrow_mut();
+                    let event_loop = loops.last_mut().unwrap();
+                    let timer = (event_loop.now + ms, event_loop.timers_created, id);
+                    event_loop.timers.push(Reverse(timer));
+                    event_loop.timers_created += 1;
+                    return Ok(None);
+                }
+                Suspension::Await(job) | Suspension::Join(job) => match &*job.result.borrow() {
+                    Some(result) if wants_result => input = result.clone(),
+                    Some(_) => {}
+                    None => {
+                        job.waiters.borrow_mut().push((id, wants_result));
+                        return Ok(None);
+                    }
+                },
+                Suspension::Yield(_) => {
+                    return Err(RuntimeError::TypeMismatch(
+                        "yield is only allowed in a sequence".to_string(),
+                    ))
+                }
+            }
+        }
+    }
+
+    /// Completes `job`, making the coroutines waiting for it ready.
+    fn complete(&self, job: &Job, result: Value) {
+        let waiters = job.waiters.take();
+        let mut loops = self.event_loops.borrow_mut();
+        let event_loop = loops.last_mut().unwrap();
+        for (id, wants_result) in waiters {
+            let input = if wants_result {
+                result.clone()
+            } else {
+                Value::Void
+            };
+            event_loop.ready.push_back((id, input));
+        }
+        *job.result.borrow_mut() = Some(result);
+    }
+}
@@ -0,0 +1,276 @@
+//! Which lines and branches of a program ran. While measuring, the
+//! interpreter counts the runs of each line with a statement, and how often
+//! the condition of each `if`, `while` and `do`-`while` on a line was true
+//! and false; the conditions on one line count as one branch point. Lines are
+//! those of the program's functions, including the lambdas in them, so that
+//! the ones that never ran count as missed.
+//!
+//! A lambda's lines are counted in the file of the function calling it, as
+//! in stack traces.
+
+use dotlin_ast::{ExpressionKind, FunctionDecl, Node, Program, Statement};
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+
+/// The coverage of one source file.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FileCoverage {
+    /// How many times each line with a statement ran
+    pub lines: BTreeMap<u32, u64>,
+    /// How many times the conditions on each line with one were true, and
+    /// how many times false
+    pub branches: BTreeMap<u32, [u64; 2]>,
+}
+
+impl FileCoverage {
+    fn lines_hit(&self) -> usize {
+        self.lines.values().filter(|&&hits| hits > 0).count()
+    }
+
+    /// Each line has two branches, the true and the false one.
+    fn branches_hit(&self) -> usize {
+        self.branches
+            .values()
+            .flatten()
+            .filter(|&&hits| hits > 0)
+            .count()
+    }
+
+    /// The lines that never ran, as ranges like `4, 9-11`; a range goes on
+    /// across lines without statements.
+    fn missed(&self) -> String {
+        let mut ranges: Vec<(u32, u32)> = Vec::new();
+        let mut in_range = false;
+        for (&line, &hits) in &self.lines {
+            match ranges.last_mut() {
+                Some(range) if hits == 0 && in_range => range.1 = line,
+                _ if hits == 0 => ranges.push((line, line)),
+                _ => {}
+            }
+            in_range = hits == 0;
+        }
+        let ranges: Vec<String> = ranges
+            .iter()
+            .map(|&(first, last)| match first == last {
+                true => first.to_string(),
+                false => format!("{}-{}", first, last),
+            })
+            .collect();
+        ranges.join(", ")
+    }
+}
+
+/// The coverage of a run, by file name.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Coverage {
+    pub files: BTreeMap<String, FileCoverage>,
+}
+
+impl Coverage {
+    /// The lines and branches of `programs`, none of which ran yet.
+    pub fn of(programs: &[Program]) -> Self {
+        let mut coverage = Coverage::default();
+        for program in programs {
+            for function in program.functions() {
+                let file = function.decl.file.clone().unwrap_or_default();
+                let file = coverage.files.entry(file).or_default();
+                function.decl.body.walk(&mut |node| {
+                    if let Some(line) = line(node) {
+                        file.lines.entry(line).or_default();
+                    }
+                    if let Some(line) = branch(node) {
+                        file.branches.entry(line).or_default();
+                    }
+                });
+            }
+        }
+        coverage
+    }
+
+    /// The coverage in LCOV's tracefile format; `path` gives the path of
+    /// the source file of each file name.
+    pub fn lcov(&self, path: impl Fn(&str) -> String) -> String {
+        let mut lcov = String::new();
+        for (file, coverage) in &self.files {
+            lcov += "TN:\n";
+            lcov += &format!("SF:{}\n", path(file));
+            for (line, [taken, not_taken]) in &coverage.branches {
+                // A branch whose condition never ran is `-`
+                let ran = taken + not_taken > 0;
+                for (branch, hits) in [taken, not_taken].into_iter().enumerate() {
+                    let hits = match ran {
+                        true => hits.to_string(),
+                        false => "-".to_string(),
+                    };
+                    lcov += &format!("BRDA:{},0,{},{}\n", line, branch, hits);
+                }
+            }
+            lcov += &format!("BRF:{}\n", 2 * coverage.branches.len());
+            lcov += &format!("BRH:{}\n", coverage.branches_hit());
+            for (line, hits) in &coverage.lines {
+                lcov += &format!("DA:{},{}\n", line, hits);
+            }
+            lcov += &format!("LF:{}\n", coverage.lines.len());
+            lcov += &format!("LH:{}\n", coverage.lines_hit());
+            lcov += "end_of_record\n";
+        }
+        lcov
+    }
+
+    /// A table of the lines and branches covered in each file, and the
+    /// lines missed.
+    pub fn summary(&self) -> String {
+        fn percent(hit: usize, total: usize) -> String {
+            match total {
+                0 => "-".to_string(),
+                _ => format!("{:.1}%", 100.0 * hit as f64 / total as f64),
+            }
+        }
+        let width = self
+            .files
+            .keys()
+            .map(String::len)
+            .chain(["File".len(), "Total".len()])
+            .max()
+            .unwrap_or_default();
+        let mut summary = format!(
+            "{:width$}  {:>7}  {:>8}  Missed lines\n",
+            "File", "Lines", "Branches"
+        );
+        let (mut lines, mut lines_hit, mut branches, mut branches_hit) = (0, 0, 0, 0);
+        for (file, coverage) in &self.files {
+            lines += coverage.lines.len();
+            lines_hit += coverage.lines_hit();
+            branches += 2 * coverage.branches.len();
+            branches_hit += coverage.branches_hit();
+            summary += &format!(
+                "{:width$}  {:>7}  {:>8}  {}\n",
+                file,
+                percent(coverage.lines_hit(), coverage.lines.len()),
+                percent(coverage.branches_hit(), 2 * coverage.branches.len()),
+                coverage.missed()
+            );
+        }
+        summary += &format!(
+            "{:width$}  {:>7}  {:>8}\n",
+            "Total",
+            percent(lines_hit, lines),
+            percent(branches_hit, branches)
+        );
+        summary
+    }
+}
+
+/// The line a statement counts on, if any. Blocks count as the statements
+/// in them.
+fn line(node: Node) -> Option<u32> {
+    match node {
+        Node::Statement(Statement::Block(_) | Statement::DoWhile { .. }) => None,
+        Node::Statement(stmt) => Some(stmt.line()).filter(|&line| line != 0),
+        Node::Expression(_) => None,
+    }
+}
+
+/// The line of a branch point, if `node` is one.
+fn branch(node: Node) -> Option<u32> {
+    let condition = match node {
+        Node::Statement(
+            Statement::If { condition, .. }
+            | Statement::While { condition, .. }
+            | Statement::DoWhile { condition, .. },
+        ) => condition,
+        Node::Expression(expr) => match &*expr.kind {
+            ExpressionKind::If { condition, .. } => condition,
+            _ => return None,
+        },
+        Node::Statement(_) => return None,
+    };
+    Some(condition.line).filter(|&line| line != 0)
+}
+
+/// The coverage of the run so far, while measuring it.
+#[derive(Default)]
+pub(crate) struct Covering {
+    enabled: Cell<bool>,
+    /// The file of each call in progress, innermost last
+    files: RefCell<Vec<String>>,
+    coverage: RefCell<Coverage>,
+}
+
+impl Covering {
+    pub(crate) fn enable(&self, programs: &[Program]) {
+        self.enabled.set(true);
+        self.coverage.replace(Coverage::of(programs));
+    }
+
+    /// Notes the file of a call of `function`, returning whether it did so
+    /// and `leave` must follow.
+    #[inline]
+    pub(crate) fn enter(&self, function: &FunctionDecl) -> bool {
+        if !self.enabled.get() {
+            return false;
+        }
+        let mut files = self.files.borrow_mut();
+        let file = match &function.file {
+            Some(file) => file.clone(),
+            // A lambda, or a function parsed without a file name
+            None => files.last().cloned().unwrap_or_default(),
+        };
+        files.push(file);
+        true
+    }
+
+    pub(crate) fn leave(&self) {
+        self.files.borrow_mut().pop();
+    }
+
+    #[inline]
+    pub(crate) fn statement(&self, stmt: &Statement) {
+        if self.enabled.get() {
+            if let Some(line) = line(Node::Statement(stmt)) {
+                self.count(line, |file| {
+                    file.lines.get_mut(&line).map(|hits| *hits += 1)
+                });
+            }
+        }
+    }
+
+    /// Counts a condition on `line` that was `taken`.
+    #[inline]
+    pub(crate) fn branch(&self, line: u32, taken: bool) {
+        if self.enabled.get() {
+            self.count(line, |file| {
+                let branches = file.branches.get_mut(&line)?;
+                branches[usize::from(!taken)] += 1;
+                Some(())
+            });
+        }
+    }
+
+    /// Counts in the coverage of the current file; lines outside functions
+    /// aren't counted.
+    fn count(&self, line: u32, count: impl FnOnce(&mut FileCoverage) -> Option<()>) {
+        let files = self.files.borrow();
+        let Some(file) = files.last() else {
+            return;
+        };
+        if line != 0 {
+            if let Some(coverage) = self.coverage.borrow_mut().files.get_mut(file) {
+                count(coverage);
+            }
+        }
+    }
+
+    /// The coverage so far, which starts over.
+    pub(crate) fn take(&self) -> Coverage {
+        let coverage = self.coverage.take();
+        // Every line is still there, not run yet
+        let mut empty = coverage.clone();
+        for file in empty.files.values_mut() {
+            file.lines.values_mut().for_each(|hits| *hits = 0);
+            file.branches.values_mut().for_each(|hits| *hits = [0, 0]);
+        }
+        self.coverage.replace(empty);
+        coverage
+    }
+}
@@ -0,0 +1,231 @@
+//! Hooks for debugging a program as the interpreter runs it. A `Debugger`
+//! decides where the program stops: at breakpoints, after a step or when
+//! asked to pause. While it is stopped, the debugger inspects the calls in
+//! progress, their variables, and evaluates expressions in them; the program
+//! goes on once `Debugger::stopped` returns.
+
+use crate::{Environment, Interpreter, RuntimeError, StackFrame, Value};
+use dotlin_ast::{Expression, FunctionDecl, Statement};
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+type Scope = Rc<RefCell<Environment>>;
+
+/// Why the program stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    Breakpoint,
+    Step,
+    /// `Debugger::pause_requested` returned true
+    Pause,
+}
+
+/// How a stopped program goes on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resume {
+    Continue,
+    /// Stops at the next statement, in a call it makes if need be
+    StepIn,
+    /// Stops at the next statement of the current call or one of its callers
+    StepOver,
+    /// Stops once the current call has returned
+    StepOut,
+}
+
+pub trait Debugger {
+    /// Whether a breakpoint is set on `line` of `file`.
+    fn breakpoint(&self, file: Option<&str>, line: u32) -> bool;
+
+    /// Whether to stop before the next statement, e.g. because the user
+    /// pressed pause while the program was running.
+    fn pause_requested(&self) -> bool {
+        false
+    }
+
+    /// The program stopped before a statement; `paused` inspects it.
+    fn stopped(&mut self, reason: StopReason, paused: &Paused) -> Resume;
+}
+
+/// A call in progress, as the debugger sees it.
+struct Frame {
+    function: String,
+    /// The file of the function, or of the caller of a lambda
+    file: Option<String>,
+    /// The line of the statement running, 0 before the first
+    line: u32,
+    /// The scope of the statement running
+    env: Scope,
+    /// The scope the function was declared in, which isn't its own
+    closure: Scope,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Stepping {
+    In,
+    /// Until the call depth is at most this
+    Over(usize),
+    /// Until the call depth is below this
+    Out(usize),
+}
+
+/// The debugger attached to an interpreter, and the calls it can inspect.
+#[derive(Default)]
+pub(crate) struct Debugging {
+    /// Taken while the program is stopped, so that expressions the debugger
+    /// evaluates run without stopping
+    debugger: RefCell<Option<Box<dyn Debugger>>>,
+    attached: Cell<bool>,
+    frames: RefCell<Vec<Frame>>,
+    stepping: Cell<Option<Stepping>>,
+}
+
+impl Debugging {
+    pub(crate) fn attach(&self, debugger: Box<dyn Debugger>) {
+        self.debugger.replace(Some(debugger));
+        self.attached.set(true);
+    }
+
+    /// Records a call of `function` whose parameters are in `env`, returning
+    /// whether it did so and `leave` must follow.
+    #[inline]
+    pub(crate) fn enter(&self, function: &FunctionDecl, env: &Scope) -> bool {
+        if !self.attached.get() {
+            return false;
+        }
+        let Some(closure) = env.borrow().enclosing.clone() else {
+            return false;
+        };
+        let mut frames = self.frames.borrow_mut();
+        let file = function
+            .file
+            .clone()
+            .or_else(|| frames.last().and_then(|caller| caller.file.clone()));
+        frames.push(Frame {
+            function: function.name.clone(),
+            file,
+            line: 0,
+            env: env.clone(),
+            closure,
+        });
+        true
+    }
+
+    pub(crate) fn leave(&self) {
+        self.frames.borrow_mut().pop();
+    }
+
+    /// Called before each statement of a debugged program; stops it if the
+    /// debugger wants to.
+    #[inline]
+    pub(crate) fn statement(&self, interpreter: &Interpreter, stmt: &Statement, env: &Scope) {
+        if self.attached.get() {
+            self.before(interpreter, stmt.line(), env);
+        }
+    }
+
+    fn before(&self, interpreter: &Interpreter, line: u32, env: &Scope) {
+        let (depth, file, moved) = {
+            let mut frames = self.frames.borrow_mut();
+            let depth = frames.len();
+            let Some(frame) = frames.last_mut().filter(|_| line != 0) else {
+                return;
+            };
+            frame.env = env.clone();
+            // A line with several statements stops once
+            let moved = std::mem::replace(&mut frame.line, line) != line;
+            (depth, frame.file.clone(), moved)
+        };
+        let Some(mut debugger) = self.debugger.take() else {
+            return;
+        };
+        let stepped = match self.stepping.get() {
+            Some(Stepping::In) => true,
+            Some(Stepping::Over(of)) => depth <= of,
+            Some(Stepping::Out(of)) => depth < of,
+            None => false,
+        };
+        let reason = if debugger.pause_requested() {
+            Some(StopReason::Pause)
+        } else if !moved {
+            None
+        } else if stepped {
+            Some(StopReason::Step)
+        } else if debugger.breakpoint(file.as_deref(), line) {
+            Some(StopReason::Breakpoint)
+        } else {
+            None
+        };
+        if let Some(reason) = reason {
+            let resume = debugger.stopped(reason, &Paused { interpreter });
+            self.stepping.set(match resume {
+                Resume::Continue => None,
+                Resume::StepIn => Some(Stepping::In),
+                Resume::StepOver => Some(Stepping::Over(depth)),
+                Resume::StepOut => Some(Stepping::Out(depth)),
+            });
+        }
+        self.debugger.replace(Some(debugger));
+    }
+}
+
+/// A program stopped by its debugger. Frames are numbered from the
+/// innermost call, 0.
+pub struct Paused<'a> {
+    interpreter: &'a Interpreter,
+}
+
+impl Paused<'_> {
+    /// The calls in progress, innermost first, each at the line it stopped on.
+    pub fn stack(&self) -> Vec<StackFrame> {
+        let frames = self.interpreter.debugging.frames.borrow();
+        frames
+            .iter()
+            .rev()
+            .map(|frame| StackFrame {
+                function: frame.function.clone(),
+                file: frame.file.clone(),
+                line: Some(frame.line).filter(|&line| line != 0),
+            })
+            .collect()
+    }
+
+    /// The variables a call can see in its own scopes, innermost first.
+    pub fn locals(&self, frame: usize) -> Vec<(String, Value)> {
+        let Some((mut env, closure)) = self.scope(frame) else {
+            return Vec::new();
+        };
+        let mut locals: Vec<(String, Value)> = Vec::new();
+        while !Rc::ptr_eq(&env, &closure) {
+            for (name, value) in env.borrow().variables() {
+                // An inner variable shadows outer ones of the same name
+                if !locals.iter().any(|(seen, _)| *seen == name) {
+                    locals.push((name, value));
+                }
+            }
+            let Some(enclosing) = env.borrow().enclosing.clone() else {
+                break;
+            };
+            env = enclosing;
+        }
+        locals
+    }
+
+    /// Evaluates `expr` in the scope of a call, where it can read and assign
+    /// the call's variables. Breakpoints don't stop the calls it makes.
+    pub fn evaluate(&self, frame: usize, expr: &Expression) -> Result<Value, RuntimeError> {
+        let env = match self.scope(frame) {
+            Some((env, _)) => env,
+            None => self.interpreter.globals.clone(),
+        };
+        let value = self.interpreter.evaluate_expression(expr, env);
+        // An error here isn't the program's
+        self.interpreter.unwinding.take();
+        value
+    }
+
+    fn scope(&self, frame: usize) -> Option<(Scope, Scope)> {
+        let frames = self.interpreter.debugging.frames.borrow();
+        let frame = frames.iter().rev().nth(frame)?;
+        Some((frame.env.clone(), frame.closure.clone()))
+    }
+}
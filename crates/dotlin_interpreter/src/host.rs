@@ -0,0 +1,72 @@
+//! Functions and objects of the application embedding the interpreter.
+//! Unlike the built-ins, a host function is a closure, so it can hold on to
+//! the application's state; a host object is a Rust value scripts pass
+//! around and call the methods the application registered for its type.
+
+use crate::{RuntimeError, Value};
+use std::any::Any;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// The Rust side of a host function or method: a method gets its receiver
+/// as the first argument.
+pub type HostClosure = dyn Fn(Vec<Value>) -> Result<Value, RuntimeError>;
+
+pub struct HostFunction {
+    pub name: String,
+    function: Box<HostClosure>,
+}
+
+impl HostFunction {
+    pub fn new(
+        name: impl Into<String>,
+        function: impl Fn(Vec<Value>) -> Result<Value, RuntimeError> + 'static,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            function: Box::new(function),
+        }
+    }
+
+    pub fn call(&self, args: Vec<Value>) -> Result<Value, RuntimeError> {
+        (self.function)(args)
+    }
+}
+
+impl std::fmt::Debug for HostFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "HostFunction({})", self.name)
+    }
+}
+
+/// A Rust value of the type scripts know as `type_name`.
+pub struct HostObject {
+    pub type_name: String,
+    value: RefCell<Box<dyn Any>>,
+}
+
+impl HostObject {
+    pub fn new(type_name: impl Into<String>, value: impl Any) -> Self {
+        Self {
+            type_name: type_name.into(),
+            value: RefCell::new(Box::new(value)),
+        }
+    }
+
+    /// Runs `f` on the value if it is a `T`.
+    pub fn with<T: Any, R>(&self, f: impl FnOnce(&mut T) -> R) -> Option<R> {
+        self.value.borrow_mut().downcast_mut().map(f)
+    }
+}
+
+impl std::fmt::Debug for HostObject {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "HostObject({})", self.type_name)
+    }
+}
+
+impl Value {
+    pub fn host_object(type_name: impl Into<String>, value: impl Any) -> Value {
+        Value::Host(Rc::new(HostObject::new(type_name, value)))
+    }
+}
@@ -0,0 +1,99 @@
+//! Where a script's output goes and where `readLine` reads its input from.
+//! The interpreter uses the process's standard streams unless the caller
+//! gives it others, so tests can capture output, the REPL can color it and
+//! an embedding application can send it wherever it likes.
+
+use crate::RuntimeError;
+use std::cell::RefCell;
+use std::io::{BufRead, Write};
+use std::rc::Rc;
+
+pub struct Io {
+    /// Where `print` and `println` write
+    pub stdout: Box<dyn Write>,
+    /// Where `eprint` and `eprintln` write
+    pub stderr: Box<dyn Write>,
+    /// Where `readLine` reads from
+    pub stdin: Box<dyn BufRead>,
+}
+
+impl Io {
+    /// The process's standard streams.
+    pub fn std() -> Self {
+        Self {
+            stdout: Box::new(std::io::stdout()),
+            stderr: Box::new(std::io::stderr()),
+            stdin: Box::new(std::io::BufReader::new(std::io::stdin())),
+        }
+    }
+
+    /// Sends stdout and stderr to `output`, and reads no input.
+    pub fn captured(output: &Captured) -> Self {
+        Self {
+            stdout: Box::new(output.clone()),
+            stderr: Box::new(output.clone()),
+            stdin: Box::new(std::io::empty()),
+        }
+    }
+
+    /// Reads a line without its line break, or `None` at the end of the input.
+    pub(crate) fn read_line(&mut self) -> Result<Option<String>, RuntimeError> {
+        let mut line = String::new();
+        match self.stdin.read_line(&mut line) {
+            Ok(0) => Ok(None),
+            Ok(_) => {
+                let end = line.trim_end_matches(['\n', '\r']).len();
+                line.truncate(end);
+                Ok(Some(line))
+            }
+            Err(e) => Err(RuntimeError::IoException(format!("stdin: {}", e))),
+        }
+    }
+}
+
+impl Default for Io {
+    fn default() -> Self {
+        Self::std()
+    }
+}
+
+/// Writes `text` to `stream` right away, so output interleaves with input
+/// prompts, mapping a failure to the exception scripts see.
+pub(crate) fn write(stream: &mut dyn Write, text: &str) -> Result<(), RuntimeError> {
+    stream
+        .write_all(text.as_bytes())
+        .and_then(|_| stream.flush())
+        .map_err(|e| RuntimeError::IoException(e.to_string()))
+}
+
+/// Output written to a shared buffer, e.g. to assert on what a script printed.
+#[derive(Debug, Clone, Default)]
+pub struct Captured(Rc<RefCell<Vec<u8>>>);
+
+impl Captured {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Everything written so far.
+    pub fn contents(&self) -> String {
+        String::from_utf8_lossy(&self.0.borrow()).into_owned()
+    }
+
+    /// Everything written so far, emptying the buffer.
+    pub fn take(&self) -> String {
+        let bytes = std::mem::take(&mut *self.0.borrow_mut());
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
+}
+
+impl Write for Captured {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
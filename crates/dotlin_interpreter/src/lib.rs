@@ -1,25 +1,160 @@
 use dotlin_ast::*;
-use std::cell::RefCell;
-use std::collections::HashMap;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::rc::Rc;
+use std::sync::Arc;
+
+mod coroutine;
+mod coverage;
+mod debug;
+mod host;
+mod io;
+mod limits;
+mod profile;
+mod resolver;
+mod sequence;
+mod thread;
+mod trace;
+
+use coroutine::EventLoop;
+pub use coroutine::Job;
+use coverage::Covering;
+pub use coverage::{Coverage, FileCoverage};
+use debug::Debugging;
+pub use debug::{Debugger, Paused, Resume, StopReason};
+pub use host::{HostClosure, HostFunction, HostObject};
+pub use io::{Captured, Io};
+use limits::Budget;
+pub use limits::{
+    with_stack, Capabilities, CapabilityNative, Limit, Limits, DEFAULT_CALL_DEPTH, STACK_SIZE,
+};
+use profile::Profiling;
+pub use profile::{FunctionProfile, Profile};
+pub use resolver::{resolve_function, resolve_program, ResolveError};
+pub use sequence::Sequence;
+use sequence::Step;
+pub use thread::{Channel, Mutex, Thread};
+use trace::Unwinding;
+pub use trace::{StackFrame, StackTrace};
 
 #[derive(Debug, Clone)]
 pub enum Value {
-    Integer(i64),
-    Float(f64),
+    /// Kotlin `Int`: 32 bits, like the native backend.
+    Integer(i32),
+    Long(i64),
+    Byte(i8),
+    Short(i16),
+    UInt(u32),
+    ULong(u64),
+    Float(f32),
+    Double(f64),
     String(String),
     Boolean(bool),
     Char(char),
     Void,
+    Null,
     Function {
-        declaration: FunctionDecl,
+        declaration: Rc<FunctionDecl>,
         closure: Rc<RefCell<Environment>>,
     },
-    // Built-in functions later?
-    NativeFunction(fn(Vec<Value>) -> Result<Value, RuntimeError>),
-    Array(Vec<Value>),
-    HashMap(std::collections::HashMap<String, Value>),
+    /// A built-in function, which gets the interpreter calling it
+    NativeFunction(fn(&Interpreter, Vec<Value>) -> Result<Value, RuntimeError>),
+    /// A function the embedding application registered
+    HostFunction(Rc<HostFunction>),
+    /// A value of a type the embedding application registered
+    Host(Rc<HostObject>),
+    /// Shared by every variable holding it, like an array on the JVM or the
+    /// native backend's `DotlinArray`
+    Array(Rc<RefCell<Vec<Value>>>),
+    /// Shared like an array
+    HashMap(Rc<RefCell<HashMap<String, Value>>>),
     Iterator(Rc<RefCell<IteratorState>>),
+    /// A `Pair` or `Triple`.
+    Tuple(Vec<Value>),
+    /// The singleton of an `object` declaration.
+    Object(Rc<ObjectInstance>),
+    Sequence(Rc<Sequence>),
+    /// What `launch` and `async` return.
+    Job(Rc<Job>),
+    /// What `thread { }` returns.
+    Thread(Rc<Thread>),
+    AtomicInt(Rc<Cell<i32>>),
+    Mutex(Rc<Mutex>),
+    Channel(Rc<Channel>),
+}
+
+/// An `object` declaration's singleton. Its properties are variables named
+/// after the object (`Registry.items`), defined when the object is first used.
+#[derive(Debug)]
+pub struct ObjectInstance {
+    pub name: String,
+    declaration: ObjectDecl,
+    /// Where the properties are defined: the globals, or the module for a private object
+    scope: Rc<RefCell<Environment>>,
+    /// The declaring module's environment, in which initializers run
+    closure: Rc<RefCell<Environment>>,
+    initialized: Cell<bool>,
+}
+
+/// An assignment target: a variable or a value, then the keys of the
+/// elements nested inside it, e.g. `grid[1][2]`.
+struct Place {
+    root: PlaceRoot,
+    keys: Vec<Value>,
+}
+
+enum PlaceRoot {
+    /// A variable, and its slot if it is a resolved local
+    Variable(String, Option<ScopeSlot>),
+    /// The result of an expression such as a call, which is not stored anywhere
+    Temporary(Value),
+}
+
+/// The body of a `sequence { ... }` call.
+fn sequence_body<'a>(callee: &Expression, arguments: &'a [Expression]) -> Option<&'a Block> {
+    match (&*callee.kind, arguments) {
+        (ExpressionKind::Variable(name), [argument]) if name == "sequence" => {
+            match &*argument.kind {
+                ExpressionKind::Lambda { body, .. } => Some(body),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// The name, receiver and lambda of a call of a scope function such as
+/// `x.let { }` or `with(x) { }`. `run { }` has no receiver.
+fn scope_function<'a>(
+    callee: &'a Expression,
+    arguments: &'a [Expression],
+) -> Option<(&'a str, Option<&'a Expression>, &'a Expression)> {
+    let call = match (&*callee.kind, arguments) {
+        (ExpressionKind::MemberAccess { object, member }, [lambda])
+            if matches!(
+                member.as_str(),
+                "let" | "run" | "apply" | "also" | "takeIf" | "takeUnless"
+            ) =>
+        {
+            (member.as_str(), Some(object), lambda)
+        }
+        (ExpressionKind::Variable(name), [lambda]) if name == "run" => {
+            (name.as_str(), None, lambda)
+        }
+        (ExpressionKind::Variable(name), [receiver, lambda]) if name == "with" => {
+            (name.as_str(), Some(receiver), lambda)
+        }
+        _ => return None,
+    };
+    matches!(&*call.2.kind, ExpressionKind::Lambda { .. }).then_some(call)
+}
+
+/// The lambda of a `thread { }` call.
+fn thread_body<'a>(callee: &Expression, arguments: &'a [Expression]) -> Option<&'a Expression> {
+    match (&*callee.kind, arguments) {
+        (ExpressionKind::Variable(name), [lambda]) if name == "thread" => Some(lambda),
+        _ => None,
+    }
 }
 
 #[cfg(test)]
@@ -72,7 +207,8 @@ mod tests {
 
         // for (k, v) in m.iter() { s = s + v }
         let body_stmt = Statement::Expression(Expression::new(ExpressionKind::Assignment {
-            name: "s".to_string(),
+            target: Expression::new(ExpressionKind::Variable("s".to_string())),
+            operator: None,
             value: Expression::new(ExpressionKind::Binary {
                 left: Expression::new(ExpressionKind::Variable("s".to_string())),
                 operator: BinaryOp::Add,
@@ -87,15 +223,20 @@ mod tests {
         };
 
         let func = FunctionDecl {
+            visibility: Visibility::Public,
             name: "main".to_string(),
             params: vec![],
             return_type: None,
-            body: Block {
-                statements: vec![m_decl, s_decl, for_stmt],
-            },
+            body: Block::new(vec![m_decl, s_decl, for_stmt]),
+            expression_body: false,
+            is_suspend: false,
+            is_operator: false,
+            file: None,
         };
 
         let program = Program {
+            package: None,
+            imports: vec![],
             declarations: vec![Declaration::Function(func)],
         };
 
@@ -199,25 +340,25 @@ mod tests {
         let e2 = g.get("e2").unwrap();
         let e3 = g.get("e3").unwrap();
 
-        // e1 and e2 should be arrays of length 2, e3 should be Void
-        if let Value::Array(a) = e1 {
+        // e1 and e2 should be (key, value) pairs, e3 should be Void
+        if let Value::Tuple(a) = e1 {
             assert_eq!(a.len(), 2);
             match (&a[0], &a[1]) {
                 (Value::String(_), Value::Integer(_)) => {}
                 _ => panic!("e1 entry shape unexpected"),
             }
         } else {
-            panic!("e1 expected array");
+            panic!("e1 expected pair");
         }
 
-        if let Value::Array(a) = e2 {
+        if let Value::Tuple(a) = e2 {
             assert_eq!(a.len(), 2);
             match (&a[0], &a[1]) {
                 (Value::String(_), Value::Integer(_)) => {}
                 _ => panic!("e2 entry shape unexpected"),
             }
         } else {
-            panic!("e2 expected array");
+            panic!("e2 expected pair");
         }
 
         assert!(matches!(e3, Value::Void));
@@ -272,16 +413,798 @@ mod tests {
         let e1 = g.get("e1").unwrap();
         assert!(matches!(e1, Value::Void));
     }
+
+    /// Declares the functions of `src` and evaluates `expr` in the global scope.
+    fn eval_with(src: &str, expr: &str) -> Value {
+        let program = dotlin_parser::Parser::new(src).parse_program().unwrap();
+        let mut interp = Interpreter::new();
+        interp.interpret_program(&program).unwrap();
+        let stmt = match dotlin_parser::Parser::new(expr).parse_repl_input().unwrap() {
+            dotlin_parser::ReplNode::Stmt(stmt) => stmt,
+            other => panic!("expected a statement, got {:?}", other),
+        };
+        interp.interpret_statement(&stmt).unwrap().unwrap()
+    }
+
+    /// Type-checks `src` and declares its functions, which need no `main`.
+    fn checked(src: &str) -> Interpreter {
+        let mut program = dotlin_parser::Parser::new(src).parse_program().unwrap();
+        dotlin_typechecker::TypeChecker::new()
+            .check_program(&mut program)
+            .unwrap();
+        let mut interp = Interpreter::new();
+        interp.interpret_program(&program).unwrap();
+        interp
+    }
+
+    /// Calls the global function `name` of `interp` without arguments.
+    fn call(interp: &Interpreter, name: &str) -> Result<Value, RuntimeError> {
+        let function = interp.globals.borrow().get(name).unwrap();
+        interp.call_value(function, vec![])
+    }
+
+    #[test]
+    fn expression_bodied_functions_and_if_expressions() {
+        let src = "fun sq(x: Int) = x * x\n\
+                   fun maxOf(a: Int, b: Int) = if (a > b) a else b\n\
+                   fun clamp(n: Int) = if (n > 10) { val d = 10\n d } else { n }\n\
+                   fun maybe(c: Boolean) = if (c) 1";
+        assert_eq!(eval_with(src, "sq(7)"), Value::Integer(49));
+        assert_eq!(eval_with(src, "maxOf(3, 9)"), Value::Integer(9));
+        assert_eq!(eval_with(src, "clamp(42)"), Value::Integer(10));
+        assert_eq!(eval_with(src, "maybe(false)"), Value::Void);
+    }
+
+    #[test]
+    fn destructuring_pairs_and_triples() {
+        let src = "fun divmod(a: Int, b: Int) = Pair(a / b, a - a / b * b)\n\
+                   fun rem(a: Int, b: Int): Int { val (_, r) = divmod(a, b)\n return r }";
+        assert_eq!(
+            eval_with(src, "divmod(17, 5)"),
+            Value::Tuple(vec![Value::Integer(3), Value::Integer(2)])
+        );
+        assert_eq!(eval_with(src, "rem(17, 5)"), Value::Integer(2));
+        assert_eq!(
+            eval_with(src, "\"a\" to 1"),
+            Value::Tuple(vec![Value::String("a".to_string()), Value::Integer(1)])
+        );
+        assert_eq!(eval_with(src, "Triple(1, 2, 3).third"), Value::Integer(3));
+        assert_eq!(
+            format!("{}", eval_with(src, "Triple(1, \"b\", true)")),
+            "(1, b, true)"
+        );
+    }
+
+    #[test]
+    fn numeric_tower_conversions_and_promotion() {
+        let src = "fun low(n: Int) = n.toByte()\n\
+                   fun mix(i: Int, l: Long) = i + l\n\
+                   fun half(f: Float) = f / 2\n\
+                   fun twice(b: Byte) = b + b";
+        assert_eq!(eval_with(src, "low(300)"), Value::Byte(44));
+        assert_eq!(eval_with(src, "twice(low(100))"), Value::Integer(200));
+        assert_eq!(
+            eval_with(src, "mix(1, 10000000000L)"),
+            Value::Long(10000000001)
+        );
+        assert_eq!(eval_with(src, "half(3.0f)"), Value::Float(1.5));
+        assert_eq!(eval_with(src, "4000000000u"), Value::UInt(4000000000));
+        assert_eq!(eval_with(src, "5000000000u"), Value::ULong(5000000000));
+        assert_eq!(eval_with(src, "-1.9.toInt()"), Value::Integer(-1));
+        // Floating-point values saturate, going through Int for Byte
+        assert_eq!(eval_with(src, "1000.7.toByte()"), Value::Byte(-24));
+        assert_eq!(eval_with(src, "-5.0.toUInt()"), Value::UInt(0));
+        assert_eq!(eval_with(src, "(-1).toUInt()"), Value::UInt(u32::MAX));
+        assert_eq!(eval_with(src, "\"2.5\".toDouble()"), Value::Double(2.5));
+    }
+
+    #[test]
+    fn floats_print_like_kotlin() {
+        let text = |expr: &str| eval_with("", expr).to_string();
+        assert_eq!(text("1.0"), "1.0");
+        assert_eq!(text("-3.0"), "-3.0");
+        assert_eq!(text("2.5f"), "2.5");
+        assert_eq!(text("0.1f"), "0.1");
+        assert_eq!(text("1.0 / 0.0"), "Infinity");
+        assert_eq!(text("-1.0 / 0.0"), "-Infinity");
+        assert_eq!(text("0.0 / 0.0"), "NaN");
+        assert_eq!(text("10000000000.0"), "1.0E10");
+        assert_eq!(text("0.00015"), "1.5E-4");
+        assert_eq!(text("4.0.toString()"), "4.0");
+    }
+
+    #[test]
+    fn bitwise_operators_and_radix_literals() {
+        let src = "fun lowByte(n: Int) = n and 0xFF\n\
+                   fun flags() = 0b0101 or 0b1000";
+        assert_eq!(eval_with(src, "lowByte(0x1234)"), Value::Integer(0x34));
+        assert_eq!(eval_with(src, "flags()"), Value::Integer(13));
+        assert_eq!(eval_with(src, "1_000_000"), Value::Integer(1_000_000));
+        assert_eq!(eval_with(src, "6 xor 3"), Value::Integer(5));
+        assert_eq!(eval_with(src, "5.inv()"), Value::Integer(-6));
+        // Shifts bind looser than arithmetic and mask their count like the JVM
+        assert_eq!(eval_with(src, "1 shl 2 + 1"), Value::Integer(8));
+        assert_eq!(eval_with(src, "1 shl 33"), Value::Integer(2));
+        assert_eq!(eval_with(src, "-16 shr 2"), Value::Integer(-4));
+        assert_eq!(eval_with(src, "-16 ushr 28"), Value::Integer(15));
+        assert_eq!(eval_with(src, "0xFFL shl 32"), Value::Long(0xFF << 32));
+        assert_eq!(eval_with(src, "0xFFu shr 4"), Value::UInt(0xF));
+        assert_eq!(eval_with(src, "17 % 5"), Value::Integer(2));
+        assert_eq!(eval_with(src, "-7 % 3"), Value::Integer(-1));
+        assert_eq!(eval_with(src, "5.5 % 2.0"), Value::Double(1.5));
+        assert_eq!(eval_with(src, "true xor true"), Value::Boolean(false));
+    }
+
+    #[test]
+    fn integer_arithmetic_wraps_and_division_by_zero_throws() {
+        let src = "fun inc(n: Int) = n + 1\n\
+                   fun div(a: Long, b: Long) = a / b";
+        assert_eq!(eval_with(src, "inc(2147483647)"), Value::Integer(i32::MIN));
+        assert_eq!(eval_with(src, "-2147483648 / -1"), Value::Integer(i32::MIN));
+        assert_eq!(eval_with(src, "0u - 1u"), Value::UInt(u32::MAX));
+        assert_eq!(eval_with(src, "Math.addExact(2, 3)"), Value::Integer(5));
+
+        let program = dotlin_parser::Parser::new(src).parse_program().unwrap();
+        let mut interp = Interpreter::new();
+        interp.interpret_program(&program).unwrap();
+        for (expr, message) in [
+            ("div(1L, 0L)", "/ by zero"),
+            ("7 % 0", "/ by zero"),
+            ("Math.multiplyExact(65536, 65536)", "integer overflow"),
+            (
+                "Math.negateExact(-9223372036854775807L - 1L)",
+                "long overflow",
+            ),
+        ] {
+            let stmt = match dotlin_parser::Parser::new(expr).parse_repl_input().unwrap() {
+                dotlin_parser::ReplNode::Stmt(stmt) => stmt,
+                other => panic!("expected a statement, got {:?}", other),
+            };
+            match interp.interpret_statement(&stmt) {
+                Err(RuntimeError::ArithmeticException(m)) => assert_eq!(m, message, "{}", expr),
+                other => panic!("{}: expected ArithmeticException, got {:?}", expr, other),
+            }
+        }
+    }
+
+    #[test]
+    fn modules_define_qualified_functions() {
+        let parse = |src: &str| dotlin_parser::Parser::new(src).parse_program().unwrap();
+        let modules = [
+            parse("package app\nfun main() { val ok = true }"),
+            parse("package geometry\nfun square(n: Int) = n * n"),
+        ];
+        let mut interp = Interpreter::new();
+        interp.interpret_modules(&modules).unwrap();
+
+        let square = interp.globals.borrow().get("geometry.square").unwrap();
+        assert!(matches!(
+            interp.call_value(square, vec![Value::Integer(4)]),
+            Ok(Value::Integer(16))
+        ));
+        assert!(interp.globals.borrow().get("square").is_err());
+    }
+
+    #[test]
+    fn private_functions_are_local_to_their_module() {
+        let parse = |src: &str| dotlin_parser::Parser::new(src).parse_program().unwrap();
+        let modules = [
+            parse("fun main() { val ok = true }\nprivate fun f() = 1\nfun a() = f()"),
+            parse("private fun f() = 2\nfun b() = f()"),
+        ];
+        let mut interp = Interpreter::new();
+        interp.interpret_modules(&modules).unwrap();
+
+        for (name, expected) in [("a", 1), ("b", 2)] {
+            let function = interp.globals.borrow().get(name).unwrap();
+            assert!(matches!(
+                interp.call_value(function, vec![]),
+                Ok(Value::Integer(n)) if n == expected
+            ));
+        }
+        assert!(interp.globals.borrow().get("f").is_err());
+    }
+
+    #[test]
+    fn objects_are_initialized_on_first_use() {
+        // Members refer to properties by the qualified names the type checker resolves
+        let interp = checked(
+            "object Log { var lines = 0\n fun write(): Int { lines = lines + 1\n return lines } }\n\
+             object Config { val first = Log.write() }\n\
+             fun lines() = Log.lines\n\
+             fun first() = Config.first",
+        );
+        assert_eq!(call(&interp, "Log.write").unwrap(), Value::Integer(1));
+        // Config's initializer runs once, when `first` is first read
+        assert_eq!(call(&interp, "first").unwrap(), Value::Integer(2));
+        assert_eq!(call(&interp, "first").unwrap(), Value::Integer(2));
+        assert_eq!(call(&interp, "lines").unwrap(), Value::Integer(2));
+    }
+
+    #[test]
+    fn assignment_to_elements_and_do_while() {
+        let src = "fun grid(): Array<Array<Int>> { val g = [[1, 2], [3, 4]]\n g[1][0] += 40\n g[0] = [0]\n return g }\n\
+                   fun counts(): HashMap<String, Int> { val m = {\"a\": 1}\n m[\"a\"] *= 5\n m[\"b\"] = 2\n return m }\n\
+                   fun steps(n: Int): Int { var i = 0\n do { i += 1\n val done = i >= n } while (!done)\n return i }";
+        assert_eq!(
+            eval_with(src, "grid()"),
+            Value::array(vec![
+                Value::array(vec![Value::Integer(0)]),
+                Value::array(vec![Value::Integer(43), Value::Integer(4)]),
+            ])
+        );
+        assert_eq!(eval_with(src, "counts()[\"a\"]"), Value::Integer(5));
+        assert_eq!(eval_with(src, "counts()[\"b\"]"), Value::Integer(2));
+        // The body runs once before the condition is first checked
+        assert_eq!(eval_with(src, "steps(0)"), Value::Integer(1));
+        assert_eq!(eval_with(src, "steps(4)"), Value::Integer(4));
+    }
+
+    #[test]
+    fn arrays_and_maps_are_shared_and_compared_by_identity() {
+        let src = "fun fill(xs: Array<Int>) { xs.push(3)\n xs[0] = 10 }\n\
+                   fun shared(): Int { val xs = [1, 2]\n fill(xs)\n val alias = xs\n alias[1] = 20\n\
+                   return xs[0] + xs[1] + xs[2] }\n\
+                   fun counts(): Int { val m = {\"a\": 1}\n val alias = m\n alias[\"b\"] = 2\n return m.size }\n\
+                   fun same(): Boolean { val a = [1]\n val b = a\n return a === b }\n\
+                   fun copy(): Boolean { val a = [1]\n val b = [1]\n return a !== b }";
+        // The callee and the alias change the caller's array rather than a copy
+        assert_eq!(eval_with(src, "shared()"), Value::Integer(33));
+        assert_eq!(eval_with(src, "counts()"), Value::Integer(2));
+        assert_eq!(eval_with(src, "same()"), Value::Boolean(true));
+        assert_eq!(eval_with(src, "copy()"), Value::Boolean(true));
+        assert_eq!(eval_with(src, "1 === 1"), Value::Boolean(true));
+    }
+
+    #[test]
+    fn runaway_recursion_stops_at_the_default_call_depth() {
+        with_stack(|| {
+            let program = dotlin_parser::Parser::new(
+                "fun down(n: Int): Int = if (n == 0) 0 else down(n - 1) + 1",
+            )
+            .parse_program()
+            .unwrap();
+            let mut interp = Interpreter::new();
+            interp.interpret_program(&program).unwrap();
+            let down = interp.globals.borrow().get("down").unwrap();
+            assert!(matches!(
+                interp.call_value(down.clone(), vec![Value::Integer(1_000_000)]),
+                Err(RuntimeError::LimitExceeded(Limit::CallDepth(
+                    DEFAULT_CALL_DEPTH
+                )))
+            ));
+            assert_eq!(
+                interp.call_value(down, vec![Value::Integer(4000)]).unwrap(),
+                Value::Integer(4000)
+            );
+        });
+    }
+
+    #[test]
+    fn limits_stop_runaway_scripts_and_capabilities_must_be_granted() {
+        let mut program = dotlin_parser::Parser::new(
+            "fun spin(): Int { var i = 0\n while (true) { i = i + 1 }\n return i }\n\
+             fun down(n: Int): Int = if (n == 0) 0 else down(n - 1) + 1\n\
+             fun grow(): String { var s = \"ab\"\n while (true) { s = s + s }\n return s }\n\
+             fun fill(): Int { val xs = [0]\n while (true) { xs.push(1) }\n return 0 }\n\
+             fun nap(): Int { runBlocking { delay(3000L) }\n return 0 }",
+        )
+        .parse_program()
+        .unwrap();
+        dotlin_typechecker::TypeChecker::new()
+            .check_program(&mut program)
+            .unwrap();
+        let mut interp = Interpreter::new();
+        interp.interpret_program(&program).unwrap();
+        let mut call = |limits: Limits, name: &str, args: Vec<Value>| {
+            interp.set_limits(limits);
+            let function = interp.globals.borrow().get(name).unwrap();
+            interp.call_value(function, args)
+        };
+
+        let limits = Limits {
+            instructions: Some(10_000),
+            ..Limits::default()
+        };
+        assert!(matches!(
+            call(limits, "spin", vec![]),
+            Err(RuntimeError::LimitExceeded(Limit::Instructions(10_000)))
+        ));
+        let limits = Limits {
+            time: Some(std::time::Duration::from_millis(20)),
+            ..Limits::default()
+        };
+        assert!(matches!(
+            call(limits.clone(), "spin", vec![]),
+            Err(RuntimeError::LimitExceeded(Limit::Time(_)))
+        ));
+        // Waiting on a timer stops at the deadline too
+        let started = std::time::Instant::now();
+        assert!(matches!(
+            call(limits, "nap", vec![]),
+            Err(RuntimeError::LimitExceeded(Limit::Time(_)))
+        ));
+        assert!(started.elapsed() < std::time::Duration::from_secs(1));
+        let limits = Limits {
+            call_depth: Some(20),
+            ..Limits::default()
+        };
+        assert!(matches!(
+            call(limits.clone(), "down", vec![Value::Integer(100)]),
+            Err(RuntimeError::LimitExceeded(Limit::CallDepth(20)))
+        ));
+        // The depth is back to zero after the error
+        assert_eq!(
+            call(limits, "down", vec![Value::Integer(15)]).unwrap(),
+            Value::Integer(15)
+        );
+        let limits = Limits {
+            collection_size: Some(1000),
+            ..Limits::default()
+        };
+        for name in ["grow", "fill"] {
+            assert!(matches!(
+                call(limits.clone(), name, vec![]),
+                Err(RuntimeError::LimitExceeded(Limit::CollectionSize(1000)))
+            ));
+        }
+
+        assert!(interp.globals.borrow().get("readFile").is_err());
+        interp.grant(Capabilities {
+            environment: true,
+            ..Capabilities::default()
+        });
+        assert!(interp.globals.borrow().get("getenv").is_ok());
+        assert!(interp.globals.borrow().get("readFile").is_err());
+        assert!(interp.globals.borrow().get("exec").is_err());
+    }
+
+    #[test]
+    fn scripts_print_to_and_read_from_the_interpreters_io() {
+        let mut program = dotlin_parser::Parser::new(
+            "fun main() {\n\
+                 println(\"a\", 1, 'c')\n\
+                 print(\"no newline\")\n\
+                 println()\n\
+                 eprintln(\"oops\")\n\
+                 var line = readLine()\n\
+                 while (line != null) { println(\"read \" + (line ?: \"\"))\n line = readLine() }\n\
+             }",
+        )
+        .parse_program()
+        .unwrap();
+        dotlin_typechecker::TypeChecker::new()
+            .check_program(&mut program)
+            .unwrap();
+        let output = Captured::new();
+        let errors = Captured::new();
+        let mut interp = Interpreter::new();
+        interp.set_io(Io {
+            stdout: Box::new(output.clone()),
+            stderr: Box::new(errors.clone()),
+            stdin: Box::new("x\r\ny".as_bytes()),
+        });
+        interp.interpret_program(&program).unwrap();
+        // Arguments are separated by one space, with none at the end of the line
+        assert_eq!(output.take(), "a 1 c\nno newline\nread x\nread y\n");
+        assert_eq!(errors.contents(), "oops\n");
+    }
+
+    #[test]
+    fn runtime_errors_record_the_calls_they_unwind_through() {
+        let mut program = dotlin_parser::Parser::with_file(
+            "fun divide(a: Int, b: Int): Int {\n\
+                 return a / b\n\
+             }\n\
+             fun apply(f: (Int) -> Int): Int = f(0)\n\
+             fun early(): Int { return 1 }\n\
+             fun main() {\n\
+                 early()\n\
+                 apply { x: Int -> divide(x + 1,\n x) }\n\
+             }",
+            "main.lin",
+        )
+        .parse_program()
+        .unwrap();
+        dotlin_typechecker::TypeChecker::new()
+            .check_program(&mut program)
+            .unwrap();
+        let mut interp = Interpreter::new();
+        let error = interp.interpret_program(&program).unwrap_err();
+        assert!(matches!(error, RuntimeError::ArithmeticException(_)));
+        // Innermost first; `early` returned, so it isn't part of the trace,
+        // and the lambda is shown in its caller's file
+        assert_eq!(
+            interp.take_stack_trace().to_string(),
+            "\tat divide(main.lin:2)\n\
+             \tat <lambda>(main.lin:8)\n\
+             \tat apply(main.lin:4)\n\
+             \tat main(main.lin:8)\n"
+        );
+        assert!(interp.take_stack_trace().is_empty());
+    }
+
+    #[test]
+    fn debuggers_stop_at_breakpoints_and_step_through_calls() {
+        struct Script {
+            resumes: Vec<Resume>,
+            stops: Rc<RefCell<Vec<String>>>,
+        }
+        impl Debugger for Script {
+            fn breakpoint(&self, file: Option<&str>, line: u32) -> bool {
+                file == Some("main.lin") && line == 7
+            }
+            fn stopped(&mut self, reason: StopReason, paused: &Paused) -> Resume {
+                let stack: Vec<String> = paused.stack().iter().map(|f| f.to_string()).collect();
+                let locals: Vec<String> = paused
+                    .locals(0)
+                    .iter()
+                    .map(|(name, value)| format!("{}={}", name, value))
+                    .collect();
+                let mut stop = format!("{:?} {} [{}]", reason, stack.join(" "), locals.join(" "));
+                if reason == StopReason::Step && stack.len() == 1 {
+                    let expr = match dotlin_parser::Parser::new("total * 2").parse_repl_input() {
+                        Ok(dotlin_parser::ReplNode::Stmt(Statement::Expression(expr))) => expr,
+                        other => panic!("expected an expression, got {:?}", other),
+                    };
+                    stop += &format!(" total * 2 = {}", paused.evaluate(0, &expr).unwrap());
+                }
+                self.stops.borrow_mut().push(stop);
+                self.resumes.remove(0)
+            }
+        }
+
+        let mut program = dotlin_parser::Parser::with_file(
+            "fun square(n: Int): Int {\n\
+                 val sq = n * n\n\
+                 return sq\n\
+             }\n\
+             fun main() {\n\
+                 var total = 0\n\
+                 total = total + square(3)\n\
+                 println(total)\n\
+             }",
+            "main.lin",
+        )
+        .parse_program()
+        .unwrap();
+        dotlin_typechecker::TypeChecker::new()
+            .check_program(&mut program)
+            .unwrap();
+        let stops = Rc::new(RefCell::new(Vec::new()));
+        let output = Captured::new();
+        let mut interp = Interpreter::new();
+        interp.set_io(Io::captured(&output));
+        interp.set_debugger(Script {
+            resumes: vec![
+                Resume::StepIn,
+                Resume::StepOver,
+                Resume::StepOut,
+                Resume::Continue,
+            ],
+            stops: stops.clone(),
+        });
+        interp.interpret_program(&program).unwrap();
+        assert_eq!(
+            *stops.borrow(),
+            [
+                "Breakpoint at main(main.lin:7) [total=0]",
+                "Step at square(main.lin:2) at main(main.lin:7) [n=3]",
+                "Step at square(main.lin:3) at main(main.lin:7) [n=3 sq=9]",
+                "Step at main(main.lin:8) [total=9] total * 2 = 18",
+            ]
+        );
+        assert_eq!(output.take(), "9\n");
+    }
+
+    #[test]
+    fn profiles_count_calls_and_fold_their_stacks() {
+        let mut program = dotlin_parser::Parser::new(
+            "fun fib(n: Int): Int {\n\
+                 if (n < 2) { return n }\n\
+                 return fib(n - 1) + fib(n - 2)\n\
+             }\n\
+             fun twice(f: (Int) -> Int): Int = f(f(1))\n\
+             fun main() {\n\
+                 fib(4)\n\
+                 twice { x: Int -> fib(x) }\n\
+             }",
+        )
+        .parse_program()
+        .unwrap();
+        dotlin_typechecker::TypeChecker::new()
+            .check_program(&mut program)
+            .unwrap();
+        let mut interp = Interpreter::new();
+        interp.enable_profiling();
+        interp.interpret_program(&program).unwrap();
+        let profile = interp.take_profile();
+
+        let calls: HashMap<&str, u64> = profile
+            .functions
+            .iter()
+            .map(|function| (function.name.as_str(), function.calls))
+            .collect();
+        // fib(4) makes 8 more calls, and each lambda call fib(1) none
+        assert_eq!(
+            calls,
+            HashMap::from([("main", 1), ("fib", 11), ("twice", 1), ("<lambda>", 2)])
+        );
+        let main = &profile.functions.iter().find(|f| f.name == "main").unwrap();
+        let total: std::time::Duration = profile.functions.iter().map(|f| f.exclusive).sum();
+        assert_eq!(main.inclusive, total);
+        // A recursive call's time is already in its caller's
+        let fib = &profile.functions.iter().find(|f| f.name == "fib").unwrap();
+        assert!(fib.inclusive <= main.inclusive);
+
+        let stacks: Vec<String> = profile
+            .folded()
+            .lines()
+            .map(|line| line.rsplit_once(' ').unwrap().0.to_string())
+            .collect();
+        assert_eq!(
+            stacks,
+            [
+                "main",
+                "main;fib",
+                "main;fib;fib",
+                "main;fib;fib;fib",
+                "main;fib;fib;fib;fib",
+                "main;twice",
+                "main;twice;<lambda>",
+                "main;twice;<lambda>;fib",
+            ]
+        );
+        assert!(profile.json().contains("\"name\": \"fib\", \"calls\": 11,"));
+        assert!(interp.take_profile().functions.is_empty());
+    }
+
+    #[test]
+    fn coverage_counts_lines_and_branches() {
+        let mut program = dotlin_parser::Parser::with_file(
+            "fun sign(n: Int): Int {\n\
+                 if (n < 0) {\n\
+                     return -1\n\
+                 }\n\
+                 return if (n == 0) 0 else 1\n\
+             }\n\
+             fun unused() {\n\
+                 println(\"never\")\n\
+             }\n\
+             fun main() {\n\
+                 var i = 0\n\
+                 while (i < 2) { sign(i)\n i += 1 }\n\
+             }",
+            "main.lin",
+        )
+        .parse_program()
+        .unwrap();
+        dotlin_typechecker::TypeChecker::new()
+            .check_program(&mut program)
+            .unwrap();
+        let mut interp = Interpreter::new();
+        interp.enable_coverage(std::slice::from_ref(&program));
+        interp.interpret_program(&program).unwrap();
+        let coverage = interp.take_coverage();
+
+        let file = &coverage.files["main.lin"];
+        assert_eq!(
+            file.lines.iter().map(|(&l, &h)| (l, h)).collect::<Vec<_>>(),
+            [(2, 2), (3, 0), (5, 2), (8, 0), (11, 1), (12, 3), (13, 2)]
+        );
+        assert_eq!(
+            file.branches
+                .iter()
+                .map(|(&l, &h)| (l, h))
+                .collect::<Vec<_>>(),
+            [(2, [0, 2]), (5, [1, 1]), (12, [2, 1])]
+        );
+        assert_eq!(
+            coverage.summary(),
+            "File        Lines  Branches  Missed lines\n\
+             main.lin    71.4%     83.3%  3, 8\n\
+             Total       71.4%     83.3%\n"
+        );
+        let lcov = coverage.lcov(|file| format!("src/{}", file));
+        assert!(lcov.starts_with("TN:\nSF:src/main.lin\nBRDA:2,0,0,0\nBRDA:2,0,1,2\n"));
+        assert!(lcov.contains("BRF:6\nBRH:5\n"));
+        assert!(lcov.ends_with("DA:13,2\nLF:7\nLH:5\nend_of_record\n"));
+        // The lines are still there once the counts are taken
+        assert_eq!(interp.take_coverage().files["main.lin"].lines[&2], 0);
+    }
+
+    #[test]
+    fn sequences_are_lazy_and_lambdas_capture_values() {
+        let interp = checked(
+            "fun naturals(): Sequence<Int> = sequence { var n = 0\n while (true) { yield(n)\n n += 1 } }\n\
+             fun oddSquares(): Array<Int> = naturals().map { it * it }.filter { it % 2 == 1 }.take(3).toList()\n\
+             fun adder(k: Int): (Int) -> Int = { x: Int -> x + k }\n\
+             fun seven(): Int { var k = 3\n val add = adder(k)\n k = 100\n return add(4) }",
+        );
+        // `naturals()` is infinite: only the elements `take(3)` needs are computed
+        assert_eq!(
+            call(&interp, "oddSquares").unwrap(),
+            Value::array(vec![
+                Value::Integer(1),
+                Value::Integer(9),
+                Value::Integer(25)
+            ])
+        );
+        // Lambdas capture the values of variables when they are created
+        assert_eq!(call(&interp, "seven").unwrap(), Value::Integer(7));
+    }
+
+    #[test]
+    fn coroutines_run_in_order_of_virtual_time() {
+        let interp = checked(
+            "object Log { var text = \"\" }\n\
+             fun log(s: String) { Log.text = Log.text + s }\n\
+             suspend fun work(id: Int, ms: Long): Int { delay(ms)\n log(id.toString())\n return id }\n\
+             fun schedule(): String { runTest {\n\
+               val a = async { work(1, 30000L) }\n\
+               val b = async { work(2, 10000L) }\n\
+               launch { delay(20000L)\n log(\"L\") }\n\
+               log(\"S\")\n\
+               val sum = a.await() + b.await()\n\
+               log(sum.toString() + \"@\" + currentTime.toString())\n\
+             }\n return Log.text }\n\
+             fun answer(): Int = runBlocking { 42 }",
+        );
+        // `runTest` fires the timers without sleeping for 30 seconds
+        assert_eq!(
+            call(&interp, "schedule").unwrap(),
+            Value::String("S2L13@30000".to_string())
+        );
+        assert_eq!(call(&interp, "answer").unwrap(), Value::Integer(42));
+    }
+
+    #[test]
+    fn threads_run_when_others_wait_for_them() {
+        let interp = checked(
+            "fun pipeline(): Int { val c: Channel<Int> = Channel()\n\
+               val hits = AtomicInt(0)\n\
+               thread { c.send(1)\n c.send(2)\n c.close() }\n\
+               val t = thread { hits.addAndGet(5) }\n\
+               var sum = 0\n for (x in c) { sum += x }\n\
+               t.join()\n return sum * 10 + hits.get() }\n\
+             fun stuck(): Int { val c: Channel<Int> = Channel()\n return c.receive() }",
+        );
+        assert_eq!(call(&interp, "pipeline").unwrap(), Value::Integer(35));
+        assert!(matches!(
+            call(&interp, "stuck"),
+            Err(RuntimeError::Deadlock(_))
+        ));
+    }
+
+    #[test]
+    fn scope_functions_run_in_place() {
+        let interp = checked(
+            "fun firstEven(numbers: Array<Int>): Int? { for (n in numbers) { n.takeIf { it % 2 == 0 }?.let { return it } }\n return null }\n\
+             fun found(): Int = firstEven([3, 4, 6]) ?: -1\n\
+             fun missing(): Int = firstEven([1]) ?: -1\n\
+             fun counted(): Int { var n = 1\n val kept = 5.also { n += it }.apply { n += this }\n return n * 100 + kept }\n\
+             fun length(): Int { val s: String? = null\n return s?.length ?: with(\"abc\") { length } }",
+        );
+        assert_eq!(call(&interp, "found").unwrap(), Value::Integer(4));
+        assert_eq!(call(&interp, "missing").unwrap(), Value::Integer(-1));
+        assert_eq!(call(&interp, "counted").unwrap(), Value::Integer(1105));
+        assert_eq!(call(&interp, "length").unwrap(), Value::Integer(3));
+    }
+
+    #[test]
+    fn properties_use_accessors_and_delegates() {
+        let interp = checked(
+            "object Log { var reads = 0 }\n\
+             object Twice {\n var value = 0\n\
+             operator fun getValue(thisRef: Any?, property: KProperty<*>): Int { Log.reads += 1\n return value * 2 }\n\
+             operator fun setValue(thisRef: Any?, property: KProperty<*>, v: Int) { value = v } }\n\
+             object Settings {\n var level: Int by Twice\n\
+             var percent = 0\n set(value) { field = if (value > 100) { 100 } else { value } }\n\
+             val computed by lazy { Log.reads += 10\n 7 }\n\
+             lateinit var name: String }\n\
+             fun delegated(): Int { Settings.level = 4\n return Settings.level + Log.reads }\n\
+             fun clamped(): Int { Settings.percent = 150\n return Settings.percent }\n\
+             fun lazy(): Int { val a = Settings.computed\n return a + Settings.computed + Log.reads }\n\
+             fun unset(): String = Settings.name",
+        );
+        assert_eq!(call(&interp, "delegated").unwrap(), Value::Integer(9));
+        assert_eq!(call(&interp, "clamped").unwrap(), Value::Integer(100));
+        assert_eq!(call(&interp, "lazy").unwrap(), Value::Integer(25));
+        assert!(matches!(
+            call(&interp, "unset"),
+            Err(RuntimeError::UninitializedProperty(name)) if name == "name"
+        ));
+    }
+
+    #[test]
+    fn value_classes_are_their_property() {
+        let interp = checked(
+            "typealias Score = Int\n\
+             @JvmInline value class Money(val cents: Score) {\n\
+             fun add(other: Money): Money = Money(cents + other.cents)\n\
+             fun dollars(): Int = cents / 100\n\
+             companion object { val zero = Money(0)\n\
+             fun of(dollars: Int): Money = Money(dollars * 100) } }\n\
+             fun total(): Money = Money(250).add(Money(1000))\n\
+             fun three(): Int = Money.of(3).add(Money.zero).dollars()\n\
+             fun dollars(): Int = total().dollars()\n\
+             fun text(): String = total().toString()",
+        );
+        assert_eq!(call(&interp, "total").unwrap(), Value::Integer(1250));
+        assert_eq!(call(&interp, "dollars").unwrap(), Value::Integer(12));
+        assert_eq!(call(&interp, "three").unwrap(), Value::Integer(3));
+        assert_eq!(
+            call(&interp, "text").unwrap(),
+            Value::String("Money(cents=1250)".to_string())
+        );
+    }
+
+    #[test]
+    fn locals_resolve_to_slots_and_scoping_errors_stop_the_program_early() {
+        let parse = |src: &str| dotlin_parser::Parser::new(src).parse_program().unwrap();
+        let interp = checked(
+            "fun sum(n: Int): Int { var total = 0\n var i = 0\n\
+             while (i < n) { val next = i + 1\n total += i\n i = next }\n return total }\n\
+             fun shadow(n: Int): Int { val n = n * 2\n var grid = [[0, 0]]\n\
+             if (n > 0) { val n = 1\n grid[0][n] = 5 }\n return grid[0][1] + n }",
+        );
+
+        let function = |name: &str| interp.globals.borrow().get(name).unwrap();
+        let call = |name: &str, n: i32| {
+            interp
+                .call_value(function(name), vec![Value::Integer(n)])
+                .unwrap()
+        };
+        assert_eq!(call("sum", 5), Value::Integer(10));
+        assert_eq!(call("shadow", 3), Value::Integer(11));
+        let Value::Function { declaration, .. } = function("sum") else {
+            panic!("sum is not a function");
+        };
+        let Some(Statement::Return(Some(total))) = declaration.body.statements.last() else {
+            panic!("sum doesn't end in a return");
+        };
+        assert_eq!(total.slot, Some(ScopeSlot { depth: 0, slot: 1 }));
+        // Its scope defines the variables in the slots they resolved to
+        let locals = declaration.body.locals.as_deref().unwrap_or_default();
+        assert_eq!(locals, ["n", "total", "i"]);
+
+        for (src, expected) in [
+            (
+                "fun main() { println(x)\n val x = 1 }",
+                ResolveError::UsedBeforeDeclaration("x".to_string()),
+            ),
+            (
+                "fun main() { val x = 1\n if (x > 0) { val y = x\n val y = 2 } }",
+                ResolveError::ConflictingDeclarations("y".to_string()),
+            ),
+            (
+                "fun main() { if (true) val x = 1 }",
+                ResolveError::DeclarationOutsideBlock("x".to_string()),
+            ),
+        ] {
+            assert!(matches!(
+                Interpreter::new().interpret_program(&parse(src)),
+                Err(RuntimeError::Resolve(e)) if e == expected
+            ));
+        }
+    }
 }
 
 impl PartialEq for Value {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
             (Value::Integer(a), Value::Integer(b)) => a == b,
+            (Value::Long(a), Value::Long(b)) => a == b,
+            (Value::Byte(a), Value::Byte(b)) => a == b,
+            (Value::Short(a), Value::Short(b)) => a == b,
+            (Value::UInt(a), Value::UInt(b)) => a == b,
+            (Value::ULong(a), Value::ULong(b)) => a == b,
             (Value::Float(a), Value::Float(b)) => a == b,
+            (Value::Double(a), Value::Double(b)) => a == b,
             (Value::String(a), Value::String(b)) => a == b,
             (Value::Boolean(a), Value::Boolean(b)) => a == b,
             (Value::Void, Value::Void) => true,
+            (Value::Null, Value::Null) => true,
             (
                 Value::Function {
                     declaration: d1, ..
@@ -299,9 +1222,18 @@ impl PartialEq for Value {
                 // Or just cast to usize.
                 *f1 as usize == *f2 as usize
             }
-            (Value::Array(a1), Value::Array(a2)) => a1 == a2,
-            (Value::HashMap(m1), Value::HashMap(m2)) => m1 == m2,
+            (Value::HostFunction(a), Value::HostFunction(b)) => Rc::ptr_eq(a, b),
+            (Value::Host(a), Value::Host(b)) => Rc::ptr_eq(a, b),
+            (Value::Array(a1), Value::Array(a2)) => *a1.borrow() == *a2.borrow(),
+            (Value::HashMap(m1), Value::HashMap(m2)) => *m1.borrow() == *m2.borrow(),
             (Value::Iterator(a), Value::Iterator(b)) => Rc::ptr_eq(a, b),
+            (Value::Tuple(t1), Value::Tuple(t2)) => t1 == t2,
+            (Value::Sequence(a), Value::Sequence(b)) => Rc::ptr_eq(a, b),
+            (Value::Job(a), Value::Job(b)) => Rc::ptr_eq(a, b),
+            (Value::Thread(a), Value::Thread(b)) => Rc::ptr_eq(a, b),
+            (Value::AtomicInt(a), Value::AtomicInt(b)) => Rc::ptr_eq(a, b),
+            (Value::Mutex(a), Value::Mutex(b)) => Rc::ptr_eq(a, b),
+            (Value::Channel(a), Value::Channel(b)) => Rc::ptr_eq(a, b),
             _ => false,
         }
     }
@@ -313,21 +1245,280 @@ pub struct IteratorState {
     pub pos: usize,
 }
 
+/// Applies an arithmetic or comparison operator to two integers of the same type.
+/// Arithmetic wraps on overflow like Kotlin; dividing by zero is an `ArithmeticException`.
+macro_rules! integer_op {
+    ($op:expr, $l:expr, $r:expr, $variant:ident) => {
+        match $op {
+            BinaryOp::Add => Ok(Value::$variant($l.wrapping_add($r))),
+            BinaryOp::Sub => Ok(Value::$variant($l.wrapping_sub($r))),
+            BinaryOp::Mul => Ok(Value::$variant($l.wrapping_mul($r))),
+            BinaryOp::Div | BinaryOp::Rem if $r == 0 => {
+                Err(RuntimeError::ArithmeticException("/ by zero".to_string()))
+            }
+            BinaryOp::Div => Ok(Value::$variant($l.wrapping_div($r))),
+            BinaryOp::Rem => Ok(Value::$variant($l.wrapping_rem($r))),
+            op => numeric_op!(op, $l, $r, $variant),
+        }
+    };
+}
+
+/// Applies an arithmetic or comparison operator to two numbers of the same type.
+macro_rules! numeric_op {
+    ($op:expr, $l:expr, $r:expr, $variant:ident) => {
+        match $op {
+            BinaryOp::Add => Ok(Value::$variant($l + $r)),
+            BinaryOp::Sub => Ok(Value::$variant($l - $r)),
+            BinaryOp::Mul => Ok(Value::$variant($l * $r)),
+            BinaryOp::Div => Ok(Value::$variant($l / $r)),
+            BinaryOp::Rem => Ok(Value::$variant($l % $r)),
+            BinaryOp::Equal => Ok(Value::Boolean($l == $r)),
+            BinaryOp::NotEqual => Ok(Value::Boolean($l != $r)),
+            BinaryOp::Less => Ok(Value::Boolean($l < $r)),
+            BinaryOp::LessEqual => Ok(Value::Boolean($l <= $r)),
+            BinaryOp::Greater => Ok(Value::Boolean($l > $r)),
+            BinaryOp::GreaterEqual => Ok(Value::Boolean($l >= $r)),
+            _ => Err(RuntimeError::TypeMismatch(
+                "Invalid operator for numeric operands".to_string(),
+            )),
+        }
+    };
+}
+
+/// Applies `and`, `or`, `xor` or a shift to an integer; shift counts are
+/// masked to the operand width like on the JVM.
+macro_rules! bitwise_op {
+    ($op:expr, $l:expr, $r:expr, $count:expr, $variant:ident, $unsigned:ty) => {
+        match $op {
+            BinaryOp::BitAnd => Some(Value::$variant($l & $r)),
+            BinaryOp::BitOr => Some(Value::$variant($l | $r)),
+            BinaryOp::BitXor => Some(Value::$variant($l ^ $r)),
+            BinaryOp::Shl => Some(Value::$variant($l.wrapping_shl($count))),
+            BinaryOp::Shr => Some(Value::$variant($l.wrapping_shr($count))),
+            BinaryOp::UShr => Some(Value::$variant(($l as $unsigned).wrapping_shr($count) as _)),
+            _ => None,
+        }
+    };
+}
+
+/// Signed integer types, narrowest first; mirrors the type checker's promotion order.
+const SIGNED_INTEGER_TYPES: [&str; 4] = ["Byte", "Short", "Int", "Long"];
+
+impl Value {
+    /// A new array, not shared with anything yet.
+    pub fn array(elements: Vec<Value>) -> Value {
+        Value::Array(Rc::new(RefCell::new(elements)))
+    }
+
+    /// A new map, not shared with anything yet.
+    pub fn map(entries: HashMap<String, Value>) -> Value {
+        Value::HashMap(Rc::new(RefCell::new(entries)))
+    }
+
+    /// Whether two values are the same one, as `===` checks: the same array
+    /// or map, rather than an equal one. Other values have no identity of
+    /// their own and are compared by value.
+    pub fn identical(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Array(a), Value::Array(b)) => Rc::ptr_eq(a, b),
+            (Value::HashMap(a), Value::HashMap(b)) => Rc::ptr_eq(a, b),
+            (Value::Object(a), Value::Object(b)) => Rc::ptr_eq(a, b),
+            _ => self == other,
+        }
+    }
+
+    /// Name of the numeric type of this value, if it is a number.
+    pub fn numeric_type(&self) -> Option<&'static str> {
+        match self {
+            Value::Byte(_) => Some("Byte"),
+            Value::Short(_) => Some("Short"),
+            Value::Integer(_) => Some("Int"),
+            Value::Long(_) => Some("Long"),
+            Value::UInt(_) => Some("UInt"),
+            Value::ULong(_) => Some("ULong"),
+            Value::Float(_) => Some("Float"),
+            Value::Double(_) => Some("Double"),
+            _ => None,
+        }
+    }
+
+    /// Converts a number the way Kotlin's `toInt()`, `toByte()`, ... do:
+    /// integers are truncated to the target width, floating-point values
+    /// saturate (going through `Int` for `Byte` and `Short`) and NaN becomes 0.
+    pub fn convert_numeric(&self, target: &str) -> Option<Value> {
+        let integer = match *self {
+            Value::Byte(n) => Some(n as i128),
+            Value::Short(n) => Some(n as i128),
+            Value::Integer(n) => Some(n as i128),
+            Value::Long(n) => Some(n as i128),
+            Value::UInt(n) => Some(n as i128),
+            Value::ULong(n) => Some(n as i128),
+            _ => None,
+        };
+        if let Some(n) = integer {
+            return Some(match target {
+                "Byte" => Value::Byte(n as i8),
+                "Short" => Value::Short(n as i16),
+                "Int" => Value::Integer(n as i32),
+                "Long" => Value::Long(n as i64),
+                "UInt" => Value::UInt(n as u32),
+                "ULong" => Value::ULong(n as u64),
+                "Float" => Value::Float(n as f32),
+                "Double" => Value::Double(n as f64),
+                _ => return None,
+            });
+        }
+        let f = match *self {
+            Value::Float(f) => f as f64,
+            Value::Double(f) => f,
+            _ => return None,
+        };
+        Some(match target {
+            "Byte" => Value::Byte(f as i32 as i8),
+            "Short" => Value::Short(f as i32 as i16),
+            "Int" => Value::Integer(f as i32),
+            "Long" => Value::Long(f as i64),
+            "UInt" => Value::UInt(f as u32),
+            "ULong" => Value::ULong(f as u64),
+            "Float" => Value::Float(f as f32),
+            "Double" => Value::Double(f),
+            _ => return None,
+        })
+    }
+
+    /// The type both operands of a numeric binary operation are converted to:
+    /// `Byte`/`Short` widen to `Int`, the wider integer wins, and a
+    /// floating-point operand makes the operation floating-point.
+    fn promoted_type(l: &str, r: &str) -> Option<&'static str> {
+        let signed = |t: &str| SIGNED_INTEGER_TYPES.iter().position(|n| *n == t);
+        let signed_or_floating = |t: &str| signed(t).is_some() || matches!(t, "Float" | "Double");
+        if let (Some(a), Some(b)) = (signed(l), signed(r)) {
+            return Some(SIGNED_INTEGER_TYPES[a.max(b).max(2)]);
+        }
+        match (l, r) {
+            ("UInt", "UInt") => Some("UInt"),
+            ("UInt" | "ULong", "UInt" | "ULong") => Some("ULong"),
+            _ if signed_or_floating(l) && signed_or_floating(r) => {
+                Some(if l == "Double" || r == "Double" {
+                    "Double"
+                } else {
+                    "Float"
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// Evaluates an infix bit operation. `and`, `or` and `xor` take two
+    /// operands of the same type; shifts take an `Int` count.
+    fn bitwise_binary(l: &Value, op: &BinaryOp, r: &Value) -> Result<Value, RuntimeError> {
+        let shift = matches!(op, BinaryOp::Shl | BinaryOp::Shr | BinaryOp::UShr);
+        let count = match r {
+            Value::Integer(n) if shift => *n as u32,
+            _ => 0,
+        };
+        let result = match (l, r) {
+            (&Value::Boolean(a), &Value::Boolean(b)) => match op {
+                BinaryOp::BitAnd => Some(Value::Boolean(a & b)),
+                BinaryOp::BitOr => Some(Value::Boolean(a | b)),
+                BinaryOp::BitXor => Some(Value::Boolean(a ^ b)),
+                _ => None,
+            },
+            (&Value::Integer(a), &Value::Integer(b)) => bitwise_op!(op, a, b, count, Integer, u32),
+            (&Value::Long(a), &Value::Integer(_)) if shift => {
+                bitwise_op!(op, a, 0, count, Long, u64)
+            }
+            (&Value::Long(a), &Value::Long(b)) => bitwise_op!(op, a, b, count, Long, u64),
+            (&Value::UInt(a), &Value::Integer(_)) if shift => {
+                bitwise_op!(op, a, 0, count, UInt, u32)
+            }
+            (&Value::UInt(a), &Value::UInt(b)) => bitwise_op!(op, a, b, count, UInt, u32),
+            (&Value::ULong(a), &Value::Integer(_)) if shift => {
+                bitwise_op!(op, a, 0, count, ULong, u64)
+            }
+            (&Value::ULong(a), &Value::ULong(b)) => bitwise_op!(op, a, b, count, ULong, u64),
+            _ => None,
+        };
+        result.ok_or_else(|| {
+            RuntimeError::TypeMismatch("Invalid operands for bitwise operator".to_string())
+        })
+    }
+
+    /// Evaluates `l op r` for two numbers after promoting them to a common type.
+    fn numeric_binary(l: &Value, op: &BinaryOp, r: &Value) -> Result<Value, RuntimeError> {
+        let promoted = l
+            .numeric_type()
+            .zip(r.numeric_type())
+            .and_then(|(lt, rt)| Self::promoted_type(lt, rt))
+            .ok_or_else(|| {
+                RuntimeError::TypeMismatch("Binary operator operand mismatch".to_string())
+            })?;
+        match (
+            l.convert_numeric(promoted).unwrap(),
+            r.convert_numeric(promoted).unwrap(),
+        ) {
+            (Value::Integer(a), Value::Integer(b)) => integer_op!(op, a, b, Integer),
+            (Value::Long(a), Value::Long(b)) => integer_op!(op, a, b, Long),
+            (Value::UInt(a), Value::UInt(b)) => integer_op!(op, a, b, UInt),
+            (Value::ULong(a), Value::ULong(b)) => integer_op!(op, a, b, ULong),
+            (Value::Float(a), Value::Float(b)) => numeric_op!(op, a, b, Float),
+            (Value::Double(a), Value::Double(b)) => numeric_op!(op, a, b, Double),
+            _ => unreachable!("numbers are promoted to Int or wider"),
+        }
+    }
+}
+
+/// A float as Kotlin prints it: whole values with a `.0`, very large and
+/// small ones in scientific notation like `1.0E10`, and `Infinity`,
+/// `-Infinity` and `NaN`.
+fn float_text<F>(n: F) -> String
+where
+    F: Into<f64> + std::fmt::Display + std::fmt::LowerExp + Copy,
+{
+    let (plain, scientific) = (n.to_string(), format!("{:e}", n));
+    let n: f64 = n.into();
+    match n {
+        _ if n.is_nan() => "NaN".to_string(),
+        f64::INFINITY => "Infinity".to_string(),
+        f64::NEG_INFINITY => "-Infinity".to_string(),
+        _ if n == 0.0 || (1e-3..1e7).contains(&n.abs()) => match plain.contains('.') {
+            true => plain,
+            false => plain + ".0",
+        },
+        _ => {
+            let (mantissa, exponent) = scientific.split_once('e').unwrap_or((&scientific, "0"));
+            match mantissa.contains('.') {
+                true => format!("{}E{}", mantissa, exponent),
+                false => format!("{}.0E{}", mantissa, exponent),
+            }
+        }
+    }
+}
+
 impl std::fmt::Display for Value {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Value::Integer(i) => write!(f, "{}", i),
-            Value::Float(n) => write!(f, "{}", n),
+            Value::Long(i) => write!(f, "{}", i),
+            Value::Byte(i) => write!(f, "{}", i),
+            Value::Short(i) => write!(f, "{}", i),
+            Value::UInt(i) => write!(f, "{}", i),
+            Value::ULong(i) => write!(f, "{}", i),
+            Value::Float(n) => write!(f, "{}", float_text(*n)),
+            Value::Double(n) => write!(f, "{}", float_text(*n)),
             Value::String(s) => write!(f, "{}", s),
             Value::Boolean(b) => write!(f, "{}", b),
             Value::Char(c) => write!(f, "'{}'", c),
             Value::Void => write!(f, "()"),
+            Value::Null => write!(f, "null"),
             Value::Function { declaration, .. } => write!(f, "fun {}", declaration.name),
             Value::NativeFunction(_) => write!(f, "<native fn>"),
+            Value::HostFunction(function) => write!(f, "fun {}", function.name),
+            Value::Host(object) => write!(f, "{}", object.type_name),
 
             Value::Array(elements) => {
                 write!(f, "Array(")?;
-                for (i, element) in elements.iter().enumerate() {
+                for (i, element) in elements.borrow().iter().enumerate() {
                     if i > 0 {
                         write!(f, ", ")?;
                     }
@@ -336,8 +1527,26 @@ impl std::fmt::Display for Value {
                 write!(f, ")")
             }
             Value::Iterator(_) => write!(f, "<iterator>"),
+            Value::Sequence(_) => write!(f, "<sequence>"),
+            Value::Job(_) => write!(f, "<job>"),
+            Value::Thread(_) => write!(f, "<thread>"),
+            Value::AtomicInt(value) => write!(f, "{}", value.get()),
+            Value::Mutex(_) => write!(f, "<mutex>"),
+            Value::Channel(_) => write!(f, "<channel>"),
+            Value::Object(object) => write!(f, "{}", object.name),
+            Value::Tuple(elements) => {
+                write!(f, "(")?;
+                for (i, element) in elements.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", element)?;
+                }
+                write!(f, ")")
+            }
             Value::HashMap(map) => {
                 write!(f, "HashMap(")?;
+                let map = map.borrow();
                 let mut iter = map.iter().enumerate();
                 if let Some((_i, (key, value))) = iter.next() {
                     write!(f, "{}: {}", key, value)?;
@@ -356,7 +1565,31 @@ pub enum RuntimeError {
     UndefinedVariable(String),
     TypeMismatch(String),
     NotAFunction(String),
-    ArgumentCount { expected: usize, got: usize },
+    ArgumentCount {
+        expected: usize,
+        got: usize,
+    },
+    /// Integer division by zero, or overflow in a `Math.*Exact` operation
+    ArithmeticException(String),
+    /// Reading or writing an array element past its end
+    IndexOutOfBounds {
+        index: i32,
+        length: usize,
+    },
+    /// Sending to or receiving from a closed channel
+    ChannelClosed {
+        send: bool,
+    },
+    /// Waiting for a thread that can't make progress
+    Deadlock(String),
+    /// Reading a `lateinit` property before assigning it
+    UninitializedProperty(String),
+    /// A scoping error the resolver found before the program ran
+    Resolve(ResolveError),
+    /// A file or process a granted capability reached failed
+    IoException(String),
+    /// The run used more than its `Limits` allow
+    LimitExceeded(Limit),
     Return(Box<Value>),
 }
 
@@ -371,6 +1604,27 @@ impl std::fmt::Display for RuntimeError {
                 "Incorrect argument count: expected {}, got {}",
                 expected, got
             ),
+            RuntimeError::ArithmeticException(msg) => write!(f, "ArithmeticException: {}", msg),
+            RuntimeError::IndexOutOfBounds { index, length } => write!(
+                f,
+                "ArrayIndexOutOfBoundsException: Index {} out of bounds for length {}",
+                index, length
+            ),
+            RuntimeError::ChannelClosed { send: true } => {
+                write!(f, "ClosedSendChannelException: Channel was closed")
+            }
+            RuntimeError::ChannelClosed { send: false } => {
+                write!(f, "ClosedReceiveChannelException: Channel was closed")
+            }
+            RuntimeError::Deadlock(msg) => write!(f, "Deadlock: {}", msg),
+            RuntimeError::UninitializedProperty(name) => write!(
+                f,
+                "UninitializedPropertyAccessException: lateinit property {} has not been initialized",
+                name
+            ),
+            RuntimeError::Resolve(e) => write!(f, "{}", e),
+            RuntimeError::IoException(msg) => write!(f, "IOException: {}", msg),
+            RuntimeError::LimitExceeded(limit) => write!(f, "Limit exceeded: {}", limit),
             RuntimeError::Return(_) => write!(f, "Return statement outside function"),
         }
     }
@@ -378,33 +1632,116 @@ impl std::fmt::Display for RuntimeError {
 
 impl std::error::Error for RuntimeError {}
 
+impl From<ResolveError> for RuntimeError {
+    fn from(e: ResolveError) -> Self {
+        RuntimeError::Resolve(e)
+    }
+}
+
+/// A scope's variables, in slots in the order they were defined. The
+/// resolver tells the interpreter the slots of local variables, and the names
+/// of a block's slots; the rest are looked up by name.
 #[derive(Debug, Clone, PartialEq)]
 pub struct Environment {
-    values: HashMap<String, Value>,
+    values: Vec<Value>,
+    names: Names,
     enclosing: Option<Rc<RefCell<Environment>>>,
 }
 
+/// The names of an environment's slots.
+#[derive(Debug, Clone, PartialEq)]
+enum Names {
+    /// Those the resolver found for a block, of which the first
+    /// `values.len()` are defined
+    Resolved(Arc<[String]>),
+    /// The slot of each variable of a scope the resolver didn't see, or
+    /// that defined one it didn't expect
+    Defined(HashMap<String, usize>),
+}
+
 impl Environment {
     pub fn new() -> Self {
         Self {
-            values: HashMap::new(),
+            values: Vec::new(),
+            names: Names::Defined(HashMap::new()),
             enclosing: None,
         }
     }
 
     pub fn with_enclosing(enclosing: Rc<RefCell<Environment>>) -> Self {
         Self {
-            values: HashMap::new(),
             enclosing: Some(enclosing),
+            ..Self::new()
         }
     }
-    pub fn define(&mut self, name: String, value: Value) {
-        self.values.insert(name, value);
+
+    /// The scope of `block`, whose variables have the slots the resolver
+    /// gave them, if it has seen the block.
+    pub fn for_block(block: &Block, enclosing: Rc<RefCell<Environment>>) -> Self {
+        match &block.locals {
+            Some(locals) => Self::with_locals(locals.clone(), enclosing),
+            None => Self::with_enclosing(enclosing),
+        }
+    }
+
+    /// A scope whose variables will be defined in the order of `locals`.
+    fn with_locals(locals: Arc<[String]>, enclosing: Rc<RefCell<Environment>>) -> Self {
+        Self {
+            values: Vec::with_capacity(locals.len()),
+            names: Names::Resolved(locals),
+            enclosing: Some(enclosing),
+        }
+    }
+
+    /// The variables of this scope, in the order they were defined.
+    pub fn variables(&self) -> Vec<(String, Value)> {
+        let mut names: Vec<(usize, &String)> = match &self.names {
+            Names::Resolved(names) => names.iter().enumerate().collect(),
+            Names::Defined(slots) => slots.iter().map(|(name, &slot)| (slot, name)).collect(),
+        };
+        names.sort();
+        names.truncate(self.values.len());
+        names
+            .into_iter()
+            .map(|(slot, name)| (name.clone(), self.values[slot].clone()))
+            .collect()
+    }
+
+    fn slot(&self, name: &str) -> Option<usize> {
+        match &self.names {
+            Names::Resolved(names) => names[..self.values.len()]
+                .iter()
+                .position(|defined| defined == name),
+            Names::Defined(slots) => slots.get(name).copied(),
+        }
+    }
+
+    /// Defines a variable in the next slot, or replaces the one of the same name.
+    pub fn define(&mut self, name: impl AsRef<str> + Into<String>, value: Value) {
+        if let Names::Resolved(names) = &self.names {
+            if names.get(self.values.len()).map(String::as_str) == Some(name.as_ref()) {
+                self.values.push(value);
+                return;
+            }
+        }
+        if let Some(slot) = self.slot(name.as_ref()) {
+            self.values[slot] = value;
+            return;
+        }
+        if let Names::Resolved(names) = &self.names {
+            // Not the variable the resolver expected next
+            let defined = names[..self.values.len()].iter().cloned();
+            self.names = Names::Defined(defined.zip(0..).collect());
+        }
+        if let Names::Defined(slots) = &mut self.names {
+            slots.insert(name.into(), self.values.len());
+        }
+        self.values.push(value);
     }
 
     pub fn get(&self, name: &str) -> Result<Value, RuntimeError> {
-        if let Some(value) = self.values.get(name) {
-            Ok(value.clone())
+        if let Some(slot) = self.slot(name) {
+            Ok(self.values[slot].clone())
         } else if let Some(ref enclosing) = self.enclosing {
             enclosing.borrow().get(name)
         } else {
@@ -413,8 +1750,8 @@ impl Environment {
     }
 
     pub fn assign(&mut self, name: String, value: Value) -> Result<(), RuntimeError> {
-        if let Some(slot) = self.values.get_mut(&name) {
-            *slot = value;
+        if let Some(slot) = self.slot(&name) {
+            self.values[slot] = value;
             Ok(())
         } else if let Some(ref enclosing) = self.enclosing {
             enclosing.borrow_mut().assign(name, value)
@@ -422,6 +1759,41 @@ impl Environment {
             Err(RuntimeError::UndefinedVariable(name))
         }
     }
+
+    /// The variable in a slot the resolver found, if it is defined yet.
+    pub fn get_at(&self, slot: ScopeSlot) -> Option<Value> {
+        match slot.depth {
+            0 => self.values.get(slot.slot as usize).cloned(),
+            depth => self.enclosing.as_ref()?.borrow().get_at(ScopeSlot {
+                depth: depth - 1,
+                ..slot
+            }),
+        }
+    }
+
+    /// Assigns the variable in a slot the resolver found, returning `false`
+    /// if there is none.
+    pub fn assign_at(&mut self, slot: ScopeSlot, value: Value) -> bool {
+        match slot.depth {
+            0 => match self.values.get_mut(slot.slot as usize) {
+                Some(variable) => {
+                    *variable = value;
+                    true
+                }
+                None => false,
+            },
+            depth => match &self.enclosing {
+                Some(enclosing) => enclosing.borrow_mut().assign_at(
+                    ScopeSlot {
+                        depth: depth - 1,
+                        ..slot
+                    },
+                    value,
+                ),
+                None => false,
+            },
+        }
+    }
 }
 
 impl Default for Environment {
@@ -432,28 +1804,76 @@ impl Default for Environment {
 
 pub struct Interpreter {
     pub globals: Rc<RefCell<Environment>>,
+    /// The object each object member belongs to, by the member's qualified name
+    object_members: RefCell<HashMap<String, Rc<ObjectInstance>>>,
+    /// Names of the functions declared `suspend`
+    suspend_functions: RefCell<HashSet<String>>,
+    /// The event loops of the `runBlocking` calls in progress, innermost last
+    event_loops: RefCell<Vec<EventLoop>>,
+    /// Threads that haven't started yet, oldest first
+    threads: RefCell<VecDeque<Rc<Thread>>>,
+    /// Methods of host objects, by type name and method name
+    host_methods: RefCell<HashMap<(String, String), Rc<HostFunction>>>,
+    /// What the run has used of its limits
+    budget: Budget,
+    /// The streams scripts print to and read from
+    io: RefCell<Io>,
+    /// The calls the last error unwound through
+    unwinding: Unwinding,
+    debugging: Debugging,
+    profiling: Profiling,
+    covering: Covering,
 }
 
 impl Interpreter {
     pub fn new() -> Self {
         let globals = Rc::new(RefCell::new(Environment::new()));
 
-        // Define native println
+        // Console I/O, through the interpreter's `Io`
         globals.borrow_mut().define(
             "println".to_string(),
-            Value::NativeFunction(|args| {
-                for arg in args {
-                    print!("{} ", arg);
-                }
-                println!();
+            Value::NativeFunction(|interpreter, args| {
+                let line = Interpreter::printed(&args) + "\n";
+                io::write(&mut interpreter.io.borrow_mut().stdout, &line)?;
                 Ok(Value::Void)
             }),
         );
+        globals.borrow_mut().define(
+            "print".to_string(),
+            Value::NativeFunction(|interpreter, args| {
+                let text = Interpreter::printed(&args);
+                io::write(&mut interpreter.io.borrow_mut().stdout, &text)?;
+                Ok(Value::Void)
+            }),
+        );
+        globals.borrow_mut().define(
+            "eprintln".to_string(),
+            Value::NativeFunction(|interpreter, args| {
+                let line = Interpreter::printed(&args) + "\n";
+                io::write(&mut interpreter.io.borrow_mut().stderr, &line)?;
+                Ok(Value::Void)
+            }),
+        );
+        globals.borrow_mut().define(
+            "eprint".to_string(),
+            Value::NativeFunction(|interpreter, args| {
+                let text = Interpreter::printed(&args);
+                io::write(&mut interpreter.io.borrow_mut().stderr, &text)?;
+                Ok(Value::Void)
+            }),
+        );
+        globals.borrow_mut().define(
+            "readLine".to_string(),
+            Value::NativeFunction(|interpreter, _| {
+                let line = interpreter.io.borrow_mut().read_line()?;
+                Ok(line.map_or(Value::Null, Value::String))
+            }),
+        );
 
         // Define math functions
         globals.borrow_mut().define(
             "abs".to_string(),
-            Value::NativeFunction(|args| {
+            Value::NativeFunction(|_, args| {
                 if args.len() != 1 {
                     return Err(RuntimeError::ArgumentCount {
                         expected: 1,
@@ -461,7 +1881,7 @@ impl Interpreter {
                     });
                 }
                 match &args[0] {
-                    Value::Float(f) => Ok(Value::Float(f.abs())),
+                    Value::Double(f) => Ok(Value::Double(f.abs())),
                     Value::Integer(i) => Ok(Value::Integer(i.abs())),
                     _ => Err(RuntimeError::TypeMismatch(
                         "abs() expects a number".to_string(),
@@ -472,7 +1892,7 @@ impl Interpreter {
 
         globals.borrow_mut().define(
             "min".to_string(),
-            Value::NativeFunction(|args| {
+            Value::NativeFunction(|_, args| {
                 if args.len() != 2 {
                     return Err(RuntimeError::ArgumentCount {
                         expected: 2,
@@ -480,10 +1900,10 @@ impl Interpreter {
                     });
                 }
                 match (&args[0], &args[1]) {
-                    (Value::Float(a), Value::Float(b)) => Ok(Value::Float(a.min(*b))),
+                    (Value::Double(a), Value::Double(b)) => Ok(Value::Double(a.min(*b))),
                     (Value::Integer(a), Value::Integer(b)) => Ok(Value::Integer((*a).min(*b))),
-                    (Value::Float(a), Value::Integer(b)) => Ok(Value::Float(a.min(*b as f64))),
-                    (Value::Integer(a), Value::Float(b)) => Ok(Value::Float((*a as f64).min(*b))),
+                    (Value::Double(a), Value::Integer(b)) => Ok(Value::Double(a.min(*b as f64))),
+                    (Value::Integer(a), Value::Double(b)) => Ok(Value::Double((*a as f64).min(*b))),
                     _ => Err(RuntimeError::TypeMismatch(
                         "min() expects two numbers".to_string(),
                     )),
@@ -493,7 +1913,7 @@ impl Interpreter {
 
         globals.borrow_mut().define(
             "max".to_string(),
-            Value::NativeFunction(|args| {
+            Value::NativeFunction(|_, args| {
                 if args.len() != 2 {
                     return Err(RuntimeError::ArgumentCount {
                         expected: 2,
@@ -501,10 +1921,10 @@ impl Interpreter {
                     });
                 }
                 match (&args[0], &args[1]) {
-                    (Value::Float(a), Value::Float(b)) => Ok(Value::Float(a.max(*b))),
+                    (Value::Double(a), Value::Double(b)) => Ok(Value::Double(a.max(*b))),
                     (Value::Integer(a), Value::Integer(b)) => Ok(Value::Integer((*a).max(*b))),
-                    (Value::Float(a), Value::Integer(b)) => Ok(Value::Float(a.max(*b as f64))),
-                    (Value::Integer(a), Value::Float(b)) => Ok(Value::Float((*a as f64).max(*b))),
+                    (Value::Double(a), Value::Integer(b)) => Ok(Value::Double(a.max(*b as f64))),
+                    (Value::Integer(a), Value::Double(b)) => Ok(Value::Double((*a as f64).max(*b))),
                     _ => Err(RuntimeError::TypeMismatch(
                         "max() expects two numbers".to_string(),
                     )),
@@ -514,7 +1934,7 @@ impl Interpreter {
 
         globals.borrow_mut().define(
             "sqrt".to_string(),
-            Value::NativeFunction(|args| {
+            Value::NativeFunction(|_, args| {
                 if args.len() != 1 {
                     return Err(RuntimeError::ArgumentCount {
                         expected: 1,
@@ -522,13 +1942,13 @@ impl Interpreter {
                     });
                 }
                 match &args[0] {
-                    Value::Float(f) => {
+                    Value::Double(f) => {
                         if *f < 0.0 {
                             Err(RuntimeError::TypeMismatch(
                                 "sqrt() expects a non-negative number".to_string(),
                             ))
                         } else {
-                            Ok(Value::Float(f.sqrt()))
+                            Ok(Value::Double(f.sqrt()))
                         }
                     }
                     Value::Integer(i) => {
@@ -537,7 +1957,7 @@ impl Interpreter {
                                 "sqrt() expects a non-negative number".to_string(),
                             ))
                         } else {
-                            Ok(Value::Float((*i as f64).sqrt()))
+                            Ok(Value::Double((*i as f64).sqrt()))
                         }
                     }
                     _ => Err(RuntimeError::TypeMismatch(
@@ -549,7 +1969,7 @@ impl Interpreter {
 
         globals.borrow_mut().define(
             "pow".to_string(),
-            Value::NativeFunction(|args| {
+            Value::NativeFunction(|_, args| {
                 if args.len() != 2 {
                     return Err(RuntimeError::ArgumentCount {
                         expected: 2,
@@ -557,11 +1977,13 @@ impl Interpreter {
                     });
                 }
                 match (&args[0], &args[1]) {
-                    (Value::Float(a), Value::Float(b)) => Ok(Value::Float(a.powf(*b))),
-                    (Value::Float(a), Value::Integer(b)) => Ok(Value::Float(a.powf(*b as f64))),
-                    (Value::Integer(a), Value::Float(b)) => Ok(Value::Float((*a as f64).powf(*b))),
+                    (Value::Double(a), Value::Double(b)) => Ok(Value::Double(a.powf(*b))),
+                    (Value::Double(a), Value::Integer(b)) => Ok(Value::Double(a.powf(*b as f64))),
+                    (Value::Integer(a), Value::Double(b)) => {
+                        Ok(Value::Double((*a as f64).powf(*b)))
+                    }
                     (Value::Integer(a), Value::Integer(b)) => {
-                        Ok(Value::Float((*a as f64).powf(*b as f64)))
+                        Ok(Value::Double((*a as f64).powf(*b as f64)))
                     }
                     _ => Err(RuntimeError::TypeMismatch(
                         "pow() expects two numbers".to_string(),
@@ -572,7 +1994,7 @@ impl Interpreter {
 
         globals.borrow_mut().define(
             "sin".to_string(),
-            Value::NativeFunction(|args| {
+            Value::NativeFunction(|_, args| {
                 if args.len() != 1 {
                     return Err(RuntimeError::ArgumentCount {
                         expected: 1,
@@ -580,8 +2002,8 @@ impl Interpreter {
                     });
                 }
                 match &args[0] {
-                    Value::Float(f) => Ok(Value::Float(f.sin())),
-                    Value::Integer(i) => Ok(Value::Float((*i as f64).sin())),
+                    Value::Double(f) => Ok(Value::Double(f.sin())),
+                    Value::Integer(i) => Ok(Value::Double((*i as f64).sin())),
                     _ => Err(RuntimeError::TypeMismatch(
                         "sin() expects a number".to_string(),
                     )),
@@ -591,7 +2013,7 @@ impl Interpreter {
 
         globals.borrow_mut().define(
             "cos".to_string(),
-            Value::NativeFunction(|args| {
+            Value::NativeFunction(|_, args| {
                 if args.len() != 1 {
                     return Err(RuntimeError::ArgumentCount {
                         expected: 1,
@@ -599,8 +2021,8 @@ impl Interpreter {
                     });
                 }
                 match &args[0] {
-                    Value::Float(f) => Ok(Value::Float(f.cos())),
-                    Value::Integer(i) => Ok(Value::Float((*i as f64).cos())),
+                    Value::Double(f) => Ok(Value::Double(f.cos())),
+                    Value::Integer(i) => Ok(Value::Double((*i as f64).cos())),
                     _ => Err(RuntimeError::TypeMismatch(
                         "cos() expects a number".to_string(),
                     )),
@@ -610,7 +2032,7 @@ impl Interpreter {
 
         globals.borrow_mut().define(
             "tan".to_string(),
-            Value::NativeFunction(|args| {
+            Value::NativeFunction(|_, args| {
                 if args.len() != 1 {
                     return Err(RuntimeError::ArgumentCount {
                         expected: 1,
@@ -618,8 +2040,8 @@ impl Interpreter {
                     });
                 }
                 match &args[0] {
-                    Value::Float(f) => Ok(Value::Float(f.tan())),
-                    Value::Integer(i) => Ok(Value::Float((*i as f64).tan())),
+                    Value::Double(f) => Ok(Value::Double(f.tan())),
+                    Value::Integer(i) => Ok(Value::Double((*i as f64).tan())),
                     _ => Err(RuntimeError::TypeMismatch(
                         "tan() expects a number".to_string(),
                     )),
@@ -627,7 +2049,135 @@ impl Interpreter {
             }),
         );
 
-        Self { globals }
+        // Concurrency primitives
+        globals.borrow_mut().define(
+            "AtomicInt".to_string(),
+            Value::NativeFunction(|_, args| match args.as_slice() {
+                [Value::Integer(initial)] => Ok(Value::AtomicInt(Rc::new(Cell::new(*initial)))),
+                _ => Err(RuntimeError::TypeMismatch(
+                    "AtomicInt() expects an Int".to_string(),
+                )),
+            }),
+        );
+        globals.borrow_mut().define(
+            "Mutex".to_string(),
+            Value::NativeFunction(|_, _| Ok(Value::Mutex(Rc::default()))),
+        );
+        globals.borrow_mut().define(
+            "Channel".to_string(),
+            Value::NativeFunction(|_, _| Ok(Value::Channel(Rc::default()))),
+        );
+
+        globals.borrow_mut().define(
+            "Pair".to_string(),
+            Value::NativeFunction(|_, args| {
+                if args.len() != 2 {
+                    return Err(RuntimeError::ArgumentCount {
+                        expected: 2,
+                        got: args.len(),
+                    });
+                }
+                Ok(Value::Tuple(args))
+            }),
+        );
+
+        globals.borrow_mut().define(
+            "Triple".to_string(),
+            Value::NativeFunction(|_, args| {
+                if args.len() != 3 {
+                    return Err(RuntimeError::ArgumentCount {
+                        expected: 3,
+                        got: args.len(),
+                    });
+                }
+                Ok(Value::Tuple(args))
+            }),
+        );
+
+        // Checked arithmetic, called as `Math.addExact(a, b)`
+        globals.borrow_mut().define(
+            "Math.addExact".to_string(),
+            Value::NativeFunction(|_, args| Interpreter::math_exact(&BinaryOp::Add, &args)),
+        );
+        globals.borrow_mut().define(
+            "Math.subtractExact".to_string(),
+            Value::NativeFunction(|_, args| Interpreter::math_exact(&BinaryOp::Sub, &args)),
+        );
+        globals.borrow_mut().define(
+            "Math.multiplyExact".to_string(),
+            Value::NativeFunction(|_, args| Interpreter::math_exact(&BinaryOp::Mul, &args)),
+        );
+        globals.borrow_mut().define(
+            "Math.negateExact".to_string(),
+            Value::NativeFunction(|_, args| {
+                if args.len() != 1 {
+                    return Err(RuntimeError::ArgumentCount {
+                        expected: 1,
+                        got: args.len(),
+                    });
+                }
+                let zero = args[0]
+                    .numeric_type()
+                    .and_then(|typ| Value::Integer(0).convert_numeric(typ))
+                    .unwrap_or(Value::Void);
+                Interpreter::math_exact(&BinaryOp::Sub, &[zero, args[0].clone()])
+            }),
+        );
+
+        Self {
+            globals,
+            object_members: RefCell::new(HashMap::new()),
+            suspend_functions: RefCell::new(HashSet::new()),
+            event_loops: RefCell::new(Vec::new()),
+            threads: RefCell::new(VecDeque::new()),
+            host_methods: RefCell::new(HashMap::new()),
+            budget: Budget::default(),
+            io: RefCell::new(Io::std()),
+            unwinding: Unwinding::default(),
+            debugging: Debugging::default(),
+            profiling: Profiling::default(),
+            covering: Covering::default(),
+        }
+    }
+
+    /// The text `print` and `println` write for their arguments: each as
+    /// Kotlin's `toString` spells it, separated by spaces.
+    fn printed(args: &[Value]) -> String {
+        let texts: Vec<String> = args
+            .iter()
+            .map(|arg| match arg {
+                Value::Char(c) => c.to_string(),
+                arg => arg.to_string(),
+            })
+            .collect();
+        texts.join(" ")
+    }
+
+    /// `Math.addExact` and friends: the plain operator on two `Int`s or two
+    /// `Long`s, except that overflow is an `ArithmeticException`.
+    fn math_exact(op: &BinaryOp, args: &[Value]) -> Result<Value, RuntimeError> {
+        macro_rules! checked {
+            ($a:expr, $b:expr, $variant:ident, $typ:expr) => {
+                match op {
+                    BinaryOp::Add => $a.checked_add($b),
+                    BinaryOp::Sub => $a.checked_sub($b),
+                    _ => $a.checked_mul($b),
+                }
+                .map(Value::$variant)
+                .ok_or_else(|| RuntimeError::ArithmeticException(format!("{} overflow", $typ)))
+            };
+        }
+        match args {
+            [Value::Integer(a), Value::Integer(b)] => checked!(a, *b, Integer, "integer"),
+            [Value::Long(a), Value::Long(b)] => checked!(a, *b, Long, "long"),
+            [_, _] => Err(RuntimeError::TypeMismatch(
+                "Exact arithmetic takes two Ints or two Longs".to_string(),
+            )),
+            _ => Err(RuntimeError::ArgumentCount {
+                expected: 2,
+                got: args.len(),
+            }),
+        }
     }
 }
 
@@ -639,30 +2189,224 @@ impl Default for Interpreter {
 
 impl Interpreter {
     pub fn interpret_program(&mut self, program: &Program) -> Result<(), RuntimeError> {
-        for decl in &program.declarations {
-            self.execute_declaration(decl, self.globals.clone())?;
+        self.interpret_modules(std::slice::from_ref(program))
+    }
+
+    /// Runs a program made of several modules, calling `main` of the entry module `modules[0]`.
+    /// Top-level functions are defined under their qualified names, which the type checker
+    /// has already substituted at every call site. Private functions live in an environment
+    /// of their module's own, which its functions close over.
+    pub fn interpret_modules(&mut self, modules: &[Program]) -> Result<(), RuntimeError> {
+        self.unwinding.take();
+        let mut entry_env = None;
+        for module in modules {
+            let module_env = Rc::new(RefCell::new(Environment::with_enclosing(
+                self.globals.clone(),
+            )));
+            for decl in &module.declarations {
+                self.define_declaration(decl, module, &module_env)?;
+            }
+            entry_env.get_or_insert(module_env);
         }
+        let (Some(entry), Some(entry_env)) = (modules.first(), entry_env) else {
+            return Ok(());
+        };
 
         // Look for main function and run it
-        let main = self.globals.borrow().get("main");
-        if let Ok(Value::Function {
-            declaration: _,
-            closure: _,
-        }) = main
-        {
-            // We can't reuse execute_expression for this easily without mocking AST,
-            // so let's just call it directly
-            self.call_value(main.unwrap(), vec![])?;
+        let main = entry_env.borrow().get(&entry.qualified_name("main"));
+        if let Ok(Value::Function { declaration, .. }) = &main {
+            if declaration.is_suspend {
+                self.run_suspend_main(main.unwrap())?;
+            } else {
+                // We can't reuse execute_expression for this easily without mocking AST,
+                // so let's just call it directly
+                self.call_value(main.unwrap(), vec![])?;
+            }
         }
 
+        // Like Kotlin, the program ends once every thread has
+        self.join_all_threads()
+    }
+
+    /// Initializes the object `name` is a member of, if it has not been used yet.
+    /// Like Kotlin, an object whose initializers refer back to it sees itself
+    /// partly initialized rather than deadlocking.
+    fn initialize_object_of(&self, name: &str) -> Result<(), RuntimeError> {
+        let Some(object) = self.object_members.borrow().get(name).cloned() else {
+            return Ok(());
+        };
+        if object.initialized.replace(true) {
+            return Ok(());
+        }
+        for property in &object.declaration.properties {
+            // A `lateinit` property is `null` until assigned; a lazy one is
+            // defined on first use
+            let value = match &property.initializer {
+                Some(initializer) => {
+                    self.evaluate_expression(initializer, object.closure.clone())?
+                }
+                None if property.lateinit => Value::Null,
+                None => continue,
+            };
+            object
+                .scope
+                .borrow_mut()
+                .define(format!("{}.{}", object.name, property.name), value);
+        }
         Ok(())
     }
 
+    /// The object of the property `name` refers to, e.g. `Registry.items`,
+    /// and the property's index in it.
+    fn object_property(&self, name: &str) -> Option<(Rc<ObjectInstance>, usize)> {
+        let object = self.object_members.borrow().get(name).cloned()?;
+        let member = name.strip_prefix(&object.name)?.strip_prefix('.')?;
+        let index = object
+            .declaration
+            .properties
+            .iter()
+            .position(|property| property.name == member)?;
+        Some((object, index))
+    }
+
+    /// Defines the object property `name` if it is a `by lazy { }` property
+    /// used for the first time, and returns its value.
+    fn initialize_lazy_property(&self, name: &str) -> Result<Option<Value>, RuntimeError> {
+        let Some((object, index)) = self.object_property(name) else {
+            return Ok(None);
+        };
+        let Some(initializer) = object.declaration.properties[index].lazy_initializer() else {
+            return Ok(None);
+        };
+        let value = self.evaluate_block_value(initializer, object.closure.clone())?;
+        object
+            .scope
+            .borrow_mut()
+            .define(name.to_string(), value.clone());
+        Ok(Some(value))
+    }
+
+    /// The error for reading the object property `name` while it is `null`,
+    /// if it is a `lateinit` property.
+    fn uninitialized_property(&self, name: &str) -> Option<RuntimeError> {
+        let (object, index) = self.object_property(name)?;
+        let property = &object.declaration.properties[index];
+        property
+            .lateinit
+            .then(|| RuntimeError::UninitializedProperty(property.name.clone()))
+    }
+
+    /// Limits what the interpreter may use from now on.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.budget = Budget::new(limits);
+    }
+
+    /// Sends what scripts print to, and reads their input from, `io`
+    /// instead of the process's standard streams. Returns the previous ones.
+    pub fn set_io(&self, io: Io) -> Io {
+        self.io.replace(io)
+    }
+
+    /// The calls the last runtime error unwound through, innermost first,
+    /// which are forgotten once taken or when the next run starts.
+    pub fn take_stack_trace(&self) -> StackTrace {
+        self.unwinding.take()
+    }
+
+    /// Adds the frame of a call outside the interpreter, such as one the
+    /// bytecode VM runs, to the trace of the error unwinding now.
+    pub fn record_frame(&self, frame: StackFrame) {
+        self.unwinding.push(frame);
+    }
+
+    /// Counts a call outside the interpreter, such as one the bytecode VM
+    /// runs, against the limit on the call depth. `leave_call` must follow.
+    pub fn enter_call(&self) -> Result<(), RuntimeError> {
+        self.budget.enter()
+    }
+
+    pub fn leave_call(&self) {
+        self.budget.leave();
+    }
+
+    /// Counts an instruction run outside the interpreter against the limits
+    /// on instructions and time.
+    #[inline]
+    pub fn tick(&self) -> Result<(), RuntimeError> {
+        self.budget.tick()
+    }
+
+    /// Checks a value built outside the interpreter against the limit on
+    /// the size of collections and strings.
+    pub fn check_size(&self, value: &Value) -> Result<(), RuntimeError> {
+        self.budget.check_size(value)
+    }
+
+    /// Lets `debugger` stop the program before any statement and inspect it.
+    pub fn set_debugger(&self, debugger: impl Debugger + 'static) {
+        self.debugging.attach(Box::new(debugger));
+    }
+
+    /// Times the calls of declared functions and lambdas from now on.
+    pub fn enable_profiling(&self) {
+        self.profiling.enable();
+    }
+
+    /// What the calls timed since profiling started, or since the profile
+    /// was last taken, spent their time on.
+    pub fn take_profile(&self) -> Profile {
+        self.profiling.take()
+    }
+
+    /// Counts the runs of the lines and branches of `programs` from now on.
+    pub fn enable_coverage(&self, programs: &[Program]) {
+        self.covering.enable(programs);
+    }
+
+    /// The coverage of the lines and branches since coverage was enabled,
+    /// or since it was last taken.
+    pub fn take_coverage(&self) -> Coverage {
+        self.covering.take()
+    }
+
+    /// Defines the natives `capabilities` grant.
+    pub fn grant(&self, capabilities: Capabilities) {
+        for native in capabilities.natives() {
+            self.globals.borrow_mut().define(
+                native.name.to_string(),
+                Value::NativeFunction(native.function),
+            );
+        }
+    }
+
+    /// Defines a global function of the embedding application.
+    pub fn register_function(&self, function: HostFunction) {
+        self.globals.borrow_mut().define(
+            function.name.clone(),
+            Value::HostFunction(Rc::new(function)),
+        );
+    }
+
+    /// Adds a method to the host objects of type `type_name`; it is called
+    /// with the object as its first argument.
+    pub fn register_method(&self, type_name: &str, method: HostFunction) {
+        self.host_methods.borrow_mut().insert(
+            (type_name.to_string(), method.name.clone()),
+            Rc::new(method),
+        );
+    }
+
+    fn host_method(&self, object: &HostObject, method: &str) -> Option<Rc<HostFunction>> {
+        let key = (object.type_name.clone(), method.to_string());
+        self.host_methods.borrow().get(&key).cloned()
+    }
+
     pub fn interpret_declaration(&self, decl: &Declaration) -> Result<(), RuntimeError> {
         self.execute_declaration(decl, self.globals.clone())
     }
 
     pub fn interpret_statement(&self, stmt: &Statement) -> Result<Option<Value>, RuntimeError> {
+        self.unwinding.take();
         match stmt {
             Statement::Expression(expr) => {
                 let val = self.evaluate_expression(expr, self.globals.clone())?;
@@ -682,14 +2426,134 @@ impl Interpreter {
     ) -> Result<(), RuntimeError> {
         match decl {
             Declaration::Function(func) => {
-                let function = Value::Function {
-                    declaration: func.clone(),
-                    closure: env.clone(),
-                };
+                let function = Self::function(func, &env)?;
+                self.declare_suspend(func, &func.name);
                 env.borrow_mut().define(func.name.clone(), function);
-                Ok(())
             }
+            Declaration::Object(object) => {
+                self.define_object(object, object.name.clone(), &env)?;
+            }
+            Declaration::ValueClass(class) => {
+                for func in &class.functions {
+                    let function = Self::function(func, &env)?;
+                    let name = format!("{}.{}", class.name, func.name);
+                    self.declare_suspend(func, &name);
+                    env.borrow_mut().define(name, function);
+                }
+                if let Some(companion) = &class.companion {
+                    self.define_object(companion, class.name.clone(), &env)?;
+                }
+            }
+            Declaration::TypeAlias(_) => {}
+        }
+        Ok(())
+    }
+
+    /// Defines a declaration of `module` under its qualified name. Private
+    /// declarations go in `module_env`, the rest in the globals.
+    fn define_declaration(
+        &self,
+        decl: &Declaration,
+        module: &Program,
+        module_env: &Rc<RefCell<Environment>>,
+    ) -> Result<(), RuntimeError> {
+        let scope = |visibility| {
+            if visibility == Visibility::Private {
+                module_env
+            } else {
+                &self.globals
+            }
+        };
+        match decl {
+            Declaration::Function(func) => {
+                let function = Self::function(func, module_env)?;
+                self.declare_suspend(func, &module.qualified_name(&func.name));
+                scope(func.visibility)
+                    .borrow_mut()
+                    .define(module.qualified_name(&func.name), function);
+            }
+            Declaration::Object(object) => {
+                self.define_object(object, module.qualified_name(&object.name), module_env)?;
+            }
+            Declaration::ValueClass(class) => {
+                let class_name = module.qualified_name(&class.name);
+                for func in &class.functions {
+                    let function = Self::function(func, module_env)?;
+                    let name = format!("{}.{}", class_name, func.name);
+                    self.declare_suspend(func, &name);
+                    let visibility = if class.visibility == Visibility::Private {
+                        Visibility::Private
+                    } else {
+                        func.visibility
+                    };
+                    scope(visibility).borrow_mut().define(name, function);
+                }
+                if let Some(companion) = &class.companion {
+                    self.define_object(companion, class_name, module_env)?;
+                }
+            }
+            // The type checker has replaced the alias wherever it is used
+            Declaration::TypeAlias(_) => {}
+        }
+        Ok(())
+    }
+
+    /// A declared function closing over `closure`, with its locals resolved.
+    fn function(
+        func: &FunctionDecl,
+        closure: &Rc<RefCell<Environment>>,
+    ) -> Result<Value, RuntimeError> {
+        let mut declaration = func.clone();
+        resolve_function(&mut declaration)?;
+        Ok(Value::Function {
+            declaration: Rc::new(declaration),
+            closure: closure.clone(),
+        })
+    }
+
+    /// Records that calls to `name` suspend, if `func` is a suspend function.
+    fn declare_suspend(&self, func: &FunctionDecl, name: &str) {
+        if func.is_suspend {
+            self.suspend_functions.borrow_mut().insert(name.to_string());
+        }
+    }
+
+    /// Defines an object's singleton and member functions; `closure` is the
+    /// environment of the declaring module.
+    fn define_object(
+        &self,
+        object: &ObjectDecl,
+        name: String,
+        closure: &Rc<RefCell<Environment>>,
+    ) -> Result<(), RuntimeError> {
+        let scope = if object.visibility == Visibility::Private {
+            closure.clone()
+        } else {
+            self.globals.clone()
+        };
+        let instance = Rc::new(ObjectInstance {
+            name: name.clone(),
+            declaration: object.clone(),
+            scope: scope.clone(),
+            closure: closure.clone(),
+            initialized: Cell::new(false),
+        });
+        scope
+            .borrow_mut()
+            .define(name.clone(), Value::Object(instance.clone()));
+
+        let mut members = self.object_members.borrow_mut();
+        for func in object.functions.iter().chain(object.accessors()) {
+            let member = format!("{}.{}", name, func.name);
+            let function = Self::function(func, closure)?;
+            self.declare_suspend(func, &member);
+            scope.borrow_mut().define(member.clone(), function);
+            members.insert(member, instance.clone());
+        }
+        for property in &object.properties {
+            members.insert(format!("{}.{}", name, property.name), instance.clone());
         }
+        Ok(())
     }
 
     fn execute_statement(
@@ -697,6 +2561,8 @@ impl Interpreter {
         stmt: &Statement,
         env: Rc<RefCell<Environment>>,
     ) -> Result<(), RuntimeError> {
+        self.debugging.statement(self, stmt, &env);
+        self.covering.statement(stmt);
         match stmt {
             Statement::Expression(expr) => {
                 self.evaluate_expression(expr, env)?;
@@ -712,7 +2578,17 @@ impl Interpreter {
                 } else {
                     Value::Void // Or Error for uninitialized?
                 };
-                env.borrow_mut().define(name.clone(), value);
+                env.borrow_mut().define(name, value);
+                Ok(())
+            }
+            Statement::Destructuring { names, initializer } => {
+                let value = self.evaluate_expression(initializer, env.clone())?;
+                let components = Self::destructure(value, names.len())?;
+                for (name, component) in names.iter().zip(components) {
+                    if name != "_" {
+                        env.borrow_mut().define(name, component);
+                    }
+                }
                 Ok(())
             }
             Statement::Return(expr) => {
@@ -724,7 +2600,7 @@ impl Interpreter {
                 Err(RuntimeError::Return(Box::new(value)))
             }
             Statement::Block(block) => {
-                let new_env = Rc::new(RefCell::new(Environment::with_enclosing(env)));
+                let new_env = Rc::new(RefCell::new(Environment::for_block(block, env)));
                 self.execute_block(block, new_env)
             }
             Statement::If {
@@ -734,6 +2610,7 @@ impl Interpreter {
             } => {
                 let cond = self.evaluate_expression(condition, env.clone())?;
                 if let Value::Boolean(b) = cond {
+                    self.covering.branch(condition.line, b);
                     if b {
                         self.execute_statement(then_branch, env)?;
                     } else if let Some(els) = else_branch {
@@ -746,10 +2623,29 @@ impl Interpreter {
                     ))
                 }
             }
+            Statement::DoWhile { body, condition } => loop {
+                // The condition sees the variables declared in the body
+                let body_env = Rc::new(RefCell::new(Environment::for_block(body, env.clone())));
+                self.execute_block(body, body_env.clone())?;
+                let cond = self.evaluate_expression(condition, body_env)?;
+                if let Value::Boolean(b) = cond {
+                    self.covering.branch(condition.line, b);
+                }
+                match cond {
+                    Value::Boolean(true) => {}
+                    Value::Boolean(false) => return Ok(()),
+                    _ => {
+                        return Err(RuntimeError::TypeMismatch(
+                            "Condition must be boolean".to_string(),
+                        ))
+                    }
+                }
+            },
             Statement::While { condition, body } => {
                 loop {
                     let cond = self.evaluate_expression(condition, env.clone())?;
                     if let Value::Boolean(b) = cond {
+                        self.covering.branch(condition.line, b);
                         if !b {
                             break;
                         }
@@ -768,42 +2664,30 @@ impl Interpreter {
                 body,
             } => {
                 let iterable_val = self.evaluate_expression(iterable, env.clone())?;
+                // The scope of every iteration holds the loop's variables
+                let locals: Arc<[String]> = match variable {
+                    ForEachTarget::Ident(name) => Arc::new([name.clone()]),
+                    ForEachTarget::Tuple(names) => {
+                        names.iter().filter(|name| *name != "_").cloned().collect()
+                    }
+                };
 
                 match iterable_val {
+                    // Like Kotlin's for loop over a list, which sees the elements as they are
+                    // when it starts
                     Value::Array(elements) => {
+                        let elements = elements.borrow().clone();
                         for element in elements {
-                            let new_env =
-                                Rc::new(RefCell::new(Environment::with_enclosing(env.clone())));
-                            match variable {
-                                dotlin_ast::ForEachTarget::Ident(name) => {
-                                    new_env.borrow_mut().define(name.clone(), element);
-                                }
-                                dotlin_ast::ForEachTarget::Tuple(names) => {
-                                    // Expect each element to be an array to destructure
-                                    if let Value::Array(inner) = element {
-                                        if inner.len() != names.len() {
-                                            return Err(RuntimeError::TypeMismatch(
-                                                "Destructuring assignment length mismatch"
-                                                    .to_string(),
-                                            ));
-                                        }
-                                        for (i, n) in names.iter().enumerate() {
-                                            new_env
-                                                .borrow_mut()
-                                                .define(n.clone(), inner[i].clone());
-                                        }
-                                    } else {
-                                        return Err(RuntimeError::TypeMismatch(
-                                            "Destructuring target requires array elements"
-                                                .to_string(),
-                                        ));
-                                    }
-                                }
-                            }
+                            let new_env = Rc::new(RefCell::new(Environment::with_locals(
+                                locals.clone(),
+                                env.clone(),
+                            )));
+                            Self::bind_loop_variable(variable, element, &new_env)?;
                             self.execute_statement(body, new_env)?;
                         }
                     }
                     Value::HashMap(map) => {
+                        let map = map.borrow().clone();
                         // If destructuring into a tuple of (key, value), iterate entries
                         if let dotlin_ast::ForEachTarget::Tuple(names) = variable {
                             if names.len() != 2 {
@@ -813,22 +2697,25 @@ impl Interpreter {
                                 ));
                             }
                             for (key, value) in map.iter() {
-                                let new_env =
-                                    Rc::new(RefCell::new(Environment::with_enclosing(env.clone())));
-                                new_env
-                                    .borrow_mut()
-                                    .define(names[0].clone(), Value::String(key.clone()));
-                                new_env.borrow_mut().define(names[1].clone(), value.clone());
+                                let new_env = Rc::new(RefCell::new(Environment::with_locals(
+                                    locals.clone(),
+                                    env.clone(),
+                                )));
+                                let entry =
+                                    Value::Tuple(vec![Value::String(key.clone()), value.clone()]);
+                                Self::bind_loop_variable(variable, entry, &new_env)?;
                                 self.execute_statement(body, new_env)?;
                             }
                         } else if let dotlin_ast::ForEachTarget::Ident(name) = variable {
                             // For HashMap iteration, iterate over the keys
                             for key in map.keys() {
-                                let new_env =
-                                    Rc::new(RefCell::new(Environment::with_enclosing(env.clone())));
+                                let new_env = Rc::new(RefCell::new(Environment::with_locals(
+                                    locals.clone(),
+                                    env.clone(),
+                                )));
                                 new_env
                                     .borrow_mut()
-                                    .define(name.clone(), Value::String(key.clone()));
+                                    .define(name, Value::String(key.clone()));
                                 self.execute_statement(body, new_env)?;
                             }
                         }
@@ -850,31 +2737,35 @@ impl Interpreter {
                         }
 
                         let element = next_opt.unwrap();
-                        let new_env =
-                            Rc::new(RefCell::new(Environment::with_enclosing(env.clone())));
-                        match variable {
-                            dotlin_ast::ForEachTarget::Ident(name) => {
-                                new_env.borrow_mut().define(name.clone(), element);
-                            }
-                            dotlin_ast::ForEachTarget::Tuple(names) => {
-                                if let Value::Array(inner) = element {
-                                    if inner.len() != names.len() {
-                                        return Err(RuntimeError::TypeMismatch(
-                                            "Destructuring assignment length mismatch".to_string(),
-                                        ));
-                                    }
-                                    for (i, n) in names.iter().enumerate() {
-                                        new_env.borrow_mut().define(n.clone(), inner[i].clone());
-                                    }
-                                } else {
-                                    return Err(RuntimeError::TypeMismatch(
-                                        "Destructuring target requires array elements".to_string(),
-                                    ));
-                                }
-                            }
-                        }
+                        let new_env = Rc::new(RefCell::new(Environment::with_locals(
+                            locals.clone(),
+                            env.clone(),
+                        )));
+                        Self::bind_loop_variable(variable, element, &new_env)?;
                         self.execute_statement(body, new_env)?;
                     },
+                    Value::Sequence(sequence) => {
+                        let mut elements = self.iterate(&sequence);
+                        while let Some(element) = self.next_element(&mut elements)? {
+                            let new_env = Rc::new(RefCell::new(Environment::with_locals(
+                                locals.clone(),
+                                env.clone(),
+                            )));
+                            Self::bind_loop_variable(variable, element, &new_env)?;
+                            self.execute_statement(body, new_env)?;
+                        }
+                    }
+                    // Until the channel is closed and every element received
+                    Value::Channel(channel) => {
+                        while let Some(element) = self.receive(&channel)? {
+                            let new_env = Rc::new(RefCell::new(Environment::with_locals(
+                                locals.clone(),
+                                env.clone(),
+                            )));
+                            Self::bind_loop_variable(variable, element, &new_env)?;
+                            self.execute_statement(body, new_env)?;
+                        }
+                    }
                     _ => {
                         return Err(RuntimeError::TypeMismatch(
                             "ForEach iterable must be an array or HashMap".to_string(),
@@ -891,23 +2782,79 @@ impl Interpreter {
         expr: &Expression,
         env: Rc<RefCell<Environment>>,
     ) -> Result<Value, RuntimeError> {
+        let result = self.evaluate_kind(expr, env);
+        if let Err(e) = &result {
+            self.unwinding.leave_expression(e, expr.line);
+        }
+        result
+    }
+
+    fn evaluate_kind(
+        &self,
+        expr: &Expression,
+        env: Rc<RefCell<Environment>>,
+    ) -> Result<Value, RuntimeError> {
+        self.budget.tick()?;
         match &*expr.kind {
-            ExpressionKind::Literal(lit) => Ok(match lit {
-                Literal::Integer(i) => Value::Integer(*i),
-                Literal::Float(f) => Value::Float(*f),
-                Literal::String(s) => Value::String(s.clone()),
-                Literal::Boolean(b) => Value::Boolean(*b),
-                Literal::Char(c) => Value::Char(*c),
-            }),
-            ExpressionKind::Variable(name) => env.borrow().get(name),
-            ExpressionKind::Assignment { name, value } => {
-                let val = self.evaluate_expression(value, env.clone())?;
-                env.borrow_mut().assign(name.clone(), val.clone())?;
+            ExpressionKind::Literal(lit) => Ok(Self::literal(lit, expr.resolved_type.as_ref())),
+            ExpressionKind::Variable(name) => {
+                if let Some(value) = expr.slot.and_then(|slot| env.borrow().get_at(slot)) {
+                    return Ok(value);
+                }
+                self.initialize_object_of(name)?;
+                let value = env.borrow().get(name);
+                match value {
+                    Err(e) if name == "currentTime" => self.current_time().ok_or(e),
+                    Err(e) => self.initialize_lazy_property(name)?.ok_or(e),
+                    Ok(Value::Null) => match self.uninitialized_property(name) {
+                        Some(e) => Err(e),
+                        None => Ok(Value::Null),
+                    },
+                    value => value,
+                }
+            }
+            ExpressionKind::Assignment {
+                target,
+                operator,
+                value,
+            } => {
+                let place = self.place(target, env.clone())?;
+                let val = match operator {
+                    Some(operator) => {
+                        let current = Self::read_place(&place, &env)?;
+                        let value = self.evaluate_expression(value, env.clone())?;
+                        Self::evaluate_binary(current, operator, value)?
+                    }
+                    None => self.evaluate_expression(value, env.clone())?,
+                };
+                self.write_place(place, val.clone(), &env)?;
                 Ok(val)
             }
             ExpressionKind::Call { callee, arguments } => {
+                if let Some((name, receiver, lambda)) =
+                    scope_function(callee, arguments).filter(|_| match &*callee.kind {
+                        ExpressionKind::Variable(name) => env.borrow().get(name).is_err(),
+                        _ => true,
+                    })
+                {
+                    return self.call_scope_function(name, receiver, lambda, env);
+                }
                 // Handle method calls (obj.method()) which are represented as Call with MemberAccess callee
                 if let ExpressionKind::MemberAccess { object, member } = &*callee.kind {
+                    // `Math.addExact(a, b)` calls a native unless `Math` is a variable
+                    if matches!(&*object.kind, ExpressionKind::Variable(name) if name == "Math")
+                        && env.borrow().get("Math").is_err()
+                    {
+                        let function = env.borrow().get(&format!("Math.{}", member))?;
+                        let args = arguments
+                            .iter()
+                            .map(|arg| self.evaluate_expression(arg, env.clone()))
+                            .collect::<Result<Vec<_>, _>>()?;
+                        return match function {
+                            Value::NativeFunction(f) => f(self, args),
+                            _ => Err(RuntimeError::NotAFunction(format!("Math.{}", member))),
+                        };
+                    }
                     // This is a method call on an object
                     let obj_val = self.evaluate_expression(object, env.clone())?;
                     let _args: Vec<Value> = arguments
@@ -915,98 +2862,29 @@ impl Interpreter {
                         .map(|arg| self.evaluate_expression(arg, env.clone()))
                         .collect::<Result<Vec<_>, _>>()?;
 
-                    // Handle type conversion methods and HashMap iteration methods
-                    match (obj_val, member.as_str()) {
-                        (Value::String(s), "toInt") => match s.parse::<i64>() {
-                            Ok(num) => Ok(Value::Integer(num)),
-                            Err(_) => Err(RuntimeError::TypeMismatch(format!(
-                                "Cannot convert string '{}' to integer",
-                                s
-                            ))),
-                        },
-                        (Value::String(s), "toFloat") => match s.parse::<f64>() {
-                            Ok(num) => Ok(Value::Float(num)),
-                            Err(_) => Err(RuntimeError::TypeMismatch(format!(
-                                "Cannot convert string '{}' to float",
-                                s
-                            ))),
-                        },
-                        (Value::Integer(n), "toFloat") => Ok(Value::Float(n as f64)),
-                        (Value::Float(f), "toInt") => Ok(Value::Integer(f as i64)),
-                        (Value::Integer(n), "toString") => Ok(Value::String(n.to_string())),
-                        (Value::Float(f), "toString") => Ok(Value::String(f.to_string())),
-                        (Value::Boolean(b), "toString") => Ok(Value::String(b.to_string())),
-                        (Value::Char(c), "toString") => Ok(Value::String(c.to_string())),
-                        // Array methods
-                        (Value::Array(mut elements), "push") => {
-                            if _args.len() != 1 {
-                                return Err(RuntimeError::TypeMismatch(
-                                    "push() expects 1 argument".to_string(),
-                                ));
-                            }
-                            elements.push(_args[0].clone());
-                            // Return void (or the new length if we want to follow JS conventions)
-                            Ok(Value::Array(elements))
-                        }
-                        (Value::Array(mut elements), "pop") => {
-                            if elements.is_empty() {
-                                return Err(RuntimeError::TypeMismatch(
-                                    "pop() called on empty array".to_string(),
-                                ));
-                            }
-                            let last_element = elements.pop().unwrap();
-                            Ok(last_element)
-                        }
-                        // HashMap iteration methods
-                        (Value::HashMap(map), "keys") => {
-                            let keys: Vec<Value> =
-                                map.keys().map(|k| Value::String(k.clone())).collect();
-                            Ok(Value::Array(keys))
-                        }
-                        (Value::HashMap(map), "iter") => {
-                            let mut entries = Vec::new();
-                            for (key, value) in map.iter() {
-                                let entry =
-                                    Value::Array(vec![Value::String(key.clone()), value.clone()]);
-                                entries.push(entry);
-                            }
-                            let it = IteratorState {
-                                items: entries,
-                                pos: 0,
-                            };
-                            Ok(Value::Iterator(Rc::new(RefCell::new(it))))
-                        }
-                        (Value::HashMap(map), "values") => {
-                            let values: Vec<Value> = map.values().cloned().collect();
-                            Ok(Value::Array(values))
-                        }
-                        (Value::HashMap(map), "size") => Ok(Value::Integer(map.len() as i64)),
-                        (Value::HashMap(map), "entries") => {
-                            let mut entries = Vec::new();
-                            for (key, value) in map.iter() {
-                                // Create an array with [key, value] for each entry
-                                let entry =
-                                    Value::Array(vec![Value::String(key.clone()), value.clone()]);
-                                entries.push(entry);
-                            }
-                            Ok(Value::Array(entries))
-                        }
-                        // Iterator next() on iterator objects
-                        (Value::Iterator(it_rc), "next") => {
-                            let mut it = it_rc.borrow_mut();
-                            if it.pos >= it.items.len() {
-                                Ok(Value::Void)
-                            } else {
-                                let v = it.items[it.pos].clone();
-                                it.pos += 1;
-                                Ok(v)
-                            }
-                        }
-                        (obj_val, method_name) => Err(RuntimeError::TypeMismatch(format!(
-                            "Method '{}' not found on type {:?}",
-                            method_name, obj_val
-                        ))),
-                    }
+                    let result = self.call_method(obj_val, member, _args)?;
+                    self.budget.check_size(&result)?;
+                    Ok(result)
+                } else if let Some(body) = sequence_body(callee, arguments)
+                    .filter(|_| env.borrow().get("sequence").is_err())
+                {
+                    Ok(Value::Sequence(Rc::new(Sequence::Builder {
+                        body: Step::compile(&body.statements, &sequence::is_yield),
+                        closure: self.capture(body, &env),
+                    })))
+                } else if let Some(builder) = coroutine::coroutine_builder(callee)
+                    .filter(|builder| env.borrow().get(builder).is_err())
+                {
+                    let body = match arguments.first() {
+                        Some(lambda) => self.evaluate_expression(lambda, env)?,
+                        None => Value::Void,
+                    };
+                    self.start_coroutine(builder, body)
+                } else if let Some(body) =
+                    thread_body(callee, arguments).filter(|_| env.borrow().get("thread").is_err())
+                {
+                    let body = self.evaluate_expression(body, env)?;
+                    Ok(self.start_thread(body))
                 } else {
                     // Regular function call
                     let callee_val = self.evaluate_expression(callee, env.clone())?;
@@ -1060,168 +2938,41 @@ impl Interpreter {
                             }
                         }
                     }
+                    BinaryOp::Elvis => match self.evaluate_expression(left, env.clone())? {
+                        Value::Null => self.evaluate_expression(right, env.clone()),
+                        value => Ok(value),
+                    },
                     // For all other operators, evaluate both operands normally
                     _ => {
                         let l = self.evaluate_expression(left, env.clone())?;
                         let r = self.evaluate_expression(right, env.clone())?;
-                        self.evaluate_binary(l, operator, r)
+                        let value = Self::evaluate_binary(l, operator, r)?;
+                        self.budget.check_size(&value)?;
+                        Ok(value)
                     }
                 }
             }
             ExpressionKind::Unary { operator, operand } => {
                 let val = self.evaluate_expression(operand, env)?;
-                match (operator, val) {
-                    (UnaryOp::Minus, Value::Integer(i)) => Ok(Value::Integer(-i)),
-                    (UnaryOp::Minus, Value::Float(f)) => Ok(Value::Float(-f)),
-                    (UnaryOp::Not, Value::Boolean(b)) => Ok(Value::Boolean(!b)),
-                    // Prefix increment and decrement (for now, treating as postfix since we handle both the same way)
-                    (UnaryOp::Increment, Value::Integer(i)) => Ok(Value::Integer(i + 1)),
-                    (UnaryOp::Decrement, Value::Integer(i)) => Ok(Value::Integer(i - 1)),
-                    (UnaryOp::Increment, Value::Float(f)) => Ok(Value::Float(f + 1.0)),
-                    (UnaryOp::Decrement, Value::Float(f)) => Ok(Value::Float(f - 1.0)),
-                    _ => Err(RuntimeError::TypeMismatch(
-                        "Invalid operand for unary operator".to_string(),
-                    )),
-                }
+                Self::evaluate_unary(operator, val)
             }
             ExpressionKind::MemberAccess { object, member } => {
                 let obj_val = self.evaluate_expression(object, env)?;
-                match (obj_val, member.as_str()) {
-                    (Value::String(s), "length") => Ok(Value::Integer(s.len() as i64)),
-                    // Type conversion methods
-                    (Value::String(s), "toInt") => match s.parse::<i64>() {
-                        Ok(num) => Ok(Value::Integer(num)),
-                        Err(_) => Err(RuntimeError::TypeMismatch(format!(
-                            "Cannot convert string '{}' to integer",
-                            s
-                        ))),
-                    },
-                    (Value::String(s), "toFloat") => match s.parse::<f64>() {
-                        Ok(num) => Ok(Value::Float(num)),
-                        Err(_) => Err(RuntimeError::TypeMismatch(format!(
-                            "Cannot convert string '{}' to float",
-                            s
-                        ))),
-                    },
-                    (Value::Integer(n), "toFloat") => Ok(Value::Float(n as f64)),
-                    (Value::Float(f), "toInt") => Ok(Value::Integer(f as i64)),
-                    (Value::Integer(n), "toString") => Ok(Value::String(n.to_string())),
-                    (Value::Float(f), "toString") => Ok(Value::String(f.to_string())),
-                    (Value::Boolean(b), "toString") => Ok(Value::String(b.to_string())),
-                    (Value::Char(c), "toString") => Ok(Value::String(c.to_string())),
-                    // Array methods
-                    (Value::Array(_elements), "push") => {
-                        // For member access, we don't have arguments, so this would be an error
-                        Err(RuntimeError::TypeMismatch(
-                            "Cannot call push() without arguments via member access".to_string(),
-                        ))
-                    }
-                    (Value::Array(_elements), "pop") => {
-                        // For member access, this would be an error since pop() should be callable
-                        Err(RuntimeError::TypeMismatch(
-                            "Cannot call pop() via member access".to_string(),
-                        ))
-                    }
-                    // HashMap iteration methods
-                    (Value::HashMap(map), "keys") => {
-                        let keys: Vec<Value> =
-                            map.keys().map(|k| Value::String(k.clone())).collect();
-                        Ok(Value::Array(keys))
-                    }
-                    (Value::HashMap(map), "iter") => {
-                        // Create iterator over entries
-                        let mut entries = Vec::new();
-                        for (key, value) in map.iter() {
-                            let entry =
-                                Value::Array(vec![Value::String(key.clone()), value.clone()]);
-                            entries.push(entry);
-                        }
-                        let it = IteratorState {
-                            items: entries,
-                            pos: 0,
-                        };
-                        Ok(Value::Iterator(Rc::new(RefCell::new(it))))
-                    }
-                    (Value::HashMap(map), "values") => {
-                        let values: Vec<Value> = map.values().cloned().collect();
-                        Ok(Value::Array(values))
-                    }
-                    (Value::HashMap(map), "size") => Ok(Value::Integer(map.len() as i64)),
-                    (Value::HashMap(map), "entries") => {
-                        let mut entries = Vec::new();
-                        for (key, value) in map.iter() {
-                            // Create an array with [key, value] for each entry
-                            let entry =
-                                Value::Array(vec![Value::String(key.clone()), value.clone()]);
-                            entries.push(entry);
-                        }
-                        Ok(Value::Array(entries))
-                    }
-                    (val, _) => Err(RuntimeError::TypeMismatch(format!(
-                        "Cannot access member '{}' on {:?}",
-                        member, val
-                    ))),
-                }
+                Self::member(obj_val, member)
             }
             ExpressionKind::ArrayLiteral { elements } => {
                 let mut values = Vec::new();
                 for element in elements {
                     values.push(self.evaluate_expression(element, env.clone())?);
                 }
-                Ok(Value::Array(values))
+                let array = Value::array(values);
+                self.budget.check_size(&array)?;
+                Ok(array)
             }
             ExpressionKind::Index { array, index } => {
                 let arr_val = self.evaluate_expression(array, env.clone())?;
                 let idx_val = self.evaluate_expression(index, env)?;
-
-                match (arr_val, idx_val) {
-                    (Value::Array(elements), Value::Integer(index)) => {
-                        let idx = index as usize;
-                        if idx < elements.len() {
-                            Ok(elements[idx].clone())
-                        } else {
-                            Err(RuntimeError::TypeMismatch(
-                                "Array index out of bounds".to_string(),
-                            ))
-                        }
-                    }
-                    (Value::String(s), Value::Integer(index)) => {
-                        let idx = index as usize;
-                        if idx < s.len() {
-                            // Return the character at the index as a Char value
-                            let ch = s.chars().nth(idx).unwrap();
-                            Ok(Value::Char(ch))
-                        } else {
-                            Err(RuntimeError::TypeMismatch(
-                                "String index out of bounds".to_string(),
-                            ))
-                        }
-                    }
-                    (Value::HashMap(map), Value::String(key)) => match map.get(&key) {
-                        Some(value) => Ok(value.clone()),
-                        None => Err(RuntimeError::TypeMismatch(format!(
-                            "Key '{}' not found in HashMap",
-                            key
-                        ))),
-                    },
-                    (Value::HashMap(map), Value::Integer(key)) => match map.get(&key.to_string()) {
-                        Some(value) => Ok(value.clone()),
-                        None => Err(RuntimeError::TypeMismatch(format!(
-                            "Key '{}' not found in HashMap",
-                            key
-                        ))),
-                    },
-                    (_, Value::Integer(_)) => Err(RuntimeError::TypeMismatch(
-                        "Indexing target is not an array or string".to_string(),
-                    )),
-                    (_, Value::String(_)) => Err(RuntimeError::TypeMismatch(
-                        "Indexing target is not a HashMap".to_string(),
-                    )),
-                    _ => Err(RuntimeError::TypeMismatch(
-                        "Index must be an integer for arrays/strings or string for HashMaps"
-                            .to_string(),
-                    )),
-                }
+                Self::index_value(arr_val, idx_val)
             }
             ExpressionKind::HashMapLiteral { pairs } => {
                 let mut map = std::collections::HashMap::new();
@@ -1229,25 +2980,498 @@ impl Interpreter {
                     let key = self.evaluate_expression(key_expr, env.clone())?;
                     let value = self.evaluate_expression(value_expr, env.clone())?;
 
-                    // Convert key to string if it's a string literal
-                    let key_str = match key {
-                        Value::String(s) => s,
-                        Value::Integer(i) => i.to_string(),
-                        _ => {
-                            return Err(RuntimeError::TypeMismatch(
-                                "HashMap key must be string or integer".to_string(),
-                            ))
-                        }
-                    };
+                    map.insert(Self::map_key(key)?, value);
+                }
+                let map = Value::map(map);
+                self.budget.check_size(&map)?;
+                Ok(map)
+            }
+            ExpressionKind::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => match self.evaluate_expression(condition, env.clone())? {
+                Value::Boolean(taken) => {
+                    self.covering.branch(condition.line, taken);
+                    match (taken, else_branch) {
+                        (true, _) => self.evaluate_block_value(then_branch, env),
+                        (false, Some(els)) => self.evaluate_block_value(els, env),
+                        (false, None) => Ok(Value::Void),
+                    }
+                }
+                _ => Err(RuntimeError::TypeMismatch(
+                    "Condition must be boolean".to_string(),
+                )),
+            },
+            ExpressionKind::Lambda { params, body } => Ok(Value::Function {
+                declaration: Rc::new(Self::lambda_declaration(params, body)),
+                closure: self.capture(body, &env),
+            }),
+            ExpressionKind::SafeAccess { object, access } => {
+                match self.evaluate_expression(object, env.clone())? {
+                    Value::Null => Ok(Value::Null),
+                    receiver => {
+                        let scope = Rc::new(RefCell::new(Environment::with_enclosing(env)));
+                        scope
+                            .borrow_mut()
+                            .define(SAFE_RECEIVER.to_string(), receiver);
+                        self.evaluate_expression(access, scope)
+                    }
+                }
+            }
+            ExpressionKind::Nullable(value) => self.evaluate_expression(value, env),
+        }
+    }
+
+    /// Runs the lambda of a scope function in place, with the receiver as
+    /// `this` or as the lambda's parameter.
+    fn call_scope_function(
+        &self,
+        name: &str,
+        receiver: Option<&Expression>,
+        lambda: &Expression,
+        env: Rc<RefCell<Environment>>,
+    ) -> Result<Value, RuntimeError> {
+        let ExpressionKind::Lambda { params, body } = &*lambda.kind else {
+            return Err(RuntimeError::NotAFunction(name.to_string()));
+        };
+        let receiver = match receiver {
+            Some(receiver) => Some(self.evaluate_expression(receiver, env.clone())?),
+            None => None,
+        };
+        let scope = Rc::new(RefCell::new(Environment::with_enclosing(env)));
+        if let Some(receiver) = &receiver {
+            let binding = params.first().map_or("this", |param| param.name.as_str());
+            scope
+                .borrow_mut()
+                .define(binding.to_string(), receiver.clone());
+        }
+        let value = self.evaluate_block_value(body, scope)?;
+        Ok(match (name, receiver) {
+            ("apply" | "also", Some(receiver)) => receiver,
+            ("takeIf", Some(receiver)) if value == Value::Boolean(true) => receiver,
+            ("takeUnless", Some(receiver)) if value == Value::Boolean(false) => receiver,
+            ("takeIf" | "takeUnless", _) => Value::Null,
+            _ => value,
+        })
+    }
+
+    /// Executes a block in a new scope and yields the value of its trailing expression.
+    fn evaluate_block_value(
+        &self,
+        block: &Block,
+        env: Rc<RefCell<Environment>>,
+    ) -> Result<Value, RuntimeError> {
+        let block_env = Rc::new(RefCell::new(Environment::for_block(block, env)));
+        let mut value = Value::Void;
+        for (i, stmt) in block.statements.iter().enumerate() {
+            match stmt {
+                Statement::Expression(expr) if i + 1 == block.statements.len() => {
+                    value = self.evaluate_expression(expr, block_env.clone())?;
+                }
+                _ => self.execute_statement(stmt, block_env.clone())?,
+            }
+        }
+        Ok(value)
+    }
+
+    /// Defines the variable of a `for` loop, destructuring the element if needed.
+    fn bind_loop_variable(
+        variable: &ForEachTarget,
+        element: Value,
+        env: &Rc<RefCell<Environment>>,
+    ) -> Result<(), RuntimeError> {
+        match variable {
+            ForEachTarget::Ident(name) => env.borrow_mut().define(name, element),
+            ForEachTarget::Tuple(names) => {
+                let components = Self::destructure(element, names.len())?;
+                for (n, component) in names.iter().zip(components) {
+                    if n != "_" {
+                        env.borrow_mut().define(n, component);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The environment a lambda or `sequence { }` body runs in. Like the native
+    /// backend, it captures the local variables it refers to by value when it
+    /// is created; object properties stay shared.
+    fn capture(&self, body: &Block, env: &Rc<RefCell<Environment>>) -> Rc<RefCell<Environment>> {
+        let mut captured = Environment::with_enclosing(env.clone());
+        let members = self.object_members.borrow();
+        for name in body.variable_names() {
+            if members.contains_key(&name) {
+                continue;
+            }
+            if let Ok(value) = env.borrow().get(&name) {
+                captured.define(name, value);
+            }
+        }
+        Rc::new(RefCell::new(captured))
+    }
+
+    /// A lambda as a function returning its last expression statement.
+    fn lambda_declaration(params: &[LambdaParam], body: &Block) -> FunctionDecl {
+        let mut statements = body.statements.clone();
+        if let Some(last) = statements.pop() {
+            statements.push(match last {
+                Statement::Expression(value) => Statement::Return(Some(value)),
+                other => other,
+            });
+        }
+        FunctionDecl {
+            visibility: Visibility::Public,
+            name: "<lambda>".to_string(),
+            params: params
+                .iter()
+                .map(|param| Param {
+                    name: param.name.clone(),
+                    typ: param.typ.clone().unwrap_or(Type::Named("Any".to_string())),
+                })
+                .collect(),
+            return_type: None,
+            body: Block {
+                statements,
+                locals: body.locals.clone(),
+            },
+            expression_body: false,
+            is_suspend: false,
+            is_operator: false,
+            file: None,
+        }
+    }
+
+    /// Splits a Pair, Triple or array into its first `count` components.
+    pub fn destructure(value: Value, count: usize) -> Result<Vec<Value>, RuntimeError> {
+        let mut components = match value {
+            Value::Tuple(elements) => elements,
+            Value::Array(elements) => elements.borrow().clone(),
+            other => {
+                return Err(RuntimeError::TypeMismatch(format!(
+                    "Cannot destructure {}",
+                    other
+                )))
+            }
+        };
+        if components.len() < count {
+            return Err(RuntimeError::TypeMismatch(format!(
+                "Cannot destructure {} components from a value with {}",
+                count,
+                components.len()
+            )));
+        }
+        components.truncate(count);
+        Ok(components)
+    }
+
+    /// The value of a literal; `typ` is the type the type checker gave it.
+    pub fn literal(lit: &Literal, typ: Option<&Type>) -> Value {
+        match lit {
+            // The type checker may have widened the literal, e.g. for `val l: Long = 1`
+            Literal::Integer(i) => match typ {
+                Some(Type::Named(name)) => Value::Long(*i)
+                    .convert_numeric(name)
+                    .unwrap_or(Value::Integer(*i as i32)),
+                _ => Value::Integer(*i as i32),
+            },
+            Literal::Long(i) => Value::Long(*i),
+            Literal::UInt(u) => Value::UInt(*u),
+            Literal::ULong(u) => Value::ULong(*u),
+            Literal::Float(f) => Value::Float(*f),
+            Literal::Double(f) => Value::Double(*f),
+            Literal::String(s) => Value::String(s.clone()),
+            Literal::Boolean(b) => Value::Boolean(*b),
+            Literal::Char(c) => Value::Char(*c),
+            Literal::Null => Value::Null,
+        }
+    }
+
+    pub fn evaluate_unary(operator: &UnaryOp, val: Value) -> Result<Value, RuntimeError> {
+        match (operator, val) {
+            (UnaryOp::Minus, Value::Byte(i)) => Ok(Value::Integer(-(i as i32))),
+            (UnaryOp::Minus, Value::Short(i)) => Ok(Value::Integer(-(i as i32))),
+            (UnaryOp::Minus, Value::Integer(i)) => Ok(Value::Integer(i.wrapping_neg())),
+            (UnaryOp::Minus, Value::Long(i)) => Ok(Value::Long(i.wrapping_neg())),
+            (UnaryOp::Minus, Value::Float(f)) => Ok(Value::Float(-f)),
+            (UnaryOp::Minus, Value::Double(f)) => Ok(Value::Double(-f)),
+            (UnaryOp::Not, Value::Boolean(b)) => Ok(Value::Boolean(!b)),
+            // Prefix increment and decrement (for now, treating as postfix since we handle both the same way)
+            (UnaryOp::Increment | UnaryOp::Decrement, val) => {
+                // Step in the operand's own type, so a Byte stays a Byte
+                let Some(typ) = val.numeric_type() else {
+                    return Err(RuntimeError::TypeMismatch(
+                        "Invalid operand for unary operator".to_string(),
+                    ));
+                };
+                let op = if matches!(operator, UnaryOp::Increment) {
+                    BinaryOp::Add
+                } else {
+                    BinaryOp::Sub
+                };
+                let one = Value::Integer(1).convert_numeric(typ).unwrap();
+                let stepped = Value::numeric_binary(&val, &op, &one)?;
+                Ok(stepped.convert_numeric(typ).unwrap())
+            }
+            _ => Err(RuntimeError::TypeMismatch(
+                "Invalid operand for unary operator".to_string(),
+            )),
+        }
+    }
+
+    /// `value.member`, a property of a built-in type.
+    pub fn member(value: Value, member: &str) -> Result<Value, RuntimeError> {
+        match (value, member) {
+            (Value::String(s), "length") => Ok(Value::Integer(s.len() as i32)),
+            // Type conversion methods
+            (Value::String(s), "toInt" | "toLong" | "toFloat" | "toDouble") => {
+                Self::parse_number(&s, &member[2..])
+            }
+            (value, "toString") if value.numeric_type().is_some() => {
+                Ok(Value::String(value.to_string()))
+            }
+            (value, method) if value.numeric_type().is_some() && method.starts_with("to") => {
+                value.convert_numeric(&method[2..]).ok_or_else(|| {
+                    RuntimeError::TypeMismatch(format!(
+                        "Method '{}' not found on type {:?}",
+                        method, value
+                    ))
+                })
+            }
+            (Value::Boolean(b), "toString") => Ok(Value::String(b.to_string())),
+            (Value::Char(c), "toString") => Ok(Value::String(c.to_string())),
+            // Array methods
+            (Value::Array(_elements), "push") => {
+                // For member access, we don't have arguments, so this would be an error
+                Err(RuntimeError::TypeMismatch(
+                    "Cannot call push() without arguments via member access".to_string(),
+                ))
+            }
+            (Value::Array(_elements), "pop") => {
+                // For member access, this would be an error since pop() should be callable
+                Err(RuntimeError::TypeMismatch(
+                    "Cannot call pop() via member access".to_string(),
+                ))
+            }
+            // HashMap iteration methods
+            (Value::HashMap(map), "keys") => {
+                let keys: Vec<Value> = map
+                    .borrow()
+                    .keys()
+                    .map(|k| Value::String(k.clone()))
+                    .collect();
+                Ok(Value::array(keys))
+            }
+            (Value::HashMap(map), "iter") => {
+                // Create iterator over entries
+                let mut entries = Vec::new();
+                for (key, value) in map.borrow().iter() {
+                    let entry = Value::Tuple(vec![Value::String(key.clone()), value.clone()]);
+                    entries.push(entry);
+                }
+                let it = IteratorState {
+                    items: entries,
+                    pos: 0,
+                };
+                Ok(Value::Iterator(Rc::new(RefCell::new(it))))
+            }
+            (Value::HashMap(map), "values") => {
+                let values: Vec<Value> = map.borrow().values().cloned().collect();
+                Ok(Value::array(values))
+            }
+            (Value::HashMap(map), "size") => Ok(Value::Integer(map.borrow().len() as i32)),
+            (Value::HashMap(map), "entries") => {
+                let mut entries = Vec::new();
+                for (key, value) in map.borrow().iter() {
+                    // Each entry is a (key, value) pair
+                    let entry = Value::Tuple(vec![Value::String(key.clone()), value.clone()]);
+                    entries.push(entry);
+                }
+                Ok(Value::array(entries))
+            }
+            // Pair and Triple components
+            (Value::Tuple(mut elements), "first" | "second" | "third") => {
+                let index = match member {
+                    "first" => 0,
+                    "second" => 1,
+                    _ => 2,
+                };
+                if index < elements.len() {
+                    Ok(elements.swap_remove(index))
+                } else {
+                    Err(RuntimeError::TypeMismatch(format!(
+                        "Cannot access member '{}' on a Pair",
+                        member
+                    )))
+                }
+            }
+            (val, _) => Err(RuntimeError::TypeMismatch(format!(
+                "Cannot access member '{}' on {:?}",
+                member, val
+            ))),
+        }
+    }
 
-                    map.insert(key_str, value);
+    /// `object.member(args)`, a method of a built-in type.
+    pub fn call_method(
+        &self,
+        object: Value,
+        member: &str,
+        args: Vec<Value>,
+    ) -> Result<Value, RuntimeError> {
+        match (object, member) {
+            (Value::String(s), "toInt" | "toLong" | "toFloat" | "toDouble") => {
+                Self::parse_number(&s, &member[2..])
+            }
+            (value, "toString") if value.numeric_type().is_some() => {
+                Ok(Value::String(value.to_string()))
+            }
+            (value, method) if value.numeric_type().is_some() && method.starts_with("to") => {
+                value.convert_numeric(&method[2..]).ok_or_else(|| {
+                    RuntimeError::TypeMismatch(format!(
+                        "Method '{}' not found on type {:?}",
+                        method, value
+                    ))
+                })
+            }
+            (Value::Boolean(b), "toString") => Ok(Value::String(b.to_string())),
+            (Value::Char(c), "toString") => Ok(Value::String(c.to_string())),
+            (Value::Integer(n), "inv") => Ok(Value::Integer(!n)),
+            (Value::Long(n), "inv") => Ok(Value::Long(!n)),
+            (Value::UInt(n), "inv") => Ok(Value::UInt(!n)),
+            (Value::ULong(n), "inv") => Ok(Value::ULong(!n)),
+            // Array methods
+            // Every holder of the array sees it grow and shrink
+            (Value::Array(elements), "push") => {
+                if args.len() != 1 {
+                    return Err(RuntimeError::TypeMismatch(
+                        "push() expects 1 argument".to_string(),
+                    ));
+                }
+                elements.borrow_mut().push(args[0].clone());
+                self.budget.check_size(&Value::Array(elements))?;
+                Ok(Value::Void)
+            }
+            (Value::Array(elements), "pop") => {
+                let popped = elements.borrow_mut().pop();
+                popped.ok_or_else(|| {
+                    RuntimeError::TypeMismatch("pop() called on empty array".to_string())
+                })
+            }
+            // HashMap iteration methods
+            (Value::HashMap(map), "keys") => {
+                let keys: Vec<Value> = map
+                    .borrow()
+                    .keys()
+                    .map(|k| Value::String(k.clone()))
+                    .collect();
+                Ok(Value::array(keys))
+            }
+            (Value::HashMap(map), "iter") => {
+                let mut entries = Vec::new();
+                for (key, value) in map.borrow().iter() {
+                    let entry = Value::Tuple(vec![Value::String(key.clone()), value.clone()]);
+                    entries.push(entry);
+                }
+                let it = IteratorState {
+                    items: entries,
+                    pos: 0,
+                };
+                Ok(Value::Iterator(Rc::new(RefCell::new(it))))
+            }
+            (Value::HashMap(map), "values") => {
+                let values: Vec<Value> = map.borrow().values().cloned().collect();
+                Ok(Value::array(values))
+            }
+            (Value::HashMap(map), "size") => Ok(Value::Integer(map.borrow().len() as i32)),
+            (Value::HashMap(map), "entries") => {
+                let mut entries = Vec::new();
+                for (key, value) in map.borrow().iter() {
+                    // Each entry is a (key, value) pair
+                    let entry = Value::Tuple(vec![Value::String(key.clone()), value.clone()]);
+                    entries.push(entry);
+                }
+                Ok(Value::array(entries))
+            }
+            // Lazy sequences
+            (Value::Array(elements), "asSequence") => Ok(Value::Sequence(Rc::new(
+                Sequence::Elements(elements.borrow().clone()),
+            ))),
+            (Value::Sequence(source), "map" | "filter") => {
+                let function = args.into_iter().next().ok_or(RuntimeError::ArgumentCount {
+                    expected: 1,
+                    got: 0,
+                })?;
+                Ok(Value::Sequence(Rc::new(if member == "map" {
+                    Sequence::Map(source, function)
+                } else {
+                    Sequence::Filter(source, function)
+                })))
+            }
+            (Value::Sequence(source), "take") => match args.first() {
+                Some(Value::Integer(count)) if *count >= 0 => Ok(Value::Sequence(Rc::new(
+                    Sequence::Take(source, *count as usize),
+                ))),
+                Some(Value::Integer(count)) => Err(RuntimeError::TypeMismatch(format!(
+                    "Requested element count {} is less than zero",
+                    count
+                ))),
+                _ => Err(RuntimeError::TypeMismatch(
+                    "take() expects an Int".to_string(),
+                )),
+            },
+            (Value::Sequence(sequence), "toList") => {
+                let mut elements = self.iterate(&sequence);
+                let mut list = Vec::new();
+                while let Some(element) = self.next_element(&mut elements)? {
+                    list.push(element);
                 }
-                Ok(Value::HashMap(map))
+                Ok(Value::array(list))
             }
+            // Iterator next() on iterator objects
+            (Value::Iterator(it_rc), "next") => {
+                let mut it = it_rc.borrow_mut();
+                if it.pos >= it.items.len() {
+                    Ok(Value::Void)
+                } else {
+                    let v = it.items[it.pos].clone();
+                    it.pos += 1;
+                    Ok(v)
+                }
+            }
+            (
+                object @ (Value::Thread(_)
+                | Value::AtomicInt(_)
+                | Value::Mutex(_)
+                | Value::Channel(_)),
+                method,
+            ) => self.call_concurrency_method(object, method, args),
+            (Value::Host(object), method) if self.host_method(&object, method).is_some() => {
+                let function = self.host_method(&object, method).unwrap();
+                let mut receiver_and_args = vec![Value::Host(object)];
+                receiver_and_args.extend(args);
+                function.call(receiver_and_args)
+            }
+            (obj_val, method_name) => Err(RuntimeError::TypeMismatch(format!(
+                "Method '{}' not found on type {:?}",
+                method_name, obj_val
+            ))),
+        }
+    }
+
+    /// The key a value stands for in a map literal.
+    pub fn map_key(key: Value) -> Result<String, RuntimeError> {
+        match key {
+            Value::String(s) => Ok(s),
+            Value::Integer(i) => Ok(i.to_string()),
+            _ => Err(RuntimeError::TypeMismatch(
+                "HashMap key must be string or integer".to_string(),
+            )),
         }
     }
 
-    fn call_value(&self, callee: Value, args: Vec<Value>) -> Result<Value, RuntimeError> {
+    pub fn call_value(&self, callee: Value, args: Vec<Value>) -> Result<Value, RuntimeError> {
         match callee {
             Value::Function {
                 declaration,
@@ -1259,25 +3483,210 @@ impl Interpreter {
                         got: args.len(),
                     });
                 }
+                if declaration.is_suspend {
+                    return Err(RuntimeError::TypeMismatch(format!(
+                        "Suspend function '{}' can only be called from a coroutine",
+                        declaration.name
+                    )));
+                }
 
-                let environment = Rc::new(RefCell::new(Environment::with_enclosing(closure)));
+                let environment = Rc::new(RefCell::new(Environment::for_block(
+                    &declaration.body,
+                    closure,
+                )));
                 for (i, param) in declaration.params.iter().enumerate() {
                     environment
                         .borrow_mut()
-                        .define(param.name.clone(), args[i].clone());
+                        .define(&param.name, args[i].clone());
                 }
 
-                match self.execute_block(&declaration.body, environment) {
+                self.budget.enter()?;
+                let debugged = self.debugging.enter(&declaration, &environment);
+                let profiled = self.profiling.enter(&declaration.name);
+                let covered = self.covering.enter(&declaration);
+                let result = self.execute_block(&declaration.body, environment);
+                if covered {
+                    self.covering.leave();
+                }
+                if profiled {
+                    self.profiling.leave();
+                }
+                if debugged {
+                    self.debugging.leave();
+                }
+                self.budget.leave();
+                if let Err(e) = &result {
+                    self.unwinding.leave_call(e, &declaration);
+                }
+                match result {
                     Ok(_) => Ok(Value::Void),
                     Err(RuntimeError::Return(val)) => Ok(*val),
                     Err(e) => Err(e),
                 }
             }
-            Value::NativeFunction(f) => f(args),
+            Value::NativeFunction(f) => f(self, args),
+            Value::HostFunction(function) => function.call(args),
             _ => Err(RuntimeError::NotAFunction(format!("{}", callee))),
         }
     }
 
+    /// `container[index]` on an array, string or map.
+    pub fn index_value(container: Value, index: Value) -> Result<Value, RuntimeError> {
+        match (container, index) {
+            (Value::Array(elements), Value::Integer(index)) => {
+                let elements = elements.borrow();
+                usize::try_from(index)
+                    .ok()
+                    .and_then(|i| elements.get(i).cloned())
+                    .ok_or(RuntimeError::IndexOutOfBounds {
+                        index,
+                        length: elements.len(),
+                    })
+            }
+            (Value::String(s), Value::Integer(index)) => {
+                let idx = index as usize;
+                if idx < s.len() {
+                    // Return the character at the index as a Char value
+                    let ch = s.chars().nth(idx).unwrap();
+                    Ok(Value::Char(ch))
+                } else {
+                    Err(RuntimeError::TypeMismatch(
+                        "String index out of bounds".to_string(),
+                    ))
+                }
+            }
+            (Value::HashMap(map), Value::String(key)) => match map.borrow().get(&key) {
+                Some(value) => Ok(value.clone()),
+                None => Err(RuntimeError::TypeMismatch(format!(
+                    "Key '{}' not found in HashMap",
+                    key
+                ))),
+            },
+            (Value::HashMap(map), Value::Integer(key)) => {
+                match map.borrow().get(&key.to_string()) {
+                    Some(value) => Ok(value.clone()),
+                    None => Err(RuntimeError::TypeMismatch(format!(
+                        "Key '{}' not found in HashMap",
+                        key
+                    ))),
+                }
+            }
+            (_, Value::Integer(_)) => Err(RuntimeError::TypeMismatch(
+                "Indexing target is not an array or string".to_string(),
+            )),
+            (_, Value::String(_)) => Err(RuntimeError::TypeMismatch(
+                "Indexing target is not a HashMap".to_string(),
+            )),
+            _ => Err(RuntimeError::TypeMismatch(
+                "Index must be an integer for arrays/strings or string for HashMaps".to_string(),
+            )),
+        }
+    }
+
+    /// Resolves an assignment target, evaluating its indices once.
+    fn place(
+        &self,
+        target: &Expression,
+        env: Rc<RefCell<Environment>>,
+    ) -> Result<Place, RuntimeError> {
+        match &*target.kind {
+            ExpressionKind::Variable(name) => {
+                self.initialize_object_of(name)?;
+                Ok(Place {
+                    root: PlaceRoot::Variable(name.clone(), target.slot),
+                    keys: Vec::new(),
+                })
+            }
+            ExpressionKind::Index { array, index } => {
+                let mut place = self.place(array, env.clone())?;
+                place.keys.push(self.evaluate_expression(index, env)?);
+                Ok(place)
+            }
+            ExpressionKind::MemberAccess { member, .. } => Err(RuntimeError::TypeMismatch(
+                format!("Cannot assign to member '{}'", member),
+            )),
+            _ => Ok(Place {
+                root: PlaceRoot::Temporary(self.evaluate_expression(target, env)?),
+                keys: Vec::new(),
+            }),
+        }
+    }
+
+    fn read_place(place: &Place, env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+        let mut value = Self::place_root(&place.root, env)?;
+        for key in &place.keys {
+            value = Self::index_value(value, key.clone())?;
+        }
+        Ok(value)
+    }
+
+    fn place_root(root: &PlaceRoot, env: &Rc<RefCell<Environment>>) -> Result<Value, RuntimeError> {
+        match root {
+            PlaceRoot::Variable(name, slot) => {
+                match slot.and_then(|slot| env.borrow().get_at(slot)) {
+                    Some(value) => Ok(value),
+                    None => env.borrow().get(name),
+                }
+            }
+            PlaceRoot::Temporary(value) => Ok(value.clone()),
+        }
+    }
+
+    fn write_place(
+        &self,
+        place: Place,
+        value: Value,
+        env: &Rc<RefCell<Environment>>,
+    ) -> Result<(), RuntimeError> {
+        // An element is replaced in the array or map every holder of it shares
+        if let Some((key, path)) = place.keys.split_last() {
+            let mut container = Self::place_root(&place.root, env)?;
+            for key in path {
+                container = Self::index_value(container, key.clone())?;
+            }
+            Self::set_element(&container, key, value)?;
+            return self.budget.check_size(&container);
+        }
+        // Nothing observes an assignment to a temporary
+        let PlaceRoot::Variable(name, slot) = place.root else {
+            return Ok(());
+        };
+        match slot {
+            Some(slot) if env.borrow_mut().assign_at(slot, value.clone()) => Ok(()),
+            _ => env.borrow_mut().assign(name, value),
+        }
+    }
+
+    /// Replaces the element `key` of an array or map.
+    pub fn set_element(container: &Value, key: &Value, value: Value) -> Result<(), RuntimeError> {
+        match (container, key) {
+            (Value::Array(elements), Value::Integer(index)) => {
+                let mut elements = elements.borrow_mut();
+                match usize::try_from(*index)
+                    .ok()
+                    .and_then(|index| elements.get_mut(index))
+                {
+                    Some(element) => {
+                        *element = value;
+                        Ok(())
+                    }
+                    None => Err(RuntimeError::IndexOutOfBounds {
+                        index: *index,
+                        length: elements.len(),
+                    }),
+                }
+            }
+            (Value::HashMap(map), Value::String(_) | Value::Integer(_)) => {
+                map.borrow_mut().insert(key.to_string(), value);
+                Ok(())
+            }
+            (container, _) => Err(RuntimeError::TypeMismatch(format!(
+                "Cannot assign to an element of {}",
+                container
+            ))),
+        }
+    }
+
     fn execute_block(
         &self,
         block: &Block,
@@ -1289,133 +3698,64 @@ impl Interpreter {
         Ok(())
     }
 
-    fn evaluate_binary(
-        &self,
+    /// Applies a binary operator other than `&&`, `||` and `?:`, which
+    /// evaluate their right side on demand.
+    pub fn evaluate_binary(
         left: Value,
         op: &BinaryOp,
         right: Value,
     ) -> Result<Value, RuntimeError> {
+        // Compound assignments evaluate like their plain operators
+        let op = match op {
+            BinaryOp::PlusEqual => &BinaryOp::Add,
+            BinaryOp::MinusEqual => &BinaryOp::Sub,
+            BinaryOp::StarEqual => &BinaryOp::Mul,
+            BinaryOp::SlashEqual => &BinaryOp::Div,
+            op => op,
+        };
         match (left, op, right) {
-            (Value::Integer(l), BinaryOp::Add, Value::Integer(r)) => Ok(Value::Integer(l + r)),
-            (Value::Integer(l), BinaryOp::Sub, Value::Integer(r)) => Ok(Value::Integer(l - r)),
-            (Value::Integer(l), BinaryOp::Mul, Value::Integer(r)) => Ok(Value::Integer(l * r)),
-            (Value::Integer(l), BinaryOp::Div, Value::Integer(r)) => Ok(Value::Integer(l / r)),
-
-            // Comparisons
-            (Value::Integer(l), BinaryOp::Equal, Value::Integer(r)) => Ok(Value::Boolean(l == r)),
-            (Value::Integer(l), BinaryOp::NotEqual, Value::Integer(r)) => {
-                Ok(Value::Boolean(l != r))
-            }
-            (Value::Integer(l), BinaryOp::Less, Value::Integer(r)) => Ok(Value::Boolean(l < r)),
-            (Value::Integer(l), BinaryOp::LessEqual, Value::Integer(r)) => {
-                Ok(Value::Boolean(l <= r))
-            }
-            (Value::Integer(l), BinaryOp::Greater, Value::Integer(r)) => Ok(Value::Boolean(l > r)),
-            (Value::Integer(l), BinaryOp::GreaterEqual, Value::Integer(r)) => {
-                Ok(Value::Boolean(l >= r))
-            }
-
-            // Float operations
-            (Value::Float(l), BinaryOp::Add, Value::Float(r)) => Ok(Value::Float(l + r)),
-            (Value::Float(l), BinaryOp::Sub, Value::Float(r)) => Ok(Value::Float(l - r)),
-            (Value::Float(l), BinaryOp::Mul, Value::Float(r)) => Ok(Value::Float(l * r)),
-            (Value::Float(l), BinaryOp::Div, Value::Float(r)) => Ok(Value::Float(l / r)),
-
-            // Compound assignment operators for integers
-            (Value::Integer(l), BinaryOp::PlusEqual, Value::Integer(r)) => {
-                Ok(Value::Integer(l + r))
-            }
-            (Value::Integer(l), BinaryOp::MinusEqual, Value::Integer(r)) => {
-                Ok(Value::Integer(l - r))
-            }
-            (Value::Integer(l), BinaryOp::StarEqual, Value::Integer(r)) => {
-                Ok(Value::Integer(l * r))
-            }
-            (Value::Integer(l), BinaryOp::SlashEqual, Value::Integer(r)) => {
-                Ok(Value::Integer(l / r))
+            (l, BinaryOp::Identical, r) => Ok(Value::Boolean(l.identical(&r))),
+            (l, BinaryOp::NotIdentical, r) => Ok(Value::Boolean(!l.identical(&r))),
+            (
+                l,
+                BinaryOp::BitAnd
+                | BinaryOp::BitOr
+                | BinaryOp::BitXor
+                | BinaryOp::Shl
+                | BinaryOp::Shr
+                | BinaryOp::UShr,
+                r,
+            ) => Value::bitwise_binary(&l, op, &r),
+            (l, op, r) if l.numeric_type().is_some() && r.numeric_type().is_some() => {
+                Value::numeric_binary(&l, op, &r)
             }
 
-            // Compound assignment operators for floats
-            (Value::Float(l), BinaryOp::PlusEqual, Value::Float(r)) => Ok(Value::Float(l + r)),
-            (Value::Float(l), BinaryOp::MinusEqual, Value::Float(r)) => Ok(Value::Float(l - r)),
-            (Value::Float(l), BinaryOp::StarEqual, Value::Float(r)) => Ok(Value::Float(l * r)),
-            (Value::Float(l), BinaryOp::SlashEqual, Value::Float(r)) => Ok(Value::Float(l / r)),
-
             // Boolean operations
             (Value::Boolean(l), BinaryOp::Equal, Value::Boolean(r)) => Ok(Value::Boolean(l == r)),
             (Value::Boolean(l), BinaryOp::NotEqual, Value::Boolean(r)) => {
                 Ok(Value::Boolean(l != r))
             }
 
-            // String concat
-            (Value::String(l), BinaryOp::Add, Value::String(r)) => {
-                Ok(Value::String(format!("{}{}", l, r)))
-            }
-            (Value::String(l), BinaryOp::Add, Value::Integer(r)) => {
-                Ok(Value::String(format!("{}{}", l, r)))
-            }
-            (Value::Integer(l), BinaryOp::Add, Value::String(r)) => {
-                Ok(Value::String(format!("{}{}", l, r)))
-            }
-            (Value::String(l), BinaryOp::Add, Value::Float(r)) => {
-                Ok(Value::String(format!("{}{}", l, r)))
-            }
-            (Value::Float(l), BinaryOp::Add, Value::String(r)) => {
-                Ok(Value::String(format!("{}{}", l, r)))
-            }
-            (Value::String(l), BinaryOp::Add, Value::Boolean(r)) => {
-                Ok(Value::String(format!("{}{}", l, r)))
-            }
-            (Value::Boolean(l), BinaryOp::Add, Value::String(r)) => {
-                Ok(Value::String(format!("{}{}", l, r)))
-            }
-
-            // Mixed operations for compound assignments
-            (Value::Integer(l), BinaryOp::PlusEqual, Value::Float(r)) => {
-                Ok(Value::Float(l as f64 + r))
-            }
-            (Value::Float(l), BinaryOp::PlusEqual, Value::Integer(r)) => {
-                Ok(Value::Float(l + r as f64))
-            }
-            (Value::Integer(l), BinaryOp::MinusEqual, Value::Float(r)) => {
-                Ok(Value::Float(l as f64 - r))
+            // Comparisons with null
+            (l, BinaryOp::Equal, r) if l == Value::Null || r == Value::Null => {
+                Ok(Value::Boolean(l == r))
             }
-            (Value::Float(l), BinaryOp::MinusEqual, Value::Integer(r)) => {
-                Ok(Value::Float(l - r as f64))
-            }
-            (Value::Integer(l), BinaryOp::StarEqual, Value::Float(r)) => {
-                Ok(Value::Float(l as f64 * r))
-            }
-            (Value::Float(l), BinaryOp::StarEqual, Value::Integer(r)) => {
-                Ok(Value::Float(l * r as f64))
-            }
-            (Value::Integer(l), BinaryOp::SlashEqual, Value::Float(r)) => {
-                Ok(Value::Float(l as f64 / r))
-            }
-            (Value::Float(l), BinaryOp::SlashEqual, Value::Integer(r)) => {
-                Ok(Value::Float(l / r as f64))
+            (l, BinaryOp::NotEqual, r) if l == Value::Null || r == Value::Null => {
+                Ok(Value::Boolean(l != r))
             }
 
-            // String concatenation for PlusEqual
-            (Value::String(l), BinaryOp::PlusEqual, Value::String(r)) => {
-                Ok(Value::String(format!("{}{}", l, r)))
-            }
-            (Value::String(l), BinaryOp::PlusEqual, Value::Integer(r)) => {
-                Ok(Value::String(format!("{}{}", l, r)))
-            }
-            (Value::Integer(l), BinaryOp::PlusEqual, Value::String(r)) => {
-                Ok(Value::String(format!("{}{}", l, r)))
-            }
-            (Value::String(l), BinaryOp::PlusEqual, Value::Float(r)) => {
-                Ok(Value::String(format!("{}{}", l, r)))
-            }
-            (Value::Float(l), BinaryOp::PlusEqual, Value::String(r)) => {
+            // String concat
+            (Value::String(l), BinaryOp::Add, Value::String(r)) => {
                 Ok(Value::String(format!("{}{}", l, r)))
             }
-            (Value::String(l), BinaryOp::PlusEqual, Value::Boolean(r)) => {
+            (Value::String(l), BinaryOp::Add, r)
+                if r.numeric_type().is_some() || matches!(r, Value::Boolean(_)) =>
+            {
                 Ok(Value::String(format!("{}{}", l, r)))
             }
-            (Value::Boolean(l), BinaryOp::PlusEqual, Value::String(r)) => {
+            (l, BinaryOp::Add, Value::String(r))
+                if l.numeric_type().is_some() || matches!(l, Value::Boolean(_)) =>
+            {
                 Ok(Value::String(format!("{}{}", l, r)))
             }
             _ => Err(RuntimeError::TypeMismatch(
@@ -1423,4 +3763,17 @@ impl Interpreter {
             )),
         }
     }
+
+    /// Parses a string for `toInt()`, `toLong()`, `toFloat()` or `toDouble()`.
+    fn parse_number(s: &str, target: &str) -> Result<Value, RuntimeError> {
+        let parsed = match target {
+            "Int" => s.parse().map(Value::Integer).ok(),
+            "Long" => s.parse().map(Value::Long).ok(),
+            "Float" => s.parse().map(Value::Float).ok(),
+            _ => s.parse().map(Value::Double).ok(),
+        };
+        parsed.ok_or_else(|| {
+            RuntimeError::TypeMismatch(format!("Cannot convert string '{}' to {}", s, target))
+        })
+    }
 }
@@ -0,0 +1,294 @@
+//! Limits on what a run may use, for scripts that aren't trusted, and the
+//! capabilities that let scripts reach outside the interpreter. Apart from a
+//! limit on the call depth, neither is in effect unless the embedding
+//! application asks for it.
+
+use crate::{Interpreter, RuntimeError, Value};
+use dotlin_ast::Type;
+use std::cell::Cell;
+use std::time::{Duration, Instant};
+
+/// How many calls may be in progress at once unless limited otherwise.
+pub const DEFAULT_CALL_DEPTH: usize = 5_000;
+
+/// The stack a thread running the interpreter needs for `DEFAULT_CALL_DEPTH`
+/// calls, even in an unoptimized build.
+pub const STACK_SIZE: usize = 512 << 20;
+
+/// Runs `run` on a thread with a stack of `STACK_SIZE`, so that recursing too
+/// deeply fails with `Limit::CallDepth` rather than overflowing the stack.
+pub fn with_stack<T: Send>(run: impl FnOnce() -> T + Send) -> T {
+    std::thread::scope(|scope| {
+        let thread = std::thread::Builder::new()
+            .stack_size(STACK_SIZE)
+            .spawn_scoped(scope, run)
+            .expect("failed to start the interpreter's thread");
+        thread
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+    })
+}
+
+/// What a run may use; `None` is unlimited. By default only the call depth
+/// is limited, to `DEFAULT_CALL_DEPTH`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Limits {
+    /// Expressions evaluated, each loop condition included
+    pub instructions: Option<u64>,
+    /// Calls in progress at once. The interpreter recurses on the Rust stack
+    /// for every call, so this is what keeps deep recursion from overflowing it.
+    pub call_depth: Option<usize>,
+    /// Elements of an array or map, or bytes of a string
+    pub collection_size: Option<usize>,
+    /// Wall-clock time
+    pub time: Option<Duration>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            instructions: None,
+            call_depth: Some(DEFAULT_CALL_DEPTH),
+            collection_size: None,
+            time: None,
+        }
+    }
+}
+
+/// The limit a run exceeded.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Limit {
+    Instructions(u64),
+    CallDepth(usize),
+    CollectionSize(usize),
+    Time(Duration),
+}
+
+impl std::fmt::Display for Limit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Limit::Instructions(n) => write!(f, "more than {} instructions executed", n),
+            Limit::CallDepth(n) => write!(f, "more than {} nested calls", n),
+            Limit::CollectionSize(n) => write!(f, "a collection or string exceeded {} elements", n),
+            Limit::Time(time) => write!(f, "ran for more than {:?}", time),
+        }
+    }
+}
+
+/// How often, in instructions, the clock is read.
+const CLOCK_INTERVAL: u64 = 1024;
+
+/// What a run has used so far of its limits.
+#[derive(Debug, Default)]
+pub(crate) struct Budget {
+    limits: Limits,
+    /// Whether each instruction needs counting
+    counting: bool,
+    executed: Cell<u64>,
+    depth: Cell<usize>,
+    deadline: Option<Instant>,
+}
+
+impl Budget {
+    /// Limits counted from now.
+    pub(crate) fn new(limits: Limits) -> Self {
+        Self {
+            counting: limits.instructions.is_some() || limits.time.is_some(),
+            deadline: limits.time.map(|time| Instant::now() + time),
+            limits,
+            executed: Cell::new(0),
+            depth: Cell::new(0),
+        }
+    }
+
+    /// Counts an instruction.
+    #[inline]
+    pub(crate) fn tick(&self) -> Result<(), RuntimeError> {
+        if !self.counting {
+            return Ok(());
+        }
+        let executed = self.executed.get() + 1;
+        self.executed.set(executed);
+        match (self.limits.instructions, self.deadline) {
+            (Some(max), _) if executed > max => Err(Limit::Instructions(max).into()),
+            (_, Some(deadline))
+                if executed.is_multiple_of(CLOCK_INTERVAL) && Instant::now() > deadline =>
+            {
+                Err(Limit::Time(self.limits.time.unwrap()).into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Waits until `at`, but fails at the deadline if `at` is past it.
+    pub(crate) fn sleep_until(&self, at: Instant) -> Result<(), RuntimeError> {
+        match self.deadline {
+            Some(deadline) if at > deadline => {
+                std::thread::sleep(deadline.saturating_duration_since(Instant::now()));
+                Err(Limit::Time(self.limits.time.unwrap()).into())
+            }
+            _ => {
+                std::thread::sleep(at.saturating_duration_since(Instant::now()));
+                Ok(())
+            }
+        }
+    }
+
+    /// Enters a call, which `leave` must follow.
+    pub(crate) fn enter(&self) -> Result<(), RuntimeError> {
+        let depth = self.depth.get() + 1;
+        match self.limits.call_depth {
+            Some(max) if depth > max => Err(Limit::CallDepth(max).into()),
+            _ => {
+                self.depth.set(depth);
+                Ok(())
+            }
+        }
+    }
+
+    pub(crate) fn leave(&self) {
+        self.depth.set(self.depth.get() - 1);
+    }
+
+    /// Checks the size of a value that may have grown.
+    pub(crate) fn check_size(&self, value: &Value) -> Result<(), RuntimeError> {
+        let Some(max) = self.limits.collection_size else {
+            return Ok(());
+        };
+        let size = match value {
+            Value::String(s) => s.len(),
+            Value::Array(elements) => elements.borrow().len(),
+            Value::HashMap(map) => map.borrow().len(),
+            _ => 0,
+        };
+        if size > max {
+            return Err(Limit::CollectionSize(max).into());
+        }
+        Ok(())
+    }
+}
+
+impl From<Limit> for RuntimeError {
+    fn from(limit: Limit) -> Self {
+        RuntimeError::LimitExceeded(limit)
+    }
+}
+
+/// Natives that reach outside the interpreter, which are only defined once
+/// granted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Capabilities {
+    /// `readFile`, `writeFile` and `fileExists`
+    pub files: bool,
+    /// `exec`, which runs a command and returns its output
+    pub processes: bool,
+    /// `getenv`
+    pub environment: bool,
+}
+
+/// A native a capability grants, with the signature the type checker needs.
+pub struct CapabilityNative {
+    pub name: &'static str,
+    pub params: Vec<Type>,
+    pub ret: Type,
+    pub function: fn(&Interpreter, Vec<Value>) -> Result<Value, RuntimeError>,
+}
+
+impl Capabilities {
+    pub fn all() -> Self {
+        Self {
+            files: true,
+            processes: true,
+            environment: true,
+        }
+    }
+
+    /// The natives these capabilities grant.
+    pub fn natives(&self) -> Vec<CapabilityNative> {
+        let string = || Type::Named("String".to_string());
+        let mut natives = Vec::new();
+        if self.files {
+            natives.push(CapabilityNative {
+                name: "readFile",
+                params: vec![string()],
+                ret: string(),
+                function: |_, args| {
+                    let path = string_argument(&args, "readFile")?;
+                    std::fs::read_to_string(path)
+                        .map(Value::String)
+                        .map_err(|e| io_error(path, e))
+                },
+            });
+            natives.push(CapabilityNative {
+                name: "writeFile",
+                params: vec![string(), string()],
+                ret: Type::Named("Unit".to_string()),
+                function: |_, args| {
+                    let path = string_argument(&args, "writeFile")?;
+                    let Some(Value::String(text)) = args.get(1) else {
+                        return Err(RuntimeError::TypeMismatch(
+                            "writeFile() expects a path and a String".to_string(),
+                        ));
+                    };
+                    std::fs::write(path, text)
+                        .map(|_| Value::Void)
+                        .map_err(|e| io_error(path, e))
+                },
+            });
+            natives.push(CapabilityNative {
+                name: "fileExists",
+                params: vec![string()],
+                ret: Type::Named("Boolean".to_string()),
+                function: |_, args| {
+                    let path = string_argument(&args, "fileExists")?;
+                    Ok(Value::Boolean(std::path::Path::new(path).exists()))
+                },
+            });
+        }
+        if self.processes {
+            natives.push(CapabilityNative {
+                name: "exec",
+                params: vec![string()],
+                ret: string(),
+                function: |_, args| {
+                    let command = string_argument(&args, "exec")?;
+                    let mut words = command.split_whitespace();
+                    let program = words.next().unwrap_or_default();
+                    std::process::Command::new(program)
+                        .args(words)
+                        .output()
+                        .map(|output| {
+                            Value::String(String::from_utf8_lossy(&output.stdout).into_owned())
+                        })
+                        .map_err(|e| io_error(command, e))
+                },
+            });
+        }
+        if self.environment {
+            natives.push(CapabilityNative {
+                name: "getenv",
+                params: vec![string()],
+                ret: Type::Nullable(Box::new(string())),
+                function: |_, args| {
+                    let name = string_argument(&args, "getenv")?;
+                    Ok(std::env::var(name).map_or(Value::Null, Value::String))
+                },
+            });
+        }
+        natives
+    }
+}
+
+fn string_argument<'a>(args: &'a [Value], name: &str) -> Result<&'a str, RuntimeError> {
+    match args.first() {
+        Some(Value::String(s)) => Ok(s),
+        _ => Err(RuntimeError::TypeMismatch(format!(
+            "{}() expects a String",
+            name
+        ))),
+    }
+}
+
+fn io_error(what: &str, e: std::io::Error) -> RuntimeError {
+    RuntimeError::IoException(format!("{}: {}", what, e))
+}
@@ -0,0 +1,145 @@
+//! Where a program spends its time. While profiling, each call of a declared
+//! function or lambda is timed: its inclusive time runs from the call to its
+//! return, and its exclusive time leaves out the calls it makes. A recursive
+//! function's inclusive time counts its outermost calls only, so that it
+//! isn't counted once per level.
+//!
+//! The profile is written in the folded-stack format flame graph tools read,
+//! one line per call stack with the exclusive time spent in it:
+//!
+//! ```text
+//! main;fib;fib 5120
+//! ```
+//!
+//! and as a JSON summary of each function.
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// The times of one function over a run.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FunctionProfile {
+    pub name: String,
+    pub calls: u64,
+    pub inclusive: Duration,
+    pub exclusive: Duration,
+}
+
+/// What a profiled run spent its time on.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Profile {
+    /// By exclusive time, most first
+    pub functions: Vec<FunctionProfile>,
+    /// Each call stack, outermost first, with the exclusive time of its
+    /// innermost call
+    pub stacks: Vec<(Vec<String>, Duration)>,
+}
+
+impl Profile {
+    /// The stacks in folded-stack format, in microseconds.
+    pub fn folded(&self) -> String {
+        self.stacks
+            .iter()
+            .map(|(stack, time)| format!("{} {}\n", stack.join(";"), time.as_micros()))
+            .collect()
+    }
+
+    /// The functions as a JSON summary, with times in microseconds.
+    pub fn json(&self) -> String {
+        let functions: Vec<String> = self
+            .functions
+            .iter()
+            .map(|function| {
+                format!(
+                    "    {{\"name\": \"{}\", \"calls\": {}, \"inclusive_us\": {}, \"exclusive_us\": {}}}",
+                    function.name.replace('\\', "\\\\").replace('"', "\\\""),
+                    function.calls,
+                    function.inclusive.as_micros(),
+                    function.exclusive.as_micros()
+                )
+            })
+            .collect();
+        format!(
+            "{{\n  \"functions\": [\n{}\n  ]\n}}\n",
+            functions.join(",\n")
+        )
+    }
+}
+
+/// A call being timed.
+struct Call {
+    function: String,
+    started: Instant,
+    /// The inclusive time of the calls it made
+    callees: Duration,
+}
+
+/// The profile of the run so far, while profiling.
+#[derive(Default)]
+pub(crate) struct Profiling {
+    enabled: Cell<bool>,
+    calls: RefCell<Vec<Call>>,
+    functions: RefCell<HashMap<String, FunctionProfile>>,
+    stacks: RefCell<HashMap<Vec<String>, Duration>>,
+}
+
+impl Profiling {
+    pub(crate) fn enable(&self) {
+        self.enabled.set(true);
+    }
+
+    /// Starts timing a call of `function`, returning whether it did so and
+    /// `leave` must follow.
+    #[inline]
+    pub(crate) fn enter(&self, function: &str) -> bool {
+        if !self.enabled.get() {
+            return false;
+        }
+        self.calls.borrow_mut().push(Call {
+            function: function.to_string(),
+            started: Instant::now(),
+            callees: Duration::ZERO,
+        });
+        true
+    }
+
+    pub(crate) fn leave(&self) {
+        let mut calls = self.calls.borrow_mut();
+        let Some(call) = calls.pop() else {
+            return;
+        };
+        let elapsed = call.started.elapsed();
+        let exclusive = elapsed.saturating_sub(call.callees);
+        if let Some(caller) = calls.last_mut() {
+            caller.callees += elapsed;
+        }
+        let recursive = calls.iter().any(|outer| outer.function == call.function);
+
+        let mut stack: Vec<String> = calls.iter().map(|outer| outer.function.clone()).collect();
+        stack.push(call.function.clone());
+        *self.stacks.borrow_mut().entry(stack).or_default() += exclusive;
+
+        let mut functions = self.functions.borrow_mut();
+        let totals = functions
+            .entry(call.function.clone())
+            .or_insert_with(|| FunctionProfile {
+                name: call.function,
+                ..FunctionProfile::default()
+            });
+        totals.calls += 1;
+        totals.exclusive += exclusive;
+        if !recursive {
+            totals.inclusive += elapsed;
+        }
+    }
+
+    /// The profile so far, which starts over.
+    pub(crate) fn take(&self) -> Profile {
+        let mut functions: Vec<FunctionProfile> = self.functions.take().into_values().collect();
+        functions.sort_by(|a, b| b.exclusive.cmp(&a.exclusive).then(a.name.cmp(&b.name)));
+        let mut stacks: Vec<_> = self.stacks.take().into_iter().collect();
+        stacks.sort();
+        Profile { functions, stacks }
+    }
+}
@@ -0,0 +1,335 @@
+//! Resolves the local variables of each function to a slot of the
+//! environment that holds them, so reading or writing one doesn't look its
+//! name up, and records the names of each block's slots, so defining one
+//! doesn't either. Scopes mirror the environments the interpreter creates:
+//! one for the parameters and body of a call, one per block, `for` iteration
+//! and safe call. Variables of lambdas, whose closures capture them by name, and of
+//! suspend functions, whose bodies define the results of suspended calls as
+//! they resume, are still checked but keep being looked up by name.
+
+use dotlin_ast::*;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ResolveError {
+    /// A variable used in a block that declares it further on
+    UsedBeforeDeclaration(String),
+    /// Two declarations of a variable in the same scope
+    ConflictingDeclarations(String),
+    /// A declaration that is the whole branch of an `if` or body of a loop
+    DeclarationOutsideBlock(String),
+}
+
+impl std::fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ResolveError::UsedBeforeDeclaration(name) => {
+                write!(f, "Variable '{}' is used before its declaration", name)
+            }
+            ResolveError::ConflictingDeclarations(name) => {
+                write!(
+                    f,
+                    "Conflicting declarations of '{}' in the same scope",
+                    name
+                )
+            }
+            ResolveError::DeclarationOutsideBlock(name) => {
+                write!(f, "Declaration of '{}' is only allowed in a block", name)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ResolveError {}
+
+/// Resolves every function of a program, including object members and
+/// property accessors.
+pub fn resolve_program(program: &mut Program) -> Result<(), ResolveError> {
+    for decl in &mut program.declarations {
+        match decl {
+            Declaration::Function(func) => resolve_function(func)?,
+            Declaration::ValueClass(class) => {
+                for func in &mut class.functions {
+                    resolve_function(func)?;
+                }
+            }
+            Declaration::Object(_) | Declaration::TypeAlias(_) => {}
+        }
+        if let Some(object) = decl.object_mut() {
+            for func in &mut object.functions {
+                resolve_function(func)?;
+            }
+            for property in &mut object.properties {
+                for accessor in property.getter.iter_mut().chain(&mut property.setter) {
+                    resolve_function(accessor)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn resolve_function(func: &mut FunctionDecl) -> Result<(), ResolveError> {
+    let mut resolver = Resolver {
+        scopes: Vec::new(),
+        function: 0,
+        annotate: !func.is_suspend,
+    };
+    let params: Vec<&str> = func
+        .params
+        .iter()
+        .map(|param| param.name.as_str())
+        .collect();
+    resolver.body(&params, &mut func.body)
+}
+
+#[derive(Default)]
+struct Scope {
+    /// Variables in slot order, and whether each is a parameter
+    variables: Vec<(String, bool)>,
+    /// Variables the block declares further on
+    pending: Vec<String>,
+}
+
+struct Resolver {
+    scopes: Vec<Scope>,
+    /// The first scope of the innermost function or lambda; variables
+    /// outside it are looked up by name
+    function: usize,
+    /// Whether to record slots, rather than only check
+    annotate: bool,
+}
+
+impl Resolver {
+    /// The body of a function or lambda, which shares the parameters' scope.
+    fn body(&mut self, params: &[&str], body: &mut Block) -> Result<(), ResolveError> {
+        self.begin_scope(body);
+        for param in params {
+            self.current().variables.push((param.to_string(), true));
+        }
+        self.statements(&mut body.statements)?;
+        self.end_scope(body);
+        Ok(())
+    }
+
+    fn begin_scope(&mut self, block: &Block) {
+        let mut pending = Vec::new();
+        for stmt in &block.statements {
+            match stmt {
+                Statement::VariableDecl { name, .. } => pending.push(name.clone()),
+                Statement::Destructuring { names, .. } => pending.extend(names.iter().cloned()),
+                _ => {}
+            }
+        }
+        self.scopes.push(Scope {
+            variables: Vec::new(),
+            pending,
+        });
+    }
+
+    /// Ends the scope of `block`, which holds the variables it declared.
+    fn end_scope(&mut self, block: &mut Block) {
+        let scope = self.scopes.pop().expect("no scope to end");
+        block.locals = Some(scope.variables.into_iter().map(|(name, _)| name).collect());
+    }
+
+    fn current(&mut self) -> &mut Scope {
+        self.scopes.last_mut().expect("no scope to declare in")
+    }
+
+    /// Declares a variable, which the interpreter defines in the slot of a
+    /// parameter of the same name if there is one.
+    fn declare(&mut self, name: &str) -> Result<(), ResolveError> {
+        if name == "_" {
+            return Ok(());
+        }
+        let scope = self.current();
+        if let Some(index) = scope.pending.iter().position(|n| n == name) {
+            scope.pending.remove(index);
+        }
+        match scope.variables.iter().find(|(n, _)| n == name) {
+            Some((_, true)) => Ok(()),
+            Some((_, false)) => Err(ResolveError::ConflictingDeclarations(name.to_string())),
+            None => {
+                scope.variables.push((name.to_string(), false));
+                Ok(())
+            }
+        }
+    }
+
+    fn block(&mut self, block: &mut Block) -> Result<(), ResolveError> {
+        self.begin_scope(block);
+        self.statements(&mut block.statements)?;
+        self.end_scope(block);
+        Ok(())
+    }
+
+    fn statements(&mut self, statements: &mut [Statement]) -> Result<(), ResolveError> {
+        statements
+            .iter_mut()
+            .try_for_each(|stmt| self.statement(stmt))
+    }
+
+    /// A branch or loop body, which runs in the scope around it unless it is a block.
+    fn branch(&mut self, stmt: &mut Statement) -> Result<(), ResolveError> {
+        match stmt {
+            Statement::VariableDecl { name, .. } => {
+                Err(ResolveError::DeclarationOutsideBlock(name.clone()))
+            }
+            Statement::Destructuring { names, .. } => {
+                Err(ResolveError::DeclarationOutsideBlock(names.join(", ")))
+            }
+            _ => self.statement(stmt),
+        }
+    }
+
+    fn statement(&mut self, stmt: &mut Statement) -> Result<(), ResolveError> {
+        match stmt {
+            Statement::Expression(expr) => self.expression(expr),
+            Statement::VariableDecl {
+                name, initializer, ..
+            } => {
+                if let Some(init) = initializer {
+                    self.expression(init)?;
+                }
+                self.declare(name)
+            }
+            Statement::Destructuring { names, initializer } => {
+                self.expression(initializer)?;
+                names.iter().try_for_each(|name| self.declare(name))
+            }
+            Statement::Return(expr) => match expr {
+                Some(expr) => self.expression(expr),
+                None => Ok(()),
+            },
+            Statement::Block(block) => self.block(block),
+            Statement::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.expression(condition)?;
+                self.branch(then_branch)?;
+                match else_branch {
+                    Some(els) => self.branch(els),
+                    None => Ok(()),
+                }
+            }
+            Statement::While { condition, body } => {
+                self.expression(condition)?;
+                self.branch(body)
+            }
+            // The condition sees the variables declared in the body
+            Statement::DoWhile { body, condition } => {
+                self.begin_scope(body);
+                self.statements(&mut body.statements)?;
+                self.expression(condition)?;
+                self.end_scope(body);
+                Ok(())
+            }
+            Statement::ForEach {
+                variable,
+                iterable,
+                body,
+            } => {
+                self.expression(iterable)?;
+                self.scopes.push(Scope::default());
+                match variable {
+                    ForEachTarget::Ident(name) => self.declare(name)?,
+                    ForEachTarget::Tuple(names) => {
+                        names.iter().try_for_each(|name| self.declare(name))?
+                    }
+                }
+                self.branch(body)?;
+                self.scopes.pop();
+                Ok(())
+            }
+        }
+    }
+
+    fn expression(&mut self, expr: &mut Expression) -> Result<(), ResolveError> {
+        match &mut *expr.kind {
+            ExpressionKind::Literal(_) => Ok(()),
+            ExpressionKind::Variable(name) => {
+                expr.slot = self.variable(name)?.filter(|_| self.annotate);
+                Ok(())
+            }
+            ExpressionKind::Assignment { target, value, .. } => {
+                self.expression(target)?;
+                self.expression(value)
+            }
+            ExpressionKind::Call { callee, arguments } => {
+                self.expression(callee)?;
+                arguments
+                    .iter_mut()
+                    .try_for_each(|arg| self.expression(arg))
+            }
+            ExpressionKind::Binary { left, right, .. } => {
+                self.expression(left)?;
+                self.expression(right)
+            }
+            ExpressionKind::Unary { operand, .. } => self.expression(operand),
+            ExpressionKind::MemberAccess { object, .. } => self.expression(object),
+            ExpressionKind::ArrayLiteral { elements } => elements
+                .iter_mut()
+                .try_for_each(|element| self.expression(element)),
+            ExpressionKind::Index { array, index } => {
+                self.expression(array)?;
+                self.expression(index)
+            }
+            ExpressionKind::HashMapLiteral { pairs } => {
+                pairs.iter_mut().try_for_each(|(key, value)| {
+                    self.expression(key)?;
+                    self.expression(value)
+                })
+            }
+            ExpressionKind::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.expression(condition)?;
+                self.block(then_branch)?;
+                match else_branch {
+                    Some(els) => self.block(els),
+                    None => Ok(()),
+                }
+            }
+            ExpressionKind::Lambda { params, body } => {
+                let params: Vec<&str> = params.iter().map(|param| param.name.as_str()).collect();
+                let function = std::mem::replace(&mut self.function, self.scopes.len());
+                let annotate = std::mem::replace(&mut self.annotate, false);
+                let resolved = self.body(&params, body);
+                self.function = function;
+                self.annotate = annotate;
+                resolved
+            }
+            ExpressionKind::SafeAccess { object, access } => {
+                self.expression(object)?;
+                self.scopes.push(Scope {
+                    variables: vec![(SAFE_RECEIVER.to_string(), true)],
+                    pending: Vec::new(),
+                });
+                self.expression(access)?;
+                self.scopes.pop();
+                Ok(())
+            }
+            ExpressionKind::Nullable(value) => self.expression(value),
+        }
+    }
+
+    /// The slot of a local variable of the current function, if it is one.
+    fn variable(&self, name: &str) -> Result<Option<ScopeSlot>, ResolveError> {
+        for (depth, scope) in self.scopes[self.function..].iter().rev().enumerate() {
+            if let Some(slot) = scope.variables.iter().position(|(n, _)| n == name) {
+                return Ok(Some(ScopeSlot {
+                    depth: depth as u32,
+                    slot: slot as u32,
+                }));
+            }
+            if scope.pending.iter().any(|n| n == name) {
+                return Err(ResolveError::UsedBeforeDeclaration(name.to_string()));
+            }
+        }
+        Ok(None)
+    }
+}
@@ -0,0 +1,462 @@
+//! Lazy sequences. Elements are computed as the sequence is iterated: a
+//! `sequence { }` body runs as a generator, a stack of suspended frames that
+//! resumes where the last `yield` left off. Coroutines suspend the same way.
+
+use crate::coroutine::Job;
+use crate::{Environment, Interpreter, RuntimeError, Value};
+use dotlin_ast::*;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+type Env = Rc<RefCell<Environment>>;
+
+#[derive(Debug)]
+pub enum Sequence {
+    /// `sequence { ... }`
+    Builder {
+        body: Rc<[Step]>,
+        closure: Env,
+    },
+    /// `array.asSequence()`
+    Elements(Vec<Value>),
+    Map(Rc<Sequence>, Value),
+    Filter(Rc<Sequence>, Value),
+    Take(Rc<Sequence>, usize),
+}
+
+/// A statement of a body that can suspend. Statements containing a call that
+/// suspends are broken up; the others run to completion.
+#[derive(Debug)]
+pub enum Step {
+    Run(Statement),
+    /// A call that suspends, such as `yield(x)`; its result is defined as the
+    /// variable `result` once the body resumes
+    Suspend {
+        call: Expression,
+        result: String,
+    },
+    Block(Rc<[Step]>),
+    If {
+        condition: Expression,
+        then_branch: Rc<[Step]>,
+        else_branch: Option<Rc<[Step]>>,
+    },
+    While {
+        condition: Expression,
+        body: Rc<[Step]>,
+    },
+    DoWhile {
+        body: Rc<[Step]>,
+        condition: Expression,
+    },
+    ForEach {
+        variable: ForEachTarget,
+        iterable: Expression,
+        body: Rc<[Step]>,
+    },
+}
+
+impl Step {
+    /// Compiles `statements`, in which the calls `suspends` picks are the ones
+    /// that suspend. Such calls are moved out of the statement evaluating them
+    /// into steps of their own.
+    pub fn compile(statements: &[Statement], suspends: &dyn Fn(&Expression) -> bool) -> Rc<[Step]> {
+        let mut steps = Vec::new();
+        let mut next = 0;
+        for stmt in statements {
+            Step::add(stmt, suspends, &mut next, &mut steps);
+        }
+        steps.into()
+    }
+
+    fn add(
+        stmt: &Statement,
+        suspends: &dyn Fn(&Expression) -> bool,
+        next: &mut usize,
+        steps: &mut Vec<Step>,
+    ) {
+        if !stmt.contains_call(suspends) {
+            steps.push(Step::Run(stmt.clone()));
+            return;
+        }
+        let (calls, stmt) = stmt.hoist_calls(suspends, next);
+        for (result, call) in calls {
+            steps.push(Step::Suspend { call, result });
+        }
+        if let Some(stmt) = stmt {
+            steps.push(Step::of(&stmt, suspends));
+        }
+    }
+
+    fn of(stmt: &Statement, suspends: &dyn Fn(&Expression) -> bool) -> Step {
+        if !stmt.contains_call(suspends) {
+            return Step::Run(stmt.clone());
+        }
+        let single = |stmt: &Statement| Step::compile(std::slice::from_ref(stmt), suspends);
+        match stmt {
+            Statement::Block(block) => Step::Block(Step::compile(&block.statements, suspends)),
+            Statement::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => Step::If {
+                condition: condition.clone(),
+                then_branch: single(then_branch),
+                else_branch: else_branch.as_deref().map(single),
+            },
+            Statement::While { condition, body } => Step::While {
+                condition: condition.clone(),
+                body: single(body),
+            },
+            Statement::DoWhile { body, condition } => Step::DoWhile {
+                body: Step::compile(&body.statements, suspends),
+                condition: condition.clone(),
+            },
+            Statement::ForEach {
+                variable,
+                iterable,
+                body,
+            } => Step::ForEach {
+                variable: variable.clone(),
+                iterable: iterable.clone(),
+                body: single(body),
+            },
+            stmt => Step::Run(stmt.clone()),
+        }
+    }
+}
+
+/// Why a suspendable body stopped.
+pub(crate) enum Suspension {
+    Yield(Value),
+    Delay(i64),
+    Await(Rc<Job>),
+    Join(Rc<Job>),
+    /// A call to a suspend function, which runs before the body resumes
+    Call(Value, Vec<Value>),
+}
+
+/// A suspended body, and where to define the result it resumes with.
+pub(crate) struct Suspended {
+    pub(crate) suspension: Suspension,
+    pub(crate) result: (String, Env),
+}
+
+/// Whether `expr` is a `yield(value)` call.
+pub(crate) fn is_yield(expr: &Expression) -> bool {
+    match &*expr.kind {
+        ExpressionKind::Call { callee, .. } => {
+            matches!(&*callee.kind, ExpressionKind::Variable(name) if name == "yield")
+        }
+        _ => false,
+    }
+}
+
+/// The state of an iteration over a sequence.
+pub(crate) enum SequenceIterator {
+    Generator(Vec<Frame>),
+    Elements(std::vec::IntoIter<Value>),
+    Map(Box<SequenceIterator>, Value),
+    Filter(Box<SequenceIterator>, Value),
+    Take(Box<SequenceIterator>, usize),
+}
+
+/// A suspended part of a generator.
+pub(crate) enum Frame {
+    /// Runs `steps` in order; `next` is the one to start next
+    Steps {
+        steps: Rc<[Step]>,
+        next: usize,
+        env: Env,
+    },
+    /// The loop at `steps[index]`, between two iterations
+    Loop {
+        steps: Rc<[Step]>,
+        index: usize,
+        env: Env,
+        iteration: Iteration,
+    },
+}
+
+pub(crate) enum Iteration {
+    While,
+    /// The scope of the previous iteration, which the condition sees
+    DoWhile(Option<Env>),
+    ForEach(SequenceIterator),
+}
+
+fn scope(env: &Env) -> Env {
+    Rc::new(RefCell::new(Environment::with_enclosing(env.clone())))
+}
+
+impl Interpreter {
+    pub(crate) fn iterate(&self, sequence: &Sequence) -> SequenceIterator {
+        match sequence {
+            Sequence::Builder { body, closure } => {
+                SequenceIterator::Generator(vec![Frame::Steps {
+                    steps: body.clone(),
+                    next: 0,
+                    env: scope(closure),
+                }])
+            }
+            Sequence::Elements(elements) => {
+                SequenceIterator::Elements(elements.clone().into_iter())
+            }
+            Sequence::Map(source, transform) => {
+                SequenceIterator::Map(Box::new(self.iterate(source)), transform.clone())
+            }
+            Sequence::Filter(source, predicate) => {
+                SequenceIterator::Filter(Box::new(self.iterate(source)), predicate.clone())
+            }
+            Sequence::Take(source, count) => {
+                SequenceIterator::Take(Box::new(self.iterate(source)), *count)
+            }
+        }
+    }
+
+    /// Computes the next element, pulling from the sources only as far as needed.
+    pub(crate) fn next_element(
+        &self,
+        iterator: &mut SequenceIterator,
+    ) -> Result<Option<Value>, RuntimeError> {
+        match iterator {
+            SequenceIterator::Generator(frames) => match self.resume(frames)? {
+                Some(Suspended {
+                    suspension: Suspension::Yield(value),
+                    ..
+                }) => Ok(Some(value)),
+                Some(_) => Err(RuntimeError::TypeMismatch(
+                    "A sequence can only suspend in yield".to_string(),
+                )),
+                None => Ok(None),
+            },
+            SequenceIterator::Elements(elements) => Ok(elements.next()),
+            SequenceIterator::Map(source, transform) => match self.next_element(source)? {
+                Some(element) => Ok(Some(self.call_value(transform.clone(), vec![element])?)),
+                None => Ok(None),
+            },
+            SequenceIterator::Filter(source, predicate) => {
+                while let Some(element) = self.next_element(source)? {
+                    let keep = self.call_value(predicate.clone(), vec![element.clone()])?;
+                    if matches!(keep, Value::Boolean(true)) {
+                        return Ok(Some(element));
+                    }
+                }
+                Ok(None)
+            }
+            SequenceIterator::Take(source, remaining) => {
+                if *remaining == 0 {
+                    return Ok(None);
+                }
+                *remaining -= 1;
+                self.next_element(source)
+            }
+        }
+    }
+
+    /// The elements a `for` loop over `value` visits.
+    pub(crate) fn loop_elements(
+        &self,
+        value: Value,
+        variable: &ForEachTarget,
+    ) -> Result<SequenceIterator, RuntimeError> {
+        let elements = match value {
+            Value::Array(elements) => elements.borrow().clone(),
+            Value::HashMap(map) => {
+                let map = map.borrow().clone();
+                match variable {
+                    ForEachTarget::Tuple(_) => map
+                        .into_iter()
+                        .map(|(key, value)| Value::Tuple(vec![Value::String(key), value]))
+                        .collect(),
+                    ForEachTarget::Ident(_) => map.into_keys().map(Value::String).collect(),
+                }
+            }
+            Value::Sequence(sequence) => return Ok(self.iterate(&sequence)),
+            _ => {
+                return Err(RuntimeError::TypeMismatch(
+                    "ForEach iterable must be an array or HashMap".to_string(),
+                ))
+            }
+        };
+        Ok(SequenceIterator::Elements(elements.into_iter()))
+    }
+
+    /// Runs a suspendable body up to its next suspension, or to its end.
+    pub(crate) fn resume(
+        &self,
+        frames: &mut Vec<Frame>,
+    ) -> Result<Option<Suspended>, RuntimeError> {
+        while let Some(frame) = frames.last_mut() {
+            match frame {
+                Frame::Steps { steps, next, env } => {
+                    if *next == steps.len() {
+                        frames.pop();
+                        continue;
+                    }
+                    let (steps, index, env) = (steps.clone(), *next, env.clone());
+                    *next += 1;
+                    if let Some(suspended) = self.start_step(steps, index, env, frames)? {
+                        return Ok(Some(suspended));
+                    }
+                }
+                Frame::Loop {
+                    steps,
+                    index,
+                    env,
+                    iteration,
+                } => {
+                    let (steps, env) = (steps.clone(), env.clone());
+                    let body = match (&steps[*index], iteration) {
+                        (Step::While { condition, body }, Iteration::While) => self
+                            .condition(condition, env.clone())?
+                            .then(|| (body.clone(), env)),
+                        (Step::DoWhile { body, condition }, Iteration::DoWhile(previous)) => {
+                            let more = match previous {
+                                Some(body_env) => self.condition(condition, body_env.clone())?,
+                                None => true,
+                            };
+                            more.then(|| {
+                                let body_env = scope(&env);
+                                *previous = Some(body_env.clone());
+                                (body.clone(), body_env)
+                            })
+                        }
+                        (Step::ForEach { variable, body, .. }, Iteration::ForEach(elements)) => {
+                            match self.next_element(elements)? {
+                                Some(element) => {
+                                    let body_env = scope(&env);
+                                    Self::bind_loop_variable(variable, element, &body_env)?;
+                                    Some((body.clone(), body_env))
+                                }
+                                None => None,
+                            }
+                        }
+                        _ => unreachable!("loop frames are only pushed for loops"),
+                    };
+                    match body {
+                        Some((steps, env)) => frames.push(Frame::Steps {
+                            steps,
+                            next: 0,
+                            env,
+                        }),
+                        None => {
+                            frames.pop();
+                        }
+                    }
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Starts `steps[index]`: runs it, or pushes the frames that will.
+    fn start_step(
+        &self,
+        steps: Rc<[Step]>,
+        index: usize,
+        env: Env,
+        frames: &mut Vec<Frame>,
+    ) -> Result<Option<Suspended>, RuntimeError> {
+        let iteration = match &steps[index] {
+            Step::Run(stmt) => {
+                self.execute_statement(stmt, env)?;
+                return Ok(None);
+            }
+            Step::Suspend { call, result } => {
+                return Ok(Some(Suspended {
+                    suspension: self.suspension(call, env.clone())?,
+                    result: (result.clone(), env),
+                }))
+            }
+            Step::Block(body) => {
+                frames.push(Frame::Steps {
+                    steps: body.clone(),
+                    next: 0,
+                    env: scope(&env),
+                });
+                return Ok(None);
+            }
+            Step::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                let branch = if self.condition(condition, env.clone())? {
+                    Some(then_branch)
+                } else {
+                    else_branch.as_ref()
+                };
+                if let Some(branch) = branch {
+                    frames.push(Frame::Steps {
+                        steps: branch.clone(),
+                        next: 0,
+                        env,
+                    });
+                }
+                return Ok(None);
+            }
+            Step::While { .. } => Iteration::While,
+            Step::DoWhile { .. } => Iteration::DoWhile(None),
+            Step::ForEach {
+                variable, iterable, ..
+            } => {
+                let value = self.evaluate_expression(iterable, env.clone())?;
+                Iteration::ForEach(self.loop_elements(value, variable)?)
+            }
+        };
+        frames.push(Frame::Loop {
+            steps,
+            index,
+            env,
+            iteration,
+        });
+        Ok(None)
+    }
+
+    /// Evaluates the operands of a call that suspends.
+    fn suspension(&self, call: &Expression, env: Env) -> Result<Suspension, RuntimeError> {
+        let ExpressionKind::Call { callee, arguments } = &*call.kind else {
+            unreachable!("only calls suspend");
+        };
+        if let ExpressionKind::MemberAccess { object, member } = &*callee.kind {
+            return match self.evaluate_expression(object, env)? {
+                Value::Job(job) if member == "await" => Ok(Suspension::Await(job)),
+                Value::Job(job) => Ok(Suspension::Join(job)),
+                other => Err(RuntimeError::TypeMismatch(format!(
+                    "Method '{}' not found on {}",
+                    member, other
+                ))),
+            };
+        }
+        let mut args = Vec::new();
+        for arg in arguments {
+            args.push(self.evaluate_expression(arg, env.clone())?);
+        }
+        match &*callee.kind {
+            ExpressionKind::Variable(name) if name == "yield" => {
+                Ok(Suspension::Yield(args.pop().unwrap_or(Value::Void)))
+            }
+            ExpressionKind::Variable(name) if name == "delay" => match args.as_slice() {
+                [Value::Long(ms)] => Ok(Suspension::Delay(*ms)),
+                [Value::Integer(ms)] => Ok(Suspension::Delay(*ms as i64)),
+                _ => Err(RuntimeError::TypeMismatch(
+                    "delay takes a Long number of milliseconds".to_string(),
+                )),
+            },
+            _ => Ok(Suspension::Call(
+                self.evaluate_expression(callee, env)?,
+                args,
+            )),
+        }
+    }
+
+    fn condition(&self, condition: &Expression, env: Env) -> Result<bool, RuntimeError> {
+        match self.evaluate_expression(condition, env)? {
+            Value::Boolean(b) => Ok(b),
+            _ => Err(RuntimeError::TypeMismatch(
+                "Condition must be boolean".to_string(),
+            )),
+        }
+    }
+}
@@ -0,0 +1,189 @@
+//! Threads, atomics, mutexes and channels. The interpreter runs threads one
+//! at a time on the current thread: a thread starts once another would
+//! otherwise wait for it, to join it or to receive from an empty channel, and
+//! then runs until it completes. Like Kotlin, the program ends once every
+//! thread has completed, so threads nobody waited for run after `main`.
+//!
+//! A thread that waits for one that has started but not completed can't go
+//! on, since that thread is suspended below it; this is reported as a
+//! deadlock, even where real threads would eventually make progress.
+
+use crate::{Interpreter, RuntimeError, Value};
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+/// What `thread { }` returns.
+#[derive(Debug)]
+pub struct Thread {
+    state: RefCell<ThreadState>,
+}
+
+#[derive(Debug)]
+enum ThreadState {
+    /// Not started yet, with its body
+    Pending(Value),
+    Running,
+    Completed,
+}
+
+/// A `Mutex`. It is not reentrant: locking it twice deadlocks.
+#[derive(Debug, Default)]
+pub struct Mutex {
+    locked: Cell<bool>,
+}
+
+/// A `Channel` with an unlimited buffer, so sending never waits.
+#[derive(Debug, Default)]
+pub struct Channel {
+    queue: RefCell<VecDeque<Value>>,
+    closed: Cell<bool>,
+}
+
+impl Interpreter {
+    /// `thread { body }`
+    pub(crate) fn start_thread(&self, body: Value) -> Value {
+        let thread = Rc::new(Thread {
+            state: RefCell::new(ThreadState::Pending(body)),
+        });
+        self.threads.borrow_mut().push_back(thread.clone());
+        Value::Thread(thread)
+    }
+
+    /// Runs every thread that hasn't started yet, including the ones they start.
+    pub(crate) fn join_all_threads(&self) -> Result<(), RuntimeError> {
+        while self.run_next_thread()? {}
+        Ok(())
+    }
+
+    /// Runs the thread started longest ago that hasn't run yet, if any.
+    fn run_next_thread(&self) -> Result<bool, RuntimeError> {
+        let next = self.threads.borrow_mut().pop_front();
+        match next {
+            Some(thread) => self.run_thread(&thread).map(|_| true),
+            None => Ok(false),
+        }
+    }
+
+    fn run_thread(&self, thread: &Thread) -> Result<(), RuntimeError> {
+        let state = thread.state.replace(ThreadState::Running);
+        let ThreadState::Pending(body) = state else {
+            thread.state.replace(state);
+            return Ok(());
+        };
+        self.call_value(body, Vec::new())?;
+        thread.state.replace(ThreadState::Completed);
+        Ok(())
+    }
+
+    fn join_thread(&self, thread: &Rc<Thread>) -> Result<(), RuntimeError> {
+        if matches!(*thread.state.borrow(), ThreadState::Running) {
+            return Err(RuntimeError::Deadlock(
+                "joining a thread that is waiting".to_string(),
+            ));
+        }
+        self.threads
+            .borrow_mut()
+            .retain(|pending| !Rc::ptr_eq(pending, thread));
+        self.run_thread(thread)
+    }
+
+    /// Receives the next element of a channel, running other threads until
+    /// one is sent. Returns `None` once the channel is closed and empty.
+    pub(crate) fn receive(&self, channel: &Channel) -> Result<Option<Value>, RuntimeError> {
+        loop {
+            if let Some(element) = channel.queue.borrow_mut().pop_front() {
+                return Ok(Some(element));
+            }
+            if channel.closed.get() {
+                return Ok(None);
+            }
+            if !self.run_next_thread()? {
+                return Err(RuntimeError::Deadlock(
+                    "receiving from a channel no thread sends to".to_string(),
+                ));
+            }
+        }
+    }
+
+    /// Calls a method of a thread, an atomic, a mutex or a channel.
+    pub(crate) fn call_concurrency_method(
+        &self,
+        object: Value,
+        method: &str,
+        args: Vec<Value>,
+    ) -> Result<Value, RuntimeError> {
+        match (object, method, args.as_slice()) {
+            (Value::Thread(thread), "join", []) => self.join_thread(&thread).map(|_| Value::Void),
+
+            (Value::AtomicInt(value), "get", []) => Ok(Value::Integer(value.get())),
+            (Value::AtomicInt(value), "set", [Value::Integer(new)]) => {
+                value.set(*new);
+                Ok(Value::Void)
+            }
+            (Value::AtomicInt(value), "incrementAndGet", []) => Ok(Value::Integer(add(&value, 1))),
+            (Value::AtomicInt(value), "decrementAndGet", []) => Ok(Value::Integer(add(&value, -1))),
+            (Value::AtomicInt(value), "addAndGet", [Value::Integer(delta)]) => {
+                Ok(Value::Integer(add(&value, *delta)))
+            }
+            (
+                Value::AtomicInt(value),
+                "compareAndSet",
+                [Value::Integer(expected), Value::Integer(new)],
+            ) => {
+                let swapped = value.get() == *expected;
+                if swapped {
+                    value.set(*new);
+                }
+                Ok(Value::Boolean(swapped))
+            }
+
+            (Value::Mutex(mutex), "lock", []) => lock(&mutex).map(|_| Value::Void),
+            (Value::Mutex(mutex), "unlock", []) => {
+                mutex.locked.set(false);
+                Ok(Value::Void)
+            }
+            (Value::Mutex(mutex), "withLock", [action]) => {
+                lock(&mutex)?;
+                let result = self.call_value(action.clone(), Vec::new());
+                mutex.locked.set(false);
+                result
+            }
+
+            (Value::Channel(channel), "send", [element]) => {
+                if channel.closed.get() {
+                    return Err(RuntimeError::ChannelClosed { send: true });
+                }
+                channel.queue.borrow_mut().push_back(element.clone());
+                Ok(Value::Void)
+            }
+            (Value::Channel(channel), "receive", []) => self
+                .receive(&channel)?
+                .ok_or(RuntimeError::ChannelClosed { send: false }),
+            (Value::Channel(channel), "close", []) => {
+                channel.closed.set(true);
+                Ok(Value::Void)
+            }
+
+            (object, method, _) => Err(RuntimeError::TypeMismatch(format!(
+                "Method '{}' not found on type {:?}",
+                method, object
+            ))),
+        }
+    }
+}
+
+/// Adds `delta` to an `AtomicInt`, wrapping like `Int` arithmetic.
+fn add(value: &Cell<i32>, delta: i32) -> i32 {
+    value.set(value.get().wrapping_add(delta));
+    value.get()
+}
+
+fn lock(mutex: &Mutex) -> Result<(), RuntimeError> {
+    if mutex.locked.replace(true) {
+        return Err(RuntimeError::Deadlock(
+            "locking a mutex that is already locked".to_string(),
+        ));
+    }
+    Ok(())
+}
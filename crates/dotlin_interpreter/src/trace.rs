@@ -0,0 +1,107 @@
+//! The calls a runtime error unwound through, recorded as it does so that
+//! an uncaught error can be reported like a Kotlin exception:
+//!
+//! ```text
+//! Runtime Error: ArithmeticException: / by zero
+//!     at divide(main.lin:2)
+//!     at main(main.lin:6)
+//! ```
+
+use crate::RuntimeError;
+use dotlin_ast::FunctionDecl;
+use std::cell::{Cell, RefCell};
+
+/// A call in progress when the error happened.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StackFrame {
+    pub function: String,
+    pub file: Option<String>,
+    /// Where the call was when the error reached it, if known
+    pub line: Option<u32>,
+}
+
+impl std::fmt::Display for StackFrame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let file = self.file.as_deref().unwrap_or("Unknown Source");
+        match self.line {
+            Some(line) => write!(f, "at {}({}:{})", self.function, file, line),
+            None => write!(f, "at {}({})", self.function, file),
+        }
+    }
+}
+
+/// The frames of an error, innermost first.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StackTrace {
+    pub frames: Vec<StackFrame>,
+}
+
+impl StackTrace {
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+}
+
+/// One frame per line, indented like the JVM's.
+impl std::fmt::Display for StackTrace {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for frame in &self.frames {
+            writeln!(f, "\t{}", frame)?;
+        }
+        Ok(())
+    }
+}
+
+/// The trace of the error unwinding now, or of the last one.
+#[derive(Debug, Default)]
+pub(crate) struct Unwinding {
+    frames: RefCell<Vec<StackFrame>>,
+    /// The line of the innermost expression the error left in the current
+    /// call, until the call records its frame
+    line: Cell<Option<u32>>,
+}
+
+impl Unwinding {
+    /// Notes that `error` left an expression on `line`, unless an inner one
+    /// of the same call was already noted.
+    #[inline]
+    pub(crate) fn leave_expression(&self, error: &RuntimeError, line: u32) {
+        if line != 0 && self.line.get().is_none() && !matches!(error, RuntimeError::Return(_)) {
+            self.line.set(Some(line));
+        }
+    }
+
+    /// Records the frame of a call of `function` that `error` left.
+    pub(crate) fn leave_call(&self, error: &RuntimeError, function: &FunctionDecl) {
+        if matches!(error, RuntimeError::Return(_)) {
+            return;
+        }
+        self.push(StackFrame {
+            function: function.name.clone(),
+            file: function.file.clone(),
+            line: self.line.take(),
+        });
+    }
+
+    /// Records the frame of a call the interpreter doesn't run itself, such
+    /// as a function compiled for the bytecode VM.
+    pub(crate) fn push(&self, frame: StackFrame) {
+        self.line.set(None);
+        self.frames.borrow_mut().push(frame);
+    }
+
+    /// Takes the frames recorded so far. A lambda has no file of its own, so
+    /// it is shown in the file of its caller.
+    pub(crate) fn take(&self) -> StackTrace {
+        self.line.set(None);
+        let mut frames = self.frames.take();
+        let mut file = None;
+        for frame in frames.iter_mut().rev() {
+            match &frame.file {
+                Some(name) => file = Some(name.clone()),
+                None => frame.file.clone_from(&file),
+            }
+        }
+        StackTrace { frames }
+    }
+}
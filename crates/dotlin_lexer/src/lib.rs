@@ -3,8 +3,26 @@ use logos::Logos;
 #[derive(Logos, Debug, PartialEq, Clone)]
 #[logos(skip r"[ \t\r\n\f]+|//.*|/\*([^*]|\*[^/])*\*/")] // Skip whitespace and comments
 pub enum Token {
+    #[token("package")]
+    Package,
+    #[token("import")]
+    Import,
+
+    #[token("public")]
+    Public,
+    #[token("internal")]
+    Internal,
+    #[token("private")]
+    Private,
+
+    #[token("suspend")]
+    Suspend,
     #[token("fun")]
     Fun,
+    #[token("object")]
+    Object,
+    #[token("class")]
+    Class,
 
     #[token("val")]
     Val,
@@ -18,6 +36,8 @@ pub enum Token {
     Else,
     #[token("while")]
     While,
+    #[token("do")]
+    Do,
     #[token("for")]
     For,
     #[token("in")]
@@ -27,6 +47,8 @@ pub enum Token {
     True,
     #[token("false")]
     False,
+    #[token("null")]
+    Null,
 
     #[token("return")]
     Return,
@@ -40,12 +62,26 @@ pub enum Token {
     #[regex("'([^'\\\\]|\\[\\'bnfrt]|u[a-fA-F0-9]{4})'", |lex| lex.slice().chars().nth(1).unwrap_or('\0'))]
     Char(char),
 
-    #[regex("-?[0-9]+\\.[0-9]+", |lex| lex.slice().parse().map_err(|_| ()))]
-    Float(f64),
+    #[regex("-?[0-9][0-9_]*\\.[0-9][0-9_]*", |lex| lex.slice().replace('_', "").parse().map_err(|_| ()))]
+    Double(f64),
 
-    #[regex("-?[0-9]+", |lex| lex.slice().parse().map_err(|_| ()))]
+    #[regex("-?[0-9][0-9_]*(\\.[0-9][0-9_]*)?[fF]", |lex| lex.slice()[..lex.slice().len() - 1].replace('_', "").parse().map_err(|_| ()))]
+    Float(f32),
+
+    /// Decimal, `0x` hexadecimal or `0b` binary, with optional `_` separators
+    #[regex("-?(0[xX][0-9a-fA-F_]+|0[bB][01_]+|[0-9][0-9_]*)", |lex| parse_signed(lex.slice()))]
     Integer(i64),
 
+    #[regex("-?(0[xX][0-9a-fA-F_]+|0[bB][01_]+|[0-9][0-9_]*)L", |lex| parse_signed(&lex.slice()[..lex.slice().len() - 1]))]
+    Long(i64),
+
+    /// `10u`: a `UInt`, or a `ULong` if it does not fit in 32 bits
+    #[regex("(0[xX][0-9a-fA-F_]+|0[bB][01_]+|[0-9][0-9_]*)[uU]", |lex| parse_unsigned(&lex.slice()[..lex.slice().len() - 1]))]
+    Unsigned(u64),
+
+    #[regex("(0[xX][0-9a-fA-F_]+|0[bB][01_]+|[0-9][0-9_]*)[uU]L", |lex| parse_unsigned(&lex.slice()[..lex.slice().len() - 2]))]
+    UnsignedLong(u64),
+
     // Symbols
     #[token("(")]
     LParen,
@@ -71,6 +107,8 @@ pub enum Token {
     Minus,
     #[token("-=")]
     MinusEqual,
+    #[token("->")]
+    Arrow,
     #[token("*")]
     Star,
     #[token("*=")]
@@ -79,12 +117,20 @@ pub enum Token {
     Slash,
     #[token("/=")]
     SlashEqual,
+    #[token("%")]
+    Percent,
+    #[token("%=")]
+    PercentEqual,
     #[token("=")]
     Equal,
     #[token("==")]
     DoubleEqual,
     #[token("!=")]
     NotEqual,
+    #[token("===")]
+    TripleEqual,
+    #[token("!==")]
+    NotDoubleEqual,
     #[token("&&")]
     And,
     #[token("||")]
@@ -105,32 +151,92 @@ pub enum Token {
     Decrement,
     #[token(".")]
     Dot,
+    #[token("?")]
+    Question,
+    #[token("@")]
+    At,
+    #[token("?.")]
+    SafeDot,
+    #[token("?:")]
+    Elvis,
 
     // Error fallback
     // In Logos 0.14+, we handle errors by checking the Result from next()
     Error,
 }
 
+/// Parses the digits of an integer literal, honouring `0x`/`0b` prefixes and `_` separators.
+fn parse_unsigned(literal: &str) -> Result<u64, ()> {
+    let digits = literal.replace('_', "");
+    let (radix, digits) = match digits.get(..2) {
+        Some("0x" | "0X") => (16, &digits[2..]),
+        Some("0b" | "0B") => (2, &digits[2..]),
+        _ => (10, &digits[..]),
+    };
+    u64::from_str_radix(digits, radix).map_err(|_| ())
+}
+
+fn parse_signed(literal: &str) -> Result<i64, ()> {
+    match literal.strip_prefix('-') {
+        Some(magnitude) => {
+            let value = parse_unsigned(magnitude)?;
+            0i64.checked_sub_unsigned(value).ok_or(())
+        }
+        None => i64::try_from(parse_unsigned(literal)?).map_err(|_| ()),
+    }
+}
+
+#[derive(Clone)]
 pub struct Lexer<'a> {
     inner: logos::Lexer<'a, Token>,
+    /// The line, counted from 1, of the token `next` returned last
+    line: u32,
+    /// How much of the input `line` has counted the line breaks of
+    counted: usize,
 }
 
 impl<'a> Lexer<'a> {
     pub fn new(input: &'a str) -> Self {
         Self {
             inner: Token::lexer(input),
+            line: 1,
+            counted: 0,
         }
     }
+
+    /// The line, counted from 1, of the token `next` returned last.
+    pub fn line(&self) -> u32 {
+        self.line
+    }
+
+    /// The tokens, each with the line it starts on.
+    pub fn with_lines(self) -> LineTokens<'a> {
+        LineTokens(self)
+    }
 }
 
 impl<'a> Iterator for Lexer<'a> {
     type Item = Token;
 
     fn next(&mut self) -> Option<Self::Item> {
-        match self.inner.next() {
-            Some(Ok(token)) => Some(token),
-            Some(Err(_)) => Some(Token::Error),
-            None => None,
-        }
+        let token = self.inner.next()?;
+        let start = self.inner.span().start;
+        let skipped = &self.inner.source().as_bytes()[self.counted..start];
+        self.line += skipped.iter().filter(|&&byte| byte == b'\n').count() as u32;
+        self.counted = start;
+        Some(token.unwrap_or(Token::Error))
+    }
+}
+
+/// Tokens paired with the line each starts on.
+#[derive(Clone)]
+pub struct LineTokens<'a>(Lexer<'a>);
+
+impl<'a> Iterator for LineTokens<'a> {
+    type Item = (Token, u32);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let token = self.0.next()?;
+        Some((token, self.0.line))
     }
 }
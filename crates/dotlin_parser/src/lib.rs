@@ -1,5 +1,5 @@
 use dotlin_ast::*;
-use dotlin_lexer::{Lexer, Token};
+use dotlin_lexer::{Lexer, LineTokens, Token};
 use std::iter::Peekable;
 
 #[derive(Debug, thiserror::Error)]
@@ -10,6 +10,8 @@ pub enum ParseError {
     UnexpectedEOF,
     #[error("Expected identifier, found: {0:?}")]
     ExpectedIdentifier(Token),
+    #[error("Invalid assignment target")]
+    InvalidAssignmentTarget,
 }
 
 #[derive(Debug)]
@@ -19,22 +21,42 @@ pub enum ReplNode {
 }
 
 pub struct Parser<'a> {
-    lexer: Peekable<Lexer<'a>>,
+    lexer: Peekable<LineTokens<'a>>,
+    /// Off while parsing the iterable of `for (x) in items { ... }`, whose body
+    /// would otherwise be taken for a trailing lambda
+    trailing_lambdas: bool,
+    /// The name of the file being parsed, which its functions record
+    file: Option<String>,
 }
 
 impl<'a> Parser<'a> {
     pub fn new(code: &'a str) -> Self {
         Self {
-            lexer: Lexer::new(code).peekable(),
+            lexer: Lexer::new(code).with_lines().peekable(),
+            trailing_lambdas: true,
+            file: None,
+        }
+    }
+
+    /// A parser of the contents of the file `file`, named in stack traces.
+    pub fn with_file(code: &'a str, file: impl Into<String>) -> Self {
+        Self {
+            file: Some(file.into()),
+            ..Self::new(code)
         }
     }
 
     fn peek(&mut self) -> Option<&Token> {
-        self.lexer.peek()
+        self.lexer.peek().map(|(token, _)| token)
     }
 
     fn advance(&mut self) -> Option<Token> {
-        self.lexer.next()
+        self.lexer.next().map(|(token, _)| token)
+    }
+
+    /// The line of the next token.
+    fn line(&mut self) -> u32 {
+        self.lexer.peek().map_or(0, |&(_, line)| line)
     }
 
     fn expect(&mut self, expected: Token) -> Result<(), ParseError> {
@@ -46,25 +68,341 @@ impl<'a> Parser<'a> {
     }
 
     pub fn parse_program(&mut self) -> Result<Program, ParseError> {
+        let package = self.parse_package()?;
+
+        let mut imports = Vec::new();
+        while self.peek() == Some(&Token::Import) {
+            imports.push(self.parse_import()?);
+        }
+
         let mut declarations = Vec::new();
         while self.peek().is_some() {
             declarations.push(self.parse_declaration()?);
         }
-        Ok(Program { declarations })
+        Ok(Program {
+            package,
+            imports,
+            declarations,
+        })
+    }
+
+    /// Parses the `package a.b` header alone; `None` for the default package.
+    pub fn parse_package(&mut self) -> Result<Option<String>, ParseError> {
+        if self.peek() != Some(&Token::Package) {
+            return Ok(None);
+        }
+        self.advance();
+        self.parse_dotted_name().map(Some)
+    }
+
+    fn parse_identifier(&mut self) -> Result<String, ParseError> {
+        match self.advance() {
+            Some(Token::Identifier(name)) => Ok(name),
+            Some(token) => Err(ParseError::ExpectedIdentifier(token)),
+            None => Err(ParseError::UnexpectedEOF),
+        }
+    }
+
+    /// `a.b.c`
+    fn parse_dotted_name(&mut self) -> Result<String, ParseError> {
+        let mut name = self.parse_identifier()?;
+        while self.peek() == Some(&Token::Dot) {
+            self.advance();
+            name.push('.');
+            name.push_str(&self.parse_identifier()?);
+        }
+        Ok(name)
+    }
+
+    /// `import a.b.foo` or `import a.b.*`
+    fn parse_import(&mut self) -> Result<Import, ParseError> {
+        self.expect(Token::Import)?;
+        let mut path = self.parse_identifier()?;
+        while self.peek() == Some(&Token::Dot) {
+            self.advance();
+            if self.peek() == Some(&Token::Star) {
+                self.advance();
+                return Ok(Import {
+                    path,
+                    wildcard: true,
+                });
+            }
+            path.push('.');
+            path.push_str(&self.parse_identifier()?);
+        }
+        Ok(Import {
+            path,
+            wildcard: false,
+        })
     }
 
     fn parse_declaration(&mut self) -> Result<Declaration, ParseError> {
+        // Annotations such as `@JvmInline` don't change what a declaration means here
+        while self.peek() == Some(&Token::At) {
+            self.advance();
+            self.parse_identifier()?;
+        }
+        let visibility = self.parse_visibility();
         match self.peek() {
-            Some(Token::Fun) => {
-                let func = self.parse_function()?;
+            Some(Token::Fun | Token::Suspend) => {
+                let func = self.parse_function(visibility)?;
                 Ok(Declaration::Function(func))
             }
+            Some(Token::Object) => Ok(Declaration::Object(self.parse_object(visibility)?)),
+            Some(Token::Identifier(id)) if id == "typealias" => {
+                self.advance();
+                let name = self.parse_identifier()?;
+                self.expect(Token::Equal)?;
+                let typ = self.parse_type()?;
+                Ok(Declaration::TypeAlias(TypeAliasDecl {
+                    visibility,
+                    name,
+                    typ,
+                }))
+            }
+            Some(Token::Identifier(id)) if id == "value" => {
+                Ok(Declaration::ValueClass(self.parse_value_class(visibility)?))
+            }
             Some(token) => Err(ParseError::UnexpectedToken(token.clone())),
             None => Err(ParseError::UnexpectedEOF),
         }
     }
 
-    fn parse_function(&mut self) -> Result<FunctionDecl, ParseError> {
+    fn parse_visibility(&mut self) -> Visibility {
+        let visibility = match self.peek() {
+            Some(Token::Public) => Visibility::Public,
+            Some(Token::Internal) => Visibility::Internal,
+            Some(Token::Private) => Visibility::Private,
+            _ => return Visibility::Public,
+        };
+        self.advance();
+        visibility
+    }
+
+    /// `object Name { val x = 1  fun f() {} }`
+    fn parse_object(&mut self, visibility: Visibility) -> Result<ObjectDecl, ParseError> {
+        self.expect(Token::Object)?;
+        let name = self.parse_identifier()?;
+        self.parse_object_body(visibility, name)
+    }
+
+    fn parse_object_body(
+        &mut self,
+        visibility: Visibility,
+        name: String,
+    ) -> Result<ObjectDecl, ParseError> {
+        self.expect(Token::LBrace)?;
+
+        let mut properties = Vec::new();
+        let mut functions = Vec::new();
+        while self.peek() != Some(&Token::RBrace) {
+            let member_visibility = self.parse_visibility();
+            match self.peek() {
+                Some(Token::Fun | Token::Suspend) => {
+                    functions.push(self.parse_function(member_visibility)?)
+                }
+                Some(Token::Identifier(id)) if id == "operator" => {
+                    functions.push(self.parse_function(member_visibility)?)
+                }
+                Some(Token::Val | Token::Var) => {
+                    properties.push(self.parse_property(member_visibility)?)
+                }
+                Some(Token::Identifier(id)) if id == "lateinit" => {
+                    properties.push(self.parse_property(member_visibility)?)
+                }
+                Some(token) => return Err(ParseError::UnexpectedToken(token.clone())),
+                None => return Err(ParseError::UnexpectedEOF),
+            }
+        }
+        self.expect(Token::RBrace)?;
+
+        Ok(ObjectDecl {
+            visibility,
+            name,
+            properties,
+            functions,
+        })
+    }
+
+    /// `value class Name(val v: Type) { fun f() {} }`; the body is optional
+    /// and may hold a `companion object { ... }`.
+    fn parse_value_class(&mut self, visibility: Visibility) -> Result<ValueClassDecl, ParseError> {
+        self.advance(); // value
+        self.expect(Token::Class)?;
+        let name = self.parse_identifier()?;
+        self.expect(Token::LParen)?;
+        self.expect(Token::Val)?;
+        let property = self.parse_identifier()?;
+        self.expect(Token::Colon)?;
+        let typ = self.parse_type()?;
+        self.expect(Token::RParen)?;
+
+        let mut functions = Vec::new();
+        let mut companion = None;
+        if self.peek() == Some(&Token::LBrace) {
+            self.advance();
+            while self.peek() != Some(&Token::RBrace) {
+                let member_visibility = self.parse_visibility();
+                if self.peek_contextual("companion") && companion.is_none() {
+                    self.advance();
+                    self.expect(Token::Object)?;
+                    companion = Some(self.parse_object_body(member_visibility, name.clone())?);
+                    continue;
+                }
+                let mut func = self.parse_function(member_visibility)?;
+                func.params.insert(
+                    0,
+                    Param {
+                        name: "this".to_string(),
+                        typ: Type::Named(name.clone()),
+                    },
+                );
+                functions.push(func);
+            }
+            self.expect(Token::RBrace)?;
+        }
+
+        Ok(ValueClassDecl {
+            visibility,
+            name,
+            property: Param {
+                name: property,
+                typ,
+            },
+            functions,
+            companion,
+        })
+    }
+
+    /// An object property: `val x = 1`, `var x: Int by Store`,
+    /// `lateinit var x: String` or `val x: Int get() = 1`. Accessors follow
+    /// the property in either order.
+    fn parse_property(&mut self, visibility: Visibility) -> Result<PropertyDecl, ParseError> {
+        let lateinit = self.peek_contextual("lateinit");
+        if lateinit {
+            self.advance();
+            self.expect(Token::Var)?;
+        }
+        let mutable = lateinit || self.advance() == Some(Token::Var);
+        let name = self.parse_identifier()?;
+        let typ = if self.peek() == Some(&Token::Colon) {
+            self.advance();
+            Some(self.parse_type()?)
+        } else {
+            None
+        };
+
+        let mut initializer = None;
+        let mut delegate = None;
+        if lateinit {
+            // A `lateinit` property is assigned later
+        } else if self.peek() == Some(&Token::Equal) {
+            self.advance();
+            initializer = Some(self.parse_expression()?);
+        } else if self.peek_contextual("by") {
+            self.advance();
+            delegate = Some(self.parse_expression()?);
+        }
+
+        let mut getter = None;
+        let mut setter = None;
+        while !lateinit && delegate.is_none() {
+            if getter.is_none() && self.peek_contextual("get") {
+                self.advance();
+                self.expect(Token::LParen)?;
+                self.expect(Token::RParen)?;
+                let (body, expression_body) = self.parse_accessor_body()?;
+                getter = Some(FunctionDecl {
+                    visibility,
+                    name: PropertyDecl::getter_name(&name),
+                    params: Vec::new(),
+                    return_type: typ.clone(),
+                    body,
+                    expression_body,
+                    is_suspend: false,
+                    is_operator: false,
+                    file: self.file.clone(),
+                });
+            } else if mutable && setter.is_none() && self.peek_contextual("set") {
+                self.advance();
+                self.expect(Token::LParen)?;
+                let param = self.parse_identifier()?;
+                let param_type = if self.peek() == Some(&Token::Colon) {
+                    self.advance();
+                    self.parse_type()?
+                } else {
+                    typ.clone().unwrap_or(Type::Named("_".to_string()))
+                };
+                self.expect(Token::RParen)?;
+                let (mut body, expression_body) = self.parse_accessor_body()?;
+                if expression_body {
+                    // A setter's expression body is evaluated for its effect
+                    if let Some(Statement::Return(Some(expr))) = body.statements.pop() {
+                        body.statements.push(Statement::Expression(expr));
+                    }
+                }
+                setter = Some(FunctionDecl {
+                    visibility,
+                    name: PropertyDecl::setter_name(&name),
+                    params: vec![Param {
+                        name: param,
+                        typ: param_type,
+                    }],
+                    return_type: None,
+                    body,
+                    expression_body,
+                    is_suspend: false,
+                    is_operator: false,
+                    file: self.file.clone(),
+                });
+            } else {
+                break;
+            }
+        }
+
+        if initializer.is_none() && delegate.is_none() && getter.is_none() && !lateinit {
+            return Err(match self.peek() {
+                Some(token) => ParseError::UnexpectedToken(token.clone()),
+                None => ParseError::UnexpectedEOF,
+            });
+        }
+        Ok(PropertyDecl {
+            visibility,
+            mutable,
+            name,
+            typ,
+            initializer,
+            lateinit,
+            delegate,
+            getter,
+            setter,
+        })
+    }
+
+    /// The body of an accessor, `= expr` or a block, and whether it is an expression.
+    fn parse_accessor_body(&mut self) -> Result<(Block, bool), ParseError> {
+        if self.peek() == Some(&Token::Equal) {
+            self.advance();
+            let expr = self.parse_expression()?;
+            return Ok((Block::new(vec![Statement::Return(Some(expr))]), true));
+        }
+        Ok((self.parse_block()?, false))
+    }
+
+    /// Whether the next token is the soft keyword `word`, such as `by` or `get`.
+    fn peek_contextual(&mut self, word: &str) -> bool {
+        matches!(self.peek(), Some(Token::Identifier(id)) if id == word)
+    }
+
+    fn parse_function(&mut self, visibility: Visibility) -> Result<FunctionDecl, ParseError> {
+        let is_operator = self.peek_contextual("operator");
+        if is_operator {
+            self.advance();
+        }
+        let is_suspend = self.peek() == Some(&Token::Suspend);
+        if is_suspend {
+            self.advance();
+        }
         self.expect(Token::Fun)?;
 
         let name = match self.advance() {
@@ -104,18 +442,64 @@ impl<'a> Parser<'a> {
             return_type = Some(self.parse_type()?);
         }
 
-        let body = self.parse_block()?;
+        // Expression body: `fun sq(x: Int) = x * x` is sugar for `{ return x * x }`
+        let expression_body = self.peek() == Some(&Token::Equal);
+        let body = if expression_body {
+            self.advance(); // consume =
+            let expr = self.parse_expression()?;
+            Block::new(vec![Statement::Return(Some(expr))])
+        } else {
+            self.parse_block()?
+        };
 
         Ok(FunctionDecl {
+            visibility,
             name,
             params,
             return_type,
             body,
+            expression_body,
+            is_suspend,
+            is_operator,
+            file: self.file.clone(),
         })
     }
 
+    /// A type as written. A `typealias` may be declared after its uses or in
+    /// another file, so the type checker resolves alias names.
     fn parse_type(&mut self) -> Result<Type, ParseError> {
+        let typ = self.parse_non_null_type()?;
+        if self.peek() == Some(&Token::Question) {
+            self.advance();
+            return Ok(Type::Nullable(Box::new(typ)));
+        }
+        Ok(typ)
+    }
+
+    fn parse_non_null_type(&mut self) -> Result<Type, ParseError> {
+        if self.peek() == Some(&Token::LParen) {
+            // Function type: (A, B) -> R
+            self.advance();
+            let mut params = Vec::new();
+            if self.peek() != Some(&Token::RParen) {
+                loop {
+                    params.push(self.parse_type()?);
+                    if self.peek() == Some(&Token::Comma) {
+                        self.advance();
+                    } else {
+                        break;
+                    }
+                }
+            }
+            self.expect(Token::RParen)?;
+            self.expect(Token::Arrow)?;
+            let result = self.parse_type()?;
+            return Ok(Type::Function(params, Box::new(result)));
+        }
+
         let base_type = match self.advance() {
+            // The star projection of a type argument, as in `KProperty<*>`
+            Some(Token::Star) => return Ok(Type::Named("*".to_string())),
             Some(Token::Identifier(id)) => {
                 if id == "Array" {
                     // Parse Array<T> syntax
@@ -141,6 +525,16 @@ impl<'a> Parser<'a> {
                         // Just a regular identifier
                         Type::Named(id)
                     }
+                } else if self.peek() == Some(&Token::Less) {
+                    // Parse other generic types such as Pair<A, B>
+                    self.advance(); // consume <
+                    let mut args = vec![self.parse_type()?];
+                    while self.peek() == Some(&Token::Comma) {
+                        self.advance();
+                        args.push(self.parse_type()?);
+                    }
+                    self.expect(Token::Greater)?;
+                    return Ok(Type::Generic(id, args));
                 } else {
                     Type::Named(id)
                 }
@@ -171,11 +565,33 @@ impl<'a> Parser<'a> {
         }
 
         self.expect(Token::RBrace)?;
-        Ok(Block { statements })
+        Ok(Block::new(statements))
+    }
+
+    /// Parses a branch of an `if` expression: either a block or a single expression.
+    fn parse_branch(&mut self) -> Result<Block, ParseError> {
+        if self.peek() == Some(&Token::LBrace) {
+            self.parse_block()
+        } else {
+            let expr = self.parse_expression()?;
+            Ok(Block::new(vec![Statement::Expression(expr)]))
+        }
     }
 
     pub fn parse_repl_input(&mut self) -> Result<ReplNode, ParseError> {
-        if self.peek() == Some(&Token::Fun) {
+        if matches!(
+            self.peek(),
+            Some(
+                Token::Fun
+                    | Token::Suspend
+                    | Token::Object
+                    | Token::At
+                    | Token::Public
+                    | Token::Internal
+                    | Token::Private
+            )
+        ) || self.peek_contextual("typealias")
+        {
             let decl = self.parse_declaration()?;
             Ok(ReplNode::Decl(decl))
         } else {
@@ -217,6 +633,19 @@ impl<'a> Parser<'a> {
             return Ok(Statement::While { condition, body });
         }
 
+        if self.peek() == Some(&Token::Do) {
+            self.advance();
+            let body = match self.parse_statement()? {
+                Statement::Block(block) => block,
+                statement => Block::new(vec![statement]),
+            };
+            self.expect(Token::While)?;
+            self.expect(Token::LParen)?;
+            let condition = self.parse_expression()?;
+            self.expect(Token::RParen)?;
+            return Ok(Statement::DoWhile { body, condition });
+        }
+
         if self.peek() == Some(&Token::For) {
             self.advance(); // consume 'for'
             self.expect(Token::LParen)?; // consume '('
@@ -278,7 +707,10 @@ impl<'a> Parser<'a> {
                 // form: for ( <var> ) in <iterable>
                 self.advance(); // consume ')'
                 self.expect(Token::In)?; // now require 'in'
-                self.parse_expression()?
+                self.trailing_lambdas = false;
+                let iterable = self.parse_expression();
+                self.trailing_lambdas = true;
+                iterable?
             } else {
                 // unexpected token
                 return Err(ParseError::UnexpectedToken(
@@ -311,11 +743,40 @@ impl<'a> Parser<'a> {
             // Handle variable declaration
             self.advance(); // consume val/var
 
+            if self.peek() == Some(&Token::LParen) {
+                // Destructuring declaration: val (a, b) = pair
+                self.advance(); // consume (
+                let mut names = Vec::new();
+                loop {
+                    match self.advance() {
+                        Some(Token::Identifier(id)) => names.push(id),
+                        Some(t) => return Err(ParseError::ExpectedIdentifier(t)),
+                        None => return Err(ParseError::UnexpectedEOF),
+                    }
+                    if self.peek() == Some(&Token::Comma) {
+                        self.advance();
+                    } else {
+                        break;
+                    }
+                }
+                self.expect(Token::RParen)?;
+                self.expect(Token::Equal)?;
+                let initializer = self.parse_expression()?;
+                return Ok(Statement::Destructuring { names, initializer });
+            }
+
             let name = match self.advance() {
                 Some(Token::Identifier(id)) => id,
                 t => return Err(ParseError::UnexpectedToken(t.unwrap_or(Token::Error))),
             };
 
+            let typ = if self.peek() == Some(&Token::Colon) {
+                self.advance();
+                Some(self.parse_type()?)
+            } else {
+                None
+            };
+
             let initializer = if self.peek() == Some(&Token::Equal) {
                 self.advance();
                 Some(self.parse_expression()?)
@@ -325,7 +786,7 @@ impl<'a> Parser<'a> {
 
             Ok(Statement::VariableDecl {
                 name,
-                typ: None,
+                typ,
                 initializer,
             })
         } else {
@@ -337,61 +798,48 @@ impl<'a> Parser<'a> {
     fn parse_expression(&mut self) -> Result<Expression, ParseError> {
         let expr = self.parse_logical_or()?;
 
-        if self.peek() == Some(&Token::Equal) {
-            self.advance();
-            let value = self.parse_expression()?; // Right-associative
-
-            if let ExpressionKind::Variable(name) = *expr.kind {
-                return Ok(Expression::new(ExpressionKind::Assignment { name, value }));
-            } else {
-                // Using UnexpectedToken for now, ideally "Invalid assignment target"
-                return Err(ParseError::UnexpectedToken(Token::Equal));
-            }
-        } else if self.peek() == Some(&Token::PlusEqual)
-            || self.peek() == Some(&Token::MinusEqual)
-            || self.peek() == Some(&Token::StarEqual)
-            || self.peek() == Some(&Token::SlashEqual)
-        {
-            // Handle compound assignment operators
-            let op_token = self.advance().unwrap();
-            let value = self.parse_expression()?; // Right-associative
-
-            if let ExpressionKind::Variable(name) = *expr.kind {
-                // Convert compound assignment to regular assignment with binary operation
-                let binary_op = match op_token {
-                    Token::PlusEqual => BinaryOp::Add,
-                    Token::MinusEqual => BinaryOp::Sub,
-                    Token::StarEqual => BinaryOp::Mul,
-                    Token::SlashEqual => BinaryOp::Div,
-                    _ => unreachable!(),
-                };
-
-                let binary_expr = Expression::new(ExpressionKind::Binary {
-                    left: Expression::new(ExpressionKind::Variable(name.clone())),
-                    operator: binary_op,
-                    right: value,
-                });
-
-                return Ok(Expression::new(ExpressionKind::Assignment {
-                    name,
-                    value: binary_expr,
-                }));
-            } else {
-                // Using UnexpectedToken for now, ideally "Invalid assignment target"
-                return Err(ParseError::UnexpectedToken(op_token));
-            }
+        let operator = match self.peek() {
+            Some(Token::Equal) => None,
+            Some(Token::PlusEqual) => Some(BinaryOp::Add),
+            Some(Token::MinusEqual) => Some(BinaryOp::Sub),
+            Some(Token::StarEqual) => Some(BinaryOp::Mul),
+            Some(Token::SlashEqual) => Some(BinaryOp::Div),
+            Some(Token::PercentEqual) => Some(BinaryOp::Rem),
+            _ => return Ok(expr),
+        };
+        self.advance();
+        if !matches!(
+            &*expr.kind,
+            ExpressionKind::Variable(_)
+                | ExpressionKind::Index { .. }
+                | ExpressionKind::MemberAccess { .. }
+        ) {
+            return Err(ParseError::InvalidAssignmentTarget);
         }
-        Ok(expr)
+        let line = expr.line;
+        let value = self.parse_expression()?; // Right-associative
+        let mut assignment = Expression::new(ExpressionKind::Assignment {
+            target: expr,
+            operator,
+            value,
+        });
+        assignment.line = line;
+        Ok(assignment)
     }
 
     fn parse_equality(&mut self) -> Result<Expression, ParseError> {
         let mut expr = self.parse_comparison()?;
         while let Some(token) = self.peek() {
             match token {
-                Token::DoubleEqual | Token::NotEqual => {
+                Token::DoubleEqual
+                | Token::NotEqual
+                | Token::TripleEqual
+                | Token::NotDoubleEqual => {
                     let op = match self.advance().unwrap() {
                         Token::DoubleEqual => BinaryOp::Equal,
                         Token::NotEqual => BinaryOp::NotEqual,
+                        Token::TripleEqual => BinaryOp::Identical,
+                        Token::NotDoubleEqual => BinaryOp::NotIdentical,
                         _ => unreachable!(),
                     };
                     let right = self.parse_comparison()?;
@@ -452,7 +900,7 @@ impl<'a> Parser<'a> {
     }
 
     fn parse_comparison(&mut self) -> Result<Expression, ParseError> {
-        let mut expr = self.parse_term()?;
+        let mut expr = self.parse_elvis()?;
         while let Some(token) = self.peek() {
             match token {
                 Token::Less | Token::LessEqual | Token::Greater | Token::GreaterEqual => {
@@ -463,7 +911,7 @@ impl<'a> Parser<'a> {
                         Token::GreaterEqual => BinaryOp::GreaterEqual,
                         _ => unreachable!(),
                     };
-                    let right = self.parse_term()?;
+                    let right = self.parse_elvis()?;
                     expr = Expression::new(ExpressionKind::Binary {
                         left: expr,
                         operator: op,
@@ -476,6 +924,58 @@ impl<'a> Parser<'a> {
         Ok(expr)
     }
 
+    /// Parses `a ?: b`, which binds looser than named infix calls.
+    fn parse_elvis(&mut self) -> Result<Expression, ParseError> {
+        let expr = self.parse_infix()?;
+        if self.peek() != Some(&Token::Elvis) {
+            return Ok(expr);
+        }
+        self.advance();
+        let right = self.parse_elvis()?; // Right-associative
+        Ok(Expression::new(ExpressionKind::Binary {
+            left: expr,
+            operator: BinaryOp::Elvis,
+            right,
+        }))
+    }
+
+    /// Parses named infix calls, which bind tighter than comparisons.
+    /// `a to b` is sugar for `Pair(a, b)`; `and`, `or`, `xor`, `shl`, `shr`
+    /// and `ushr` are the bit operations.
+    fn parse_infix(&mut self) -> Result<Expression, ParseError> {
+        let mut expr = self.parse_term()?;
+        while let Some(Token::Identifier(id)) = self.peek() {
+            let op = match id.as_str() {
+                "to" => None,
+                "and" => Some(BinaryOp::BitAnd),
+                "or" => Some(BinaryOp::BitOr),
+                "xor" => Some(BinaryOp::BitXor),
+                "shl" => Some(BinaryOp::Shl),
+                "shr" => Some(BinaryOp::Shr),
+                "ushr" => Some(BinaryOp::UShr),
+                _ => break,
+            };
+            self.advance(); // consume the function name
+            let right = self.parse_term()?;
+            expr = match op {
+                Some(operator) => Expression::new(ExpressionKind::Binary {
+                    left: expr,
+                    operator,
+                    right,
+                }),
+                None => {
+                    let mut pair = Expression::new(ExpressionKind::Variable("Pair".to_string()));
+                    pair.line = expr.line;
+                    Expression::new(ExpressionKind::Call {
+                        callee: pair,
+                        arguments: vec![expr, right],
+                    })
+                }
+            };
+        }
+        Ok(expr)
+    }
+
     fn parse_term(&mut self) -> Result<Expression, ParseError> {
         let mut expr = self.parse_factor()?;
         while let Some(token) = self.peek() {
@@ -503,10 +1003,11 @@ impl<'a> Parser<'a> {
         let mut expr = self.parse_unary()?;
         while let Some(token) = self.peek() {
             match token {
-                Token::Star | Token::Slash => {
+                Token::Star | Token::Slash | Token::Percent => {
                     let op = match self.advance().unwrap() {
                         Token::Star => BinaryOp::Mul,
                         Token::Slash => BinaryOp::Div,
+                        Token::Percent => BinaryOp::Rem,
                         _ => unreachable!(),
                     };
                     let right = self.parse_unary()?;
@@ -523,6 +1024,7 @@ impl<'a> Parser<'a> {
     }
 
     fn parse_unary(&mut self) -> Result<Expression, ParseError> {
+        let line = self.line();
         if let Some(Token::Minus | Token::Not | Token::Increment | Token::Decrement) = self.peek() {
             let op = match self.advance().unwrap() {
                 Token::Minus => UnaryOp::Minus,
@@ -532,38 +1034,64 @@ impl<'a> Parser<'a> {
                 _ => unreachable!(),
             };
             let right = self.parse_unary()?;
-            return Ok(Expression::new(ExpressionKind::Unary {
+            let mut expr = Expression::new(ExpressionKind::Unary {
                 operator: op,
                 operand: right,
-            }));
+            });
+            expr.line = line;
+            return Ok(expr);
         }
         self.parse_postfix()
     }
 
     fn parse_postfix(&mut self) -> Result<Expression, ParseError> {
         let mut expr = self.parse_primary()?;
+        let line = expr.line;
+        let trailing_lambdas = self.trailing_lambdas;
 
         while let Some(token) = self.peek() {
             match token {
                 Token::LParen => {
                     self.advance(); // consume (
-                    let mut args = Vec::new();
-                    if self.peek() != Some(&Token::RParen) {
-                        loop {
-                            args.push(self.parse_expression()?);
-                            if self.peek() == Some(&Token::Comma) {
-                                self.advance();
-                            } else {
-                                break;
-                            }
-                        }
-                    }
-                    self.expect(Token::RParen)?;
+                    let args = self.parse_arguments()?;
                     expr = Expression::new(ExpressionKind::Call {
                         callee: expr,
                         arguments: args,
                     });
                 }
+                Token::SafeDot => {
+                    // `a?.b(c) { ... }` takes its arguments along, so the
+                    // whole call is skipped when `a` is null
+                    self.advance(); // consume ?.
+                    let member = self.parse_identifier()?;
+                    let receiver =
+                        Expression::new(ExpressionKind::Variable(SAFE_RECEIVER.to_string()));
+                    let mut access = Expression::new(ExpressionKind::MemberAccess {
+                        object: receiver,
+                        member,
+                    });
+                    let mut arguments = None;
+                    if self.peek() == Some(&Token::LParen) {
+                        self.advance(); // consume (
+                        arguments = Some(self.parse_arguments()?);
+                    }
+                    if trailing_lambdas && self.peek() == Some(&Token::LBrace) {
+                        self.advance(); // consume {
+                        arguments
+                            .get_or_insert_with(Vec::new)
+                            .push(self.parse_lambda()?);
+                    }
+                    if let Some(arguments) = arguments {
+                        access = Expression::new(ExpressionKind::Call {
+                            callee: access,
+                            arguments,
+                        });
+                    }
+                    expr = Expression::new(ExpressionKind::SafeAccess {
+                        object: expr,
+                        access,
+                    });
+                }
                 Token::Dot => {
                     self.advance(); // consume .
                     let member = match self.advance() {
@@ -583,6 +1111,36 @@ impl<'a> Parser<'a> {
                     self.expect(Token::RBracket)?;
                     expr = Expression::new(ExpressionKind::Index { array: expr, index });
                 }
+                Token::LBrace
+                    if trailing_lambdas
+                        && matches!(
+                            &*expr.kind,
+                            ExpressionKind::Variable(_)
+                                | ExpressionKind::MemberAccess { .. }
+                                | ExpressionKind::Call { .. }
+                        ) =>
+                {
+                    // Trailing lambda: `f(a) { ... }` passes the lambda as the last argument
+                    self.advance(); // consume {
+                    let lambda = self.parse_lambda()?;
+                    expr = match *expr.kind {
+                        ExpressionKind::Call {
+                            callee,
+                            mut arguments,
+                        } => {
+                            arguments.push(lambda);
+                            Expression::new(ExpressionKind::Call { callee, arguments })
+                        }
+                        kind => {
+                            let mut callee = Expression::new(kind);
+                            callee.line = line;
+                            Expression::new(ExpressionKind::Call {
+                                callee,
+                                arguments: vec![lambda],
+                            })
+                        }
+                    };
+                }
                 Token::Increment => {
                     // Parse postfix increment: expr++
                     self.advance(); // consume ++
@@ -601,25 +1159,71 @@ impl<'a> Parser<'a> {
                 }
                 _ => break,
             }
+            expr.line = line;
         }
 
         Ok(expr)
     }
 
+    /// Parses call arguments after their opening `(`.
+    fn parse_arguments(&mut self) -> Result<Vec<Expression>, ParseError> {
+        let mut args = Vec::new();
+        if self.peek() != Some(&Token::RParen) {
+            loop {
+                args.push(self.parse_expression()?);
+                if self.peek() == Some(&Token::Comma) {
+                    self.advance();
+                } else {
+                    break;
+                }
+            }
+        }
+        self.expect(Token::RParen)?;
+        Ok(args)
+    }
+
     fn parse_primary(&mut self) -> Result<Expression, ParseError> {
+        let line = self.line();
+        let mut expr = self.parse_primary_kind()?;
+        expr.line = line;
+        Ok(expr)
+    }
+
+    fn parse_primary_kind(&mut self) -> Result<Expression, ParseError> {
         match self.advance() {
-            Some(Token::Integer(i)) => Ok(Expression::new(ExpressionKind::Literal(
-                Literal::Integer(i),
-            ))),
+            Some(Token::Integer(i)) => {
+                // Like Kotlin, a literal too large for Int is a Long
+                let lit = if i32::try_from(i).is_ok() {
+                    Literal::Integer(i)
+                } else {
+                    Literal::Long(i)
+                };
+                Ok(Expression::new(ExpressionKind::Literal(lit)))
+            }
+            Some(Token::Long(i)) => Ok(Expression::new(ExpressionKind::Literal(Literal::Long(i)))),
+            Some(Token::Unsigned(u)) => {
+                let lit = match u32::try_from(u) {
+                    Ok(u) => Literal::UInt(u),
+                    Err(_) => Literal::ULong(u),
+                };
+                Ok(Expression::new(ExpressionKind::Literal(lit)))
+            }
+            Some(Token::UnsignedLong(u)) => {
+                Ok(Expression::new(ExpressionKind::Literal(Literal::ULong(u))))
+            }
             Some(Token::Float(f)) => {
                 Ok(Expression::new(ExpressionKind::Literal(Literal::Float(f))))
             }
+            Some(Token::Double(f)) => {
+                Ok(Expression::new(ExpressionKind::Literal(Literal::Double(f))))
+            }
             Some(Token::True) => Ok(Expression::new(ExpressionKind::Literal(Literal::Boolean(
                 true,
             )))),
             Some(Token::False) => Ok(Expression::new(ExpressionKind::Literal(Literal::Boolean(
                 false,
             )))),
+            Some(Token::Null) => Ok(Expression::new(ExpressionKind::Literal(Literal::Null))),
             Some(Token::String(s)) => {
                 // Remove quotes
                 let content = s.trim_matches('"').to_string();
@@ -635,6 +1239,24 @@ impl<'a> Parser<'a> {
                 ))))
             }
             Some(Token::Identifier(id)) => Ok(Expression::new(ExpressionKind::Variable(id))),
+            Some(Token::If) => {
+                // `if` in expression position: `val m = if (a > b) a else b`
+                self.expect(Token::LParen)?;
+                let condition = self.parse_expression()?;
+                self.expect(Token::RParen)?;
+                let then_branch = self.parse_branch()?;
+                let else_branch = if self.peek() == Some(&Token::Else) {
+                    self.advance();
+                    Some(self.parse_branch()?)
+                } else {
+                    None
+                };
+                Ok(Expression::new(ExpressionKind::If {
+                    condition,
+                    then_branch,
+                    else_branch,
+                }))
+            }
             Some(Token::LParen) => {
                 let expr = self.parse_expression()?;
                 self.expect(Token::RParen)?;
@@ -656,28 +1278,114 @@ impl<'a> Parser<'a> {
                 self.expect(Token::RBracket)?;
                 Ok(Expression::new(ExpressionKind::ArrayLiteral { elements }))
             }
-            Some(Token::LBrace) => {
-                // Parse HashMap literal: {key: value, key2: value2, ...}
-                let mut pairs = Vec::new();
-                if self.peek() != Some(&Token::RBrace) {
-                    loop {
-                        let key = self.parse_expression()?;
-                        self.expect(Token::Colon)?; // Expect colon between key and value
-                        let value = self.parse_expression()?;
-                        pairs.push((key, value));
+            Some(Token::LBrace) => self.parse_brace_expression(),
+            Some(t) => Err(ParseError::UnexpectedToken(t)),
+            None => Err(ParseError::UnexpectedEOF),
+        }
+    }
 
-                        if self.peek() == Some(&Token::Comma) {
-                            self.advance();
-                        } else {
-                            break;
-                        }
-                    }
+    /// Parses what follows `{` in expression position: a map literal
+    /// `{key: value, ...}` or `{}`, otherwise a lambda.
+    fn parse_brace_expression(&mut self) -> Result<Expression, ParseError> {
+        if self.peek() == Some(&Token::RBrace) {
+            self.advance();
+            return Ok(Expression::new(ExpressionKind::HashMapLiteral {
+                pairs: Vec::new(),
+            }));
+        }
+        if self.has_lambda_header()
+            || matches!(
+                self.peek(),
+                Some(Token::Val | Token::Var | Token::If | Token::While | Token::Do)
+                    | Some(Token::For | Token::Return | Token::LBrace)
+            )
+        {
+            return self.parse_lambda();
+        }
+
+        // A map literal if the first expression is followed by `:`
+        let first = self.parse_expression()?;
+        if self.peek() != Some(&Token::Colon) {
+            let mut statements = vec![Statement::Expression(first)];
+            while self.peek() != Some(&Token::RBrace) {
+                if self.peek().is_none() {
+                    return Err(ParseError::UnexpectedEOF);
                 }
-                self.expect(Token::RBrace)?;
-                Ok(Expression::new(ExpressionKind::HashMapLiteral { pairs }))
+                statements.push(self.parse_statement()?);
             }
-            Some(t) => Err(ParseError::UnexpectedToken(t)),
-            None => Err(ParseError::UnexpectedEOF),
+            self.advance(); // consume }
+            return Ok(Expression::new(ExpressionKind::Lambda {
+                params: Vec::new(),
+                body: Block::new(statements),
+            }));
+        }
+        let mut pairs = Vec::new();
+        let mut key = first;
+        loop {
+            self.expect(Token::Colon)?; // Expect colon between key and value
+            let value = self.parse_expression()?;
+            pairs.push((key, value));
+            if self.peek() != Some(&Token::Comma) {
+                break;
+            }
+            self.advance();
+            key = self.parse_expression()?;
         }
+        self.expect(Token::RBrace)?;
+        Ok(Expression::new(ExpressionKind::HashMapLiteral { pairs }))
+    }
+
+    /// Whether the tokens after `{` are a lambda's `a, b: Int ->` parameter list.
+    fn has_lambda_header(&self) -> bool {
+        let mut tokens = self.lexer.clone();
+        loop {
+            match tokens.next().map(|(token, _)| token) {
+                Some(Token::Arrow) => return true,
+                Some(
+                    Token::Identifier(_)
+                    | Token::Colon
+                    | Token::Comma
+                    | Token::Less
+                    | Token::Greater
+                    | Token::LBracket
+                    | Token::RBracket
+                    | Token::Question,
+                ) => {}
+                _ => return false,
+            }
+        }
+    }
+
+    /// Parses a lambda after its opening `{`.
+    fn parse_lambda(&mut self) -> Result<Expression, ParseError> {
+        let mut params = Vec::new();
+        if self.has_lambda_header() {
+            while self.peek() != Some(&Token::Arrow) {
+                let name = self.parse_identifier()?;
+                let typ = if self.peek() == Some(&Token::Colon) {
+                    self.advance();
+                    Some(self.parse_type()?)
+                } else {
+                    None
+                };
+                params.push(LambdaParam { name, typ });
+                if self.peek() == Some(&Token::Comma) {
+                    self.advance();
+                }
+            }
+            self.advance(); // consume ->
+        }
+        let mut statements = Vec::new();
+        while self.peek() != Some(&Token::RBrace) {
+            if self.peek().is_none() {
+                return Err(ParseError::UnexpectedEOF);
+            }
+            statements.push(self.parse_statement()?);
+        }
+        self.advance(); // consume }
+        Ok(Expression::new(ExpressionKind::Lambda {
+            params,
+            body: Block::new(statements),
+        }))
     }
 }
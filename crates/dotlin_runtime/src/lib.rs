@@ -1,6 +1,7 @@
 #![allow(clippy::not_unsafe_ptr_arg_deref)]
 use std::alloc::{alloc, dealloc, Layout};
 use std::collections::HashMap as StdHashMap;
+use std::io::Write;
 
 #[no_mangle]
 pub extern "C" fn println_i64(val: i64) {
@@ -21,6 +22,47 @@ pub extern "C" fn println_str(ptr: *const u8) {
     }
 }
 
+/// `print`: writes the text without a newline, flushed right away so it
+/// shows before the program reads input or exits.
+#[no_mangle]
+pub extern "C" fn print_str(ptr: *const u8) {
+    if let Some(text) = constant_text(ptr) {
+        let mut stdout = std::io::stdout();
+        let _ = stdout.write_all(text.as_bytes());
+        let _ = stdout.flush();
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn eprintln_str(ptr: *const u8) {
+    if let Some(text) = constant_text(ptr) {
+        eprintln!("{}", text);
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn eprint_str(ptr: *const u8) {
+    if let Some(text) = constant_text(ptr) {
+        eprint!("{}", text);
+    }
+}
+
+/// `readLine()`: the next line of standard input without its line break,
+/// boxed as a `String?`, or null at the end of the input.
+#[no_mangle]
+pub extern "C" fn dotlin_read_line() -> *mut u64 {
+    let mut line = String::new();
+    match std::io::stdin().read_line(&mut line) {
+        Ok(0) | Err(_) => std::ptr::null_mut(),
+        Ok(_) => {
+            let end = line.trim_end_matches(['\n', '\r']).len();
+            let boxed = dotlin_tuple_new(1);
+            unsafe { *boxed = alloc_string(&line[..end]) as u64 };
+            boxed
+        }
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn dotlin_string_concat(s1: *const u8, s2: *const u8) -> *const u8 {
     if s1.is_null() {
@@ -45,11 +87,48 @@ pub extern "C" fn dotlin_string_concat(s1: *const u8, s2: *const u8) -> *const u
     }
 }
 
+/// A float as Kotlin prints it: whole values with a `.0`, very large and
+/// small ones in scientific notation like `1.0E10`, and `Infinity`,
+/// `-Infinity` and `NaN`.
+fn float_text<F>(val: F) -> String
+where
+    F: Into<f64> + std::fmt::Display + std::fmt::LowerExp + Copy,
+{
+    let (plain, scientific) = (val.to_string(), format!("{:e}", val));
+    let val: f64 = val.into();
+    match val {
+        _ if val.is_nan() => "NaN".to_string(),
+        f64::INFINITY => "Infinity".to_string(),
+        f64::NEG_INFINITY => "-Infinity".to_string(),
+        _ if val == 0.0 || (1e-3..1e7).contains(&val.abs()) => match plain.contains('.') {
+            true => plain,
+            false => plain + ".0",
+        },
+        _ => {
+            let (mantissa, exponent) = scientific.split_once('e').unwrap_or((&scientific, "0"));
+            match mantissa.contains('.') {
+                true => format!("{}E{}", mantissa, exponent),
+                false => format!("{}.0E{}", mantissa, exponent),
+            }
+        }
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn println_f64(val: f64) {
+    println!("{}", float_text(val));
+}
+
+#[no_mangle]
+pub extern "C" fn println_u64(val: u64) {
     println!("{}", val);
 }
 
+#[no_mangle]
+pub extern "C" fn println_f32(val: f32) {
+    println!("{}", float_text(val));
+}
+
 #[no_mangle]
 pub extern "C" fn dotlin_string_compare(s1: *const u8, s2: *const u8) -> i64 {
     if s1.is_null() || s2.is_null() {
@@ -144,6 +223,65 @@ pub extern "C" fn dotlin_math_pow(base: f64, exp: f64) -> f64 {
     base.powf(exp)
 }
 
+/// `%` on floating-point numbers: the remainder has the sign of the dividend.
+#[no_mangle]
+pub extern "C" fn dotlin_float_rem(a: f64, b: f64) -> f64 {
+    a % b
+}
+
+/// Reports an uncaught `ArithmeticException` (division by zero, or overflow in
+/// `Math.addExact` and friends) and terminates the program.
+#[no_mangle]
+pub extern "C" fn dotlin_throw_arithmetic_exception(message: *const u8) -> ! {
+    let message = unsafe {
+        let len = *(message as *const u64);
+        let s = std::slice::from_raw_parts(message.add(8), len as usize);
+        std::str::from_utf8(s).unwrap_or("invalid utf8")
+    };
+    throw_exception("ArithmeticException", message);
+}
+
+/// Reports reading the `lateinit` property `name` before it was assigned
+/// and terminates the program.
+#[no_mangle]
+pub extern "C" fn dotlin_throw_uninitialized_property(name: *const u8) -> ! {
+    let name = unsafe {
+        let len = *(name as *const u64);
+        let s = std::slice::from_raw_parts(name.add(8), len as usize);
+        std::str::from_utf8(s).unwrap_or("invalid utf8")
+    };
+    throw_exception(
+        "UninitializedPropertyAccessException",
+        &format!("lateinit property {} has not been initialized", name),
+    );
+}
+
+/// Runs an `object`'s initializer the first time the object is used.
+/// `state` is 0 before initialization, 1 once it is done, and otherwise
+/// identifies the thread running the initializer: that thread may use the
+/// object while initializing it, while other threads wait until it is done.
+#[no_mangle]
+pub extern "C" fn dotlin_object_init(state: *mut u64, init: extern "C" fn()) {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    thread_local! {
+        static THREAD_TOKEN: u8 = const { 0 };
+    }
+    let state = unsafe { AtomicU64::from_ptr(state) };
+    let token = THREAD_TOKEN.with(|byte| byte as *const u8 as u64);
+    loop {
+        match state.compare_exchange(0, token, Ordering::Acquire, Ordering::Acquire) {
+            Ok(_) => {
+                init();
+                state.store(1, Ordering::Release);
+                return;
+            }
+            Err(1) => return,
+            Err(owner) if owner == token => return,
+            Err(_) => std::thread::yield_now(),
+        }
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn dotlin_math_sin(val: f64) -> f64 {
     val.sin()
@@ -207,7 +345,13 @@ pub extern "C" fn dotlin_array_get(array_ptr: *mut DotlinArray, index: u64) -> u
     unsafe {
         let array = &*array_ptr;
         if index >= array.size {
-            return 0; // Error value
+            throw_exception(
+                "ArrayIndexOutOfBoundsException",
+                &format!(
+                    "Index {} out of bounds for length {}",
+                    index as i64, array.size
+                ),
+            );
         }
 
         let data_ptr = array.data;
@@ -223,22 +367,18 @@ pub extern "C" fn dotlin_array_set(array_ptr: *mut DotlinArray, index: u64, valu
 
     unsafe {
         let array = &mut *array_ptr;
-
-        // If we need to grow the array to accommodate this index
-        if index >= array.capacity {
-            // Ensure capacity is sufficient
-            let new_capacity = std::cmp::max(array.capacity * 2, index + 1);
-            let new_data = grow_array(array.data, array.capacity as usize, new_capacity as usize);
-            array.data = new_data;
-            array.capacity = new_capacity;
+        if index >= array.size {
+            throw_exception(
+                "ArrayIndexOutOfBoundsException",
+                &format!(
+                    "Index {} out of bounds for length {}",
+                    index as i64, array.size
+                ),
+            );
         }
 
         let data_ptr = array.data;
         std::ptr::write(data_ptr.add(index as usize), value);
-
-        if index >= array.size {
-            array.size = index + 1;
-        }
     }
 }
 
@@ -470,7 +610,7 @@ pub extern "C" fn dotlin_to_string(val: i64) -> *const u8 {
 
 #[no_mangle]
 pub extern "C" fn dotlin_float_to_string(val: f64) -> *const u8 {
-    let s = val.to_string();
+    let s = float_text(val);
 
     unsafe {
         let total_len = s.len() as u64;
@@ -484,6 +624,16 @@ pub extern "C" fn dotlin_float_to_string(val: f64) -> *const u8 {
     }
 }
 
+#[no_mangle]
+pub extern "C" fn dotlin_u64_to_string(val: u64) -> *const u8 {
+    alloc_string(&val.to_string())
+}
+
+#[no_mangle]
+pub extern "C" fn dotlin_f32_to_string(val: f32) -> *const u8 {
+    alloc_string(&float_text(val))
+}
+
 #[no_mangle]
 pub extern "C" fn dotlin_bool_to_string(val: i8) -> *const u8 {
     let s = if val != 0 {
@@ -601,24 +751,20 @@ pub extern "C" fn dotlin_map_entries(map_ptr: *mut DotlinHashMap) -> *mut Dotlin
 
     unsafe {
         let map = &*(*map_ptr).data;
-        // For now, return an array of alternating key-value pairs
-        // In a real implementation, we would create proper entry objects
-        let mut entries: Vec<u64> = Vec::new();
-        for (key, &value) in map.iter() {
-            // Convert key to a simple hash representation (placeholder)
-            let key_hash = key.as_bytes().iter().fold(0u64, |acc, &b| acc ^ (b as u64));
-            entries.push(key_hash);
-            entries.push(value);
-        }
+        // Each entry is a Pair of (key string, value)
+        let entries: Vec<u64> = map
+            .iter()
+            .map(|(key, &value)| {
+                let pair = dotlin_tuple_new(2);
+                *pair = alloc_string(key) as u64;
+                *pair.add(1) = value;
+                pair as u64
+            })
+            .collect();
 
         let capacity = entries.len() as u64;
-        let mut vec: Vec<u64> = vec![0; capacity as usize];
-        for (i, &entry) in entries.iter().enumerate() {
-            vec[i] = entry;
-        }
-        let boxed = vec.into_boxed_slice();
         let array = DotlinArray {
-            data: Box::into_raw(boxed) as *mut u64,
+            data: Box::into_raw(entries.into_boxed_slice()) as *mut u64,
             size: capacity,
             capacity,
         };
@@ -627,6 +773,29 @@ pub extern "C" fn dotlin_map_entries(map_ptr: *mut DotlinHashMap) -> *mut Dotlin
     }
 }
 
+/// Allocates a length-prefixed string in the layout used by generated code.
+fn alloc_string(s: &str) -> *const u8 {
+    unsafe {
+        let total_len = s.len() as u64;
+        let layout = Layout::from_size_align((total_len + 8) as usize, 8).unwrap();
+        let new_ptr = alloc(layout);
+
+        *(new_ptr as *mut u64) = total_len;
+        std::ptr::copy_nonoverlapping(s.as_ptr(), new_ptr.add(8), s.len());
+
+        new_ptr
+    }
+}
+
+// Tuple runtime functions
+/// Allocates a Pair/Triple: `arity` zeroed 8-byte slots that generated code
+/// reads and writes directly.
+#[no_mangle]
+pub extern "C" fn dotlin_tuple_new(arity: u64) -> *mut u64 {
+    let slots: Vec<u64> = vec![0; arity.max(1) as usize];
+    Box::into_raw(slots.into_boxed_slice()) as *mut u64
+}
+
 #[repr(C)]
 pub struct DotlinIterator {
     entries: *mut DotlinArray,
@@ -663,3 +832,831 @@ pub extern "C" fn dotlin_iterator_next(it_ptr: *mut DotlinIterator) -> u64 {
         val
     }
 }
+
+// Closure and sequence runtime functions
+/// Allocates a closure: the address of the lambda's code followed by
+/// `captures` slots that generated code fills with the captured values.
+#[no_mangle]
+pub extern "C" fn dotlin_closure_new(function: u64, captures: u64) -> *mut u64 {
+    let mut slots: Vec<u64> = vec![0; 1 + captures as usize];
+    slots[0] = function;
+    Box::into_raw(slots.into_boxed_slice()) as *mut u64
+}
+
+/// Calls a closure taking one argument. Arguments and results are passed as
+/// 8-byte slots, like array elements.
+fn call_closure(closure: *mut u64, argument: u64) -> u64 {
+    unsafe {
+        let function: extern "C" fn(*mut u64, u64) -> u64 = std::mem::transmute(*closure);
+        function(closure, argument)
+    }
+}
+
+/// A lazy sequence. Elements are only computed while iterating.
+pub enum DotlinSequence {
+    /// `sequence { }`: `resume` runs the body up to its next `yield` in a
+    /// copy of `frame`, whose slots hold the resume state, the yielded value
+    /// and the body's variables.
+    Builder {
+        resume: extern "C" fn(*mut u64) -> u64,
+        frame: Vec<u64>,
+    },
+    Elements(*mut DotlinArray),
+    Map(*mut DotlinSequence, *mut u64),
+    Filter(*mut DotlinSequence, *mut u64),
+    Take(*mut DotlinSequence, u64),
+}
+
+pub enum DotlinSequenceIterator {
+    Generator {
+        resume: extern "C" fn(*mut u64) -> u64,
+        frame: Vec<u64>,
+    },
+    Elements(*mut DotlinArray, u64),
+    Map(Box<DotlinSequenceIterator>, *mut u64),
+    Filter(Box<DotlinSequenceIterator>, *mut u64),
+    Take(Box<DotlinSequenceIterator>, u64),
+}
+
+impl DotlinSequenceIterator {
+    fn next(&mut self) -> Option<u64> {
+        match self {
+            DotlinSequenceIterator::Generator { resume, frame } => {
+                (resume(frame.as_mut_ptr()) != 0).then(|| frame[1])
+            }
+            DotlinSequenceIterator::Elements(array, position) => {
+                if *position >= dotlin_array_length(*array) {
+                    return None;
+                }
+                *position += 1;
+                Some(dotlin_array_get(*array, *position - 1))
+            }
+            DotlinSequenceIterator::Map(source, transform) => source
+                .next()
+                .map(|element| call_closure(*transform, element)),
+            DotlinSequenceIterator::Filter(source, predicate) => {
+                while let Some(element) = source.next() {
+                    if call_closure(*predicate, element) as u8 != 0 {
+                        return Some(element);
+                    }
+                }
+                None
+            }
+            DotlinSequenceIterator::Take(source, remaining) => {
+                if *remaining == 0 {
+                    return None;
+                }
+                *remaining -= 1;
+                source.next()
+            }
+        }
+    }
+}
+
+fn new_sequence(sequence: DotlinSequence) -> *mut DotlinSequence {
+    Box::into_raw(Box::new(sequence))
+}
+
+/// Creates the sequence of a `sequence { }` body compiled to `resume`, with
+/// a frame of `slots` zeroed slots; see `dotlin_sequence_frame`.
+#[no_mangle]
+pub extern "C" fn dotlin_sequence_builder(
+    resume: extern "C" fn(*mut u64) -> u64,
+    slots: u64,
+) -> *mut DotlinSequence {
+    new_sequence(DotlinSequence::Builder {
+        resume,
+        frame: vec![0; slots as usize],
+    })
+}
+
+/// The frame every iteration of a `sequence { }` starts from, for generated
+/// code to store the captured variables in.
+#[no_mangle]
+pub extern "C" fn dotlin_sequence_frame(sequence: *mut DotlinSequence) -> *mut u64 {
+    match unsafe { &mut *sequence } {
+        DotlinSequence::Builder { frame, .. } => frame.as_mut_ptr(),
+        _ => std::ptr::null_mut(),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn dotlin_sequence_from_array(array: *mut DotlinArray) -> *mut DotlinSequence {
+    new_sequence(DotlinSequence::Elements(array))
+}
+
+#[no_mangle]
+pub extern "C" fn dotlin_sequence_map(
+    sequence: *mut DotlinSequence,
+    transform: *mut u64,
+) -> *mut DotlinSequence {
+    new_sequence(DotlinSequence::Map(sequence, transform))
+}
+
+#[no_mangle]
+pub extern "C" fn dotlin_sequence_filter(
+    sequence: *mut DotlinSequence,
+    predicate: *mut u64,
+) -> *mut DotlinSequence {
+    new_sequence(DotlinSequence::Filter(sequence, predicate))
+}
+
+#[no_mangle]
+pub extern "C" fn dotlin_sequence_take(
+    sequence: *mut DotlinSequence,
+    count: i64,
+) -> *mut DotlinSequence {
+    if count < 0 {
+        throw_exception(
+            "IllegalArgumentException",
+            &format!("Requested element count {} is less than zero.", count),
+        );
+    }
+    new_sequence(DotlinSequence::Take(sequence, count as u64))
+}
+
+#[no_mangle]
+pub extern "C" fn dotlin_sequence_iterator(
+    sequence: *mut DotlinSequence,
+) -> *mut DotlinSequenceIterator {
+    fn iterate(sequence: *mut DotlinSequence) -> DotlinSequenceIterator {
+        match unsafe { &*sequence } {
+            DotlinSequence::Builder { resume, frame } => DotlinSequenceIterator::Generator {
+                resume: *resume,
+                frame: frame.clone(),
+            },
+            DotlinSequence::Elements(array) => DotlinSequenceIterator::Elements(*array, 0),
+            DotlinSequence::Map(source, transform) => {
+                DotlinSequenceIterator::Map(Box::new(iterate(*source)), *transform)
+            }
+            DotlinSequence::Filter(source, predicate) => {
+                DotlinSequenceIterator::Filter(Box::new(iterate(*source)), *predicate)
+            }
+            DotlinSequence::Take(source, count) => {
+                DotlinSequenceIterator::Take(Box::new(iterate(*source)), *count)
+            }
+        }
+    }
+    Box::into_raw(Box::new(iterate(sequence)))
+}
+
+/// Advances the iterator: returns 1 and stores the element in `element`, or
+/// returns 0 once the sequence is exhausted.
+#[no_mangle]
+pub extern "C" fn dotlin_sequence_next(
+    iterator: *mut DotlinSequenceIterator,
+    element: *mut u64,
+) -> u64 {
+    match unsafe { &mut *iterator }.next() {
+        Some(value) => {
+            unsafe { *element = value };
+            1
+        }
+        None => 0,
+    }
+}
+
+/// `sequence.toList()`
+#[no_mangle]
+pub extern "C" fn dotlin_sequence_to_array(sequence: *mut DotlinSequence) -> *mut DotlinArray {
+    let array = dotlin_array_new(8, 8);
+    let iterator = dotlin_sequence_iterator(sequence);
+    let mut iterator = unsafe { Box::from_raw(iterator) };
+    while let Some(element) = iterator.next() {
+        dotlin_array_push(array, element);
+    }
+    array
+}
+
+// Coroutine runtime functions
+//
+// A coroutine is compiled to a resume function that runs it up to its next
+// suspension in a frame of 8-byte slots: the resume state, the result, the
+// address of the slots saving its variables, then its arguments or captured
+// variables. The resume function returns 1 when the coroutine suspends and 0
+// once it has completed, with its result in slot 1. Calls to a suspend
+// function resume the callee's frame the same way, until it completes.
+//
+// `runBlocking` runs a deterministic event loop on the current thread: tasks
+// run in the order they became ready, and timers fire in the order of their
+// deadline, then of their creation. `runTest` fires timers in virtual time,
+// without sleeping.
+
+/// Allocates the frame of a coroutine or of a suspend function call.
+#[no_mangle]
+pub extern "C" fn dotlin_frame_new(slots: u64) -> *mut u64 {
+    let slots: Vec<u64> = vec![0; slots.max(1) as usize];
+    Box::into_raw(slots.into_boxed_slice()) as *mut u64
+}
+
+/// What `launch` and `async` return.
+pub struct DotlinJob {
+    result: Option<u64>,
+    /// Tasks waiting for the job to complete
+    waiters: Vec<usize>,
+}
+
+struct Task {
+    resume: extern "C" fn(*mut u64) -> u64,
+    frame: *mut u64,
+    job: *mut DotlinJob,
+}
+
+struct EventLoop {
+    virtual_time: bool,
+    started: std::time::Instant,
+    /// Milliseconds since the loop started: the deadline of the last timer fired
+    now: i64,
+    ready: std::collections::VecDeque<usize>,
+    /// Deadline, creation order and task of each pending `delay`
+    timers: std::collections::BinaryHeap<std::cmp::Reverse<(i64, u64, usize)>>,
+    timers_created: u64,
+    tasks: Vec<Task>,
+    /// The task running
+    current: usize,
+}
+
+thread_local! {
+    /// The event loops of the `runBlocking` calls in progress, innermost last
+    static EVENT_LOOPS: std::cell::RefCell<Vec<EventLoop>> = const { std::cell::RefCell::new(Vec::new()) };
+}
+
+fn with_event_loop<T>(f: impl FnOnce(&mut EventLoop) -> T) -> T {
+    EVENT_LOOPS.with(|loops| {
+        let mut loops = loops.borrow_mut();
+        match loops.last_mut() {
+            Some(event_loop) => f(event_loop),
+            None => {
+                eprintln!("Coroutines can only run inside runBlocking");
+                std::process::exit(1);
+            }
+        }
+    })
+}
+
+fn spawn(resume: extern "C" fn(*mut u64) -> u64, frame: *mut u64) -> *mut DotlinJob {
+    let job = Box::into_raw(Box::new(DotlinJob {
+        result: None,
+        waiters: Vec::new(),
+    }));
+    with_event_loop(|event_loop| {
+        event_loop.ready.push_back(event_loop.tasks.len());
+        event_loop.tasks.push(Task { resume, frame, job });
+    });
+    job
+}
+
+/// The next task to resume: a ready one, or else the one whose timer fires
+/// first, once it has.
+fn next_ready() -> Option<usize> {
+    with_event_loop(|event_loop| {
+        if let Some(task) = event_loop.ready.pop_front() {
+            return Some(task);
+        }
+        let std::cmp::Reverse((deadline, _, task)) = event_loop.timers.pop()?;
+        event_loop.now = deadline;
+        if !event_loop.virtual_time {
+            let at = event_loop.started + std::time::Duration::from_millis(deadline as u64);
+            std::thread::sleep(at.saturating_duration_since(std::time::Instant::now()));
+        }
+        Some(task)
+    })
+}
+
+/// `runBlocking` (or `runTest`, in virtual time): runs the coroutine compiled
+/// to `resume` and every coroutine it launches to completion, and returns its
+/// result.
+#[no_mangle]
+pub extern "C" fn dotlin_run_blocking(
+    resume: extern "C" fn(*mut u64) -> u64,
+    frame: *mut u64,
+    virtual_time: u64,
+) -> u64 {
+    EVENT_LOOPS.with(|loops| {
+        loops.borrow_mut().push(EventLoop {
+            virtual_time: virtual_time != 0,
+            started: std::time::Instant::now(),
+            now: 0,
+            ready: std::collections::VecDeque::new(),
+            timers: std::collections::BinaryHeap::new(),
+            timers_created: 0,
+            tasks: Vec::new(),
+            current: 0,
+        })
+    });
+    let job = spawn(resume, frame);
+    while let Some(task) = next_ready() {
+        let (resume, frame) = with_event_loop(|event_loop| {
+            event_loop.current = task;
+            let task = &event_loop.tasks[task];
+            (task.resume, task.frame)
+        });
+        if resume(frame) != 0 {
+            continue;
+        }
+        with_event_loop(|event_loop| {
+            let job = unsafe { &mut *event_loop.tasks[task].job };
+            job.result = Some(unsafe { *frame.add(1) });
+            event_loop.ready.extend(job.waiters.drain(..));
+        });
+    }
+    EVENT_LOOPS.with(|loops| loops.borrow_mut().pop());
+    dotlin_job_result(job)
+}
+
+/// `launch` or `async`: starts the coroutine compiled to `resume` in the
+/// current event loop.
+#[no_mangle]
+pub extern "C" fn dotlin_launch(
+    resume: extern "C" fn(*mut u64) -> u64,
+    frame: *mut u64,
+) -> *mut DotlinJob {
+    spawn(resume, frame)
+}
+
+/// `delay(ms)`: returns 1 if the running task must suspend until its timer
+/// fires, or 0 for a delay that is not positive.
+#[no_mangle]
+pub extern "C" fn dotlin_delay(ms: i64) -> u64 {
+    if ms <= 0 {
+        return 0;
+    }
+    with_event_loop(|event_loop| {
+        let timer = (
+            event_loop.now + ms,
+            event_loop.timers_created,
+            event_loop.current,
+        );
+        event_loop.timers.push(std::cmp::Reverse(timer));
+        event_loop.timers_created += 1;
+    });
+    1
+}
+
+/// `await()` or `join()`: returns 0 if `job` has completed, or else 1 after
+/// making the running task wait for it.
+#[no_mangle]
+pub extern "C" fn dotlin_await(job: *mut DotlinJob) -> u64 {
+    let job = unsafe { &mut *job };
+    if job.result.is_some() {
+        return 0;
+    }
+    with_event_loop(|event_loop| job.waiters.push(event_loop.current));
+    1
+}
+
+/// The result of a completed job, in an 8-byte slot.
+#[no_mangle]
+pub extern "C" fn dotlin_job_result(job: *mut DotlinJob) -> u64 {
+    unsafe { &*job }.result.unwrap_or(0)
+}
+
+/// `currentTime`: milliseconds since the current event loop started.
+#[no_mangle]
+pub extern "C" fn dotlin_current_time() -> i64 {
+    with_event_loop(|event_loop| event_loop.now)
+}
+
+// Thread runtime functions
+//
+// `thread { }` runs a closure on a new thread. Arrays, maps and the other
+// runtime values are not synchronized: the type checker only lets threads
+// share immutable values and the values below, which are, and nothing checks
+// this at run time. Like Kotlin, the program ends once every thread has
+// completed; see `dotlin_join_threads`.
+
+pub struct DotlinThread {
+    handle: std::sync::Mutex<Option<std::thread::JoinHandle<()>>>,
+}
+
+/// Threads that may not have been joined yet
+static THREADS: std::sync::Mutex<Vec<std::sync::Arc<DotlinThread>>> =
+    std::sync::Mutex::new(Vec::new());
+
+/// Ends the program with a Kotlin exception thrown in the current thread.
+fn throw_exception(exception: &str, message: &str) -> ! {
+    let _ = std::io::stdout().flush();
+    let thread = std::thread::current();
+    eprint!(
+        "Exception in thread \"{}\" {}: {}\n{}",
+        thread.name().unwrap_or("main"),
+        exception,
+        message,
+        stack_trace()
+    );
+    std::process::exit(1);
+}
+
+// Stack traces: compiled functions record their calls on a per-thread stack,
+// and the line each is at, so an uncaught exception can print the calls it
+// escaped from like the JVM does, e.g. `\tat main(main.lin:12)`.
+
+struct Call {
+    /// The function's name and source file as length-prefixed string
+    /// constants; the file is null for a lambda
+    function: *const u8,
+    file: *const u8,
+    line: u64,
+}
+
+thread_local! {
+    /// The calls in progress on this thread, innermost last
+    static CALLS: std::cell::RefCell<Vec<Call>> = const { std::cell::RefCell::new(Vec::new()) };
+}
+
+#[no_mangle]
+pub extern "C" fn dotlin_call_enter(function: *const u8, file: *const u8) {
+    CALLS.with(|calls| {
+        calls.borrow_mut().push(Call {
+            function,
+            file,
+            line: 0,
+        })
+    });
+}
+
+/// Notes that the innermost call reached source line `line`.
+#[no_mangle]
+pub extern "C" fn dotlin_call_line(line: u64) {
+    CALLS.with(|calls| {
+        if let Some(call) = calls.borrow_mut().last_mut() {
+            call.line = line;
+        }
+    });
+}
+
+#[no_mangle]
+pub extern "C" fn dotlin_call_leave() {
+    CALLS.with(|calls| calls.borrow_mut().pop());
+}
+
+/// The text of a length-prefixed string constant, or `None` for null.
+fn constant_text(string: *const u8) -> Option<&'static str> {
+    if string.is_null() {
+        return None;
+    }
+    unsafe {
+        let len = *(string as *const u64);
+        let bytes = std::slice::from_raw_parts(string.add(8), len as usize);
+        std::str::from_utf8(bytes).ok()
+    }
+}
+
+/// The calls in progress, innermost first, one `\tat` line each. A lambda
+/// is shown in the file of its caller.
+fn stack_trace() -> String {
+    CALLS.with(|calls| {
+        let calls = calls.borrow();
+        let mut file = None;
+        let mut lines: Vec<String> = calls
+            .iter()
+            .map(|call| {
+                file = constant_text(call.file).or(file);
+                let function = constant_text(call.function).unwrap_or("<unknown>");
+                let file = file.unwrap_or("Unknown Source");
+                match call.line {
+                    0 => format!("\tat {}({})\n", function, file),
+                    line => format!("\tat {}({}:{})\n", function, file, line),
+                }
+            })
+            .collect();
+        lines.reverse();
+        lines.concat()
+    })
+}
+
+/// Makes a panic in the runtime end the program like an uncaught exception,
+/// with the stack trace of the compiled calls that led to it.
+#[no_mangle]
+pub extern "C" fn dotlin_install_panic_hook() {
+    std::panic::set_hook(Box::new(|info| {
+        let message = match info.payload().downcast_ref::<&str>() {
+            Some(message) => message.to_string(),
+            None => match info.payload().downcast_ref::<String>() {
+                Some(message) => message.clone(),
+                None => "panic".to_string(),
+            },
+        };
+        throw_exception("RuntimeException", &message);
+    }));
+}
+
+// Profiling: executables compiled with `dotc --profile` also time every
+// call of a compiled function, and write where the time went once `main`
+// returns, like `dotc --run --profile` does in the interpreter. A call's
+// inclusive time runs until it returns, and its exclusive time leaves out the
+// calls it makes; a recursive function's inclusive time counts its outermost
+// calls only.
+
+/// A call being timed.
+struct TimedCall {
+    function: &'static str,
+    started: std::time::Instant,
+    /// The inclusive time of the calls it made
+    callees: std::time::Duration,
+}
+
+/// The calls, inclusive and exclusive time of a function.
+#[derive(Default)]
+struct FunctionTimes {
+    calls: u64,
+    inclusive: std::time::Duration,
+    exclusive: std::time::Duration,
+}
+
+/// The times of every thread so far: by function, and the exclusive time
+/// spent in each call stack, outermost first.
+#[derive(Default)]
+struct ProfileData {
+    functions: StdHashMap<&'static str, FunctionTimes>,
+    stacks: StdHashMap<Vec<&'static str>, std::time::Duration>,
+}
+
+static PROFILE: std::sync::Mutex<Option<ProfileData>> = std::sync::Mutex::new(None);
+
+thread_local! {
+    /// The calls being timed on this thread, innermost last
+    static TIMED_CALLS: std::cell::RefCell<Vec<TimedCall>> =
+        const { std::cell::RefCell::new(Vec::new()) };
+}
+
+#[no_mangle]
+pub extern "C" fn dotlin_profile_enter(function: *const u8) {
+    let function = constant_text(function).unwrap_or("<unknown>");
+    TIMED_CALLS.with(|calls| {
+        calls.borrow_mut().push(TimedCall {
+            function,
+            started: std::time::Instant::now(),
+            callees: std::time::Duration::ZERO,
+        })
+    });
+}
+
+#[no_mangle]
+pub extern "C" fn dotlin_profile_leave() {
+    TIMED_CALLS.with(|calls| {
+        let mut calls = calls.borrow_mut();
+        let Some(call) = calls.pop() else {
+            return;
+        };
+        let elapsed = call.started.elapsed();
+        let exclusive = elapsed.saturating_sub(call.callees);
+        if let Some(caller) = calls.last_mut() {
+            caller.callees += elapsed;
+        }
+        let recursive = calls.iter().any(|outer| outer.function == call.function);
+        let mut stack: Vec<&'static str> = calls.iter().map(|outer| outer.function).collect();
+        stack.push(call.function);
+
+        let mut profile = PROFILE.lock().unwrap_or_else(|e| e.into_inner());
+        let profile = profile.get_or_insert_with(ProfileData::default);
+        *profile.stacks.entry(stack).or_default() += exclusive;
+        let times = profile.functions.entry(call.function).or_default();
+        times.calls += 1;
+        times.exclusive += exclusive;
+        if !recursive {
+            times.inclusive += elapsed;
+        }
+    });
+}
+
+/// Writes the profile to `profile.folded`, in the folded-stack format flame
+/// graph tools read, and to the summary `profile.json`, with times in
+/// microseconds. The launcher of a profiled program calls it once every
+/// thread has finished.
+#[no_mangle]
+pub extern "C" fn dotlin_profile_report() {
+    let profile = PROFILE
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .take()
+        .unwrap_or_default();
+    let mut stacks: Vec<_> = profile.stacks.into_iter().collect();
+    stacks.sort();
+    let folded: String = stacks
+        .iter()
+        .map(|(stack, time)| format!("{} {}\n", stack.join(";"), time.as_micros()))
+        .collect();
+    let mut functions: Vec<_> = profile.functions.into_iter().collect();
+    functions.sort_by(|a, b| b.1.exclusive.cmp(&a.1.exclusive).then(a.0.cmp(b.0)));
+    let functions: Vec<String> = functions
+        .iter()
+        .map(|(name, times)| {
+            format!(
+                "    {{\"name\": \"{}\", \"calls\": {}, \"inclusive_us\": {}, \"exclusive_us\": {}}}",
+                name.replace('\\', "\\\\").replace('"', "\\\""),
+                times.calls,
+                times.inclusive.as_micros(),
+                times.exclusive.as_micros()
+            )
+        })
+        .collect();
+    let json = format!(
+        "{{\n  \"functions\": [\n{}\n  ]\n}}\n",
+        functions.join(",\n")
+    );
+    let written = std::fs::write("profile.folded", folded)
+        .and_then(|()| std::fs::write("profile.json", json));
+    match written {
+        Ok(()) => eprintln!("Profile written to profile.folded and profile.json"),
+        Err(e) => eprintln!("Failed to write the profile: {}", e),
+    }
+}
+
+/// `thread { }`: runs the closure on a new thread, named like Kotlin's.
+#[no_mangle]
+pub extern "C" fn dotlin_thread_start(closure: *mut u64) -> *const DotlinThread {
+    static STARTED: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+    let number = STARTED.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    // Raw pointers can't cross threads; the closure is never freed
+    let closure = closure as usize;
+    let handle = std::thread::Builder::new()
+        .name(format!("Thread-{}", number))
+        .spawn(move || {
+            let closure = closure as *mut u64;
+            let code: extern "C" fn(*mut u64) -> u64 = unsafe { std::mem::transmute(*closure) };
+            code(closure);
+        })
+        .unwrap_or_else(|e| throw_exception("OutOfMemoryError", &e.to_string()));
+    let thread = std::sync::Arc::new(DotlinThread {
+        handle: std::sync::Mutex::new(Some(handle)),
+    });
+    THREADS.lock().unwrap().push(thread.clone());
+    std::sync::Arc::into_raw(thread)
+}
+
+/// `thread.join()`
+#[no_mangle]
+pub extern "C" fn dotlin_thread_join(thread: *const DotlinThread) -> u64 {
+    let handle = unsafe { &*thread }.handle.lock().unwrap().take();
+    if let Some(handle) = handle {
+        let _ = handle.join();
+    }
+    0
+}
+
+/// Waits for every thread, including the ones started meanwhile. The
+/// launcher calls it once `main` returns.
+#[no_mangle]
+pub extern "C" fn dotlin_join_threads() {
+    loop {
+        let next = THREADS.lock().unwrap().pop();
+        match next {
+            Some(thread) => {
+                dotlin_thread_join(&*thread);
+            }
+            None => return,
+        }
+    }
+}
+
+/// `AtomicInt(initial)`
+#[no_mangle]
+pub extern "C" fn dotlin_atomic_new(initial: i64) -> *const std::sync::atomic::AtomicI32 {
+    Box::into_raw(Box::new(std::sync::atomic::AtomicI32::new(initial as i32)))
+}
+
+#[no_mangle]
+pub extern "C" fn dotlin_atomic_get(atomic: *const std::sync::atomic::AtomicI32) -> i64 {
+    unsafe { &*atomic }.load(std::sync::atomic::Ordering::SeqCst) as i64
+}
+
+#[no_mangle]
+pub extern "C" fn dotlin_atomic_set(
+    atomic: *const std::sync::atomic::AtomicI32,
+    value: i64,
+) -> u64 {
+    unsafe { &*atomic }.store(value as i32, std::sync::atomic::Ordering::SeqCst);
+    0
+}
+
+/// `addAndGet(delta)`, which `incrementAndGet()` and `decrementAndGet()` call too.
+#[no_mangle]
+pub extern "C" fn dotlin_atomic_add_and_get(
+    atomic: *const std::sync::atomic::AtomicI32,
+    delta: i64,
+) -> i64 {
+    let delta = delta as i32;
+    let previous = unsafe { &*atomic }.fetch_add(delta, std::sync::atomic::Ordering::SeqCst);
+    previous.wrapping_add(delta) as i64
+}
+
+/// `compareAndSet(expected, new)`: returns 1 if the value was `expected`.
+#[no_mangle]
+pub extern "C" fn dotlin_atomic_compare_and_set(
+    atomic: *const std::sync::atomic::AtomicI32,
+    expected: i64,
+    new: i64,
+) -> u64 {
+    unsafe { &*atomic }
+        .compare_exchange(
+            expected as i32,
+            new as i32,
+            std::sync::atomic::Ordering::SeqCst,
+            std::sync::atomic::Ordering::SeqCst,
+        )
+        .is_ok() as u64
+}
+
+/// A `Mutex`. Generated code locks and unlocks it in separate calls, so it
+/// can't hold a `std::sync::MutexGuard`; it is not reentrant.
+#[derive(Default)]
+pub struct DotlinMutex {
+    locked: std::sync::Mutex<bool>,
+    unlocked: std::sync::Condvar,
+}
+
+#[no_mangle]
+pub extern "C" fn dotlin_mutex_new() -> *const DotlinMutex {
+    Box::into_raw(Box::default())
+}
+
+#[no_mangle]
+pub extern "C" fn dotlin_mutex_lock(mutex: *const DotlinMutex) -> u64 {
+    let mutex = unsafe { &*mutex };
+    let mut locked = mutex.locked.lock().unwrap();
+    while *locked {
+        locked = mutex.unlocked.wait(locked).unwrap();
+    }
+    *locked = true;
+    0
+}
+
+#[no_mangle]
+pub extern "C" fn dotlin_mutex_unlock(mutex: *const DotlinMutex) -> u64 {
+    let mutex = unsafe { &*mutex };
+    *mutex.locked.lock().unwrap() = false;
+    mutex.unlocked.notify_one();
+    0
+}
+
+/// `mutex.withLock { }`: calls the closure holding the lock and returns its
+/// result.
+#[no_mangle]
+pub extern "C" fn dotlin_mutex_with_lock(mutex: *const DotlinMutex, closure: *mut u64) -> u64 {
+    dotlin_mutex_lock(mutex);
+    let code: extern "C" fn(*mut u64) -> u64 = unsafe { std::mem::transmute(*closure) };
+    let result = code(closure);
+    dotlin_mutex_unlock(mutex);
+    result
+}
+
+/// A `Channel` with an unlimited buffer, so sending never waits.
+#[derive(Default)]
+pub struct DotlinChannel {
+    /// The elements sent but not received yet, and whether it is closed
+    state: std::sync::Mutex<(std::collections::VecDeque<u64>, bool)>,
+    changed: std::sync::Condvar,
+}
+
+#[no_mangle]
+pub extern "C" fn dotlin_channel_new() -> *const DotlinChannel {
+    Box::into_raw(Box::default())
+}
+
+#[no_mangle]
+pub extern "C" fn dotlin_channel_send(channel: *const DotlinChannel, element: u64) -> u64 {
+    let channel = unsafe { &*channel };
+    let mut state = channel.state.lock().unwrap();
+    if state.1 {
+        throw_exception("ClosedSendChannelException", "Channel was closed");
+    }
+    state.0.push_back(element);
+    channel.changed.notify_one();
+    0
+}
+
+#[no_mangle]
+pub extern "C" fn dotlin_channel_close(channel: *const DotlinChannel) -> u64 {
+    let channel = unsafe { &*channel };
+    channel.state.lock().unwrap().1 = true;
+    channel.changed.notify_all();
+    0
+}
+
+/// Waits for the next element: returns 1 and stores it in `element`, or
+/// returns 0 once the channel is closed and every element received.
+#[no_mangle]
+pub extern "C" fn dotlin_channel_next(channel: *const DotlinChannel, element: *mut u64) -> u64 {
+    let channel = unsafe { &*channel };
+    let mut state = channel.state.lock().unwrap();
+    loop {
+        if let Some(next) = state.0.pop_front() {
+            unsafe { *element = next };
+            return 1;
+        }
+        if state.1 {
+            return 0;
+        }
+        state = channel.changed.wait(state).unwrap();
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn dotlin_channel_receive(channel: *const DotlinChannel) -> u64 {
+    let mut element = 0;
+    if dotlin_channel_next(channel, &mut element) == 0 {
+        throw_exception("ClosedReceiveChannelException", "Channel was closed");
+    }
+    element
+}
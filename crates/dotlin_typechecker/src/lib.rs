@@ -1,5 +1,5 @@
 use dotlin_ast::*;
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap, HashSet};
 
 #[derive(Debug, thiserror::Error)]
 pub enum TypeError {
@@ -17,11 +17,152 @@ pub enum TypeError {
     },
     #[error("Undefined member '{member}' on type {typ:?}")]
     UndefinedMember { typ: Type, member: String },
+    #[error("Cannot infer return type of recursive function {0}; declare it explicitly")]
+    RecursiveInference(String),
+    #[error("Unresolved import: {0}")]
+    UnresolvedImport(String),
+    #[error("Cannot access '{0}': it is private in another file")]
+    PrivateAccess(String),
+    #[error("Cannot assign to {0}")]
+    NotAssignable(String),
+    #[error("Cannot reassign variable '{0}', which a lambda captures")]
+    CapturedAssignment(String),
+    #[error("'return' is not allowed inside a lambda")]
+    ReturnInLambda,
+    #[error("'yield' is only allowed as a statement of a sequence {{ }} body")]
+    InvalidYield,
+    #[error("Cannot infer {0}")]
+    CannotInfer(String),
+    #[error("'{0}' can only be called from a coroutine or a suspend function")]
+    SuspendOutsideCoroutine(String),
+    #[error("Cannot call '{0}' here: a coroutine can't suspend in a loop condition, an if expression, a scope function's lambda, a safe call or the right side of &&, || and ?:")]
+    UnsupportedSuspension(String),
+    #[error("Cannot share {name} with another thread: {typ:?} is not thread-safe")]
+    NotThreadSafe { name: String, typ: Type },
+    #[error("'lateinit' is not allowed on {0}")]
+    InvalidLateinit(String),
+    #[error("Cannot delegate to {delegate}: it has no suitable 'operator fun {function}'")]
+    InvalidDelegate { delegate: String, function: String },
+    #[error("Conflicting declarations of '{0}'")]
+    ConflictingDeclarations(String),
+    #[error("{0} is defined in terms of itself")]
+    RecursiveType(String),
 }
 
+/// Parameter types and optional return type of a function.
+type FunctionSignature = (Vec<Type>, Option<Type>);
+
+/// The top-level functions and objects names in one file can resolve to.
+#[derive(Default)]
+struct ModuleScope {
+    // Names usable unqualified, mapped to qualified names
+    names: HashMap<String, String>,
+    // Qualified names of the file's own private functions
+    private: HashSet<String>,
+    // Names, unqualified and qualified, of private declarations in other files
+    // that would otherwise be visible
+    hidden: HashSet<String>,
+}
+
+/// The members of an `object` declaration.
+#[derive(Default)]
+struct ObjectInfo {
+    // Property types, known once the initializers have been checked
+    properties: HashMap<String, Option<Type>>,
+    functions: HashSet<String>,
+    // Members declared `private`, only usable inside the object
+    private_members: HashSet<String>,
+    // Properties read by calling their getter
+    getters: HashSet<String>,
+    // Properties assigned by calling their setter
+    setters: HashSet<String>,
+    // Properties with a delegate, which have no value of their own to assign
+    delegated: HashSet<String>,
+}
+
+/// A `value class`, whose instances are values of its property's type.
+struct ValueClassInfo {
+    property: String,
+    typ: Type,
+    functions: HashSet<String>,
+}
+
+/// A lambda, `sequence { }` or coroutine body being checked.
+struct LambdaContext {
+    // Number of enclosing scopes; variables declared in them are captured
+    depth: usize,
+    // Set for a `sequence { }` body
+    sequence: bool,
+    // Set for the body of `runBlocking`, `launch` and the other coroutine builders
+    coroutine: bool,
+    // Set for the body of `thread { }`
+    thread: bool,
+    // Type of the values yielded so far
+    element: Option<Type>,
+}
+
+/// `a.b.c` for a chain of member accesses on a variable.
+fn dotted_name(expr: &Expression) -> Option<String> {
+    match &*expr.kind {
+        ExpressionKind::Variable(name) => Some(name.clone()),
+        ExpressionKind::MemberAccess { object, member } => {
+            Some(format!("{}.{}", dotted_name(object)?, member))
+        }
+        _ => None,
+    }
+}
+
+/// Signed integer types, narrowest first.
+const SIGNED_INTEGER_TYPES: [&str; 4] = ["Byte", "Short", "Int", "Long"];
+const UNSIGNED_INTEGER_TYPES: [&str; 2] = ["UInt", "ULong"];
+const FLOATING_POINT_TYPES: [&str; 2] = ["Float", "Double"];
+
 pub struct TypeChecker {
     scopes: Vec<HashMap<String, Type>>,
-    functions: HashMap<String, (Vec<Type>, Option<Type>)>,
+    functions: HashMap<String, FunctionSignature>,
+    // Expression-bodied functions whose return type has not been inferred yet,
+    // with the index of the module and the object declaring them
+    pending_inference: HashMap<String, (usize, Option<String>, FunctionDecl)>,
+    inferring: Vec<String>,
+    // Declared return type of the function whose body is being checked
+    return_type: Option<Type>,
+    // Functions visible in each module
+    module_scopes: Vec<ModuleScope>,
+    // Index into `module_scopes` of the module being checked
+    current_module: usize,
+    // Members of every object, by qualified object name
+    objects: HashMap<String, ObjectInfo>,
+    // Every value class, by qualified name, which its type and constructor
+    // are called by once checked
+    value_classes: HashMap<String, ValueClassInfo>,
+    // Qualified name of the object whose members are being checked
+    current_object: Option<String>,
+    // Lambdas enclosing the expression being checked, innermost last
+    lambdas: Vec<LambdaContext>,
+    // Variables a lambda captured, and variables reassigned, by the index of
+    // their scope and their name; lambdas capture values, so none may be both
+    captured: HashSet<(usize, String)>,
+    reassigned: HashSet<(usize, String)>,
+    // Key in `functions` of the function whose body is being checked
+    current_function: Option<String>,
+    // The functions each function calls, and those `thread { }` bodies call
+    calls: HashMap<String, BTreeSet<String>>,
+    thread_calls: BTreeSet<String>,
+    // An object property each function uses that isn't thread-safe, with its type
+    shared_properties: HashMap<String, (String, Type)>,
+    // Keys in `functions` of the functions declared `suspend`
+    suspend_functions: HashSet<String>,
+    // Keys in `functions` of the functions declared `operator`
+    operator_functions: HashSet<String>,
+    // Qualified name and type of the property whose accessor is being
+    // checked; the accessor names its value `field`
+    current_property: Option<(String, Type)>,
+    // Set while checking the body of a suspend function
+    suspending: bool,
+    // Number of enclosing constructs a coroutine can't suspend in
+    suspension_barrier: usize,
+    // Methods of the embedding application's types, by type and method name
+    host_methods: HashMap<(String, String), (Vec<Type>, Type)>,
 }
 
 impl Default for TypeChecker {
@@ -34,152 +175,1090 @@ impl TypeChecker {
     pub fn new() -> Self {
         let mut functions = HashMap::new();
         // Built-ins
-        functions.insert("println".to_string(), (vec![], None)); // Special handling
+        for printer in ["println", "print", "eprintln", "eprint"] {
+            functions.insert(printer.to_string(), (vec![], None)); // Special handling
+        }
+        functions.insert(
+            "readLine".to_string(),
+            (
+                vec![],
+                Some(Type::Nullable(Box::new(Type::Named("String".to_string())))),
+            ),
+        );
 
         // Math functions
         functions.insert(
             "abs".to_string(),
             (
-                vec![Type::Named("Float".to_string())],
-                Some(Type::Named("Float".to_string())),
+                vec![Type::Named("Double".to_string())],
+                Some(Type::Named("Double".to_string())),
             ),
         );
         functions.insert(
             "min".to_string(),
             (
                 vec![
-                    Type::Named("Float".to_string()),
-                    Type::Named("Float".to_string()),
+                    Type::Named("Double".to_string()),
+                    Type::Named("Double".to_string()),
                 ],
-                Some(Type::Named("Float".to_string())),
+                Some(Type::Named("Double".to_string())),
             ),
         );
         functions.insert(
             "max".to_string(),
             (
                 vec![
-                    Type::Named("Float".to_string()),
-                    Type::Named("Float".to_string()),
+                    Type::Named("Double".to_string()),
+                    Type::Named("Double".to_string()),
                 ],
-                Some(Type::Named("Float".to_string())),
+                Some(Type::Named("Double".to_string())),
             ),
         );
         functions.insert(
             "sqrt".to_string(),
             (
-                vec![Type::Named("Float".to_string())],
-                Some(Type::Named("Float".to_string())),
+                vec![Type::Named("Double".to_string())],
+                Some(Type::Named("Double".to_string())),
             ),
         );
         functions.insert(
             "pow".to_string(),
             (
                 vec![
-                    Type::Named("Float".to_string()),
-                    Type::Named("Float".to_string()),
+                    Type::Named("Double".to_string()),
+                    Type::Named("Double".to_string()),
                 ],
-                Some(Type::Named("Float".to_string())),
+                Some(Type::Named("Double".to_string())),
             ),
         );
         functions.insert(
             "sin".to_string(),
             (
-                vec![Type::Named("Float".to_string())],
-                Some(Type::Named("Float".to_string())),
+                vec![Type::Named("Double".to_string())],
+                Some(Type::Named("Double".to_string())),
             ),
         );
         functions.insert(
             "cos".to_string(),
             (
-                vec![Type::Named("Float".to_string())],
-                Some(Type::Named("Float".to_string())),
+                vec![Type::Named("Double".to_string())],
+                Some(Type::Named("Double".to_string())),
             ),
         );
         functions.insert(
             "tan".to_string(),
             (
-                vec![Type::Named("Float".to_string())],
-                Some(Type::Named("Float".to_string())),
+                vec![Type::Named("Double".to_string())],
+                Some(Type::Named("Double".to_string())),
             ),
         );
         functions.insert(
             "floor".to_string(),
             (
-                vec![Type::Named("Float".to_string())],
-                Some(Type::Named("Float".to_string())),
+                vec![Type::Named("Double".to_string())],
+                Some(Type::Named("Double".to_string())),
             ),
         );
         functions.insert(
             "ceil".to_string(),
             (
-                vec![Type::Named("Float".to_string())],
-                Some(Type::Named("Float".to_string())),
+                vec![Type::Named("Double".to_string())],
+                Some(Type::Named("Double".to_string())),
             ),
         );
         functions.insert(
             "round".to_string(),
             (
-                vec![Type::Named("Float".to_string())],
-                Some(Type::Named("Float".to_string())),
+                vec![Type::Named("Double".to_string())],
+                Some(Type::Named("Double".to_string())),
             ),
         );
         functions.insert(
             "log".to_string(),
             (
-                vec![Type::Named("Float".to_string())],
-                Some(Type::Named("Float".to_string())),
+                vec![Type::Named("Double".to_string())],
+                Some(Type::Named("Double".to_string())),
             ),
         );
         functions.insert(
             "exp".to_string(),
             (
-                vec![Type::Named("Float".to_string())],
-                Some(Type::Named("Float".to_string())),
+                vec![Type::Named("Double".to_string())],
+                Some(Type::Named("Double".to_string())),
             ),
         );
         functions.insert(
             "PI".to_string(),
-            (vec![], Some(Type::Named("Float".to_string()))),
+            (vec![], Some(Type::Named("Double".to_string()))),
         );
         functions.insert(
             "E".to_string(),
-            (vec![], Some(Type::Named("Float".to_string()))),
+            (vec![], Some(Type::Named("Double".to_string()))),
         );
 
         Self {
             scopes: vec![HashMap::new()],
             functions,
+            pending_inference: HashMap::new(),
+            inferring: Vec::new(),
+            return_type: None,
+            module_scopes: Vec::new(),
+            current_module: 0,
+            objects: HashMap::new(),
+            value_classes: HashMap::new(),
+            current_object: None,
+            lambdas: Vec::new(),
+            captured: HashSet::new(),
+            reassigned: HashSet::new(),
+            current_function: None,
+            calls: HashMap::new(),
+            thread_calls: BTreeSet::new(),
+            shared_properties: HashMap::new(),
+            suspend_functions: HashSet::new(),
+            operator_functions: HashSet::new(),
+            current_property: None,
+            suspending: false,
+            suspension_barrier: 0,
+            host_methods: HashMap::new(),
         }
     }
 
+    /// Declares a global function the embedding application provides.
+    pub fn declare_function(&mut self, name: &str, params: Vec<Type>, ret: Type) {
+        self.functions.insert(name.to_string(), (params, Some(ret)));
+    }
+
+    /// Declares a method of a type the embedding application provides.
+    pub fn declare_method(&mut self, typ: &str, name: &str, params: Vec<Type>, ret: Type) {
+        self.host_methods
+            .insert((typ.to_string(), name.to_string()), (params, ret));
+    }
+
     pub fn check_program(&mut self, program: &mut Program) -> Result<(), TypeError> {
-        // First pass: gather function signatures
-        for decl in &program.declarations {
-            let Declaration::Function(func) = decl;
-            let params = func.params.iter().map(|p| p.typ.clone()).collect();
-            self.functions
-                .insert(func.name.clone(), (params, func.return_type.clone()));
+        self.check_modules(std::slice::from_mut(program))
+    }
+
+    /// Checks the files of a program together. Calls to functions declared in
+    /// the same package or brought in by an `import` are rewritten to the
+    /// function's qualified name (`a.b.foo`), which is how the interpreter and
+    /// codegen name top-level functions. Private functions are only visible
+    /// inside their own file, so two files may each have a private `helper`.
+    /// References to object members become their qualified names too
+    /// (`Registry.items` is the variable `a.b.Registry.items`).
+    pub fn check_modules(&mut self, modules: &mut [Program]) -> Result<(), TypeError> {
+        Self::check_conflicts(modules)?;
+        self.module_scopes = (0..modules.len())
+            .map(|index| Self::module_scope(index, modules))
+            .collect::<Result<_, _>>()?;
+        self.resolve_type_aliases(modules)?;
+        self.qualify_value_classes(modules);
+
+        // First pass: gather function signatures and object members
+        for (index, module) in modules.iter().enumerate() {
+            self.current_module = index;
+            for decl in &module.declarations {
+                if let Declaration::ValueClass(class) = decl {
+                    // The companion's members share the class's namespace
+                    if let Some(companion) = &class.companion {
+                        let members = companion.functions.iter().chain(companion.accessors());
+                        let mut names = members
+                            .map(|func| &func.name)
+                            .chain(companion.properties.iter().map(|p| &p.name));
+                        if let Some(name) =
+                            names.find(|name| class.functions.iter().any(|f| &f.name == *name))
+                        {
+                            return Err(TypeError::ConflictingDeclarations(format!(
+                                "{}.{}",
+                                class.name, name
+                            )));
+                        }
+                    }
+                    self.value_classes.insert(
+                        module.qualified_name(&class.name),
+                        ValueClassInfo {
+                            property: class.property.name.clone(),
+                            typ: class.property.typ.clone(),
+                            functions: class.functions.iter().map(|f| f.name.clone()).collect(),
+                        },
+                    );
+                }
+            }
+            for item in module.functions() {
+                let func = item.decl;
+                let key = match item.object {
+                    Some(_) => item.name,
+                    None => self.function_key(&item.name),
+                };
+                let params = func.params.iter().map(|p| p.typ.clone()).collect();
+                self.functions
+                    .insert(key.clone(), (params, func.return_type.clone()));
+                if func.is_suspend {
+                    self.suspend_functions.insert(key.clone());
+                }
+                if func.is_operator {
+                    self.operator_functions.insert(key.clone());
+                }
+                if func.expression_body && func.return_type.is_none() {
+                    self.pending_inference
+                        .insert(key, (index, item.object, func.clone()));
+                }
+            }
+            for object in module.objects() {
+                let mut info = ObjectInfo::default();
+                for func in &object.functions {
+                    info.functions.insert(func.name.clone());
+                    if func.visibility == Visibility::Private {
+                        info.private_members.insert(func.name.clone());
+                    }
+                }
+                for property in &object.properties {
+                    let name = &property.name;
+                    info.properties.insert(name.clone(), None);
+                    if property.visibility == Visibility::Private {
+                        info.private_members.insert(name.clone());
+                    }
+                    // A property delegated to an object gets accessors calling it
+                    let delegated = property.delegate.is_some();
+                    let by_object = delegated && property.lazy_initializer().is_none();
+                    if property.getter.is_some() || by_object {
+                        info.getters.insert(name.clone());
+                        info.functions.insert(PropertyDecl::getter_name(name));
+                    }
+                    if property.setter.is_some() || (by_object && property.mutable) {
+                        info.setters.insert(name.clone());
+                        info.functions.insert(PropertyDecl::setter_name(name));
+                    }
+                    if delegated {
+                        info.delegated.insert(name.clone());
+                    }
+                }
+                self.objects
+                    .insert(module.qualified_name(&object.name), info);
+            }
+        }
+
+        if let Some(name) = self
+            .value_classes
+            .keys()
+            .find(|name| self.is_recursive_value_class(name))
+        {
+            return Err(TypeError::RecursiveType(format!("value class {}", name)));
+        }
+
+        // Property types come from their initializers, checked in declaration order
+        for (index, module) in modules.iter_mut().enumerate() {
+            self.current_module = index;
+            let package = module.package.clone();
+            for decl in &mut module.declarations {
+                let Some(object) = decl.object_mut() else {
+                    continue;
+                };
+                let object_name = match &package {
+                    Some(package) => format!("{}.{}", package, object.name),
+                    None => object.name.clone(),
+                };
+                self.current_object = Some(object_name.clone());
+                for property in &mut object.properties {
+                    let result = self.check_property(property);
+                    let typ = match result {
+                        Ok(typ) => typ,
+                        Err(e) => {
+                            self.current_object = None;
+                            return Err(e);
+                        }
+                    };
+                    if let Some(info) = self.objects.get_mut(&object_name) {
+                        info.properties.insert(property.name.clone(), Some(typ));
+                    }
+                }
+                self.current_object = None;
+            }
+        }
+
+        // Infer return types of expression-bodied functions, resolving
+        // forward references on demand
+        for (index, module) in modules.iter().enumerate() {
+            self.current_module = index;
+            for item in module.functions() {
+                let key = match item.object {
+                    Some(_) => item.name,
+                    None => self.function_key(&item.name),
+                };
+                if self.pending_inference.contains_key(&key) {
+                    self.infer_return_type(&key)?;
+                }
+            }
         }
 
         // Second pass: check bodies
-        for decl in &mut program.declarations {
-            self.check_declaration(decl)?;
+        for (index, module) in modules.iter_mut().enumerate() {
+            self.current_module = index;
+            let package = module.package.clone();
+            let qualified = |name: &str| match &package {
+                Some(package) => format!("{}.{}", package, name),
+                None => name.to_string(),
+            };
+            for decl in &mut module.declarations {
+                match decl {
+                    Declaration::Function(func) => {
+                        self.current_function = Some(self.function_key(&qualified(&func.name)));
+                        self.check_function(func)?;
+                    }
+                    Declaration::ValueClass(class) => {
+                        let class_name = qualified(&class.name);
+                        for func in &mut class.functions {
+                            self.current_function = Some(format!("{}.{}", class_name, func.name));
+                            self.check_function(func)?;
+                        }
+                    }
+                    Declaration::Object(_) | Declaration::TypeAlias(_) => {}
+                }
+                if let Some(object) = decl.object_mut() {
+                    let object_name = qualified(&object.name);
+                    self.current_object = Some(object_name.clone());
+                    let result = object
+                        .functions
+                        .iter_mut()
+                        .try_for_each(|func| {
+                            self.current_function = Some(format!("{}.{}", object_name, func.name));
+                            self.check_function(func)
+                        })
+                        .and_then(|()| {
+                            object
+                                .properties
+                                .iter_mut()
+                                .try_for_each(|property| self.check_accessors(property))
+                        });
+                    self.current_object = None;
+                    result?;
+                }
+                self.current_function = None;
+            }
+        }
+        self.check_thread_calls()?;
+
+        // The backends represent a value class instance by its property's value
+        for module in modules.iter_mut() {
+            module.visit_types_mut(&mut |typ| {
+                Self::substitute_named(typ, &mut |name| self.erased_value_class(name))
+            });
+        }
+        Ok(())
+    }
+
+    /// Replaces the names of type aliases with the types they stand for.
+    fn resolve_type_aliases(&self, modules: &mut [Program]) -> Result<(), TypeError> {
+        let mut aliases = HashMap::new();
+        for (index, module) in modules.iter().enumerate() {
+            for decl in &module.declarations {
+                if let Declaration::TypeAlias(alias) = decl {
+                    aliases.insert(
+                        module.qualified_name(&alias.name),
+                        (index, alias.typ.clone()),
+                    );
+                }
+            }
+        }
+        let mut resolved = HashMap::new();
+        for name in aliases.keys() {
+            self.expand_alias(name, &aliases, &mut resolved, &mut Vec::new())?;
+        }
+        if resolved.is_empty() {
+            return Ok(());
+        }
+        for (index, module) in modules.iter_mut().enumerate() {
+            let names = &self.module_scopes[index].names;
+            module.visit_types_mut(&mut |typ| {
+                Self::substitute_named(typ, &mut |name| resolved.get(names.get(name)?).cloned())
+            });
+        }
+        Ok(())
+    }
+
+    /// Replaces the names of value classes in types with their qualified
+    /// names, so that two packages may each have a value class of a name.
+    fn qualify_value_classes(&self, modules: &mut [Program]) {
+        let classes: HashSet<String> = modules
+            .iter()
+            .flat_map(|module| {
+                module.declarations.iter().filter_map(|decl| match decl {
+                    Declaration::ValueClass(class) => Some(module.qualified_name(&class.name)),
+                    _ => None,
+                })
+            })
+            .collect();
+        if classes.is_empty() {
+            return;
+        }
+        for (index, module) in modules.iter_mut().enumerate() {
+            let names = &self.module_scopes[index].names;
+            module.visit_types_mut(&mut |typ| {
+                Self::substitute_named(typ, &mut |name| {
+                    let qualified = names.get(name).filter(|name| classes.contains(*name))?;
+                    Some(Type::Named(qualified.clone()))
+                })
+            });
+        }
+    }
+
+    /// The type the alias `name` stands for, with the aliases it mentions
+    /// expanded in turn. `expanding` holds the aliases being expanded.
+    fn expand_alias(
+        &self,
+        name: &str,
+        aliases: &HashMap<String, (usize, Type)>,
+        resolved: &mut HashMap<String, Type>,
+        expanding: &mut Vec<String>,
+    ) -> Result<Type, TypeError> {
+        if let Some(typ) = resolved.get(name) {
+            return Ok(typ.clone());
+        }
+        if expanding.iter().any(|alias| alias == name) {
+            return Err(TypeError::RecursiveType(format!("typealias {}", name)));
+        }
+        expanding.push(name.to_string());
+        let (module, mut typ) = aliases[name].clone();
+        let mut error = None;
+        Self::substitute_named(&mut typ, &mut |alias| {
+            let qualified = self.module_scopes[module]
+                .names
+                .get(alias)
+                .filter(|qualified| aliases.contains_key(*qualified))?;
+            match self.expand_alias(qualified, aliases, resolved, expanding) {
+                Ok(typ) => Some(typ),
+                Err(e) => {
+                    error.get_or_insert(e);
+                    None
+                }
+            }
+        });
+        expanding.pop();
+        if let Some(e) = error {
+            return Err(e);
+        }
+        resolved.insert(name.to_string(), typ.clone());
+        Ok(typ)
+    }
+
+    /// Replaces each `Named` type in `typ` that `replace` gives a type for.
+    fn substitute_named(typ: &mut Type, replace: &mut dyn FnMut(&str) -> Option<Type>) {
+        match typ {
+            Type::Named(name) => {
+                if let Some(replacement) = replace(name) {
+                    *typ = replacement;
+                }
+            }
+            Type::Array(element) => Self::substitute_named(element, replace),
+            Type::Map(key, value) => {
+                Self::substitute_named(key, replace);
+                Self::substitute_named(value, replace);
+            }
+            Type::Generic(_, args) => {
+                for arg in args {
+                    Self::substitute_named(arg, replace);
+                }
+            }
+            Type::Function(params, ret) => {
+                for param in params {
+                    Self::substitute_named(param, replace);
+                }
+                Self::substitute_named(ret, replace);
+            }
+            Type::Nullable(inner) => {
+                Self::substitute_named(inner, replace);
+                // `N?` for an alias of `Int?` is just `Int?`
+                if let Type::Nullable(nested) = &**inner {
+                    *typ = Type::Nullable(nested.clone());
+                }
+            }
+        }
+    }
+
+    /// The type of value class `name`'s property, itself erased, or `None`
+    /// if `name` is not a value class.
+    fn erased_value_class(&self, name: &str) -> Option<Type> {
+        let mut typ = self.value_classes.get(name)?.typ.clone();
+        Self::substitute_named(&mut typ, &mut |name| self.erased_value_class(name));
+        Some(typ)
+    }
+
+    /// Whether the property type of value class `name` mentions it, directly
+    /// or through other value classes.
+    fn is_recursive_value_class(&self, name: &str) -> bool {
+        let mut pending = vec![self.value_classes[name].typ.clone()];
+        let mut seen = HashSet::new();
+        let mut found = false;
+        while let Some(mut typ) = pending.pop() {
+            Self::substitute_named(&mut typ, &mut |mentioned| {
+                if mentioned == name {
+                    found = true;
+                } else if let Some(class) = self.value_classes.get(mentioned) {
+                    if seen.insert(mentioned.to_string()) {
+                        pending.push(class.typ.clone());
+                    }
+                }
+                None
+            });
+        }
+        found
+    }
+
+    fn value_class(&self, typ: &Type) -> Option<&ValueClassInfo> {
+        match typ {
+            Type::Named(name) => self.value_classes.get(name),
+            _ => None,
+        }
+    }
+
+    /// `Name(property=value)`, the `toString()` of value class instance
+    /// `value`, a checked expression.
+    fn value_class_string(&self, mut value: Expression) -> Expression {
+        let Some((name, class)) = (match &value.resolved_type {
+            Some(Type::Named(name)) => self.value_classes.get_key_value(name),
+            _ => None,
+        }) else {
+            return value;
+        };
+        let string = Type::Named("String".to_string());
+        let line = value.line;
+        let typed = |kind: ExpressionKind| Expression {
+            kind: Box::new(kind),
+            resolved_type: Some(string.clone()),
+            slot: None,
+            line,
+        };
+        value.resolved_type = Some(class.typ.clone());
+        let text = if class.typ == string {
+            value
+        } else if self.value_class(&class.typ).is_some() {
+            self.value_class_string(value)
+        } else {
+            typed(ExpressionKind::Call {
+                callee: Expression::new(ExpressionKind::MemberAccess {
+                    object: value,
+                    member: "toString".to_string(),
+                }),
+                arguments: Vec::new(),
+            })
+        };
+        // Named without its package, as Kotlin does
+        let simple_name = name.rsplit('.').next().unwrap_or(name);
+        let prefix = typed(ExpressionKind::Literal(Literal::String(format!(
+            "{}({}=",
+            simple_name, class.property
+        ))));
+        let prefixed = typed(ExpressionKind::Binary {
+            left: prefix,
+            operator: BinaryOp::Add,
+            right: text,
+        });
+        typed(ExpressionKind::Binary {
+            left: prefixed,
+            operator: BinaryOp::Add,
+            right: typed(ExpressionKind::Literal(Literal::String(")".to_string()))),
+        })
+    }
+
+    /// Checks a property initializer or delegate against the declared type,
+    /// filling in an omitted one, and gives the property's accessors its type.
+    fn check_property(&mut self, property: &mut PropertyDecl) -> Result<Type, TypeError> {
+        let typ = if property.lateinit {
+            match &property.typ {
+                Some(typ) if Self::is_reference_type(typ) => typ.clone(),
+                Some(typ) => {
+                    return Err(TypeError::InvalidLateinit(format!(
+                        "property '{}' of type {:?}",
+                        property.name, typ
+                    )))
+                }
+                None => {
+                    return Err(TypeError::InvalidLateinit(format!(
+                        "property '{}' without a type",
+                        property.name
+                    )))
+                }
+            }
+        } else if property.delegate.is_some() {
+            self.check_delegate(property)?
+        } else if let Some(initializer) = &mut property.initializer {
+            let mut typ = self.check_expression(initializer)?;
+            if let Some(declared) = &property.typ {
+                if Self::coerce(initializer, declared) {
+                    typ = declared.clone();
+                }
+                if typ != *declared {
+                    return Err(TypeError::Mismatch {
+                        expected: declared.clone(),
+                        found: typ,
+                    });
+                }
+            }
+            typ
+        } else {
+            match &property.typ {
+                Some(typ) => typ.clone(),
+                // Only a getter, whose body gives the type
+                None => {
+                    let getter = self.accessor_key(&PropertyDecl::getter_name(&property.name));
+                    let typ = self.infer_return_type(&getter)?;
+                    if let Some(getter) = &mut property.getter {
+                        getter.return_type = Some(typ.clone());
+                    }
+                    typ
+                }
+            }
+        };
+        property.typ = Some(typ.clone());
+
+        if let Some(getter) = &mut property.getter {
+            if let Some(declared) = getter.return_type.as_ref().filter(|t| **t != typ) {
+                return Err(TypeError::Mismatch {
+                    expected: typ,
+                    found: declared.clone(),
+                });
+            }
+            getter.return_type = Some(typ.clone());
+            let key = self.accessor_key(&getter.name);
+            self.pending_inference.remove(&key);
+            self.functions.insert(key, (Vec::new(), Some(typ.clone())));
+        }
+        if let Some(setter) = &mut property.setter {
+            let param = &mut setter.params[0];
+            if param.typ == Type::Named("_".to_string()) {
+                param.typ = typ.clone();
+            } else if param.typ != typ {
+                return Err(TypeError::Mismatch {
+                    expected: typ,
+                    found: param.typ.clone(),
+                });
+            }
+            let key = self.accessor_key(&setter.name);
+            self.functions.insert(key, (vec![typ.clone()], None));
+        }
+        Ok(typ)
+    }
+
+    /// The key in `functions` of an accessor of the object being checked.
+    fn accessor_key(&self, name: &str) -> String {
+        format!(
+            "{}.{}",
+            self.current_object.as_deref().unwrap_or_default(),
+            name
+        )
+    }
+
+    /// Types a delegated property: `by lazy { ... }` takes the lambda's type,
+    /// and an object delegate the return type of its `getValue`. A property
+    /// delegated to an object gets accessors calling its `getValue` and
+    /// `setValue` with a `null` receiver and the property's name.
+    fn check_delegate(&mut self, property: &mut PropertyDecl) -> Result<Type, TypeError> {
+        let lazy = property.lazy_initializer().is_some();
+        let Some(delegate) = &mut property.delegate else {
+            unreachable!("check_delegate called on a property without a delegate");
+        };
+        if lazy {
+            if property.mutable {
+                return Err(TypeError::InvalidDelegate {
+                    delegate: "lazy".to_string(),
+                    function: "setValue".to_string(),
+                });
+            }
+            let ExpressionKind::Call { arguments, .. } = &mut *delegate.kind else {
+                unreachable!("lazy delegates are calls");
+            };
+            let Type::Function(_, typ) =
+                self.check_lambda(&mut arguments[0], Some(&[]), property.typ.as_ref())?
+            else {
+                unreachable!("lambdas have function types");
+            };
+            return Ok(*typ);
+        }
+
+        let path = dotted_name(delegate).unwrap_or_default();
+        let invalid = |function: &str| TypeError::InvalidDelegate {
+            delegate: path.clone(),
+            function: function.to_string(),
+        };
+        let kproperty = Type::Generic("KProperty".to_string(), vec![Type::Named("*".to_string())]);
+        let (get_value, params, typ) = match self.operator(&path, "getValue")? {
+            Some((name, (params, Some(typ))))
+                if params.len() == 2
+                    && matches!(params[0], Type::Nullable(_))
+                    && params[1] == kproperty =>
+            {
+                (name, params, typ)
+            }
+            _ => return Err(invalid("getValue")),
+        };
+        if let Some(declared) = property.typ.as_ref().filter(|t| **t != typ) {
+            return Err(TypeError::Mismatch {
+                expected: declared.clone(),
+                found: typ,
+            });
+        }
+        let arguments = |value: Option<Expression>| {
+            let mut this_ref = Expression::new(ExpressionKind::Literal(Literal::Null));
+            this_ref.resolved_type = Some(params[0].clone());
+            let mut name = Expression::new(ExpressionKind::Literal(Literal::String(
+                property.name.clone(),
+            )));
+            name.resolved_type = Some(kproperty.clone());
+            [this_ref, name].into_iter().chain(value).collect()
+        };
+        let call = |name: &str, arguments: Vec<Expression>, typ: &Type| {
+            let mut call = Expression::new(ExpressionKind::Call {
+                callee: Expression::new(ExpressionKind::Variable(name.to_string())),
+                arguments,
+            });
+            call.resolved_type = Some(typ.clone());
+            call
+        };
+        let unit = Type::Named("Unit".to_string());
+
+        property.getter = Some(FunctionDecl {
+            visibility: property.visibility,
+            name: PropertyDecl::getter_name(&property.name),
+            params: Vec::new(),
+            return_type: Some(typ.clone()),
+            body: Block::new(vec![Statement::Return(Some(call(
+                &get_value,
+                arguments(None),
+                &typ,
+            )))]),
+            expression_body: true,
+            is_suspend: false,
+            is_operator: false,
+            file: None,
+        });
+        if property.mutable {
+            let set_value = match self.operator(&path, "setValue")? {
+                Some((name, (set_params, None)))
+                    if set_params.len() == 3
+                        && set_params[..2] == params[..]
+                        && set_params[2] == typ =>
+                {
+                    name
+                }
+                _ => return Err(invalid("setValue")),
+            };
+            let mut value = Expression::new(ExpressionKind::Variable("value".to_string()));
+            value.resolved_type = Some(typ.clone());
+            property.setter = Some(FunctionDecl {
+                visibility: property.visibility,
+                name: PropertyDecl::setter_name(&property.name),
+                params: vec![Param {
+                    name: "value".to_string(),
+                    typ: typ.clone(),
+                }],
+                return_type: None,
+                body: Block::new(vec![Statement::Expression(call(
+                    &set_value,
+                    arguments(Some(value)),
+                    &unit,
+                ))]),
+                expression_body: false,
+                is_suspend: false,
+                is_operator: false,
+                file: None,
+            });
+        }
+        Ok(typ)
+    }
+
+    /// The qualified name and signature of the `operator fun` `function` of
+    /// the object `path` names.
+    fn operator(
+        &mut self,
+        path: &str,
+        function: &str,
+    ) -> Result<Option<(String, FunctionSignature)>, TypeError> {
+        let Some(name) = self.object_member(&format!("{}.{}", path, function))? else {
+            return Ok(None);
+        };
+        if !self.operator_functions.contains(&name) {
+            return Ok(None);
+        }
+        Ok(self.lookup_function(&name)?.map(|(params, ret)| {
+            let ret = ret.filter(|t| *t != Type::Named("Unit".to_string()));
+            (name, (params, ret))
+        }))
+    }
+
+    /// Checks the bodies of a property's own accessors, which name the
+    /// property's value `field`.
+    fn check_accessors(&mut self, property: &mut PropertyDecl) -> Result<(), TypeError> {
+        if property.delegate.is_some() {
+            // Its accessors only call the delegate
+            return Ok(());
+        }
+        self.current_property = Some((
+            self.accessor_key(&property.name),
+            property
+                .typ
+                .clone()
+                .unwrap_or(Type::Named("Unit".to_string())),
+        ));
+        let result = property
+            .getter
+            .iter_mut()
+            .chain(&mut property.setter)
+            .try_for_each(|accessor| self.check_function(accessor));
+        self.current_property = None;
+        result
+    }
+
+    /// Resolves the names usable unqualified in `modules[index]`. Explicit
+    /// imports shadow functions of the same package, which in turn shadow
+    /// wildcard imports.
+    fn module_scope(index: usize, modules: &[Program]) -> Result<ModuleScope, TypeError> {
+        let module = &modules[index];
+        let mut scope = ModuleScope::default();
+        // Qualified names can name any package, so every other file's private declaration is hidden
+        for (_, m) in modules.iter().enumerate().filter(|(i, _)| *i != index) {
+            for (name, visibility) in m.declarations.iter().map(Self::declared_name) {
+                if visibility == Visibility::Private {
+                    scope.hidden.insert(m.qualified_name(name));
+                }
+            }
+        }
+        // Visible declarations of `package`, hiding the unqualified names of other files' private ones
+        let functions_in = |package: Option<&str>, scope: &mut ModuleScope| {
+            let mut visible = Vec::new();
+            for (i, m) in modules.iter().enumerate() {
+                if m.package.as_deref() != package {
+                    continue;
+                }
+                for decl in &m.declarations {
+                    let (name, visibility) = Self::declared_name(decl);
+                    let qualified = m.qualified_name(name);
+                    if visibility != Visibility::Private {
+                        visible.push((name.to_string(), qualified));
+                    } else if i == index {
+                        if let Declaration::Function(_) = decl {
+                            scope.private.insert(qualified.clone());
+                        }
+                        visible.push((name.to_string(), qualified));
+                    } else {
+                        scope.hidden.insert(name.to_string());
+                    }
+                }
+            }
+            visible
+        };
+
+        for import in module.imports.iter().filter(|i| i.wildcard) {
+            if !modules
+                .iter()
+                .any(|m| m.package.as_deref() == Some(import.path.as_str()))
+            {
+                return Err(TypeError::UnresolvedImport(format!("{}.*", import.path)));
+            }
+            let visible = functions_in(Some(&import.path), &mut scope);
+            scope.names.extend(visible);
+        }
+        let visible = functions_in(module.package.as_deref(), &mut scope);
+        scope.names.extend(visible);
+        for import in module.imports.iter().filter(|i| !i.wildcard) {
+            let (package, name) = import
+                .path
+                .rsplit_once('.')
+                .ok_or_else(|| TypeError::UnresolvedImport(import.path.clone()))?;
+            let found = functions_in(Some(package), &mut scope)
+                .into_iter()
+                .find(|(n, _)| n == name);
+            match found {
+                Some((name, qualified)) => {
+                    scope.names.insert(name, qualified);
+                }
+                None if scope.hidden.contains(&import.path) => {
+                    return Err(TypeError::PrivateAccess(import.path.clone()))
+                }
+                None => return Err(TypeError::UnresolvedImport(import.path.clone())),
+            }
+        }
+        // Only other files' private functions not shadowed by a visible one stay hidden
+        let shadowed: Vec<String> = scope
+            .hidden
+            .iter()
+            .filter(|name| scope.names.contains_key(*name))
+            .cloned()
+            .collect();
+        for name in shadowed {
+            scope.hidden.remove(&name);
+        }
+        Ok(scope)
+    }
+
+    /// Rejects two top-level declarations of one name in a package, unless
+    /// they are private to different files.
+    fn check_conflicts(modules: &[Program]) -> Result<(), TypeError> {
+        let mut declared: HashMap<String, Vec<(usize, Visibility)>> = HashMap::new();
+        for (index, module) in modules.iter().enumerate() {
+            for (name, visibility) in module.declarations.iter().map(Self::declared_name) {
+                let qualified = module.qualified_name(name);
+                let others = declared.entry(qualified.clone()).or_default();
+                let private = |v: Visibility| v == Visibility::Private;
+                if others
+                    .iter()
+                    .any(|&(other, v)| other == index || !(private(v) || private(visibility)))
+                {
+                    return Err(TypeError::ConflictingDeclarations(qualified));
+                }
+                others.push((index, visibility));
+            }
         }
         Ok(())
     }
 
-    fn check_declaration(&mut self, decl: &mut Declaration) -> Result<(), TypeError> {
+    fn declared_name(decl: &Declaration) -> (&str, Visibility) {
         match decl {
-            Declaration::Function(func) => {
-                self.scopes.push(HashMap::new());
-                for param in &func.params {
-                    self.define_var(param.name.clone(), param.typ.clone());
+            Declaration::Function(func) => (&func.name, func.visibility),
+            Declaration::Object(object) => (&object.name, object.visibility),
+            Declaration::TypeAlias(alias) => (&alias.name, alias.visibility),
+            Declaration::ValueClass(class) => (&class.name, class.visibility),
+        }
+    }
+
+    /// The key of a function in `functions`: its qualified name, suffixed with
+    /// the module index when it is private to the module being checked.
+    fn function_key(&self, name: &str) -> String {
+        match self.module_scopes.get(self.current_module) {
+            Some(scope) if scope.private.contains(name) => {
+                format!("{}@{}", name, self.current_module)
+            }
+            _ => name.to_string(),
+        }
+    }
+
+    fn check_function(&mut self, func: &mut FunctionDecl) -> Result<(), TypeError> {
+        self.scopes.push(HashMap::new());
+        for param in &func.params {
+            self.define_var(param.name.clone(), param.typ.clone());
+        }
+        self.return_type = func.return_type.clone();
+        self.suspending = func.is_suspend;
+        let result = self.check_block(&mut func.body);
+        self.return_type = None;
+        self.suspending = false;
+        self.scopes.pop();
+        result?;
+
+        if func.expression_body {
+            self.resolve_expression_body(func)?;
+        }
+        Ok(())
+    }
+
+    /// Reconciles the type of an expression body with the declared return type,
+    /// or records the inferred one so codegen can build the signature.
+    fn resolve_expression_body(&mut self, func: &mut FunctionDecl) -> Result<(), TypeError> {
+        let Some(Statement::Return(Some(expr))) = func.body.statements.first() else {
+            return Ok(());
+        };
+        let body_typ = expr
+            .resolved_type
+            .clone()
+            .unwrap_or(Type::Named("Unit".to_string()));
+
+        match &func.return_type {
+            Some(declared) => {
+                if *declared != body_typ {
+                    return Err(TypeError::Mismatch {
+                        expected: declared.clone(),
+                        found: body_typ,
+                    });
+                }
+            }
+            None if body_typ == Type::Named("Unit".to_string()) => {
+                // A Unit body has no value to return
+                if let Some(Statement::Return(Some(expr))) = func.body.statements.pop() {
+                    func.body.statements.push(Statement::Expression(expr));
+                }
+            }
+            None => func.return_type = Some(body_typ),
+        }
+        Ok(())
+    }
+
+    fn infer_return_type(&mut self, name: &str) -> Result<Type, TypeError> {
+        let Some((module, object, func)) = self.pending_inference.remove(name) else {
+            return Ok(self
+                .functions
+                .get(name)
+                .and_then(|(_, ret)| ret.clone())
+                .unwrap_or(Type::Named("Unit".to_string())));
+        };
+
+        self.inferring.push(name.to_string());
+        // Function bodies only see globals, never the locals of the caller being checked
+        let globals = self.scopes[0].clone();
+        let saved_scopes = std::mem::replace(&mut self.scopes, vec![globals]);
+        let saved_return_type = self.return_type.take();
+        // Names in the body resolve against the imports of its own module
+        let saved_module = std::mem::replace(&mut self.current_module, module);
+        let saved_object = std::mem::replace(&mut self.current_object, object);
+        let saved_property = self.current_property.take();
+        let saved_lambdas = std::mem::take(&mut self.lambdas);
+        let saved_suspending = std::mem::replace(&mut self.suspending, func.is_suspend);
+        let saved_barrier = std::mem::take(&mut self.suspension_barrier);
+        self.scopes.push(HashMap::new());
+        for param in &func.params {
+            self.define_var(param.name.clone(), param.typ.clone());
+        }
+        let result = match func.body.statements.first() {
+            Some(Statement::Return(Some(expr))) => self.check_expression(&mut expr.clone()),
+            _ => Ok(Type::Named("Unit".to_string())),
+        };
+        self.scopes = saved_scopes;
+        self.return_type = saved_return_type;
+        self.current_module = saved_module;
+        self.current_object = saved_object;
+        self.current_property = saved_property;
+        self.lambdas = saved_lambdas;
+        self.suspending = saved_suspending;
+        self.suspension_barrier = saved_barrier;
+        self.inferring.pop();
+
+        let typ = result?;
+        if let Some(entry) = self.functions.get_mut(name) {
+            entry.1 = Some(typ.clone());
+        }
+        Ok(typ)
+    }
+
+    /// Looks up a function signature, inferring its return type first if it is
+    /// an expression-bodied function that has not been resolved yet.
+    fn lookup_function(&mut self, name: &str) -> Result<Option<FunctionSignature>, TypeError> {
+        let key = self.function_key(name);
+        if self.inferring.contains(&key) {
+            return Err(TypeError::RecursiveInference(name.to_string()));
+        }
+        if self.pending_inference.contains_key(&key) {
+            self.infer_return_type(&key)?;
+        }
+        Ok(self.functions.get(&key).cloned())
+    }
+
+    /// Returns the type a block evaluates to: its last expression statement, or Unit.
+    fn check_block_value(&mut self, block: &mut Block) -> Result<Type, TypeError> {
+        self.scopes.push(HashMap::new());
+        let mut typ = Type::Named("Unit".to_string());
+        let count = block.statements.len();
+        for (i, stmt) in block.statements.iter_mut().enumerate() {
+            match stmt {
+                Statement::Expression(expr) if i + 1 == count => {
+                    typ = self.check_expression(expr)?;
+                }
+                _ => {
+                    if let Err(e) = self.check_statement(stmt) {
+                        self.scopes.pop();
+                        return Err(e);
+                    }
                 }
-                self.check_block(&mut func.body)?;
-                self.scopes.pop();
-                Ok(())
             }
         }
+        self.scopes.pop();
+        Ok(typ)
     }
 
     fn check_block(&mut self, block: &mut Block) -> Result<(), TypeError> {
@@ -190,6 +1269,9 @@ impl TypeChecker {
     }
 
     fn check_statement(&mut self, stmt: &mut Statement) -> Result<(), TypeError> {
+        if stmt.yielded().is_some() {
+            return self.check_yield(stmt);
+        }
         match stmt {
             Statement::Expression(expr) => {
                 self.check_expression(expr)?;
@@ -201,7 +1283,16 @@ impl TypeChecker {
                 initializer,
             } => {
                 let resolved_typ = if let Some(init) = initializer {
-                    self.check_expression(init)?
+                    let init_typ = match typ {
+                        Some(explicit_typ) => self.check_expression_as(init, explicit_typ)?,
+                        None => self.check_expression(init)?,
+                    };
+                    match typ {
+                        Some(explicit_typ) if Self::coerce(init, explicit_typ) => {
+                            explicit_typ.clone()
+                        }
+                        _ => init_typ,
+                    }
                 } else {
                     typ.clone().unwrap_or(Type::Named("Int".to_string()))
                 };
@@ -219,9 +1310,33 @@ impl TypeChecker {
                 self.define_var(name.clone(), resolved_typ);
                 Ok(())
             }
+            Statement::Destructuring { names, initializer } => {
+                let init_typ = self.check_expression(initializer)?;
+                let component_types = Self::destructure_types(&init_typ, names.len())?;
+                for (name, typ) in names.iter().zip(component_types) {
+                    if name != "_" {
+                        self.define_var(name.clone(), typ);
+                    }
+                }
+                Ok(())
+            }
             Statement::Return(expr) => {
+                if !self.lambdas.is_empty() {
+                    return Err(TypeError::ReturnInLambda);
+                }
                 if let Some(e) = expr {
-                    self.check_expression(e)?;
+                    let typ = match self.return_type.clone() {
+                        Some(expected) => self.check_expression_as(e, &expected)?,
+                        None => self.check_expression(e)?,
+                    };
+                    if let Some(expected) = self.return_type.clone() {
+                        if typ != expected && !Self::coerce(e, &expected) {
+                            return Err(TypeError::Mismatch {
+                                expected,
+                                found: typ,
+                            });
+                        }
+                    }
                 }
                 Ok(())
             }
@@ -244,7 +1359,8 @@ impl TypeChecker {
                 Ok(())
             }
             Statement::While { condition, body } => {
-                let cond_typ = self.check_expression(condition)?;
+                let cond_typ =
+                    self.without_suspension(|checker| checker.check_expression(condition))?;
                 if cond_typ != Type::Named("Boolean".to_string()) {
                     return Err(TypeError::Mismatch {
                         expected: Type::Named("Boolean".to_string()),
@@ -254,6 +1370,21 @@ impl TypeChecker {
                 self.check_statement(body)?;
                 Ok(())
             }
+            Statement::DoWhile { body, condition } => {
+                // The condition is checked in the body's scope
+                self.scopes.push(HashMap::new());
+                self.check_block(body)?;
+                let cond_typ =
+                    self.without_suspension(|checker| checker.check_expression(condition))?;
+                self.scopes.pop();
+                if cond_typ != Type::Named("Boolean".to_string()) {
+                    return Err(TypeError::Mismatch {
+                        expected: Type::Named("Boolean".to_string()),
+                        found: cond_typ,
+                    });
+                }
+                Ok(())
+            }
             Statement::Block(block) => {
                 self.scopes.push(HashMap::new());
                 self.check_block(block)?;
@@ -277,10 +1408,17 @@ impl TypeChecker {
                                 // Create a new scope for the for-each loop
                                 self.scopes.push(HashMap::new());
 
+                                let entry_type =
+                                    Self::pair_type((*key_type).clone(), (*value_type).clone());
+                                iterable.resolved_type = Some(Type::Generic(
+                                    "Iterator".to_string(),
+                                    vec![entry_type.clone()],
+                                ));
+
                                 match variable {
                                     dotlin_ast::ForEachTarget::Ident(name) => {
-                                        // Iterating over a map with a single identifier yields keys
-                                        self.define_var(name.clone(), (*key_type).clone());
+                                        // Iterating with a single identifier yields the entries
+                                        self.define_var(name.clone(), entry_type);
                                     }
                                     dotlin_ast::ForEachTarget::Tuple(names) => {
                                         // If two names, assign key and value types respectively
@@ -318,12 +1456,15 @@ impl TypeChecker {
                 let iterable_type = self.check_expression(iterable)?;
 
                 // For now, we'll handle iteration over arrays and HashMaps
-                let element_type = match iterable_type {
-                    Type::Array(element_type) => *element_type,
+                let element_type = match &iterable_type {
+                    Type::Array(element_type) => (**element_type).clone(),
                     Type::Map(key_type, _) => {
                         // For HashMap iteration, we can iterate over keys, values, or entries
                         // For now, assume iterating over keys
-                        *key_type
+                        (**key_type).clone()
+                    }
+                    Type::Generic(name, args) if name == "Sequence" || name == "Channel" => {
+                        args[0].clone()
                     }
                     _ => {
                         return Err(TypeError::Mismatch {
@@ -342,9 +1483,17 @@ impl TypeChecker {
                         self.define_var(name.clone(), element_type.clone());
                     }
                     dotlin_ast::ForEachTarget::Tuple(names) => {
-                        // For now, assign the same element_type to each destructured name
-                        for n in names {
-                            self.define_var(n.clone(), element_type.clone());
+                        let component_types = match (&iterable_type, names.len()) {
+                            // Destructuring a map directly yields (key, value)
+                            (Type::Map(key_type, value_type), 2) => {
+                                vec![(**key_type).clone(), (**value_type).clone()]
+                            }
+                            _ => Self::destructure_types(&element_type, names.len())?,
+                        };
+                        for (n, typ) in names.iter().zip(component_types) {
+                            if n != "_" {
+                                self.define_var(n.clone(), typ);
+                            }
                         }
                     }
                 }
@@ -364,18 +1513,74 @@ impl TypeChecker {
         let typ = match &mut *expr.kind {
             ExpressionKind::Literal(lit) => match lit {
                 Literal::Integer(_) => Type::Named("Int".to_string()),
+                Literal::Long(_) => Type::Named("Long".to_string()),
+                Literal::UInt(_) => Type::Named("UInt".to_string()),
+                Literal::ULong(_) => Type::Named("ULong".to_string()),
                 Literal::Float(_) => Type::Named("Float".to_string()),
+                Literal::Double(_) => Type::Named("Double".to_string()),
                 Literal::String(_) => Type::Named("String".to_string()),
                 Literal::Boolean(_) => Type::Named("Boolean".to_string()),
                 Literal::Char(_) => Type::Named("Char".to_string()),
+                Literal::Null => Self::null_type(),
+            },
+            ExpressionKind::Variable(name) => match self.lookup_var(name) {
+                Ok(typ) => {
+                    let typ = typ.clone();
+                    self.check_not_shared(name, &typ)?;
+                    self.note_captured(name)?;
+                    typ
+                }
+                Err(_) if name == "currentTime" && self.in_coroutine() => {
+                    Type::Named("Long".to_string())
+                }
+                Err(_) if name == "field" && self.current_property.is_some() => {
+                    let (qualified, typ) = self.current_property.clone().unwrap();
+                    *name = qualified;
+                    typ
+                }
+                Err(e) => match self.property(name)? {
+                    Some((qualified, typ)) => {
+                        self.read_property(expr, qualified);
+                        typ
+                    }
+                    None => return self.check_receiver_member(expr)?.ok_or(e),
+                },
             },
-            ExpressionKind::Variable(name) => self.lookup_var(name)?.clone(),
-            ExpressionKind::Assignment { name, value } => {
-                let var_typ = self.lookup_var(name)?.clone();
-                let val_typ = self.check_expression(value)?;
-                if var_typ != val_typ {
+            ExpressionKind::Assignment { .. } => {
+                if let Some(typ) = self.check_accessor_assignment(expr)? {
+                    expr.resolved_type = Some(typ.clone());
+                    return Ok(typ);
+                }
+                let ExpressionKind::Assignment {
+                    target,
+                    operator,
+                    value,
+                } = &mut *expr.kind
+                else {
+                    unreachable!("the target is not a property with accessors");
+                };
+                let target_typ = self.check_expression(target)?;
+                Self::check_assignable(target)?;
+                if let ExpressionKind::Variable(name) = &*target.kind {
+                    self.check_not_captured(name)?;
+                }
+                let mut val_typ = self.check_expression(value)?;
+                if let Some(operator) = operator {
+                    // `a += b` must type as `a = a + b`
+                    let mut current = target.clone();
+                    val_typ = Self::binary_type(
+                        operator,
+                        &mut current,
+                        target_typ.clone(),
+                        value,
+                        val_typ,
+                    )?;
+                } else if Self::coerce(value, &target_typ) {
+                    val_typ = target_typ.clone();
+                }
+                if target_typ != val_typ {
                     return Err(TypeError::Mismatch {
-                        expected: var_typ,
+                        expected: target_typ,
                         found: val_typ,
                     });
                 }
@@ -387,78 +1592,28 @@ impl TypeChecker {
                 right,
             } => {
                 let lt = self.check_expression(left)?;
-                let rt = self.check_expression(right)?;
-
-                match operator {
-                    BinaryOp::Add
-                    | BinaryOp::Sub
-                    | BinaryOp::Mul
-                    | BinaryOp::Div
-                    | BinaryOp::PlusEqual
-                    | BinaryOp::MinusEqual
-                    | BinaryOp::StarEqual
-                    | BinaryOp::SlashEqual => {
-                        if lt == Type::Named("String".to_string())
-                            && rt == Type::Named("String".to_string())
-                            && matches!(operator, BinaryOp::Add)
-                        {
-                            Type::Named("String".to_string())
-                        } else if lt == rt
-                            && (lt == Type::Named("Int".to_string())
-                                || lt == Type::Named("Float".to_string()))
-                        {
-                            lt
-                        } else {
-                            return Err(TypeError::Mismatch {
-                                expected: lt,
-                                found: rt,
-                            });
-                        }
-                    }
-                    BinaryOp::Equal
-                    | BinaryOp::NotEqual
-                    | BinaryOp::Less
-                    | BinaryOp::LessEqual
-                    | BinaryOp::Greater
-                    | BinaryOp::GreaterEqual => {
-                        if lt != rt {
-                            return Err(TypeError::Mismatch {
-                                expected: lt,
-                                found: rt,
-                            });
-                        }
-                        Type::Named("Boolean".to_string())
-                    }
-                    BinaryOp::And | BinaryOp::Or => {
-                        if lt != Type::Named("Boolean".to_string()) {
-                            return Err(TypeError::Mismatch {
-                                expected: Type::Named("Boolean".to_string()),
-                                found: lt,
-                            });
-                        }
-                        if rt != Type::Named("Boolean".to_string()) {
-                            return Err(TypeError::Mismatch {
-                                expected: Type::Named("Boolean".to_string()),
-                                found: rt,
-                            });
-                        }
-                        Type::Named("Boolean".to_string())
-                    }
-                }
+                // The right side of &&, || and ?: is only evaluated on demand
+                let rt = if matches!(operator, BinaryOp::And | BinaryOp::Or | BinaryOp::Elvis) {
+                    self.without_suspension(|checker| checker.check_expression(right))?
+                } else {
+                    self.check_expression(right)?
+                };
+                Self::binary_type(operator, left, lt, right, rt)?
             }
             ExpressionKind::Unary { operator, operand } => {
                 let ot = self.check_expression(operand)?;
                 match operator {
                     UnaryOp::Minus => {
-                        if ot != Type::Named("Int".to_string())
-                            && ot != Type::Named("Float".to_string())
-                        {
-                            return Err(TypeError::Mismatch {
-                                expected: Type::Named("Int".to_string()),
-                                found: ot,
-                            });
+                        // Negating a Byte or Short widens it to Int, like any other arithmetic
+                        match Self::numeric_result_type(&ot, &ot) {
+                            Some(typ) if !Self::is_unsigned_type(&typ) => typ,
+                            _ => {
+                                return Err(TypeError::Mismatch {
+                                    expected: Type::Named("Int".to_string()),
+                                    found: ot,
+                                })
+                            }
                         }
-                        ot
                     }
                     UnaryOp::Not => {
                         if ot != Type::Named("Boolean".to_string()) {
@@ -471,51 +1626,70 @@ impl TypeChecker {
                     }
                     UnaryOp::Increment | UnaryOp::Decrement => {
                         // Increment and decrement can only be applied to numeric types
-                        if ot != Type::Named("Int".to_string())
-                            && ot != Type::Named("Float".to_string())
-                        {
+                        if !Self::is_numeric_type(&ot) {
                             return Err(TypeError::Mismatch {
                                 expected: Type::Named("Int".to_string()),
                                 found: ot,
                             });
                         }
+                        if let ExpressionKind::Variable(name) = &*operand.kind {
+                            self.check_not_captured(name)?;
+                        }
                         ot // The result type is the same as the operand type
                     }
                 }
             }
             ExpressionKind::Call { callee, arguments } => {
+                if let Some(name) = self.qualified_callee(callee)? {
+                    *callee.kind = ExpressionKind::Variable(name);
+                }
                 // Handle method calls (obj.method()) which are represented as Call with MemberAccess callee
-                if let ExpressionKind::MemberAccess { object, member } = &*callee.kind {
+                if let Some(member) = self.math_member(callee) {
+                    self.check_math_exact(&member, arguments)?
+                } else if let ExpressionKind::MemberAccess { object, member } = &mut *callee.kind {
                     // This is a method call on an object
-                    // We need to handle mutability properly
-                    let mut obj_expr = (*object).clone();
-                    let obj_typ = self.check_expression(&mut obj_expr)?;
+                    let obj_typ = self.check_expression(object)?;
 
                     // Handle type conversion methods and HashMap iteration methods
                     match (&obj_typ, member.as_str()) {
-                        // String conversion methods
-                        (Type::Named(name), "toInt") if name == "String" => {
-                            Type::Named("Int".to_string())
-                        }
-                        (Type::Named(name), "toFloat") if name == "String" => {
-                            Type::Named("Float".to_string())
+                        // Scope functions, whose lambda is inlined
+                        (typ, "let" | "run" | "apply" | "also" | "takeIf" | "takeUnless")
+                            if Self::is_lambda_argument(arguments) =>
+                        {
+                            let receiver = Some(typ.clone());
+                            self.check_scope_function(member, receiver, &mut arguments[0])?
                         }
 
-                        // Numeric conversion methods
-                        (Type::Named(name), "toFloat") if name == "Int" => {
-                            Type::Named("Float".to_string())
+                        // Value class members take the instance as `this`
+                        (typ, "toString") if self.value_class(typ).is_some() => {
+                            *expr = self.value_class_string(object.clone());
+                            Type::Named("String".to_string())
+                        }
+                        (Type::Named(class), method)
+                            if self
+                                .value_classes
+                                .get(class)
+                                .is_some_and(|class| class.functions.contains(method)) =>
+                        {
+                            let name = format!("{}.{}", class, method);
+                            self.note_call(&name);
+                            let (params, ret) = self.lookup_function(&name)?.unwrap();
+                            self.check_arguments(&name, &params[1..], arguments)?;
+                            arguments.insert(0, object.clone());
+                            *callee.kind = ExpressionKind::Variable(name);
+                            ret.unwrap_or(Type::Named("Int".to_string()))
                         }
-                        (Type::Named(name), "toInt") if name == "Float" => {
-                            Type::Named("Int".to_string())
+
+                        // String and numeric conversion methods
+                        (typ, method) if Self::conversion_type(typ, method).is_some() => {
+                            Self::conversion_type(typ, method).unwrap()
                         }
 
                         // To string methods
-                        (Type::Named(name), "toString") if name == "Int" => {
-                            Type::Named("String".to_string())
-                        }
-                        (Type::Named(name), "toString") if name == "Float" => {
+                        (typ, "toString") if Self::is_numeric_type(typ) => {
                             Type::Named("String".to_string())
                         }
+                        (typ, "inv") if Self::is_bitwise_type(typ) => typ.clone(),
                         (Type::Named(name), "toString") if name == "Boolean" => {
                             Type::Named("String".to_string())
                         }
@@ -525,19 +1699,65 @@ impl TypeChecker {
 
                         // Array methods
                         (Type::Array(_), "push") => Type::Named("Int".to_string()), // returns void but using Int as placeholder
-                        (Type::Array(_), "pop") => Type::Named("Int".to_string()), // returns the popped element
+                        (Type::Array(elem), "pop") => (**elem).clone(), // returns the popped element
 
                         // HashMap iteration methods
                         (Type::Map(_, _), "keys") => {
                             Type::Array(Box::new(Type::Named("String".to_string())))
                         } // Returns array of keys
-                        (Type::Map(_, _), "values") => {
-                            Type::Array(Box::new(Type::Named("Int".to_string())))
-                        } // Returns array of values (for now)
+                        (Type::Map(_, value_type), "values") => Type::Array(value_type.clone()), // Returns array of values
                         (Type::Map(_, _), "size") => Type::Named("Int".to_string()), // Returns size as int
-                        (Type::Map(_, _), "entries") => {
-                            Type::Array(Box::new(Type::Named("Int".to_string())))
-                        } // Returns array of alternating key-value pairs (for now)
+                        (Type::Map(key_type, value_type), "entries") => Type::Array(Box::new(
+                            Self::pair_type((**key_type).clone(), (**value_type).clone()),
+                        )),
+
+                        // Lazy sequences
+                        (Type::Array(elem), "asSequence") => {
+                            Self::check_arity(member, arguments, 0)?;
+                            Self::sequence_type((**elem).clone())
+                        }
+                        (Type::Generic(name, args), method) if name == "Sequence" => {
+                            let elem = args[0].clone();
+                            self.check_sequence_method(method, elem, arguments)?
+                        }
+
+                        // Coroutine jobs
+                        (Type::Generic(name, args), "await") if name == "Deferred" => {
+                            Self::check_arity(member, arguments, 0)?;
+                            self.check_suspension_point(member)?;
+                            args[0].clone()
+                        }
+                        (Type::Named(name) | Type::Generic(name, _), "join")
+                            if name == "Job" || name == "Deferred" =>
+                        {
+                            Self::check_arity(member, arguments, 0)?;
+                            self.check_suspension_point(member)?;
+                            Type::Named("Unit".to_string())
+                        }
+
+                        // Threads and their synchronization
+                        (Type::Named(name), method)
+                            if matches!(name.as_str(), "Thread" | "AtomicInt" | "Mutex") =>
+                        {
+                            let name = name.clone();
+                            self.check_concurrency_method(&name, method, None, arguments)?
+                        }
+                        (Type::Generic(name, args), method) if name == "Channel" => {
+                            let elem = args[0].clone();
+                            self.check_concurrency_method(name, method, Some(elem), arguments)?
+                        }
+
+                        // Methods of the embedding application's types
+                        (Type::Named(name), method)
+                            if self
+                                .host_methods
+                                .contains_key(&(name.clone(), method.to_string())) =>
+                        {
+                            let key = (name.clone(), method.to_string());
+                            let (params, ret) = self.host_methods[&key].clone();
+                            self.check_arguments(method, &params, arguments)?;
+                            ret
+                        }
 
                         // Undefined method
                         (obj_type, method_name) => {
@@ -547,34 +1767,115 @@ impl TypeChecker {
                             })
                         }
                     }
-                } else if let ExpressionKind::Variable(name) = &*callee.kind {
-                    // Regular function call
-                    if name == "println" {
-                        // println is special, accepts anything for now
+                } else if let Some((name, Type::Function(params, ret))) = match &*callee.kind {
+                    ExpressionKind::Variable(name) => self
+                        .lookup_var(name)
+                        .ok()
+                        .map(|typ| (name.clone(), typ.clone())),
+                    _ => None,
+                } {
+                    // Call through a variable holding a lambda
+                    let typ = Type::Function(params.clone(), ret.clone());
+                    self.check_not_shared(&name, &typ)?;
+                    self.check_arguments(&name, &params, arguments)?;
+                    callee.resolved_type = Some(Type::Function(params, ret.clone()));
+                    *ret
+                } else if let ExpressionKind::Variable(name) = &mut *callee.kind {
+                    // Regular function call; members of the enclosing object come first
+                    let member = self
+                        .object_member(name)?
+                        .filter(|member| self.functions.contains_key(member));
+                    if let Some(member) = member {
+                        *name = member;
+                    } else if let Some(qualified) = self
+                        .module_scopes
+                        .get(self.current_module)
+                        .and_then(|scope| scope.names.get(name.as_str()))
+                    {
+                        *name = qualified.clone();
+                    }
+                    self.note_call(name);
+                    if matches!(name.as_str(), "println" | "print" | "eprintln" | "eprint") {
+                        // The printers are special, they accept anything for now
                         for arg in arguments {
-                            self.check_expression(arg)?;
+                            let typ = self.check_expression(arg)?;
+                            if self.value_class(&typ).is_some() {
+                                *arg = self.value_class_string(arg.clone());
+                            }
                         }
                         Type::Named("Int".to_string())
-                    } else if let Some((params, ret)) = self.functions.get(name).cloned() {
-                        if params.len() != arguments.len() {
+                    } else if Self::is_tuple_type(name) {
+                        let arity = if name == "Pair" { 2 } else { 3 };
+                        if arguments.len() != arity {
                             return Err(TypeError::ArgumentCount {
                                 name: name.clone(),
-                                expected: params.len(),
+                                expected: arity,
                                 got: arguments.len(),
                             });
                         }
-                        for (i, arg) in arguments.iter_mut().enumerate() {
-                            let at = self.check_expression(arg)?;
-                            if at != params[i] {
-                                return Err(TypeError::Mismatch {
-                                    expected: params[i].clone(),
-                                    found: at,
-                                });
-                            }
+                        let mut component_types = Vec::new();
+                        for arg in arguments.iter_mut() {
+                            component_types.push(self.check_expression(arg)?);
                         }
+                        Type::Generic(name.clone(), component_types)
+                    } else if name == "sequence" {
+                        Self::check_arity(name, arguments, 1)?;
+                        self.check_sequence_builder(&mut arguments[0])?
+                    } else if name == "yield" {
+                        return Err(TypeError::InvalidYield);
+                    } else if name == "delay" {
+                        self.check_suspension_point(name)?;
+                        self.check_arguments(name, &[Type::Named("Long".to_string())], arguments)?;
+                        Type::Named("Unit".to_string())
+                    } else if matches!(
+                        name.as_str(),
+                        "runBlocking" | "runTest" | "launch" | "async"
+                    ) {
+                        Self::check_arity(name, arguments, 1)?;
+                        self.check_coroutine_builder(name, &mut arguments[0])?
+                    } else if name == "thread" {
+                        Self::check_arity(name, arguments, 1)?;
+                        self.check_thread_builder(&mut arguments[0])?
+                    } else if name == "run" && Self::is_lambda_argument(arguments) {
+                        self.check_scope_function(name, None, &mut arguments[0])?
+                    } else if name == "with"
+                        && arguments.len() == 2
+                        && Self::is_lambda_argument(&arguments[1..])
+                    {
+                        let receiver = self.check_expression(&mut arguments[0])?;
+                        self.check_scope_function(name, Some(receiver), &mut arguments[1])?
+                    } else if name == "AtomicInt" {
+                        self.check_arguments(name, &[Type::Named("Int".to_string())], arguments)?;
+                        Type::Named("AtomicInt".to_string())
+                    } else if name == "Mutex" {
+                        Self::check_arity(name, arguments, 0)?;
+                        Type::Named("Mutex".to_string())
+                    } else if name == "Channel" {
+                        return Err(TypeError::CannotInfer(
+                            "the element type of Channel(); declare the variable as Channel<T>"
+                                .to_string(),
+                        ));
+                    } else if let Some(typ) = self
+                        .value_classes
+                        .get(name.as_str())
+                        .map(|class| class.typ.clone())
+                    {
+                        // An instance is its property's value
+                        let class = name.clone();
+                        self.check_arguments(name, &[typ], arguments)?;
+                        *expr = arguments.pop().unwrap();
+                        Type::Named(class)
+                    } else if let Some((params, ret)) = self.lookup_function(name)? {
+                        if self.suspend_functions.contains(&self.function_key(name)) {
+                            self.check_suspension_point(name)?;
+                        }
+                        self.check_arguments(name, &params, arguments)?;
                         ret.unwrap_or(Type::Named("Int".to_string()))
+                    } else if self.is_hidden(name) {
+                        return Err(TypeError::PrivateAccess(name.clone()));
                     } else {
-                        return Err(TypeError::UndefinedVariable(name.clone()));
+                        let e = TypeError::UndefinedVariable(name.clone());
+                        return self.check_receiver_member(expr)?.ok_or(e);
                     }
                 } else {
                     return Err(TypeError::NotAFunction(
@@ -583,6 +1884,15 @@ impl TypeChecker {
                 }
             }
             ExpressionKind::MemberAccess { object, member } => {
+                // `Registry.items` is the object property `a.b.Registry.items`
+                let path = dotted_name(object).map(|object| format!("{}.{}", object, member));
+                if let Some((qualified, typ)) =
+                    path.map(|p| self.property(&p)).transpose()?.flatten()
+                {
+                    self.read_property(expr, qualified);
+                    expr.resolved_type = Some(typ.clone());
+                    return Ok(typ);
+                }
                 let obj_typ = self.check_expression(object)?;
                 match (&obj_typ, member.as_str()) {
                     // String length property
@@ -590,23 +1900,18 @@ impl TypeChecker {
                         Type::Named("Int".to_string())
                     }
 
-                    // Type conversion methods
-                    (Type::Named(name), "toInt") if name == "String" => {
-                        Type::Named("Int".to_string())
-                    }
-                    (Type::Named(name), "toFloat") if name == "String" => {
-                        Type::Named("Float".to_string())
-                    }
-                    (Type::Named(name), "toFloat") if name == "Int" => {
-                        Type::Named("Float".to_string())
-                    }
-                    (Type::Named(name), "toInt") if name == "Float" => {
-                        Type::Named("Int".to_string())
-                    }
-                    (Type::Named(name), "toString") if name == "Int" => {
+                    // A property reference is its name
+                    (Type::Generic(name, _), "name") if name == "KProperty" => {
+                        let reference = object.clone();
+                        *expr = reference;
                         Type::Named("String".to_string())
                     }
-                    (Type::Named(name), "toString") if name == "Float" => {
+
+                    // Type conversion methods
+                    (typ, method) if Self::conversion_type(typ, method).is_some() => {
+                        Self::conversion_type(typ, method).unwrap()
+                    }
+                    (typ, "toString") if Self::is_numeric_type(typ) => {
                         Type::Named("String".to_string())
                     }
                     (Type::Named(name), "toString") if name == "Boolean" => {
@@ -618,19 +1923,48 @@ impl TypeChecker {
 
                     // Array methods
                     (Type::Array(_), "push") => Type::Named("Int".to_string()), // returns void but using Int as placeholder
-                    (Type::Array(_), "pop") => Type::Named("Int".to_string()), // returns the popped value
+                    (Type::Array(elem), "pop") => (**elem).clone(), // returns the popped value
 
                     // HashMap iteration methods
                     (Type::Map(_, _), "keys") => {
                         Type::Array(Box::new(Type::Named("String".to_string())))
                     } // Returns array of keys
-                    (Type::Map(_, _), "values") => {
-                        Type::Array(Box::new(Type::Named("Int".to_string())))
-                    } // Returns array of values (for now)
+                    (Type::Map(_, value_type), "values") => Type::Array(value_type.clone()), // Returns array of values
                     (Type::Map(_, _), "size") => Type::Named("Int".to_string()), // Returns size as int
-                    (Type::Map(_, _), "entries") => {
-                        Type::Array(Box::new(Type::Named("Int".to_string())))
-                    } // Returns array of alternating key-value pairs (for now)
+                    (Type::Map(key_type, value_type), "entries") => Type::Array(Box::new(
+                        Self::pair_type((**key_type).clone(), (**value_type).clone()),
+                    )),
+
+                    // Pair and Triple components
+                    (Type::Generic(name, args), "first" | "second" | "third")
+                        if Self::is_tuple_type(name) =>
+                    {
+                        let index = match member.as_str() {
+                            "first" => 0,
+                            "second" => 1,
+                            _ => 2,
+                        };
+                        match args.get(index) {
+                            Some(typ) => typ.clone(),
+                            None => {
+                                return Err(TypeError::UndefinedMember {
+                                    typ: obj_typ.clone(),
+                                    member: member.clone(),
+                                })
+                            }
+                        }
+                    }
+
+                    // A value class instance is its property's value
+                    (typ, member)
+                        if self
+                            .value_class(typ)
+                            .is_some_and(|class| class.property == member) =>
+                    {
+                        let typ = self.value_class(typ).unwrap().typ.clone();
+                        *expr = object.clone();
+                        typ
+                    }
 
                     // Undefined member access
                     _ => {
@@ -737,21 +2071,2200 @@ impl TypeChecker {
                     Type::Map(Box::new(key_type), Box::new(value_type))
                 }
             }
+            ExpressionKind::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                let cond_typ = self.check_expression(condition)?;
+                if cond_typ != Type::Named("Boolean".to_string()) {
+                    return Err(TypeError::Mismatch {
+                        expected: Type::Named("Boolean".to_string()),
+                        found: cond_typ,
+                    });
+                }
+                let then_typ =
+                    self.without_suspension(|checker| checker.check_block_value(then_branch))?;
+                match else_branch {
+                    Some(els) => {
+                        let else_typ =
+                            self.without_suspension(|checker| checker.check_block_value(els))?;
+                        let typ = Self::unify_branch_types(then_typ, else_typ)?;
+                        // `if (a) x else null` is nullable
+                        for branch in [then_branch, els] {
+                            if let Some(Statement::Expression(value)) = branch.statements.last_mut()
+                            {
+                                Self::coerce(value, &typ);
+                            }
+                        }
+                        typ
+                    }
+                    // Without an else branch the expression has no value
+                    None => Type::Named("Unit".to_string()),
+                }
+            }
+            ExpressionKind::Lambda { .. } => return self.check_lambda(expr, None, None),
+            ExpressionKind::SafeAccess { object, access } => {
+                let receiver = match self.check_expression(object)? {
+                    Type::Nullable(inner) => *inner,
+                    typ => typ,
+                };
+                self.scopes.push(HashMap::new());
+                self.define_var(SAFE_RECEIVER.to_string(), receiver);
+                let result = self.without_suspension(|checker| checker.check_expression(access));
+                self.scopes.pop();
+                match result? {
+                    Type::Nullable(inner) => Type::Nullable(inner),
+                    typ => Type::Nullable(Box::new(typ)),
+                }
+            }
+            ExpressionKind::Nullable(value) => {
+                Type::Nullable(Box::new(self.check_expression(value)?))
+            }
         };
         expr.resolved_type = Some(typ.clone());
         Ok(typ)
     }
 
-    fn define_var(&mut self, name: String, typ: Type) {
-        self.scopes.last_mut().unwrap().insert(name, typ);
+    /// Checks an expression whose type is known from context, which lets
+    /// lambdas leave out their parameter types.
+    fn check_expression_as(
+        &mut self,
+        expr: &mut Expression,
+        expected: &Type,
+    ) -> Result<Type, TypeError> {
+        match (&*expr.kind, expected) {
+            (ExpressionKind::Lambda { .. }, Type::Function(params, ret)) => {
+                self.check_lambda(expr, Some(params), Some(ret))
+            }
+            // `Channel()` takes its element type from where it is used
+            (ExpressionKind::Call { callee, arguments }, Type::Generic(name, args))
+                if name == "Channel"
+                    && matches!(&*callee.kind, ExpressionKind::Variable(callee) if callee == "Channel") =>
+            {
+                Self::check_arity(name, arguments, 0)?;
+                if !self.is_thread_safe(&args[0]) {
+                    return Err(TypeError::NotThreadSafe {
+                        name: "the elements of a channel".to_string(),
+                        typ: args[0].clone(),
+                    });
+                }
+                expr.resolved_type = Some(expected.clone());
+                Ok(expected.clone())
+            }
+            _ => self.check_expression(expr),
+        }
     }
 
-    fn lookup_var(&self, name: &str) -> Result<&Type, TypeError> {
-        for scope in self.scopes.iter().rev() {
-            if let Some(typ) = scope.get(name) {
-                return Ok(typ);
+    /// Checks call arguments against parameter types.
+    fn check_arguments(
+        &mut self,
+        name: &str,
+        params: &[Type],
+        arguments: &mut [Expression],
+    ) -> Result<(), TypeError> {
+        Self::check_arity(name, arguments, params.len())?;
+        for (param, arg) in params.iter().zip(arguments.iter_mut()) {
+            let at = self.check_expression_as(arg, param)?;
+            if at != *param && !Self::coerce(arg, param) {
+                return Err(TypeError::Mismatch {
+                    expected: param.clone(),
+                    found: at,
+                });
             }
         }
-        Err(TypeError::UndefinedVariable(name.to_string()))
+        Ok(())
+    }
+
+    fn check_arity(name: &str, arguments: &[Expression], expected: usize) -> Result<(), TypeError> {
+        if arguments.len() != expected {
+            return Err(TypeError::ArgumentCount {
+                name: name.to_string(),
+                expected,
+                got: arguments.len(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Types a lambda. Parameter types come from the lambda itself or from the
+    /// expected `params`; a lambda without parameters expecting one gets `it`.
+    /// The result is the type of the body's last expression, unless a `Unit`
+    /// result is expected.
+    fn check_lambda(
+        &mut self,
+        expr: &mut Expression,
+        expected_params: Option<&[Type]>,
+        expected_ret: Option<&Type>,
+    ) -> Result<Type, TypeError> {
+        let ExpressionKind::Lambda { params, body } = &mut *expr.kind else {
+            return self.check_expression(expr);
+        };
+        if let Some(expected) = expected_params {
+            if params.is_empty() && expected.len() == 1 {
+                params.push(LambdaParam {
+                    name: "it".to_string(),
+                    typ: None,
+                });
+            }
+            if params.len() != expected.len() {
+                return Err(TypeError::ArgumentCount {
+                    name: "lambda".to_string(),
+                    expected: expected.len(),
+                    got: params.len(),
+                });
+            }
+        }
+
+        let mut param_types = Vec::new();
+        for (i, param) in params.iter_mut().enumerate() {
+            let typ = match (&param.typ, expected_params) {
+                (Some(typ), Some(expected)) if *typ != expected[i] => {
+                    return Err(TypeError::Mismatch {
+                        expected: expected[i].clone(),
+                        found: typ.clone(),
+                    })
+                }
+                (Some(typ), _) => typ.clone(),
+                (None, Some(expected)) => expected[i].clone(),
+                (None, None) => {
+                    return Err(TypeError::CannotInfer(format!(
+                        "the type of lambda parameter {}",
+                        param.name
+                    )))
+                }
+            };
+            param.typ = Some(typ.clone());
+            param_types.push(typ);
+        }
+
+        self.lambdas.push(LambdaContext {
+            depth: self.scopes.len(),
+            sequence: false,
+            coroutine: false,
+            thread: false,
+            element: None,
+        });
+        self.scopes.push(HashMap::new());
+        for (param, typ) in params.iter().zip(&param_types) {
+            self.define_var(param.name.clone(), typ.clone());
+        }
+        let result = self.check_block_value(body);
+        self.scopes.pop();
+        self.lambdas.pop();
+        let mut ret = result?;
+
+        let unit = Type::Named("Unit".to_string());
+        match expected_ret {
+            Some(expected) if *expected == unit => ret = unit,
+            Some(expected) if ret != *expected => {
+                let coerced = match body.statements.last_mut() {
+                    Some(Statement::Expression(last)) => Self::coerce(last, expected),
+                    _ => false,
+                };
+                if !coerced {
+                    return Err(TypeError::Mismatch {
+                        expected: expected.clone(),
+                        found: ret,
+                    });
+                }
+                ret = expected.clone();
+            }
+            _ => {}
+        }
+
+        let typ = Type::Function(param_types, Box::new(ret));
+        expr.resolved_type = Some(typ.clone());
+        Ok(typ)
+    }
+
+    /// Whether the only argument of a call is a lambda, as for a scope function.
+    fn is_lambda_argument(arguments: &[Expression]) -> bool {
+        matches!(arguments, [argument] if matches!(&*argument.kind, ExpressionKind::Lambda { .. }))
+    }
+
+    /// Types a call of the scope function `name`: `x.let { }`, `x.run { }`,
+    /// `x.apply { }`, `x.also { }`, `x.takeIf { }`, `x.takeUnless { }`,
+    /// `with(x) { }` or `run { }`. The lambda gets the receiver as `this` or
+    /// as its parameter. It is inlined, so unlike other lambdas it can assign
+    /// the variables around it and `return` from the enclosing function.
+    fn check_scope_function(
+        &mut self,
+        name: &str,
+        receiver: Option<Type>,
+        lambda: &mut Expression,
+    ) -> Result<Type, TypeError> {
+        let ExpressionKind::Lambda { params, body } = &mut *lambda.kind else {
+            return Err(TypeError::NotAFunction(name.to_string()));
+        };
+        let parameter = match &receiver {
+            Some(receiver) if !matches!(name, "run" | "apply" | "with") => Some(receiver),
+            _ => None,
+        };
+        if params.is_empty() && parameter.is_some() {
+            params.push(LambdaParam {
+                name: "it".to_string(),
+                typ: None,
+            });
+        }
+        if params.len() != usize::from(parameter.is_some()) {
+            return Err(TypeError::ArgumentCount {
+                name: "lambda".to_string(),
+                expected: usize::from(parameter.is_some()),
+                got: params.len(),
+            });
+        }
+
+        self.scopes.push(HashMap::new());
+        let mut param_types = Vec::new();
+        match (params.first_mut(), parameter) {
+            (Some(param), Some(typ)) => {
+                if param.typ.as_ref().is_some_and(|declared| declared != typ) {
+                    self.scopes.pop();
+                    return Err(TypeError::Mismatch {
+                        expected: typ.clone(),
+                        found: param.typ.clone().unwrap(),
+                    });
+                }
+                param.typ = Some(typ.clone());
+                param_types.push(typ.clone());
+                self.define_var(param.name.clone(), typ.clone());
+            }
+            _ => {
+                if let Some(typ) = &receiver {
+                    self.define_var("this".to_string(), typ.clone());
+                }
+            }
+        }
+        let result = self.without_suspension(|checker| checker.check_block_value(body));
+        self.scopes.pop();
+        let value = result?;
+        lambda.resolved_type = Some(Type::Function(param_types, Box::new(value.clone())));
+
+        Ok(match (name, receiver) {
+            ("apply" | "also", Some(receiver)) => receiver,
+            ("takeIf" | "takeUnless", Some(receiver)) => {
+                let boolean = Type::Named("Boolean".to_string());
+                if value != boolean {
+                    return Err(TypeError::Mismatch {
+                        expected: boolean,
+                        found: value,
+                    });
+                }
+                match receiver {
+                    Type::Nullable(_) => receiver,
+                    _ => Type::Nullable(Box::new(receiver)),
+                }
+            }
+            _ => value,
+        })
+    }
+
+    /// Checks an undefined variable or function call as a member of `this`,
+    /// the receiver of an enclosing `run`, `apply` or `with`, rewriting
+    /// `name` to `this.name`. Returns `None` if the receiver has no such member.
+    fn check_receiver_member(&mut self, expr: &mut Expression) -> Result<Option<Type>, TypeError> {
+        if self.lookup_var("this").is_err() {
+            return Ok(None);
+        }
+        let mut member = expr.clone();
+        let name = match &mut *member.kind {
+            ExpressionKind::Variable(name) => name,
+            ExpressionKind::Call { callee, .. } => match &mut *callee.kind {
+                ExpressionKind::Variable(name) => name,
+                _ => return Ok(None),
+            },
+            _ => return Ok(None),
+        };
+        let receiver = Expression::new(ExpressionKind::Variable("this".to_string()));
+        let access = ExpressionKind::MemberAccess {
+            object: receiver,
+            member: std::mem::take(name),
+        };
+        match &mut *member.kind {
+            ExpressionKind::Call { callee, .. } => *callee.kind = access,
+            kind => *kind = access,
+        }
+        match self.check_expression(&mut member) {
+            Ok(typ) => {
+                *expr = member;
+                Ok(Some(typ))
+            }
+            Err(TypeError::UndefinedMember { .. }) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Types `sequence { ... }`; its element type is that of the yielded values.
+    fn check_sequence_builder(&mut self, lambda: &mut Expression) -> Result<Type, TypeError> {
+        let ExpressionKind::Lambda { params, body } = &mut *lambda.kind else {
+            return Err(TypeError::Mismatch {
+                expected: Type::Function(Vec::new(), Box::new(Type::Named("Unit".to_string()))),
+                found: self.check_expression(lambda)?,
+            });
+        };
+        if !params.is_empty() {
+            return Err(TypeError::ArgumentCount {
+                name: "lambda".to_string(),
+                expected: 0,
+                got: params.len(),
+            });
+        }
+        self.lambdas.push(LambdaContext {
+            depth: self.scopes.len(),
+            sequence: true,
+            coroutine: false,
+            thread: false,
+            element: None,
+        });
+        self.scopes.push(HashMap::new());
+        let saved_barrier = std::mem::take(&mut self.suspension_barrier);
+        let result = self.check_block(body);
+        self.suspension_barrier = saved_barrier;
+        self.scopes.pop();
+        let context = self.lambdas.pop().unwrap();
+        result?;
+
+        let element = context.element.ok_or_else(|| {
+            TypeError::CannotInfer("the element type of a sequence without yield".to_string())
+        })?;
+        lambda.resolved_type = Some(Type::Function(
+            Vec::new(),
+            Box::new(Type::Named("Unit".to_string())),
+        ));
+        Ok(Self::sequence_type(element))
+    }
+
+    /// Checks `yield(value)`, which must be a statement of the innermost `sequence { }` body.
+    fn check_yield(&mut self, stmt: &mut Statement) -> Result<(), TypeError> {
+        if !self.lambdas.last().is_some_and(|context| context.sequence) {
+            return Err(TypeError::InvalidYield);
+        }
+        if self.suspension_barrier > 0 {
+            return Err(TypeError::UnsupportedSuspension("yield".to_string()));
+        }
+        if let Statement::Expression(call) = stmt {
+            if let ExpressionKind::Call { arguments, .. } = &mut *call.kind {
+                let value = &mut arguments[0];
+                let typ = self.check_expression(value)?;
+                let context = self.lambdas.last_mut().unwrap();
+                match &context.element {
+                    Some(element) if *element != typ && !Self::coerce(value, element) => {
+                        return Err(TypeError::Mismatch {
+                            expected: element.clone(),
+                            found: typ,
+                        });
+                    }
+                    Some(_) => {}
+                    None => context.element = Some(typ),
+                }
+            }
+            call.resolved_type = Some(Type::Named("Unit".to_string()));
+        }
+        Ok(())
+    }
+
+    /// Types `runBlocking`, `runTest`, `launch` or `async` called with `lambda`,
+    /// whose body runs as a coroutine. `launch` and `async` start a coroutine
+    /// in the event loop of the enclosing one.
+    fn check_coroutine_builder(
+        &mut self,
+        builder: &str,
+        lambda: &mut Expression,
+    ) -> Result<Type, TypeError> {
+        let ExpressionKind::Lambda { params, body } = &mut *lambda.kind else {
+            return Err(TypeError::Mismatch {
+                expected: Type::Function(Vec::new(), Box::new(Type::Named("Unit".to_string()))),
+                found: self.check_expression(lambda)?,
+            });
+        };
+        if !params.is_empty() {
+            return Err(TypeError::ArgumentCount {
+                name: "lambda".to_string(),
+                expected: 0,
+                got: params.len(),
+            });
+        }
+        if matches!(builder, "launch" | "async") && !self.in_coroutine() {
+            return Err(TypeError::SuspendOutsideCoroutine(builder.to_string()));
+        }
+        self.lambdas.push(LambdaContext {
+            depth: self.scopes.len(),
+            sequence: false,
+            coroutine: true,
+            thread: false,
+            element: None,
+        });
+        let saved_barrier = std::mem::take(&mut self.suspension_barrier);
+        let result = self.check_block_value(body);
+        self.suspension_barrier = saved_barrier;
+        self.lambdas.pop();
+        let ret = result?;
+
+        lambda.resolved_type = Some(Type::Function(Vec::new(), Box::new(ret.clone())));
+        Ok(match builder {
+            "runBlocking" => ret,
+            "launch" => Type::Named("Job".to_string()),
+            "async" => Type::Generic("Deferred".to_string(), vec![ret]),
+            _ => Type::Named("Unit".to_string()),
+        })
+    }
+
+    /// Checks `thread { body }`. The body may only use variables of the
+    /// enclosing function that are thread-safe.
+    fn check_thread_builder(&mut self, lambda: &mut Expression) -> Result<Type, TypeError> {
+        let ExpressionKind::Lambda { params, body } = &mut *lambda.kind else {
+            return Err(TypeError::Mismatch {
+                expected: Type::Function(Vec::new(), Box::new(Type::Named("Unit".to_string()))),
+                found: self.check_expression(lambda)?,
+            });
+        };
+        if !params.is_empty() {
+            return Err(TypeError::ArgumentCount {
+                name: "lambda".to_string(),
+                expected: 0,
+                got: params.len(),
+            });
+        }
+        self.lambdas.push(LambdaContext {
+            depth: self.scopes.len(),
+            sequence: false,
+            coroutine: false,
+            thread: true,
+            element: None,
+        });
+        self.scopes.push(HashMap::new());
+        let result = self.check_block(body);
+        self.scopes.pop();
+        self.lambdas.pop();
+        result?;
+
+        let unit = Type::Named("Unit".to_string());
+        lambda.resolved_type = Some(Type::Function(Vec::new(), Box::new(unit)));
+        Ok(Type::Named("Thread".to_string()))
+    }
+
+    /// Checks a method call on a `Thread`, `AtomicInt`, `Mutex` or
+    /// `Channel<element>` and returns its type.
+    fn check_concurrency_method(
+        &mut self,
+        typ: &str,
+        method: &str,
+        element: Option<Type>,
+        arguments: &mut [Expression],
+    ) -> Result<Type, TypeError> {
+        let int = Type::Named("Int".to_string());
+        let unit = Type::Named("Unit".to_string());
+        let (params, ret) = match (typ, method) {
+            ("Thread", "join") => (vec![], unit),
+            ("AtomicInt", "get" | "incrementAndGet" | "decrementAndGet") => (vec![], int),
+            ("AtomicInt", "set") => (vec![int], unit),
+            ("AtomicInt", "addAndGet") => (vec![int.clone()], int),
+            ("AtomicInt", "compareAndSet") => {
+                (vec![int.clone(), int], Type::Named("Boolean".to_string()))
+            }
+            ("Mutex", "lock" | "unlock") => (vec![], unit),
+            ("Mutex", "withLock") => {
+                Self::check_arity(method, arguments, 1)?;
+                return match self.check_lambda(&mut arguments[0], Some(&[]), None)? {
+                    Type::Function(_, ret) => Ok(*ret),
+                    found => Err(TypeError::Mismatch {
+                        expected: Type::Function(Vec::new(), Box::new(unit)),
+                        found,
+                    }),
+                };
+            }
+            ("Channel", "send") => (vec![element.unwrap()], unit),
+            ("Channel", "receive") => (vec![], element.unwrap()),
+            ("Channel", "close") => (vec![], unit),
+            _ => {
+                let typ = match element {
+                    Some(element) => Type::Generic(typ.to_string(), vec![element]),
+                    None => Type::Named(typ.to_string()),
+                };
+                return Err(TypeError::UndefinedMember {
+                    typ,
+                    member: method.to_string(),
+                });
+            }
+        };
+        self.check_arguments(method, &params, arguments)?;
+        Ok(ret)
+    }
+
+    /// Whether values of a type may be used by several threads: immutable
+    /// values, and the types made to be shared. A value class is as safe as
+    /// its property.
+    fn is_thread_safe(&self, typ: &Type) -> bool {
+        match typ {
+            Type::Named(name) => match self.erased_value_class(name) {
+                Some(property) => self.is_thread_safe(&property),
+                None => name != "Job",
+            },
+            Type::Generic(name, args) if Self::is_tuple_type(name) => {
+                args.iter().all(|arg| self.is_thread_safe(arg))
+            }
+            Type::Generic(name, _) => name == "Channel",
+            Type::Nullable(inner) => self.is_thread_safe(inner),
+            Type::Array(_) | Type::Map(_, _) | Type::Function(_, _) => false,
+        }
+    }
+
+    /// A `thread { }` body may only use the enclosing function's variables
+    /// whose values are thread-safe.
+    fn check_not_shared(&self, name: &str, typ: &Type) -> Result<(), TypeError> {
+        let Some(thread) = self.lambdas.iter().rev().find(|context| context.thread) else {
+            return Ok(());
+        };
+        let declared_at = self
+            .scopes
+            .iter()
+            .rposition(|scope| scope.contains_key(name));
+        match declared_at {
+            Some(depth) if depth < thread.depth && !self.is_thread_safe(typ) => {
+                Err(TypeError::NotThreadSafe {
+                    name: format!("'{}'", name),
+                    typ: typ.clone(),
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Every thread shares an object's properties, so a `thread { }` body may
+    /// only use those whose values are thread-safe, itself or in the
+    /// functions it calls.
+    fn note_property_use(&mut self, qualified: &str, typ: &Type) -> Result<(), TypeError> {
+        if self.is_thread_safe(typ) {
+            return Ok(());
+        }
+        if self.lambdas.iter().any(|context| context.thread) {
+            return Err(TypeError::NotThreadSafe {
+                name: format!("'{}'", qualified),
+                typ: typ.clone(),
+            });
+        }
+        if let Some(function) = &self.current_function {
+            self.shared_properties
+                .entry(function.clone())
+                .or_insert_with(|| (qualified.to_string(), typ.clone()));
+        }
+        Ok(())
+    }
+
+    /// Notes that the code being checked calls the function `name`.
+    fn note_call(&mut self, name: &str) {
+        let key = self.function_key(name);
+        if !self.functions.contains_key(&key) {
+            return;
+        }
+        if self.lambdas.iter().any(|context| context.thread) {
+            self.thread_calls.insert(key.clone());
+        }
+        if let Some(function) = &self.current_function {
+            self.calls.entry(function.clone()).or_default().insert(key);
+        }
+    }
+
+    /// Checks that no function a `thread { }` body calls, directly or not,
+    /// uses an object property that isn't thread-safe.
+    fn check_thread_calls(&self) -> Result<(), TypeError> {
+        let mut reached = HashSet::new();
+        let mut pending: Vec<&String> = self.thread_calls.iter().rev().collect();
+        while let Some(function) = pending.pop() {
+            if !reached.insert(function) {
+                continue;
+            }
+            if let Some((property, typ)) = self.shared_properties.get(function) {
+                return Err(TypeError::NotThreadSafe {
+                    name: format!("'{}', which '{}' uses,", property, function),
+                    typ: typ.clone(),
+                });
+            }
+            if let Some(callees) = self.calls.get(function) {
+                pending.extend(callees.iter().rev());
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the expression being checked runs in a coroutine, so it may suspend.
+    fn in_coroutine(&self) -> bool {
+        match self.lambdas.last() {
+            Some(context) => context.coroutine,
+            None => self.suspending,
+        }
+    }
+
+    /// Checks that a call to the suspending `name` is made where a coroutine can suspend.
+    fn check_suspension_point(&self, name: &str) -> Result<(), TypeError> {
+        if !self.in_coroutine() {
+            return Err(TypeError::SuspendOutsideCoroutine(name.to_string()));
+        }
+        if self.suspension_barrier > 0 {
+            return Err(TypeError::UnsupportedSuspension(name.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Runs `check` on an expression a coroutine can't suspend in.
+    fn without_suspension<T>(
+        &mut self,
+        check: impl FnOnce(&mut Self) -> Result<T, TypeError>,
+    ) -> Result<T, TypeError> {
+        self.suspension_barrier += 1;
+        let result = check(self);
+        self.suspension_barrier -= 1;
+        result
+    }
+
+    fn sequence_type(element: Type) -> Type {
+        Type::Generic("Sequence".to_string(), vec![element])
+    }
+
+    /// Types the `map`, `filter`, `take` and `toList` operations of a sequence.
+    fn check_sequence_method(
+        &mut self,
+        method: &str,
+        element: Type,
+        arguments: &mut [Expression],
+    ) -> Result<Type, TypeError> {
+        let sequence = Self::sequence_type(element.clone());
+        Ok(match method {
+            "map" => {
+                Self::check_arity(method, arguments, 1)?;
+                let params = [element];
+                match self.check_lambda(&mut arguments[0], Some(&params), None)? {
+                    Type::Function(_, ret) => Self::sequence_type(*ret),
+                    found => {
+                        return Err(TypeError::Mismatch {
+                            expected: Type::Function(
+                                params.to_vec(),
+                                Box::new(Type::Named("Any".to_string())),
+                            ),
+                            found,
+                        })
+                    }
+                }
+            }
+            "filter" => {
+                Self::check_arity(method, arguments, 1)?;
+                let predicate =
+                    Type::Function(vec![element], Box::new(Type::Named("Boolean".to_string())));
+                self.check_arguments(method, &[predicate], arguments)?;
+                sequence
+            }
+            "take" => {
+                self.check_arguments(method, &[Type::Named("Int".to_string())], arguments)?;
+                sequence
+            }
+            "toList" => {
+                Self::check_arity(method, arguments, 0)?;
+                Type::Array(Box::new(element))
+            }
+            _ => {
+                return Err(TypeError::UndefinedMember {
+                    typ: sequence,
+                    member: method.to_string(),
+                })
+            }
+        })
+    }
+
+    /// Lambdas capture variables by value, so a captured variable may not be
+    /// reassigned, inside the lambda or out of it, before or after.
+    fn check_not_captured(&mut self, name: &str) -> Result<(), TypeError> {
+        let Some(declared_at) = self
+            .scopes
+            .iter()
+            .rposition(|scope| scope.contains_key(name))
+        else {
+            return Ok(());
+        };
+        let in_lambda = self
+            .lambdas
+            .last()
+            .is_some_and(|context| declared_at < context.depth);
+        let variable = (declared_at, name.to_string());
+        if in_lambda || self.captured.contains(&variable) {
+            return Err(TypeError::CapturedAssignment(name.to_string()));
+        }
+        self.reassigned.insert(variable);
+        Ok(())
+    }
+
+    /// Notes that the lambda being checked captures `name`, if declared
+    /// outside it.
+    fn note_captured(&mut self, name: &str) -> Result<(), TypeError> {
+        let Some(context) = self.lambdas.last() else {
+            return Ok(());
+        };
+        let declared_at = self
+            .scopes
+            .iter()
+            .rposition(|scope| scope.contains_key(name));
+        match declared_at {
+            Some(depth) if depth < context.depth => {
+                let variable = (depth, name.to_string());
+                if self.reassigned.contains(&variable) {
+                    return Err(TypeError::CapturedAssignment(name.to_string()));
+                }
+                self.captured.insert(variable);
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Computes the common type of two `if` branches.
+    fn unify_branch_types(then_typ: Type, else_typ: Type) -> Result<Type, TypeError> {
+        let nullable = |typ: &Type| match typ {
+            Type::Nullable(_) => typ.clone(),
+            _ => Type::Nullable(Box::new(typ.clone())),
+        };
+        if then_typ == else_typ {
+            Ok(then_typ)
+        } else if then_typ == Self::null_type() || nullable(&then_typ) == else_typ {
+            Ok(nullable(&else_typ))
+        } else if else_typ == Self::null_type() || nullable(&else_typ) == then_typ {
+            Ok(nullable(&then_typ))
+        } else {
+            Err(TypeError::Mismatch {
+                expected: then_typ,
+                found: else_typ,
+            })
+        }
+    }
+
+    fn named_in(typ: &Type, names: &[&str]) -> Option<usize> {
+        match typ {
+            Type::Named(name) => names.iter().position(|n| n == name),
+            _ => None,
+        }
+    }
+
+    fn is_numeric_type(typ: &Type) -> bool {
+        Self::named_in(typ, &SIGNED_INTEGER_TYPES).is_some()
+            || Self::named_in(typ, &UNSIGNED_INTEGER_TYPES).is_some()
+            || Self::named_in(typ, &FLOATING_POINT_TYPES).is_some()
+    }
+
+    fn is_unsigned_type(typ: &Type) -> bool {
+        Self::named_in(typ, &UNSIGNED_INTEGER_TYPES).is_some()
+    }
+
+    /// Types with `and`, `or`, `xor`, `inv()` and shifts.
+    fn is_bitwise_type(typ: &Type) -> bool {
+        Self::named_in(typ, &["Int", "Long", "UInt", "ULong"]).is_some()
+    }
+
+    /// Result type of arithmetic on two numeric operands, following Kotlin:
+    /// `Byte` and `Short` widen to `Int`, integers widen to the wider operand,
+    /// and any floating-point operand makes the result floating-point.
+    /// Signed and unsigned operands cannot be mixed.
+    fn numeric_result_type(lt: &Type, rt: &Type) -> Option<Type> {
+        let widest = |names: &[&str], l: usize, r: usize| Type::Named(names[l.max(r)].to_string());
+        let signed = |t| Self::named_in(t, &SIGNED_INTEGER_TYPES);
+        let unsigned = |t| Self::named_in(t, &UNSIGNED_INTEGER_TYPES);
+        let floating = |t| Self::named_in(t, &FLOATING_POINT_TYPES);
+
+        match (signed(lt), signed(rt), unsigned(lt), unsigned(rt)) {
+            // The widest of the two, but never narrower than Int
+            (Some(l), Some(r), _, _) => Some(widest(&SIGNED_INTEGER_TYPES, l.max(r), 2)),
+            (_, _, Some(l), Some(r)) => Some(widest(&UNSIGNED_INTEGER_TYPES, l, r)),
+            _ => match (floating(lt), floating(rt)) {
+                (Some(l), Some(r)) => Some(widest(&FLOATING_POINT_TYPES, l, r)),
+                (Some(f), None) if signed(rt).is_some() => {
+                    Some(Type::Named(FLOATING_POINT_TYPES[f].to_string()))
+                }
+                (None, Some(f)) if signed(lt).is_some() => {
+                    Some(Type::Named(FLOATING_POINT_TYPES[f].to_string()))
+                }
+                _ => None,
+            },
+        }
+    }
+
+    /// Result type of a conversion method such as `toLong()` or `"1.5".toDouble()`.
+    fn conversion_type(typ: &Type, method: &str) -> Option<Type> {
+        let target = method.strip_prefix("to")?;
+        let numeric = SIGNED_INTEGER_TYPES
+            .iter()
+            .chain(&UNSIGNED_INTEGER_TYPES)
+            .chain(&FLOATING_POINT_TYPES)
+            .find(|name| **name == target)?;
+        let from_string = matches!(typ, Type::Named(name) if name == "String")
+            && matches!(*numeric, "Int" | "Long" | "Float" | "Double");
+        (Self::is_numeric_type(typ) || from_string).then(|| Type::Named(numeric.to_string()))
+    }
+
+    /// Gives an unsuffixed integer literal the expected integer type when its
+    /// value fits, as Kotlin does for `val b: Byte = 1`. Returns whether the
+    /// literal was retyped.
+    fn coerce_literal(expr: &mut Expression, expected: &Type) -> bool {
+        let ExpressionKind::Literal(Literal::Integer(value)) = &*expr.kind else {
+            return false;
+        };
+        let fits = match Self::named_in(expected, &SIGNED_INTEGER_TYPES) {
+            Some(0) => i8::try_from(*value).is_ok(),
+            Some(1) => i16::try_from(*value).is_ok(),
+            Some(_) => true,
+            None => false,
+        };
+        if fits {
+            expr.resolved_type = Some(expected.clone());
+        }
+        fits
+    }
+
+    /// The type of `null`.
+    fn null_type() -> Type {
+        Type::Nullable(Box::new(Type::Named("Nothing".to_string())))
+    }
+
+    /// Gives an expression the expected type where Kotlin converts it
+    /// implicitly: an integer literal as in `coerce_literal`, or `null` or a
+    /// value of type `T` where `T?` is expected. Returns whether the
+    /// expression was converted.
+    fn coerce(expr: &mut Expression, expected: &Type) -> bool {
+        if Self::coerce_literal(expr, expected) {
+            return true;
+        }
+        let Type::Nullable(inner) = expected else {
+            return false;
+        };
+        match &expr.resolved_type {
+            Some(found) if *found == Self::null_type() => {
+                expr.resolved_type = Some(expected.clone());
+                true
+            }
+            Some(Type::Nullable(_)) | None => false,
+            Some(found) => {
+                if *found != **inner && !Self::coerce_literal(expr, inner) {
+                    return false;
+                }
+                let value = std::mem::replace(
+                    expr,
+                    Expression::new(ExpressionKind::Literal(Literal::Null)),
+                );
+                *expr = Expression::new(ExpressionKind::Nullable(value));
+                expr.resolved_type = Some(expected.clone());
+                true
+            }
+        }
+    }
+
+    fn is_tuple_type(name: &str) -> bool {
+        name == "Pair" || name == "Triple"
+    }
+
+    fn pair_type(first: Type, second: Type) -> Type {
+        Type::Generic("Pair".to_string(), vec![first, second])
+    }
+
+    /// Types of the first `count` components of a destructured value.
+    fn destructure_types(typ: &Type, count: usize) -> Result<Vec<Type>, TypeError> {
+        match typ {
+            Type::Generic(name, args) if Self::is_tuple_type(name) => {
+                if count > args.len() {
+                    return Err(TypeError::UndefinedMember {
+                        typ: typ.clone(),
+                        member: format!("component{}", args.len() + 1),
+                    });
+                }
+                Ok(args[..count].to_vec())
+            }
+            Type::Array(element_type) => Ok(vec![(**element_type).clone(); count]),
+            _ => Err(TypeError::UndefinedMember {
+                typ: typ.clone(),
+                member: "component1".to_string(),
+            }),
+        }
+    }
+
+    /// A function or value class constructor called by a qualified name, as in
+    /// `a.b.foo()`, `a.Id(1)` or `Registry.register()`.
+    fn qualified_callee(&self, callee: &Expression) -> Result<Option<String>, TypeError> {
+        let Some(name) = dotted_name(callee) else {
+            return Ok(None);
+        };
+        let Some((root, _)) = name.split_once('.') else {
+            return Ok(None);
+        };
+        if self.lookup_var(root).is_ok() {
+            return Ok(None);
+        }
+        if let Some(member) = self.object_member(&name)? {
+            return Ok(self.functions.contains_key(&member).then_some(member));
+        }
+        let known = self.functions.contains_key(&self.function_key(&name))
+            || self.value_classes.contains_key(&name)
+            || self.is_hidden(&name);
+        Ok(known.then_some(name))
+    }
+
+    /// Resolves a reference to an object member to its qualified name. Inside
+    /// an object's functions its members can be named alone; elsewhere they
+    /// take the object's name, as in `Registry.items`.
+    fn object_member(&self, path: &str) -> Result<Option<String>, TypeError> {
+        let qualified = match path.split_once('.') {
+            None => match &self.current_object {
+                Some(object) => format!("{}.{}", object, path),
+                None => return Ok(None),
+            },
+            Some((root, rest)) => {
+                if self.lookup_var(root).is_ok() {
+                    return Ok(None);
+                }
+                let root = self
+                    .module_scopes
+                    .get(self.current_module)
+                    .and_then(|scope| scope.names.get(root))
+                    .map_or(root, String::as_str);
+                format!("{}.{}", root, rest)
+            }
+        };
+        let Some((object, member)) = qualified.rsplit_once('.') else {
+            return Ok(None);
+        };
+        let Some(info) = self.objects.get(object) else {
+            if self.is_hidden(object) {
+                return Err(TypeError::PrivateAccess(path.to_string()));
+            }
+            return Ok(None);
+        };
+        if !info.properties.contains_key(member) && !info.functions.contains(member) {
+            return Ok(None);
+        }
+        if info.private_members.contains(member) && self.current_object.as_deref() != Some(object) {
+            return Err(TypeError::PrivateAccess(path.to_string()));
+        }
+        Ok(Some(qualified))
+    }
+
+    /// The qualified name and type of the object property `path` refers to,
+    /// if any, which the code being checked uses.
+    fn property(&mut self, path: &str) -> Result<Option<(String, Type)>, TypeError> {
+        let qualified = match self.object_member(path)? {
+            Some(qualified) => qualified,
+            // Already resolved, as when an inferred body is checked again
+            None if self.lookup_var(path).is_err() => path.to_string(),
+            None => return Ok(None),
+        };
+        let typ = qualified
+            .rsplit_once('.')
+            .and_then(|(object, member)| self.objects.get(object)?.properties.get(member)?.clone());
+        if let Some(typ) = &typ {
+            self.note_property_use(&qualified, typ)?;
+        }
+        Ok(typ.map(|typ| (qualified, typ)))
+    }
+
+    /// Makes `expr` read the object property `qualified`: a call to its
+    /// getter if it has one, otherwise its variable.
+    fn read_property(&self, expr: &mut Expression, qualified: String) {
+        let getter = qualified.rsplit_once('.').filter(|(object, member)| {
+            self.objects
+                .get(*object)
+                .is_some_and(|info| info.getters.contains(*member))
+        });
+        *expr.kind = match getter {
+            Some((object, member)) => ExpressionKind::Call {
+                callee: Expression::new(ExpressionKind::Variable(format!(
+                    "{}.{}",
+                    object,
+                    PropertyDecl::getter_name(member)
+                ))),
+                arguments: Vec::new(),
+            },
+            None => ExpressionKind::Variable(qualified),
+        };
+    }
+
+    /// Checks an assignment to an object property with accessors or a
+    /// delegate, rewriting it to a call of the setter, or to an assignment
+    /// of the property's value if only its getter is custom. `None` if the
+    /// target is something else.
+    fn check_accessor_assignment(
+        &mut self,
+        expr: &mut Expression,
+    ) -> Result<Option<Type>, TypeError> {
+        let ExpressionKind::Assignment {
+            target,
+            operator,
+            value,
+        } = &mut *expr.kind
+        else {
+            return Ok(None);
+        };
+        let path = match &*target.kind {
+            ExpressionKind::Variable(name) if self.lookup_var(name).is_err() => name.clone(),
+            ExpressionKind::MemberAccess { .. } => match dotted_name(target) {
+                Some(path) => path,
+                None => return Ok(None),
+            },
+            _ => return Ok(None),
+        };
+        if path == "field" && self.current_property.is_some() {
+            return Ok(None);
+        }
+        let Some((qualified, typ)) = self.property(&path)? else {
+            return Ok(None);
+        };
+        let (object, member) = qualified.rsplit_once('.').unwrap();
+        let info = &self.objects[object];
+        let setter = info
+            .setters
+            .contains(member)
+            .then(|| format!("{}.{}", object, PropertyDecl::setter_name(member)));
+        if setter.is_none() && info.delegated.contains(member) {
+            return Err(TypeError::NotAssignable(format!(
+                "delegated property '{}'",
+                path
+            )));
+        }
+        if setter.is_none() && !info.getters.contains(member) {
+            return Ok(None);
+        }
+
+        let mut val_typ = self.check_expression(value)?;
+        let mut value = value.clone();
+        if let Some(operator) = operator.clone() {
+            // `a += b` is `a = a + b`, reading `a` through its getter
+            let mut current = Expression::new(ExpressionKind::Variable(qualified.clone()));
+            self.read_property(&mut current, qualified.clone());
+            current.resolved_type = Some(typ.clone());
+            val_typ = Self::binary_type(&operator, &mut current, typ.clone(), &mut value, val_typ)?;
+            value = Expression::new(ExpressionKind::Binary {
+                left: current,
+                operator,
+                right: value,
+            });
+            value.resolved_type = Some(val_typ.clone());
+        } else if Self::coerce(&mut value, &typ) {
+            val_typ = typ.clone();
+        }
+        if val_typ != typ {
+            return Err(TypeError::Mismatch {
+                expected: typ,
+                found: val_typ,
+            });
+        }
+
+        match setter {
+            Some(setter) => {
+                *expr.kind = ExpressionKind::Call {
+                    callee: Expression::new(ExpressionKind::Variable(setter)),
+                    arguments: vec![value],
+                };
+                Ok(Some(Type::Named("Unit".to_string())))
+            }
+            None => {
+                let mut target = Expression::new(ExpressionKind::Variable(qualified));
+                target.resolved_type = Some(typ.clone());
+                *expr.kind = ExpressionKind::Assignment {
+                    target,
+                    operator: None,
+                    value,
+                };
+                Ok(Some(typ))
+            }
+        }
+    }
+
+    /// Whether values of `typ` are references rather than primitive values
+    /// or `null`, as a `lateinit` property's must be.
+    fn is_reference_type(typ: &Type) -> bool {
+        match typ {
+            Type::Named(name) => {
+                !Self::is_numeric_type(typ) && !matches!(name.as_str(), "Boolean" | "Char")
+            }
+            Type::Nullable(_) => false,
+            _ => true,
+        }
+    }
+
+    /// Whether `name` is a private declaration of another file.
+    fn is_hidden(&self, name: &str) -> bool {
+        self.module_scopes
+            .get(self.current_module)
+            .is_some_and(|scope| scope.hidden.contains(name))
+    }
+
+    /// The type of `left operator right`. Integer literals on either side of
+    /// a comparison or bit operation take the other operand's type.
+    fn binary_type(
+        operator: &BinaryOp,
+        left: &mut Expression,
+        mut lt: Type,
+        right: &mut Expression,
+        mut rt: Type,
+    ) -> Result<Type, TypeError> {
+        Ok(match operator {
+            BinaryOp::Add
+            | BinaryOp::Sub
+            | BinaryOp::Mul
+            | BinaryOp::Div
+            | BinaryOp::Rem
+            | BinaryOp::PlusEqual
+            | BinaryOp::MinusEqual
+            | BinaryOp::StarEqual
+            | BinaryOp::SlashEqual => {
+                if lt == Type::Named("String".to_string())
+                    && rt == Type::Named("String".to_string())
+                    && matches!(operator, BinaryOp::Add)
+                {
+                    Type::Named("String".to_string())
+                } else if let Some(typ) = Self::numeric_result_type(&lt, &rt) {
+                    typ
+                } else {
+                    return Err(TypeError::Mismatch {
+                        expected: lt,
+                        found: rt,
+                    });
+                }
+            }
+            // `x == null`, or a nullable value against one of its type
+            BinaryOp::Equal | BinaryOp::NotEqual | BinaryOp::Identical | BinaryOp::NotIdentical
+                if matches!(lt, Type::Nullable(_)) || matches!(rt, Type::Nullable(_)) =>
+            {
+                let comparable = lt == rt
+                    || lt == Self::null_type()
+                    || rt == Self::null_type()
+                    || Self::coerce(right, &lt)
+                    || Self::coerce(left, &rt);
+                if !comparable {
+                    return Err(TypeError::Mismatch {
+                        expected: lt,
+                        found: rt,
+                    });
+                }
+                Type::Named("Boolean".to_string())
+            }
+            BinaryOp::Less | BinaryOp::LessEqual | BinaryOp::Greater | BinaryOp::GreaterEqual
+                if matches!(lt, Type::Nullable(_)) || matches!(rt, Type::Nullable(_)) =>
+            {
+                let nullable = if matches!(lt, Type::Nullable(_)) {
+                    lt
+                } else {
+                    rt
+                };
+                return Err(TypeError::UndefinedMember {
+                    typ: nullable,
+                    member: "compareTo".to_string(),
+                });
+            }
+            BinaryOp::Equal
+            | BinaryOp::NotEqual
+            | BinaryOp::Identical
+            | BinaryOp::NotIdentical
+            | BinaryOp::Less
+            | BinaryOp::LessEqual
+            | BinaryOp::Greater
+            | BinaryOp::GreaterEqual => {
+                // `x == 0` compares against a literal of x's integer type
+                if Self::coerce_literal(right, &lt) {
+                    rt = lt.clone();
+                } else if Self::coerce_literal(left, &rt) {
+                    lt = rt.clone();
+                }
+                let ordered = matches!(
+                    operator,
+                    BinaryOp::Less
+                        | BinaryOp::LessEqual
+                        | BinaryOp::Greater
+                        | BinaryOp::GreaterEqual
+                );
+                if lt != rt && !(ordered && Self::numeric_result_type(&lt, &rt).is_some()) {
+                    return Err(TypeError::Mismatch {
+                        expected: lt,
+                        found: rt,
+                    });
+                }
+                Type::Named("Boolean".to_string())
+            }
+            BinaryOp::And | BinaryOp::Or => {
+                if lt != Type::Named("Boolean".to_string()) {
+                    return Err(TypeError::Mismatch {
+                        expected: Type::Named("Boolean".to_string()),
+                        found: lt,
+                    });
+                }
+                if rt != Type::Named("Boolean".to_string()) {
+                    return Err(TypeError::Mismatch {
+                        expected: Type::Named("Boolean".to_string()),
+                        found: rt,
+                    });
+                }
+                Type::Named("Boolean".to_string())
+            }
+            BinaryOp::BitAnd | BinaryOp::BitOr | BinaryOp::BitXor => {
+                // Defined for two operands of the same integer type, or two Booleans
+                if Self::coerce_literal(right, &lt) {
+                    rt = lt.clone();
+                } else if Self::coerce_literal(left, &rt) {
+                    lt = rt.clone();
+                }
+                if lt != rt
+                    || !(Self::is_bitwise_type(&lt) || Self::named_in(&lt, &["Boolean"]).is_some())
+                {
+                    return Err(TypeError::Mismatch {
+                        expected: lt,
+                        found: rt,
+                    });
+                }
+                lt
+            }
+            // `a ?: b` is `b` when `a` is null; `b` may be nullable too
+            BinaryOp::Elvis => match lt {
+                _ if lt == Self::null_type() => rt,
+                Type::Nullable(inner) => {
+                    if rt == *inner || Self::coerce_literal(right, &inner) {
+                        *inner
+                    } else if Self::coerce(right, &Type::Nullable(inner.clone())) {
+                        Type::Nullable(inner)
+                    } else {
+                        return Err(TypeError::Mismatch {
+                            expected: *inner,
+                            found: rt,
+                        });
+                    }
+                }
+                // Never null, so `b` is never used
+                _ if rt == lt || Self::coerce_literal(right, &lt) => lt,
+                _ => {
+                    return Err(TypeError::Mismatch {
+                        expected: lt,
+                        found: rt,
+                    })
+                }
+            },
+            BinaryOp::Shl | BinaryOp::Shr | BinaryOp::UShr => {
+                // The shift count is always an Int; unsigned types have no `ushr`
+                let int = Type::Named("Int".to_string());
+                let signed = Self::named_in(&lt, &["Int", "Long"]).is_some();
+                if !Self::is_bitwise_type(&lt) || (!signed && *operator == BinaryOp::UShr) {
+                    return Err(TypeError::Mismatch {
+                        expected: int,
+                        found: lt,
+                    });
+                }
+                if rt != int {
+                    return Err(TypeError::Mismatch {
+                        expected: int,
+                        found: rt,
+                    });
+                }
+                lt
+            }
+        })
+    }
+
+    /// Only variables, object properties and elements of arrays and maps can be assigned.
+    fn check_assignable(target: &Expression) -> Result<(), TypeError> {
+        match &*target.kind {
+            ExpressionKind::Variable(_) => Ok(()),
+            ExpressionKind::Index { array, .. }
+                if matches!(array.resolved_type, Some(Type::Array(_) | Type::Map(_, _))) =>
+            {
+                Ok(())
+            }
+            ExpressionKind::Index { array, .. } => {
+                Err(TypeError::NotAssignable(match &array.resolved_type {
+                    Some(typ) => format!("an element of {:?}", typ),
+                    None => "an element".to_string(),
+                }))
+            }
+            ExpressionKind::MemberAccess { member, .. } => {
+                Err(TypeError::NotAssignable(format!("member '{}'", member)))
+            }
+            _ => Err(TypeError::NotAssignable("an expression".to_string())),
+        }
+    }
+
+    /// The member name of a `Math.member` callee, unless `Math` is a variable.
+    fn math_member(&self, callee: &Expression) -> Option<String> {
+        match &*callee.kind {
+            ExpressionKind::MemberAccess { object, member }
+                if matches!(&*object.kind, ExpressionKind::Variable(name) if name == "Math")
+                    && self.lookup_var("Math").is_err() =>
+            {
+                Some(member.clone())
+            }
+            _ => None,
+        }
+    }
+
+    /// `Math.addExact`, `subtractExact`, `multiplyExact` and `negateExact`
+    /// take `Int`s or `Long`s, all of the same type.
+    fn check_math_exact(
+        &mut self,
+        member: &str,
+        arguments: &mut [Expression],
+    ) -> Result<Type, TypeError> {
+        let arity = match member {
+            "addExact" | "subtractExact" | "multiplyExact" => 2,
+            "negateExact" => 1,
+            _ => {
+                return Err(TypeError::UndefinedMember {
+                    typ: Type::Named("Math".to_string()),
+                    member: member.to_string(),
+                })
+            }
+        };
+        if arguments.len() != arity {
+            return Err(TypeError::ArgumentCount {
+                name: format!("Math.{}", member),
+                expected: arity,
+                got: arguments.len(),
+            });
+        }
+        let mut types = Vec::new();
+        for arg in arguments.iter_mut() {
+            types.push(self.check_expression(arg)?);
+        }
+        // `Math.addExact(big, 1)` passes the literal as a Long
+        let typ = types
+            .iter()
+            .find(|t| Self::named_in(t, &["Long"]).is_some())
+            .unwrap_or(&types[0])
+            .clone();
+        for (arg, at) in arguments.iter_mut().zip(types) {
+            if Self::named_in(&typ, &["Int", "Long"]).is_none()
+                || (at != typ && !Self::coerce_literal(arg, &typ))
+            {
+                return Err(TypeError::Mismatch {
+                    expected: typ,
+                    found: at,
+                });
+            }
+        }
+        Ok(typ)
+    }
+
+    fn define_var(&mut self, name: String, typ: Type) {
+        // A new variable, whatever one of the same name in a scope of the
+        // same depth went through
+        let variable = (self.scopes.len() - 1, name);
+        self.captured.remove(&variable);
+        self.reassigned.remove(&variable);
+        self.scopes.last_mut().unwrap().insert(variable.1, typ);
+    }
+
+    fn lookup_var(&self, name: &str) -> Result<&Type, TypeError> {
+        for scope in self.scopes.iter().rev() {
+            if let Some(typ) = scope.get(name) {
+                return Ok(typ);
+            }
+        }
+        Err(TypeError::UndefinedVariable(name.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(src: &str) -> Result<Program, TypeError> {
+        let mut program = dotlin_parser::Parser::new(src).parse_program().unwrap();
+        TypeChecker::new().check_program(&mut program)?;
+        Ok(program)
+    }
+
+    fn return_type_of(program: &Program, name: &str) -> Option<Type> {
+        program.functions().into_iter().find_map(|item| {
+            (item.decl.name == name)
+                .then(|| item.decl.return_type.clone())
+                .flatten()
+        })
+    }
+
+    #[test]
+    fn infers_expression_body_return_types() {
+        // `half` is used before it is declared
+        let program = check(
+            "fun avg(a: Double, b: Double) = half(a + b)\n\
+             fun half(x: Double) = x / 2.0\n\
+             fun pick(c: Boolean) = if (c) \"yes\" else \"no\"",
+        )
+        .unwrap();
+        let double = Type::Named("Double".to_string());
+        assert_eq!(return_type_of(&program, "avg"), Some(double.clone()));
+        assert_eq!(return_type_of(&program, "half"), Some(double));
+        assert_eq!(
+            return_type_of(&program, "pick"),
+            Some(Type::Named("String".to_string()))
+        );
+    }
+
+    #[test]
+    fn rejects_mismatched_if_branches_and_recursive_inference() {
+        assert!(matches!(
+            check("fun f(c: Boolean) = if (c) 1 else \"one\""),
+            Err(TypeError::Mismatch { .. })
+        ));
+        assert!(matches!(
+            check("fun loop(n: Int) = loop(n)"),
+            Err(TypeError::RecursiveInference(name)) if name == "loop"
+        ));
+        assert!(check("fun fact(n: Int): Int = if (n < 2) 1 else n * fact(n - 1)").is_ok());
+    }
+
+    #[test]
+    fn destructures_pairs_and_triples() {
+        let program = check(
+            "fun split(s: String) = s to s.length\n\
+             fun third(a: Int, b: Float, c: String) = Triple(a, b, c).third\n\
+             fun main() {\n\
+                 val (word, len) = split(\"hi\")\n\
+                 val total = len + 1\n\
+             }",
+        )
+        .unwrap();
+        assert_eq!(
+            return_type_of(&program, "split"),
+            Some(Type::Generic(
+                "Pair".to_string(),
+                vec![
+                    Type::Named("String".to_string()),
+                    Type::Named("Int".to_string())
+                ]
+            ))
+        );
+        assert_eq!(
+            return_type_of(&program, "third"),
+            Some(Type::Named("String".to_string()))
+        );
+
+        assert!(matches!(
+            check("fun main() { val (a, b, c) = 1 to 2 }"),
+            Err(TypeError::UndefinedMember { member, .. }) if member == "component3"
+        ));
+    }
+
+    #[test]
+    fn promotes_numeric_types_like_kotlin() {
+        let program = check(
+            "fun widen(b: Byte, s: Short) = b + s\n\
+             fun mixed(i: Int, l: Long) = i * l\n\
+             fun ratio(n: Int, f: Float) = n / f\n\
+             fun precise(f: Float) = f + 1.0\n\
+             fun unsigned(u: UInt) = u + 1u\n\
+             fun narrow(d: Double) = d.toByte()\n\
+             fun big() = 3000000000\n\
+             fun small(): Short { return 7 }",
+        )
+        .unwrap();
+        let named = |name: &str| Some(Type::Named(name.to_string()));
+        assert_eq!(return_type_of(&program, "widen"), named("Int"));
+        assert_eq!(return_type_of(&program, "mixed"), named("Long"));
+        assert_eq!(return_type_of(&program, "ratio"), named("Float"));
+        assert_eq!(return_type_of(&program, "precise"), named("Double"));
+        assert_eq!(return_type_of(&program, "unsigned"), named("UInt"));
+        assert_eq!(return_type_of(&program, "narrow"), named("Byte"));
+        assert_eq!(return_type_of(&program, "big"), named("Long"));
+
+        assert!(check("fun main() { val b: Byte = 1\n val l: Long = 5 }").is_ok());
+        assert!(matches!(
+            check("fun main() { val b: Byte = 300 }"),
+            Err(TypeError::Mismatch { .. })
+        ));
+        assert!(matches!(
+            check("fun f(u: UInt, i: Int) = u + i"),
+            Err(TypeError::Mismatch { .. })
+        ));
+        assert!(matches!(
+            check("fun f(): Int { return 1L }"),
+            Err(TypeError::Mismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn checks_bitwise_operators() {
+        let program = check(
+            "fun mask(l: Long) = l and 0xFF\n\
+             fun shifted(u: UInt) = u shl 3\n\
+             fun flipped(n: Int) = n.inv() % 7\n\
+             fun either(a: Boolean, b: Boolean) = a xor b",
+        )
+        .unwrap();
+        let named = |name: &str| Some(Type::Named(name.to_string()));
+        assert_eq!(return_type_of(&program, "mask"), named("Long"));
+        assert_eq!(return_type_of(&program, "shifted"), named("UInt"));
+        assert_eq!(return_type_of(&program, "flipped"), named("Int"));
+        assert_eq!(return_type_of(&program, "either"), named("Boolean"));
+
+        for src in [
+            "fun f(i: Int, l: Long) = i and l",
+            "fun f(d: Double) = d shl 1",
+            "fun f(n: Int) = n shl 1L",
+            "fun f(u: UInt) = u ushr 1",
+        ] {
+            assert!(
+                matches!(check(src), Err(TypeError::Mismatch { .. })),
+                "{}",
+                src
+            );
+        }
+    }
+
+    #[test]
+    fn checks_math_exact_calls() {
+        let program = check(
+            "fun sum(a: Int, b: Int) = Math.addExact(a, b)\n\
+             fun scaled(l: Long) = Math.multiplyExact(l, 1000)",
+        )
+        .unwrap();
+        let named = |name: &str| Some(Type::Named(name.to_string()));
+        assert_eq!(return_type_of(&program, "sum"), named("Int"));
+        assert_eq!(return_type_of(&program, "scaled"), named("Long"));
+
+        assert!(matches!(
+            check("fun f(a: Int, b: Long) = Math.addExact(a, b)"),
+            Err(TypeError::Mismatch { .. })
+        ));
+        assert!(matches!(
+            check("fun f(d: Double) = Math.negateExact(d)"),
+            Err(TypeError::Mismatch { .. })
+        ));
+        assert!(matches!(
+            check("fun f(a: Int) = Math.addExact(a)"),
+            Err(TypeError::ArgumentCount { .. })
+        ));
+    }
+
+    #[test]
+    fn resolves_calls_across_modules() {
+        let parse = |src: &str| dotlin_parser::Parser::new(src).parse_program().unwrap();
+        let called = |program: &Program| -> Vec<String> {
+            let Declaration::Function(main) = &program.declarations[0] else {
+                panic!("expected main first");
+            };
+            main.body
+                .statements
+                .iter()
+                .filter_map(|stmt| match stmt {
+                    Statement::Expression(expr) => match &*expr.kind {
+                        ExpressionKind::Call { callee, .. } => match &*callee.kind {
+                            ExpressionKind::Variable(name) => Some(name.clone()),
+                            _ => None,
+                        },
+                        _ => None,
+                    },
+                    _ => None,
+                })
+                .collect()
+        };
+
+        let mut modules = vec![
+            parse(
+                "import geometry.area\n\
+                 import text.*\n\
+                 fun main() { area(2.0) shout(\"hi\") geometry.perimeter(1.0) helper() }\n\
+                 fun helper() {}",
+            ),
+            parse(
+                "package geometry\n\
+                 fun area(r: Double) = r * r * 3.14\n\
+                 fun perimeter(r: Double) = 2.0 * 3.14 * r",
+            ),
+            parse("package text\nfun shout(s: String) { println(s) }"),
+        ];
+        TypeChecker::new().check_modules(&mut modules).unwrap();
+        assert_eq!(
+            called(&modules[0]),
+            [
+                "geometry.area",
+                "text.shout",
+                "geometry.perimeter",
+                "helper"
+            ]
+        );
+        assert_eq!(
+            return_type_of(&modules[1], "area"),
+            Some(Type::Named("Double".to_string()))
+        );
+
+        // Functions of other packages need an import
+        let mut modules = vec![
+            parse("fun main() { perimeter(1.0) }"),
+            parse("package geometry\nfun perimeter(r: Double) = r"),
+        ];
+        assert!(matches!(
+            TypeChecker::new().check_modules(&mut modules),
+            Err(TypeError::UndefinedVariable(_))
+        ));
+
+        for import in ["import geometry.volume", "import shapes.*"] {
+            let mut modules = vec![
+                parse(&format!("{}\nfun main() {{}}", import)),
+                parse("package geometry\nfun area(r: Double) = r"),
+            ];
+            assert!(matches!(
+                TypeChecker::new().check_modules(&mut modules),
+                Err(TypeError::UnresolvedImport(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn private_functions_stay_in_their_file() {
+        let parse = |src: &str| dotlin_parser::Parser::new(src).parse_program().unwrap();
+
+        // Both files may declare a private `scale`; each call sees its own
+        let mut modules = vec![
+            parse("fun main() { val a: Int = twice(1) val b: Boolean = scale() }\nprivate fun scale() = true"),
+            parse("internal fun twice(n: Int) = n * scale()\nprivate fun scale() = 2"),
+        ];
+        TypeChecker::new().check_modules(&mut modules).unwrap();
+        assert_eq!(
+            return_type_of(&modules[1], "twice"),
+            Some(Type::Named("Int".to_string()))
+        );
+
+        for (main, lib) in [
+            (
+                "fun main() { secret() }",
+                "package lib\nprivate fun secret() {}",
+            ),
+            (
+                "import lib.*\nfun main() { secret() }",
+                "package lib\nprivate fun secret() {}",
+            ),
+            (
+                "fun main() { lib.secret() }",
+                "package lib\nprivate fun secret() {}",
+            ),
+            (
+                "import lib.secret\nfun main() {}",
+                "package lib\nprivate fun secret() {}",
+            ),
+            (
+                "package lib\nfun main() { secret() }",
+                "package lib\nprivate fun secret() {}",
+            ),
+        ] {
+            let mut modules = vec![parse(main), parse(lib)];
+            let result = TypeChecker::new().check_modules(&mut modules);
+            if main.starts_with("fun main() { secret") {
+                // Another package's functions are never visible unqualified
+                assert!(
+                    matches!(result, Err(TypeError::UndefinedVariable(_))),
+                    "{}",
+                    main
+                );
+            } else {
+                assert!(
+                    matches!(result, Err(TypeError::PrivateAccess(_))),
+                    "{}",
+                    main
+                );
+            }
+        }
+
+        // A package declares each name once, but for private ones in different files
+        for (a, b) in [
+            (
+                "fun f() {}
+fun f() {}",
+                "fun g() {}",
+            ),
+            (
+                "private fun f() {}
+private fun f() {}",
+                "fun g() {}",
+            ),
+            ("fun f() {}", "fun f() {}"),
+            ("package p\nfun f() {}", "package p\nobject f { }"),
+        ] {
+            let mut modules = vec![parse(a), parse(b)];
+            assert!(
+                matches!(
+                    TypeChecker::new().check_modules(&mut modules),
+                    Err(TypeError::ConflictingDeclarations(_))
+                ),
+                "{} / {}",
+                a,
+                b
+            );
+        }
+    }
+
+    #[test]
+    fn object_members_resolve_to_qualified_names() {
+        let program = check(
+            "object Registry {\n var count = 0\n private val step = 2.5\n\
+             fun register(n: Int): Int { count = count + n\n return count }\n\
+             fun scaled() = step * count }\n\
+             fun main() { val total: Int = Registry.register(1) }\n\
+             fun size() = Registry.count",
+        )
+        .unwrap();
+        let registry = program.objects().next().unwrap();
+        assert_eq!(
+            registry.properties[1].typ,
+            Some(Type::Named("Double".to_string()))
+        );
+        assert_eq!(
+            return_type_of(&program, "scaled"),
+            Some(Type::Named("Double".to_string()))
+        );
+        assert_eq!(
+            return_type_of(&program, "size"),
+            Some(Type::Named("Int".to_string()))
+        );
+
+        assert!(matches!(
+            check("object Registry { private val step = 2 }\nfun main() { val s = Registry.step }"),
+            Err(TypeError::PrivateAccess(_))
+        ));
+        assert!(matches!(
+            check(
+                "object Registry { var count = 0 }\nfun main() { val s: String = Registry.count }"
+            ),
+            Err(TypeError::Mismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn assignment_targets_and_compound_operators() {
+        assert!(check(
+            "object Stats { var total = 0L }\n\
+             fun main() {\n\
+             val xs = [1, 2]\n xs[0] += 3\n xs[1] %= 2\n\
+             val m = {\"a\": 1.5}\n m[\"b\"] = 2.0\n m[\"a\"] *= 2.0\n\
+             var s = \"a\"\n s += \"b\"\n\
+             Stats.total += 1\n\
+             do { val next = s } while (next != s) }"
+        )
+        .is_ok());
+
+        // `b += 1` is `b = b + 1`, an Int
+        assert!(matches!(
+            check("fun main() { var b: Byte = 1\n b += 1 }"),
+            Err(TypeError::Mismatch { .. })
+        ));
+        assert!(matches!(
+            check("fun main() { val xs = [1]\n xs[0] = \"one\" }"),
+            Err(TypeError::Mismatch { .. })
+        ));
+        for src in [
+            "fun main() { val s = \"abc\"\n s[0] = 'x' }",
+            "fun main() { val p = 1 to 2\n p.first = 3 }",
+        ] {
+            assert!(
+                matches!(check(src), Err(TypeError::NotAssignable(_))),
+                "{}",
+                src
+            );
+        }
+        assert!(matches!(
+            check("fun main() { do { val n = 1 } while (n) }"),
+            Err(TypeError::Mismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn lambdas_and_sequences_infer_types() {
+        let program = check(
+            "fun apply(f: (Int) -> Int, x: Int) = f(x)\n\
+             fun tripled() = apply({ it * 3 }, 7)\n\
+             fun evens() = sequence { var i = 0\n while (true) { yield(i)\n i += 2 } }\n\
+             fun firstEvens() = evens().take(3).toList()\n\
+             fun halves() = [1, 2].asSequence().map { it / 2.0 }\n\
+             fun main() { val k = 2\n val scale = { x: Int -> x * k }\n val y = scale(4) }",
+        )
+        .unwrap();
+        let int = Type::Named("Int".to_string());
+        let sequence =
+            |elem: &str| Type::Generic("Sequence".to_string(), vec![Type::Named(elem.to_string())]);
+        assert_eq!(return_type_of(&program, "tripled"), Some(int.clone()));
+        assert_eq!(return_type_of(&program, "evens"), Some(sequence("Int")));
+        assert_eq!(
+            return_type_of(&program, "firstEvens"),
+            Some(Type::Array(Box::new(int)))
+        );
+        assert_eq!(return_type_of(&program, "halves"), Some(sequence("Double")));
+
+        // Nor outside it, before or after the capture, as the lambda would
+        // keep the old value
+        for src in [
+            "fun main() { var n = 0\n val inc = { n += 1 } }",
+            "fun main() { var n = 5\n val f = { n + 1 }\n n = 10\n println(f()) }",
+            "fun main() { var n = 5\n n++\n val f = { n + 1 } }",
+            "fun main() { var n = 0\n while (n < 3) { val f = { n }\n n += 1 } }",
+        ] {
+            assert!(
+                matches!(check(src), Err(TypeError::CapturedAssignment(ref name)) if name == "n"),
+                "{}",
+                src
+            );
+        }
+        // A variable of the same name in another scope is another variable
+        assert!(check(
+            "fun main() { if (true) { val n = 5\n val f = { n } }\n if (true) { var n = 1\n n = 2 } }"
+        )
+        .is_ok());
+        assert!(matches!(
+            check("fun main() { val f = { x: Int -> return x } }"),
+            Err(TypeError::ReturnInLambda)
+        ));
+        for src in [
+            "fun main() { yield(1) }",
+            "fun main() { val s = sequence { val x = yield(1) } }",
+        ] {
+            assert!(
+                matches!(check(src), Err(TypeError::InvalidYield)),
+                "{}",
+                src
+            );
+        }
+        assert!(matches!(
+            check("fun main() { val s = sequence { println(1) } }"),
+            Err(TypeError::CannotInfer(_))
+        ));
+        assert!(matches!(
+            check("fun main() { val f = { x: Int -> x }\n val s: String = f(1) }"),
+            Err(TypeError::Mismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn checks_suspend_functions_and_coroutine_builders() {
+        let program = check(
+            "suspend fun fetch(id: Int): Int { delay(10L)\n return id }\n\
+             fun answer() = runBlocking { val d = async { fetch(1) }\n d.await() + fetch(2) }\n\
+             fun worker() = runBlocking { launch { delay(5L) } }\n\
+             fun clock() = runBlocking { currentTime }\n\
+             fun main() { runTest { delay(1000L) } }",
+        )
+        .unwrap();
+        assert_eq!(
+            return_type_of(&program, "answer"),
+            Some(Type::Named("Int".to_string()))
+        );
+        assert_eq!(
+            return_type_of(&program, "worker"),
+            Some(Type::Named("Job".to_string()))
+        );
+        assert_eq!(
+            return_type_of(&program, "clock"),
+            Some(Type::Named("Long".to_string()))
+        );
+
+        for src in [
+            "fun main() { delay(1L) }",
+            "suspend fun f() { }\n fun main() { f() }",
+            "fun main() { val j = launch { delay(1L) } }",
+            "fun main() { runBlocking { val f = { delay(1L) } } }",
+        ] {
+            assert!(
+                matches!(check(src), Err(TypeError::SuspendOutsideCoroutine(_))),
+                "{}",
+                src
+            );
+        }
+        for src in [
+            "suspend fun ready(): Boolean = true\n suspend fun main() { while (ready()) { } }",
+            "suspend fun one(): Int = 1\n suspend fun main() { val x = if (true) one() else 2 }",
+            "suspend fun ready(): Boolean = true\n suspend fun main() { val b = true && ready() }",
+        ] {
+            assert!(
+                matches!(check(src), Err(TypeError::UnsupportedSuspension(_))),
+                "{}",
+                src
+            );
+        }
+    }
+
+    #[test]
+    fn threads_only_share_thread_safe_values() {
+        let program = check(
+            "fun counted(): Int { val hits = AtomicInt(0)\n val t = thread { hits.incrementAndGet() }\n t.join()\n return hits.get() }\n\
+             fun locked(): String { val lock = Mutex()\n return lock.withLock { \"done\" } }\n\
+             fun received(): Int { val c: Channel<Int> = Channel()\n val name = \"x\"\n thread { c.send(name.length)\n c.close() }\n var n = 0\n for (x in c) { n += x }\n return n }\n\
+             object Counter { val hits = AtomicInt(0)\n val names = [\"a\"] }\n\
+             fun count() { Counter.hits.incrementAndGet() }\n\
+             @JvmInline value class Id(val n: Int)\n\
+             fun shared(): Int { val id = Id(7)\n val t = thread { println(id.n) }\n t.join()\n return id.n }\n\
+             fun main() { thread { count() }\n Counter.names.push(\"b\") }",
+        )
+        .unwrap();
+        assert_eq!(
+            return_type_of(&program, "locked"),
+            Some(Type::Named("String".to_string()))
+        );
+
+        for src in [
+            "fun main() { val a = [1, 2]\n thread { println(a) } }",
+            "fun main() { val f = { x: Int -> x }\n thread { f(1) } }",
+            "fun main() { val c: Channel<Array<Int>> = Channel() }",
+            // A value class is only as safe as its property
+            "@JvmInline value class Box(val xs: Array<Int>)\n\
+             fun main() { val b = Box([1])\n thread { b.xs.push(2) } }",
+            "@JvmInline value class Id(val n: Int) { companion object { val cache = [1] } }\n\
+             fun main() { thread { Id.cache.push(2) } }",
+            "@JvmInline value class Id(val n: Int) { companion object { val cache = [1]\n\
+             fun remember(n: Int) { cache.push(n) } } }\n\
+             fun main() { thread { Id.remember(2) } }",
+            "@JvmInline value class Id(val n: Int) { companion object { val cache = [1] }\n\
+             fun remember() { Id.cache.push(n) } }\n\
+             fun main() { val id = Id(1)\n thread { id.remember() } }",
+            // Every thread shares an object's properties, also through the
+            // functions a thread calls
+            "object Shared { var items = [1, 2] }\n fun main() { thread { Shared.items.push(9) } }",
+            "object Shared { var items = [1, 2] }\n fun add() { Shared.items.push(9) }\n\
+             fun work() { add() }\n fun main() { thread { work() } }",
+            "object Shared { val items = [1]\n fun add() { items.push(9) } }\n\
+             fun main() { thread { Shared.add() } }",
+        ] {
+            assert!(
+                matches!(check(src), Err(TypeError::NotThreadSafe { .. })),
+                "{}",
+                src
+            );
+        }
+        assert!(matches!(
+            check("fun main() { val c = Channel() }"),
+            Err(TypeError::CannotInfer(_))
+        ));
+        assert!(matches!(
+            check("fun main() { val c: Channel<Int> = Channel()\n c.send(\"a\") }"),
+            Err(TypeError::Mismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn scope_functions_and_nullable_values() {
+        let program = check(
+            "fun doubled() = 21.let { it * 2 }\n\
+             fun length() = \"abc\".run { length }\n\
+             fun kept() = \"abc\".also { val n = it.length }\n\
+             fun summed() = with(Pair(1, 2)) { first + second }\n\
+             fun positive(n: Int) = n.takeIf { it > 0 }\n\
+             fun orZero(n: Int) = positive(n) ?: 0\n\
+             fun described(s: String?) = s?.let { it + \"!\" }\n\
+             fun counted(): Int { var n = 0\n 5.let { n += it }\n return n }\n\
+             fun main() { val none: Int? = null\n val same = none == null }",
+        )
+        .unwrap();
+        let int = Type::Named("Int".to_string());
+        let string = Type::Named("String".to_string());
+        let nullable = |typ: &Type| Some(Type::Nullable(Box::new(typ.clone())));
+        assert_eq!(return_type_of(&program, "doubled"), Some(int.clone()));
+        assert_eq!(return_type_of(&program, "length"), Some(int.clone()));
+        assert_eq!(return_type_of(&program, "kept"), Some(string.clone()));
+        assert_eq!(return_type_of(&program, "summed"), Some(int.clone()));
+        assert_eq!(return_type_of(&program, "positive"), nullable(&int));
+        assert_eq!(return_type_of(&program, "orZero"), Some(int));
+        assert_eq!(return_type_of(&program, "described"), nullable(&string));
+
+        for src in [
+            "fun main() { val s: String? = \"a\"\n val n = s.length }",
+            "fun main() { val x: Int? = 1\n val b = x < 2 }",
+        ] {
+            assert!(
+                matches!(check(src), Err(TypeError::UndefinedMember { .. })),
+                "{}",
+                src
+            );
+        }
+        for src in [
+            "fun main() { val n = 3.takeIf { 5 } }",
+            "fun main() { val x: Int = null }",
+            "fun main() { val s = 1.let { a: String -> a } }",
+        ] {
+            assert!(
+                matches!(check(src), Err(TypeError::Mismatch { .. })),
+                "{}",
+                src
+            );
+        }
+        assert!(matches!(
+            check("suspend fun f() { 1.let { delay(5L) } }\n fun main() { }"),
+            Err(TypeError::UnsupportedSuspension(_))
+        ));
+    }
+
+    #[test]
+    fn property_accessors_and_delegates() {
+        let delegate = "object Store {\n var value = 0\n\
+             operator fun getValue(thisRef: Any?, property: KProperty<*>): Int = value\n\
+             operator fun setValue(thisRef: Any?, property: KProperty<*>, v: Int) { value = v } }\n";
+        let mut program = check(&format!(
+            "{}object Settings {{\n var level: Int by Store\n\
+             var percent = 0\n set(value) {{ field = value * 2 }}\n\
+             val half get() = percent / 2\n\
+             val answer: Long by lazy {{ 42 }}\n\
+             lateinit var name: String }}\n\
+             fun main() {{ Settings.percent += 1\n Settings.level = 3 }}",
+            delegate
+        ))
+        .unwrap();
+        let settings = program.objects().nth(1).unwrap();
+        let types: Vec<_> = settings.properties.iter().map(|p| p.typ.clone()).collect();
+        let named = |name: &str| Some(Type::Named(name.to_string()));
+        assert_eq!(
+            types,
+            [
+                named("Int"),
+                named("Int"),
+                named("Int"),
+                named("Long"),
+                named("String")
+            ]
+        );
+        // The delegated property gets accessors calling the delegate
+        assert!(settings.properties[0].getter.is_some() && settings.properties[0].setter.is_some());
+        assert_eq!(
+            settings.properties[1].setter.as_ref().unwrap().params[0].typ,
+            Type::Named("Int".to_string())
+        );
+
+        let Declaration::Function(main) = program.declarations.pop().unwrap() else {
+            panic!("main is the last declaration");
+        };
+        let callees: Vec<_> = main
+            .body
+            .statements
+            .iter()
+            .map(|stmt| match stmt {
+                Statement::Expression(expr) => match &*expr.kind {
+                    ExpressionKind::Call { callee, .. } => match &*callee.kind {
+                        ExpressionKind::Variable(name) => name.clone(),
+                        other => panic!("unexpected callee {:?}", other),
+                    },
+                    other => panic!("unexpected statement {:?}", other),
+                },
+                other => panic!("unexpected statement {:?}", other),
+            })
+            .collect();
+        assert_eq!(callees, ["Settings.<set-percent>", "Settings.<set-level>"]);
+
+        for src in [
+            "object O { lateinit var n: Int }\nfun main() { }",
+            "object O { lateinit var s: String? }\nfun main() { }",
+        ] {
+            assert!(
+                matches!(check(src), Err(TypeError::InvalidLateinit(_))),
+                "{}",
+                src
+            );
+        }
+        for src in [
+            "object O { var n by lazy { 1 } }\nfun main() { }",
+            "object D { fun getValue(thisRef: Any?, property: KProperty<*>): Int = 1 }\n\
+             object O { val n: Int by D }\nfun main() { }",
+            "object D { operator fun getValue(thisRef: Any?, property: KProperty<*>): Int = 1 }\n\
+             object O { var n: Int by D }\nfun main() { }",
+        ] {
+            assert!(
+                matches!(check(src), Err(TypeError::InvalidDelegate { .. })),
+                "{}",
+                src
+            );
+        }
+        assert!(matches!(
+            check(&format!(
+                "{}object O {{ val n: String by Store }}\nfun main() {{ }}",
+                delegate
+            )),
+            Err(TypeError::Mismatch { .. })
+        ));
+        assert!(matches!(
+            check("object O { val n by lazy { 1 } }\nfun main() { O.n = 2 }"),
+            Err(TypeError::NotAssignable(_))
+        ));
+    }
+
+    #[test]
+    fn type_aliases_and_value_classes() {
+        let parse = |src: &str| dotlin_parser::Parser::new(src).parse_program().unwrap();
+        let mut modules = vec![
+            parse(
+                "import units.*
+                 typealias Handler = (Id) -> Unit
+                 fun run(h: Handler, ids: Array<Id>?) { h(1) }
+                 fun main() { val m = Meters(2.0).plus(Meters(1.0))
+ println(m.v) }",
+            ),
+            parse(
+                "package units
+                 typealias Id = Int
+                 @JvmInline value class Meters(val v: Double) {
+                 fun plus(other: Meters) = Meters(v + other.v) }",
+            ),
+        ];
+        TypeChecker::new().check_modules(&mut modules).unwrap();
+        let named = |name: &str| Type::Named(name.to_string());
+        let run = modules[0].functions()[0].decl;
+        assert_eq!(
+            run.params[0].typ,
+            Type::Function(vec![named("Int")], Box::new(named("Unit")))
+        );
+        assert_eq!(
+            run.params[1].typ,
+            Type::Nullable(Box::new(Type::Array(Box::new(named("Int")))))
+        );
+        // The value class is erased to its property's type
+        let plus = modules[1].functions()[0].decl;
+        assert_eq!(
+            plus.params[0],
+            Param {
+                name: "this".to_string(),
+                typ: named("Double")
+            }
+        );
+        assert_eq!(plus.return_type, Some(named("Double")));
+        let Some(Statement::VariableDecl {
+            initializer: Some(value),
+            ..
+        }) = modules[0].functions()[1].decl.body.statements.first()
+        else {
+            panic!("main starts with a variable");
+        };
+        match &*value.kind {
+            ExpressionKind::Call { callee, arguments } => {
+                assert_eq!(
+                    *callee.kind,
+                    ExpressionKind::Variable("units.Meters.plus".to_string())
+                );
+                assert_eq!(
+                    *arguments[0].kind,
+                    ExpressionKind::Literal(Literal::Double(2.0))
+                );
+            }
+            other => panic!("unexpected initializer {:?}", other),
+        }
+        assert_eq!(value.resolved_type, Some(named("Double")));
+
+        for src in [
+            "value class M(val v: Double)\nfun main() { val d: Double = M(1.0) }",
+            "value class M(val v: Double)\nfun f(m: M) { }\nfun main() { f(2.0) }",
+            "value class M(val v: Int)\nfun main() { println(M(1) + M(2)) }",
+        ] {
+            assert!(
+                matches!(check(src), Err(TypeError::Mismatch { .. })),
+                "{}",
+                src
+            );
+        }
+        for src in [
+            "typealias A = B\ntypealias B = Array<A>\nfun main() { }",
+            "value class M(val v: Array<M>)\nfun main() { }",
+        ] {
+            assert!(
+                matches!(check(src), Err(TypeError::RecursiveType(_))),
+                "{}",
+                src
+            );
+        }
+        assert!(matches!(
+            check(
+                "value class M(val v: Int) { fun zero() = 0\n\
+                 companion object { fun zero() = M(0) } }\nfun main() { }"
+            ),
+            Err(TypeError::ConflictingDeclarations(name)) if name == "M.zero"
+        ));
     }
 }
@@ -0,0 +1,179 @@
+use dotlin_ast::{BinaryOp, UnaryOp};
+use dotlin_interpreter::{Interpreter, RuntimeError, Value};
+
+/// A built-in function of the interpreter, such as `println`.
+pub type NativeFunction = fn(&Interpreter, Vec<Value>) -> Result<Value, RuntimeError>;
+
+/// Where a variable lives.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Slot {
+    /// A local of the current call, counted from its first parameter
+    Local(u32),
+    /// A REPL variable, held in the interpreter's globals under `Module::names[n]`
+    Global(u32),
+}
+
+/// Where the right operand of a binary operator comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    /// Popped from the stack
+    Stack,
+    Constant(u32),
+    Local(u32),
+}
+
+/// One instruction of the stack machine. Operands are popped from the top of
+/// the stack, the last one first, and results pushed onto it. Jump targets
+/// are indices into the function's code.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Op {
+    /// Pushes `Module::constants[n]`
+    Constant(u32),
+    /// Pushes `Unit`
+    Void,
+    Load(Slot),
+    /// Pops a value into a variable
+    Store(Slot),
+    /// Pops a value into a new REPL variable named `Module::names[n]`
+    Define(u32),
+    Pop,
+    /// Pushes copies of the top `n` values
+    Duplicate(u32),
+    /// Pops `n` keys and pushes the element of a variable they lead to, e.g.
+    /// `grid[i][j]`, without copying the variable
+    LoadElement(Slot, u32),
+    /// Pops a value, then `n` keys, and stores the value in the element of
+    /// a variable they lead to
+    StoreElement(Slot, u32),
+    /// Pops an index and a container and pushes the element
+    Index,
+    /// Pops the left operand of an operator other than `&&`, `||` and
+    /// `?:` and pushes the result
+    Binary(BinaryOp, Operand),
+    Unary(UnaryOp),
+    /// Jumps if the top of the stack is the given boolean, leaving it there:
+    /// the left side of `&&` (`false`) or `||` (`true`)
+    ShortCircuit(bool, u32),
+    /// Pops the two sides of `&&` or `||` and pushes the result
+    Logical(BinaryOp),
+    Jump(u32),
+    /// Pops a condition and jumps if it is `false`
+    JumpIfFalse(u32),
+    /// Pops the left operand of a comparison and jumps if it is `false`
+    JumpUnless(BinaryOp, Operand, u32),
+    /// Jumps if the top of the stack is `null`, leaving it there
+    JumpIfNull(u32),
+    /// Jumps if the top of the stack isn't `null`, otherwise pops it
+    JumpIfNotNull(u32),
+    /// Calls `Module::functions[function]` with the top `args` values
+    Call {
+        function: u32,
+        args: u32,
+    },
+    CallNative {
+        native: u32,
+        args: u32,
+    },
+    /// Calls the REPL function named `Module::names[name]`
+    CallGlobal {
+        name: u32,
+        args: u32,
+    },
+    /// Calls the method `Module::names[name]` of the value below the arguments
+    CallMethod {
+        name: u32,
+        args: u32,
+    },
+    /// Calls the function value below the arguments
+    CallValue {
+        args: u32,
+    },
+    /// Pops a value and pushes its member `Module::names[n]`
+    Member(u32),
+    /// Pops `n` values and pushes an array of them
+    Array(u32),
+    /// Pops `n` key-value pairs and pushes a map of them
+    Map(u32),
+    /// Pops a Pair, Triple or array and pushes its first `n` components
+    Destructure(u32),
+    /// Pops what a `for` loop iterates over and stores an iterator over it
+    /// in a local. `Some(n)` when each element is destructured into `n`
+    /// variables, which makes a map yield its entries rather than its keys.
+    Iterate {
+        slot: u32,
+        destructure: Option<u32>,
+    },
+    /// Pushes the next element of the iterator in a local, or jumps to `end`
+    /// once there are no more
+    Next {
+        slot: u32,
+        end: u32,
+    },
+    /// Pops the return value and leaves the function
+    Return,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    /// Qualified name, e.g. `a.b.foo`
+    pub name: String,
+    pub params: u32,
+    /// Slots the function needs for its parameters and locals together
+    pub locals: u32,
+    pub code: Vec<Op>,
+    /// The source file, for stack traces
+    pub file: Option<String>,
+    /// `(op, line)` pairs, ordered by op: the ops from `op` up to the next
+    /// pair's come from `line`
+    pub lines: Vec<(u32, u32)>,
+}
+
+impl Function {
+    /// The source line of the op at `ip`, if known.
+    pub fn line(&self, ip: usize) -> Option<u32> {
+        let entries = self.lines.partition_point(|&(op, _)| op as usize <= ip);
+        entries.checked_sub(1).map(|entry| self.lines[entry].1)
+    }
+}
+
+/// The compiled functions of a program, and the values they refer to.
+#[derive(Debug, Clone, Default)]
+pub struct Module {
+    pub functions: Vec<Function>,
+    pub constants: Vec<Value>,
+    pub natives: Vec<NativeFunction>,
+    /// Names of members, methods and REPL variables
+    pub names: Vec<String>,
+    /// The entry module's `main`, if it has one
+    pub main: Option<u32>,
+}
+
+impl Module {
+    /// The index of a function by its qualified name.
+    pub fn function(&self, name: &str) -> Option<u32> {
+        self.functions
+            .iter()
+            .rposition(|function| function.name == name)
+            .map(|index| index as u32)
+    }
+
+    pub(crate) fn name(&mut self, name: &str) -> u32 {
+        match self.names.iter().position(|n| n == name) {
+            Some(index) => index as u32,
+            None => {
+                self.names.push(name.to_string());
+                self.names.len() as u32 - 1
+            }
+        }
+    }
+
+    pub(crate) fn constant(&mut self, value: Value) -> u32 {
+        self.constants.push(value);
+        self.constants.len() as u32 - 1
+    }
+
+    pub(crate) fn native(&mut self, native: NativeFunction) -> u32 {
+        self.natives.push(native);
+        self.natives.len() as u32 - 1
+    }
+}
@@ -0,0 +1,722 @@
+use crate::bytecode::{Function, Module, Op, Operand, Slot};
+use dotlin_ast::*;
+use dotlin_interpreter::{Environment, Interpreter, Value};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+#[derive(Debug, thiserror::Error)]
+pub enum CompileError {
+    /// A construct only the tree-walking interpreter runs, such as a lambda
+    #[error("The bytecode VM does not support {0}")]
+    Unsupported(String),
+}
+
+/// Compiles a type-checked program made of several modules, the entry module
+/// first. Top-level functions are called by their qualified names, which the
+/// type checker has substituted at every call site; private functions are
+/// only visible from their own module.
+pub fn compile(modules: &[Program]) -> Result<Module, CompileError> {
+    for module in modules {
+        if module.objects().next().is_some() {
+            return Err(CompileError::Unsupported("objects".to_string()));
+        }
+    }
+    let mut public = HashMap::new();
+    let mut private = vec![HashMap::new(); modules.len()];
+    let mut count = 0;
+    for (index, module) in modules.iter().enumerate() {
+        for function in module.functions() {
+            if function.decl.is_suspend {
+                return Err(CompileError::Unsupported("suspend functions".to_string()));
+            }
+            let table = if function.private {
+                &mut private[index]
+            } else {
+                &mut public
+            };
+            table.insert(function.name, count);
+            count += 1;
+        }
+    }
+
+    let natives = Interpreter::new().globals;
+    let mut compiled = Module::default();
+    for (index, module) in modules.iter().enumerate() {
+        for function in module.functions() {
+            let function = Compiler::new(&mut compiled, &public, &private[index], &natives)
+                .function(&function.name, function.decl)?;
+            compiled.functions.push(function);
+        }
+    }
+    if let Some(entry) = modules.first() {
+        let main = entry.qualified_name("main");
+        compiled.main = private[0].get(&main).or(public.get(&main)).copied();
+    }
+    Ok(compiled)
+}
+
+/// Compiles the body of one function. Each variable gets a slot of its own
+/// for as long as it is in scope.
+pub(crate) struct Compiler<'a> {
+    module: &'a mut Module,
+    functions: &'a HashMap<String, u32>,
+    private: &'a HashMap<String, u32>,
+    /// Where the built-in functions are defined
+    natives: &'a Rc<RefCell<Environment>>,
+    /// Whether names that aren't locals or functions refer to REPL variables
+    repl: bool,
+    code: Vec<Op>,
+    /// The variables in scope, innermost scope last; empty at the top level of the REPL
+    scopes: Vec<Vec<(String, u32)>>,
+    next_slot: u32,
+    locals: u32,
+    lines: Vec<(u32, u32)>,
+    /// The line of the expression being compiled
+    line: u32,
+}
+
+impl<'a> Compiler<'a> {
+    pub(crate) fn new(
+        module: &'a mut Module,
+        functions: &'a HashMap<String, u32>,
+        private: &'a HashMap<String, u32>,
+        natives: &'a Rc<RefCell<Environment>>,
+    ) -> Self {
+        Self {
+            module,
+            functions,
+            private,
+            natives,
+            repl: false,
+            code: Vec::new(),
+            scopes: Vec::new(),
+            next_slot: 0,
+            locals: 0,
+            lines: Vec::new(),
+            line: 0,
+        }
+    }
+
+    /// Compiles for the REPL, where unknown names are looked up when used.
+    pub(crate) fn repl(mut self) -> Self {
+        self.repl = true;
+        self
+    }
+
+    pub(crate) fn function(
+        mut self,
+        name: &str,
+        decl: &FunctionDecl,
+    ) -> Result<Function, CompileError> {
+        self.begin_scope();
+        for param in &decl.params {
+            self.declare(&param.name);
+        }
+        for stmt in &decl.body.statements {
+            self.statement(stmt)?;
+        }
+        self.code.push(Op::Void);
+        self.code.push(Op::Return);
+        Ok(Function {
+            name: name.to_string(),
+            params: decl.params.len() as u32,
+            locals: self.locals,
+            code: self.code,
+            file: decl.file.clone(),
+            lines: self.lines,
+        })
+    }
+
+    /// A REPL line as a function returning the value of an expression statement.
+    pub(crate) fn line(mut self, stmt: &Statement) -> Result<Function, CompileError> {
+        match stmt {
+            Statement::Expression(expr) => self.expression(expr)?,
+            _ => {
+                self.statement(stmt)?;
+                self.code.push(Op::Void);
+            }
+        }
+        self.code.push(Op::Return);
+        Ok(Function {
+            name: "<repl>".to_string(),
+            params: 0,
+            locals: self.locals,
+            code: self.code,
+            file: None,
+            lines: self.lines,
+        })
+    }
+
+    fn begin_scope(&mut self) {
+        self.scopes.push(Vec::new());
+    }
+
+    fn end_scope(&mut self) {
+        if let Some(scope) = self.scopes.pop() {
+            self.next_slot -= scope.len() as u32;
+        }
+    }
+
+    /// A new slot for a variable of the current scope.
+    fn declare(&mut self, name: &str) -> u32 {
+        let slot = self.next_slot;
+        self.next_slot += 1;
+        self.locals = self.locals.max(self.next_slot);
+        self.scopes
+            .last_mut()
+            .expect("locals are declared in a scope")
+            .push((name.to_string(), slot));
+        slot
+    }
+
+    /// Pops a value into a new variable: a local, or at the top level of the
+    /// REPL a global.
+    fn define(&mut self, name: &str) {
+        if self.scopes.is_empty() {
+            let name = self.module.name(name);
+            self.code.push(Op::Define(name));
+        } else {
+            let slot = self.declare(name);
+            self.code.push(Op::Store(Slot::Local(slot)));
+        }
+    }
+
+    /// Pops the top `names.len()` values into new variables, skipping `_`.
+    fn define_all(&mut self, names: &[String]) {
+        if self.scopes.is_empty() {
+            for name in names.iter().rev() {
+                if name == "_" {
+                    self.code.push(Op::Pop);
+                } else {
+                    let name = self.module.name(name);
+                    self.code.push(Op::Define(name));
+                }
+            }
+            return;
+        }
+        let slots: Vec<Option<u32>> = names
+            .iter()
+            .map(|name| (name != "_").then(|| self.declare(name)))
+            .collect();
+        for slot in slots.into_iter().rev() {
+            self.code.push(match slot {
+                Some(slot) => Op::Store(Slot::Local(slot)),
+                None => Op::Pop,
+            });
+        }
+    }
+
+    fn local(&self, name: &str) -> Option<u32> {
+        self.scopes
+            .iter()
+            .rev()
+            .flat_map(|scope| scope.iter().rev())
+            .find(|(n, _)| n == name)
+            .map(|(_, slot)| *slot)
+    }
+
+    /// Where the variable `name` lives.
+    fn slot(&mut self, name: &str) -> Result<Slot, CompileError> {
+        match self.local(name) {
+            Some(slot) => Ok(Slot::Local(slot)),
+            None if self.repl => Ok(Slot::Global(self.module.name(name))),
+            None => Err(CompileError::Unsupported(format!(
+                "the variable '{}'",
+                name
+            ))),
+        }
+    }
+
+    /// Compiles the right operand of a binary operator; the instruction reads
+    /// a literal or a local itself.
+    fn operand(&mut self, expr: &Expression) -> Result<Operand, CompileError> {
+        match &*expr.kind {
+            ExpressionKind::Literal(lit) => {
+                let value = Interpreter::literal(lit, expr.resolved_type.as_ref());
+                Ok(Operand::Constant(self.module.constant(value)))
+            }
+            ExpressionKind::Variable(name) if self.local(name).is_some() => {
+                Ok(Operand::Local(self.local(name).unwrap()))
+            }
+            _ => {
+                self.expression(expr)?;
+                Ok(Operand::Stack)
+            }
+        }
+    }
+
+    /// Compiles a condition and a jump taken when it is false, comparing in
+    /// the jump itself if it can.
+    fn jump_unless(&mut self, condition: &Expression) -> Result<usize, CompileError> {
+        if let ExpressionKind::Binary {
+            left,
+            operator:
+                operator @ (BinaryOp::Less
+                | BinaryOp::LessEqual
+                | BinaryOp::Greater
+                | BinaryOp::GreaterEqual
+                | BinaryOp::Equal
+                | BinaryOp::NotEqual),
+            right,
+        } = &*condition.kind
+        {
+            self.expression(left)?;
+            let right = self.operand(right)?;
+            return Ok(self.emit_jump(Op::JumpUnless(operator.clone(), right, 0)));
+        }
+        self.expression(condition)?;
+        Ok(self.emit_jump(Op::JumpIfFalse(0)))
+    }
+
+    fn emit_jump(&mut self, op: Op) -> usize {
+        self.code.push(op);
+        self.code.len() - 1
+    }
+
+    /// Points the jump at `at` to the next instruction.
+    fn patch(&mut self, at: usize) {
+        let here = self.code.len() as u32;
+        match &mut self.code[at] {
+            Op::Jump(target)
+            | Op::JumpIfFalse(target)
+            | Op::JumpUnless(_, _, target)
+            | Op::JumpIfNull(target)
+            | Op::JumpIfNotNull(target)
+            | Op::ShortCircuit(_, target)
+            | Op::Next { end: target, .. } => *target = here,
+            op => unreachable!("{:?} is not a jump", op),
+        }
+    }
+
+    fn block(&mut self, block: &Block) -> Result<(), CompileError> {
+        self.begin_scope();
+        for stmt in &block.statements {
+            self.statement(stmt)?;
+        }
+        self.end_scope();
+        Ok(())
+    }
+
+    fn statement(&mut self, stmt: &Statement) -> Result<(), CompileError> {
+        match stmt {
+            Statement::Expression(expr) => self.effect(expr)?,
+            Statement::Block(block) => self.block(block)?,
+            Statement::VariableDecl {
+                name, initializer, ..
+            } => {
+                match initializer {
+                    Some(init) => self.expression(init)?,
+                    None => self.code.push(Op::Void),
+                }
+                self.define(name);
+            }
+            Statement::Destructuring { names, initializer } => {
+                self.expression(initializer)?;
+                self.code.push(Op::Destructure(names.len() as u32));
+                self.define_all(names);
+            }
+            Statement::Return(value) => {
+                match value {
+                    Some(value) => self.expression(value)?,
+                    None => self.code.push(Op::Void),
+                }
+                self.code.push(Op::Return);
+            }
+            Statement::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                let otherwise = self.jump_unless(condition)?;
+                self.statement(then_branch)?;
+                match else_branch {
+                    Some(branch) => {
+                        let end = self.emit_jump(Op::Jump(0));
+                        self.patch(otherwise);
+                        self.statement(branch)?;
+                        self.patch(end);
+                    }
+                    None => self.patch(otherwise),
+                }
+            }
+            Statement::While { condition, body } => {
+                let start = self.code.len() as u32;
+                let end = self.jump_unless(condition)?;
+                self.statement(body)?;
+                self.code.push(Op::Jump(start));
+                self.patch(end);
+            }
+            Statement::DoWhile { body, condition } => {
+                // The condition sees the variables declared in the body
+                let start = self.code.len() as u32;
+                self.begin_scope();
+                for stmt in &body.statements {
+                    self.statement(stmt)?;
+                }
+                let end = self.jump_unless(condition)?;
+                self.end_scope();
+                self.code.push(Op::Jump(start));
+                self.patch(end);
+            }
+            Statement::ForEach {
+                variable,
+                iterable,
+                body,
+            } => {
+                if let Some(Type::Generic(name, _)) = &iterable.resolved_type {
+                    if name == "Sequence" || name == "Channel" {
+                        return Err(CompileError::Unsupported(format!(
+                            "iterating over a {}",
+                            name
+                        )));
+                    }
+                }
+                self.expression(iterable)?;
+                self.begin_scope();
+                let iterator = self.declare("$iterator");
+                let destructure = match variable {
+                    ForEachTarget::Ident(_) => None,
+                    ForEachTarget::Tuple(names) => Some(names.len() as u32),
+                };
+                self.code.push(Op::Iterate {
+                    slot: iterator,
+                    destructure,
+                });
+                let start = self.code.len() as u32;
+                let end = self.emit_jump(Op::Next {
+                    slot: iterator,
+                    end: 0,
+                });
+                self.begin_scope();
+                match variable {
+                    ForEachTarget::Ident(name) => {
+                        let slot = self.declare(name);
+                        self.code.push(Op::Store(Slot::Local(slot)));
+                    }
+                    ForEachTarget::Tuple(names) => {
+                        self.code.push(Op::Destructure(names.len() as u32));
+                        self.define_all(names);
+                    }
+                }
+                self.statement(body)?;
+                self.end_scope();
+                self.code.push(Op::Jump(start));
+                self.patch(end);
+                self.end_scope();
+            }
+        }
+        Ok(())
+    }
+
+    /// Compiles an expression statement, whose value is dropped.
+    fn effect(&mut self, expr: &Expression) -> Result<(), CompileError> {
+        match &*expr.kind {
+            ExpressionKind::Assignment {
+                target,
+                operator,
+                value,
+            } => {
+                let outer = self.line;
+                self.mark_line(expr.line);
+                let result = self.assignment(target, operator.as_ref(), value, false);
+                self.mark_line(outer);
+                result
+            }
+            _ => {
+                self.expression(expr)?;
+                self.code.push(Op::Pop);
+                Ok(())
+            }
+        }
+    }
+
+    /// Compiles a block whose value is that of its trailing expression.
+    fn block_value(&mut self, block: &Block) -> Result<(), CompileError> {
+        self.begin_scope();
+        let last = block.statements.len().checked_sub(1);
+        for (i, stmt) in block.statements.iter().enumerate() {
+            match stmt {
+                Statement::Expression(expr) if Some(i) == last => self.expression(expr)?,
+                _ => self.statement(stmt)?,
+            }
+        }
+        if !matches!(block.statements.last(), Some(Statement::Expression(_))) {
+            self.code.push(Op::Void);
+        }
+        self.end_scope();
+        Ok(())
+    }
+
+    /// Compiles `expr`, attributing its ops to its line for stack traces.
+    fn expression(&mut self, expr: &Expression) -> Result<(), CompileError> {
+        let outer = self.line;
+        self.mark_line(expr.line);
+        let result = self.expression_kind(expr);
+        self.mark_line(outer);
+        result
+    }
+
+    fn mark_line(&mut self, line: u32) {
+        if line == 0 || line == self.line {
+            return;
+        }
+        self.line = line;
+        let op = self.code.len() as u32;
+        match self.lines.last_mut() {
+            // No op came from the previous line
+            Some(last) if last.0 == op => last.1 = line,
+            _ => self.lines.push((op, line)),
+        }
+    }
+
+    fn expression_kind(&mut self, expr: &Expression) -> Result<(), CompileError> {
+        match &*expr.kind {
+            ExpressionKind::Literal(lit) => {
+                let value = Interpreter::literal(lit, expr.resolved_type.as_ref());
+                let constant = self.module.constant(value);
+                self.code.push(Op::Constant(constant));
+            }
+            ExpressionKind::Variable(name) => {
+                let slot = self.slot(name)?;
+                self.code.push(Op::Load(slot));
+            }
+            ExpressionKind::Assignment {
+                target,
+                operator,
+                value,
+            } => self.assignment(target, operator.as_ref(), value, true)?,
+            ExpressionKind::Call { callee, arguments } => self.call(callee, arguments)?,
+            ExpressionKind::Binary {
+                left,
+                operator: operator @ (BinaryOp::And | BinaryOp::Or),
+                right,
+            } => {
+                self.expression(left)?;
+                let end = self.emit_jump(Op::ShortCircuit(*operator == BinaryOp::Or, 0));
+                self.expression(right)?;
+                self.code.push(Op::Logical(operator.clone()));
+                self.patch(end);
+            }
+            ExpressionKind::Binary {
+                left,
+                operator: BinaryOp::Elvis,
+                right,
+            } => {
+                self.expression(left)?;
+                let end = self.emit_jump(Op::JumpIfNotNull(0));
+                self.expression(right)?;
+                self.patch(end);
+            }
+            ExpressionKind::Binary {
+                left,
+                operator,
+                right,
+            } => {
+                self.expression(left)?;
+                let right = self.operand(right)?;
+                self.code.push(Op::Binary(operator.clone(), right));
+            }
+            ExpressionKind::Unary { operator, operand } => {
+                self.expression(operand)?;
+                self.code.push(Op::Unary(operator.clone()));
+            }
+            ExpressionKind::MemberAccess { object, member } => {
+                self.expression(object)?;
+                let member = self.module.name(member);
+                self.code.push(Op::Member(member));
+            }
+            ExpressionKind::ArrayLiteral { elements } => {
+                for element in elements {
+                    self.expression(element)?;
+                }
+                self.code.push(Op::Array(elements.len() as u32));
+            }
+            ExpressionKind::Index { .. } => match self.element(expr)? {
+                Some((slot, depth)) => self.code.push(Op::LoadElement(slot, depth)),
+                None => {
+                    let ExpressionKind::Index { array, index } = &*expr.kind else {
+                        unreachable!()
+                    };
+                    self.expression(array)?;
+                    self.expression(index)?;
+                    self.code.push(Op::Index);
+                }
+            },
+            ExpressionKind::HashMapLiteral { pairs } => {
+                for (key, value) in pairs {
+                    self.expression(key)?;
+                    self.expression(value)?;
+                }
+                self.code.push(Op::Map(pairs.len() as u32));
+            }
+            ExpressionKind::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                let otherwise = self.jump_unless(condition)?;
+                self.block_value(then_branch)?;
+                let end = self.emit_jump(Op::Jump(0));
+                self.patch(otherwise);
+                match else_branch {
+                    Some(branch) => self.block_value(branch)?,
+                    None => self.code.push(Op::Void),
+                }
+                self.patch(end);
+            }
+            ExpressionKind::Lambda { .. } => {
+                return Err(CompileError::Unsupported("lambdas".to_string()))
+            }
+            ExpressionKind::SafeAccess { object, access } => {
+                self.expression(object)?;
+                let end = self.emit_jump(Op::JumpIfNull(0));
+                self.begin_scope();
+                let receiver = self.declare(SAFE_RECEIVER);
+                self.code.push(Op::Store(Slot::Local(receiver)));
+                self.expression(access)?;
+                self.end_scope();
+                self.patch(end);
+            }
+            ExpressionKind::Nullable(value) => self.expression(value)?,
+        }
+        Ok(())
+    }
+
+    /// For an element of a variable, such as `grid[i][j]`, pushes its keys
+    /// and returns the variable and the number of keys.
+    fn element(&mut self, expr: &Expression) -> Result<Option<(Slot, u32)>, CompileError> {
+        let mut keys = Vec::new();
+        let mut root = expr;
+        while let ExpressionKind::Index { array, index } = &*root.kind {
+            keys.push(index);
+            root = array;
+        }
+        let ExpressionKind::Variable(name) = &*root.kind else {
+            return Ok(None);
+        };
+        let slot = self.slot(name)?;
+        for key in keys.iter().rev() {
+            self.expression(key)?;
+        }
+        Ok(Some((slot, keys.len() as u32)))
+    }
+
+    /// `target = value` or `target op= value`; `keep` leaves the assigned
+    /// value on the stack.
+    fn assignment(
+        &mut self,
+        target: &Expression,
+        operator: Option<&BinaryOp>,
+        value: &Expression,
+        keep: bool,
+    ) -> Result<(), CompileError> {
+        match &*target.kind {
+            ExpressionKind::Variable(name) => {
+                let slot = self.slot(name)?;
+                if let Some(operator) = operator {
+                    self.code.push(Op::Load(slot));
+                    let value = self.operand(value)?;
+                    self.code.push(Op::Binary(operator.clone(), value));
+                } else {
+                    self.expression(value)?;
+                }
+                if keep {
+                    self.code.push(Op::Duplicate(1));
+                }
+                self.code.push(Op::Store(slot));
+            }
+            ExpressionKind::Index { .. } => {
+                let Some((slot, depth)) = self.element(target)? else {
+                    return Err(CompileError::Unsupported(
+                        "assignment to an element of a temporary".to_string(),
+                    ));
+                };
+                if let Some(operator) = operator {
+                    self.code.push(Op::Duplicate(depth));
+                    self.code.push(Op::LoadElement(slot, depth));
+                    let value = self.operand(value)?;
+                    self.code.push(Op::Binary(operator.clone(), value));
+                } else {
+                    self.expression(value)?;
+                }
+                if keep {
+                    // The keys are below the value, so it is parked in a local
+                    self.begin_scope();
+                    let parked = self.declare("$value");
+                    self.code.push(Op::Store(Slot::Local(parked)));
+                    self.code.push(Op::Load(Slot::Local(parked)));
+                    self.code.push(Op::StoreElement(slot, depth));
+                    self.code.push(Op::Load(Slot::Local(parked)));
+                    self.end_scope();
+                } else {
+                    self.code.push(Op::StoreElement(slot, depth));
+                }
+            }
+            _ => {
+                return Err(CompileError::Unsupported(
+                    "assignment to a member or temporary".to_string(),
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    fn arguments(&mut self, arguments: &[Expression]) -> Result<u32, CompileError> {
+        for arg in arguments {
+            self.expression(arg)?;
+        }
+        Ok(arguments.len() as u32)
+    }
+
+    fn call(&mut self, callee: &Expression, arguments: &[Expression]) -> Result<(), CompileError> {
+        match &*callee.kind {
+            ExpressionKind::Variable(name) if self.local(name).is_none() => {
+                if let Some(&function) = self.private.get(name).or(self.functions.get(name)) {
+                    let args = self.arguments(arguments)?;
+                    self.code.push(Op::Call { function, args });
+                } else if let Some(native) = self.native(name) {
+                    let args = self.arguments(arguments)?;
+                    self.code.push(Op::CallNative { native, args });
+                } else if self.repl {
+                    let args = self.arguments(arguments)?;
+                    let name = self.module.name(name);
+                    self.code.push(Op::CallGlobal { name, args });
+                } else {
+                    return Err(CompileError::Unsupported(format!("calls to '{}'", name)));
+                }
+            }
+            // `Math.addExact(a, b)` calls a native unless `Math` is a variable
+            ExpressionKind::MemberAccess { object, member }
+                if matches!(&*object.kind, ExpressionKind::Variable(name)
+                    if name == "Math" && self.local(name).is_none()) =>
+            {
+                let Some(native) = self.native(&format!("Math.{}", member)) else {
+                    return Err(CompileError::Unsupported(format!("Math.{}", member)));
+                };
+                let args = self.arguments(arguments)?;
+                self.code.push(Op::CallNative { native, args });
+            }
+            ExpressionKind::MemberAccess { object, member } => {
+                self.expression(object)?;
+                let args = self.arguments(arguments)?;
+                let name = self.module.name(member);
+                self.code.push(Op::CallMethod { name, args });
+            }
+            _ => {
+                self.expression(callee)?;
+                let args = self.arguments(arguments)?;
+                self.code.push(Op::CallValue { args });
+            }
+        }
+        Ok(())
+    }
+
+    /// The built-in function `name`, added to the module.
+    fn native(&mut self, name: &str) -> Option<u32> {
+        match self.natives.borrow().get(name) {
+            Ok(Value::NativeFunction(native)) => Some(self.module.native(native)),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,273 @@
+//! A bytecode compiler and stack machine for type-checked programs. Variables
+//! are resolved to slots at compile time, so running a loop neither looks up
+//! names nor copies the arrays it indexes. Programs using what only the
+//! tree-walking interpreter supports, such as lambdas or coroutines, fail to
+//! compile and are left to it.
+
+mod bytecode;
+mod compiler;
+mod vm;
+
+pub use bytecode::{Function, Module, NativeFunction, Op, Operand, Slot};
+pub use compiler::{compile, CompileError};
+pub use vm::Vm;
+
+use compiler::Compiler;
+use dotlin_ast::{Declaration, Statement};
+use dotlin_interpreter::{RuntimeError, Value};
+use std::collections::HashMap;
+
+/// The state of a REPL: top-level variables live in the interpreter's
+/// globals, where the lines and declarations the VM can't compile see them.
+#[derive(Default)]
+pub struct Session {
+    vm: Vm,
+    module: Module,
+    /// The compiled REPL functions, by name
+    functions: HashMap<String, u32>,
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn vm(&self) -> &Vm {
+        &self.vm
+    }
+
+    pub fn declare(&mut self, decl: &Declaration) -> Result<(), RuntimeError> {
+        self.vm.interpreter().interpret_declaration(decl)?;
+        let Declaration::Function(func) = decl else {
+            return Ok(());
+        };
+        // Redefining a function replaces it for its callers too
+        let count = self.module.functions.len() as u32;
+        let index = *self.functions.entry(func.name.clone()).or_insert(count);
+        let natives = self.vm.interpreter().globals.clone();
+        let private = HashMap::new();
+        let compiled = Compiler::new(&mut self.module, &self.functions, &private, &natives)
+            .repl()
+            .function(&func.name, func)
+            .unwrap_or_else(|_| Self::forward(&mut self.module, &func.name, func.params.len()));
+        if index == count {
+            self.module.functions.push(compiled);
+        } else {
+            self.module.functions[index as usize] = compiled;
+        }
+        Ok(())
+    }
+
+    /// A function that calls the interpreter's definition of `name`.
+    fn forward(module: &mut Module, name: &str, params: usize) -> Function {
+        let params = params as u32;
+        let mut code: Vec<Op> = (0..params).map(|i| Op::Load(Slot::Local(i))).collect();
+        code.push(Op::CallGlobal {
+            name: module.name(name),
+            args: params,
+        });
+        code.push(Op::Return);
+        Function {
+            name: name.to_string(),
+            params,
+            locals: params,
+            code,
+            file: None,
+            lines: Vec::new(),
+        }
+    }
+
+    /// Runs a line, returning the value of an expression statement.
+    pub fn execute(&mut self, stmt: &Statement) -> Result<Option<Value>, RuntimeError> {
+        let natives = self.vm.interpreter().globals.clone();
+        let private = HashMap::new();
+        let compiled = Compiler::new(&mut self.module, &self.functions, &private, &natives)
+            .repl()
+            .line(stmt);
+        let Ok(line) = compiled else {
+            return self.vm.interpreter().interpret_statement(stmt);
+        };
+        self.module.functions.push(line);
+        let index = self.module.functions.len() as u32 - 1;
+        let value = self.vm.call_function(&self.module, index, Vec::new());
+        // Each line runs once
+        self.module.functions.pop();
+        let value = value?;
+        Ok(match stmt {
+            Statement::Expression(_) => Some(value),
+            _ => None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dotlin_interpreter::{Limit, Limits, DEFAULT_CALL_DEPTH};
+
+    fn compiled(src: &str) -> Result<Module, CompileError> {
+        let mut program = dotlin_parser::Parser::new(src).parse_program().unwrap();
+        dotlin_typechecker::TypeChecker::new()
+            .check_program(&mut program)
+            .unwrap();
+        compile(std::slice::from_ref(&program))
+    }
+
+    #[test]
+    fn loops_update_variables_and_elements_in_place() {
+        let module = compiled(
+            "fun sum(n: Int): Int { var total = 0\n var i = 0\n\
+             while (i < n) { total += i\n i = i + 1 }\n return total }\n\
+             fun grid(): Int { var g = [[1, 2], [3, 4]]\n g[1][0] = 30\n g[0][1] *= 5\n\
+             return g[0][1] + g[1][0] }\n\
+             fun squares(): Int { val xs = [1, 2]\n var s = 0\n\
+             for (x in xs) { val sq = x * x\n s = s + sq }\n return s }\n\
+             fun fib(n: Int): Int = if (n < 2) n else fib(n - 1) + fib(n - 2)\n\
+             fun pairs(): Int { var s = 0\n for ((a, b) in [Pair(1, 2), Pair(3, 4)]) { s = s + a * b }\n\
+             do { val t = s\n s = t - 1 } while (t > 13)\n return s }\n\
+             fun fill(xs: Array<Int>) { xs.push(3) }\n\
+             fun shared(): Int { val xs = [1]\n val ys = xs\n ys[0] = 5\n fill(xs)\n\
+             return if (xs === ys) xs[0] + xs[1] else 0 }\n\
+             fun main() { }",
+        )
+        .unwrap();
+        let mut vm = Vm::new();
+        let mut call = |name: &str| vm.call(&module, name, vec![]);
+        assert_eq!(call("grid").unwrap(), Value::Integer(40));
+        assert_eq!(call("shared").unwrap(), Value::Integer(8));
+        assert_eq!(call("squares").unwrap(), Value::Integer(5));
+        assert_eq!(call("pairs").unwrap(), Value::Integer(12));
+        assert_eq!(
+            vm.call(&module, "sum", vec![Value::Integer(5)]).unwrap(),
+            Value::Integer(10)
+        );
+        assert_eq!(
+            vm.call(&module, "fib", vec![Value::Integer(15)]).unwrap(),
+            Value::Integer(610)
+        );
+    }
+
+    #[test]
+    fn errors_unwind_and_unsupported_code_is_left_to_the_interpreter() {
+        let module = compiled(
+            "fun div(a: Int, b: Int): Int = a / b\n\
+             fun outside(): Int { val xs = [1, 2]\n return xs[5] }\n\
+             fun main() { }",
+        )
+        .unwrap();
+        let mut vm = Vm::new();
+        assert!(matches!(
+            vm.call(&module, "div", vec![Value::Integer(1), Value::Integer(0)]),
+            Err(RuntimeError::ArithmeticException(_))
+        ));
+        assert!(matches!(
+            vm.call(&module, "outside", vec![]),
+            Err(RuntimeError::IndexOutOfBounds {
+                index: 5,
+                length: 2
+            })
+        ));
+        // The VM is usable after an error
+        assert_eq!(
+            vm.call(&module, "div", vec![Value::Integer(9), Value::Integer(2)])
+                .unwrap(),
+            Value::Integer(4)
+        );
+
+        assert!(matches!(
+            compiled("fun twice(f: (Int) -> Int): Int = f(f(1))\n fun main() { twice { it + 1 } }"),
+            Err(CompileError::Unsupported(what)) if what == "lambdas"
+        ));
+        assert!(compiled("object Counter { var n = 0 }\n fun main() { }").is_err());
+    }
+
+    #[test]
+    fn runs_stop_at_the_interpreters_limits() {
+        let module = compiled(
+            "fun down(n: Int): Int = if (n == 0) 0 else down(n - 1) + 1\n\
+             fun spin(): Int { var i = 0\n while (true) { i = i + 1 }\n return i }\n\
+             fun grow(): Int { val xs = [0]\n while (true) { xs.push(1) }\n return 0 }\n\
+             fun main() { }",
+        )
+        .unwrap();
+        let mut vm = Vm::new();
+        let limit = |error| match error {
+            Err(RuntimeError::LimitExceeded(limit)) => limit,
+            other => panic!("expected a limit to be exceeded, got {:?}", other),
+        };
+        // The call depth is limited by default
+        assert_eq!(
+            limit(vm.call(&module, "down", vec![Value::Integer(10_000)])),
+            Limit::CallDepth(DEFAULT_CALL_DEPTH)
+        );
+        assert_eq!(
+            vm.call(&module, "down", vec![Value::Integer(100)]).unwrap(),
+            Value::Integer(100)
+        );
+
+        vm.set_limits(Limits {
+            instructions: Some(10_000),
+            ..Limits::default()
+        });
+        assert_eq!(
+            limit(vm.call(&module, "spin", vec![])),
+            Limit::Instructions(10_000)
+        );
+        vm.set_limits(Limits {
+            time: Some(std::time::Duration::from_millis(50)),
+            ..Limits::default()
+        });
+        assert!(matches!(
+            limit(vm.call(&module, "spin", vec![])),
+            Limit::Time(_)
+        ));
+        vm.set_limits(Limits {
+            collection_size: Some(100),
+            ..Limits::default()
+        });
+        assert_eq!(
+            limit(vm.call(&module, "grow", vec![])),
+            Limit::CollectionSize(100)
+        );
+    }
+
+    #[test]
+    fn repl_lines_share_variables_with_declarations() {
+        let stmt = |src: &str| match dotlin_parser::Parser::new(src).parse_repl_input().unwrap() {
+            dotlin_parser::ReplNode::Stmt(stmt) => stmt,
+            other => panic!("expected a statement, got {:?}", other),
+        };
+        let decl = |src: &str| match dotlin_parser::Parser::new(src).parse_repl_input().unwrap() {
+            dotlin_parser::ReplNode::Decl(decl) => decl,
+            other => panic!("expected a declaration, got {:?}", other),
+        };
+        let mut session = Session::new();
+        session.declare(&decl("fun sq(x: Int) = x * x")).unwrap();
+        session.execute(&stmt("var xs = [1, 2, 3]")).unwrap();
+        session.execute(&stmt("xs[1] = sq(xs[2])")).unwrap();
+        assert_eq!(
+            session.execute(&stmt("xs[1] + xs[0]")).unwrap(),
+            Some(Value::Integer(10))
+        );
+        // A lambda is left to the interpreter, which sees the same variables
+        session
+            .execute(&stmt("val f = { n: Int -> n + xs[1] }"))
+            .unwrap();
+        assert_eq!(
+            session.execute(&stmt("f(1)")).unwrap(),
+            Some(Value::Integer(10))
+        );
+        session
+            .declare(&decl("fun sq(x: Int) = x * x * x"))
+            .unwrap();
+        assert_eq!(
+            session.execute(&stmt("sq(2)")).unwrap(),
+            Some(Value::Integer(8))
+        );
+        assert!(session.execute(&stmt("sq(undefined)")).is_err());
+        assert_eq!(
+            session.execute(&stmt("xs[2]")).unwrap(),
+            Some(Value::Integer(3))
+        );
+    }
+}
@@ -0,0 +1,555 @@
+use crate::bytecode::{Module, Op, Operand, Slot};
+use dotlin_ast::BinaryOp;
+use dotlin_interpreter::{Interpreter, IteratorState, Limits, RuntimeError, StackFrame, Value};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// A call in progress.
+struct Frame {
+    function: usize,
+    ip: usize,
+    /// Where the call's locals start on the stack
+    base: usize,
+}
+
+/// Runs compiled modules. Locals and operands share one stack; built-in
+/// methods and function values are left to an interpreter, whose globals
+/// hold the REPL's variables and whose limits apply to the VM's runs too.
+pub struct Vm {
+    interpreter: Interpreter,
+    stack: Vec<Value>,
+    frames: Vec<Frame>,
+}
+
+impl Vm {
+    pub fn new() -> Self {
+        Self::with_interpreter(Interpreter::new())
+    }
+
+    pub fn with_interpreter(interpreter: Interpreter) -> Self {
+        Self {
+            interpreter,
+            stack: Vec::new(),
+            frames: Vec::new(),
+        }
+    }
+
+    pub fn interpreter(&self) -> &Interpreter {
+        &self.interpreter
+    }
+
+    /// Limits what runs may use from now on.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.interpreter.set_limits(limits);
+    }
+
+    /// Runs the program's `main`, if it has one.
+    pub fn run(&mut self, module: &Module) -> Result<(), RuntimeError> {
+        if let Some(main) = module.main {
+            self.call_function(module, main, Vec::new())?;
+        }
+        Ok(())
+    }
+
+    /// Calls a compiled function by its qualified name.
+    pub fn call(
+        &mut self,
+        module: &Module,
+        name: &str,
+        args: Vec<Value>,
+    ) -> Result<Value, RuntimeError> {
+        let function = module
+            .function(name)
+            .ok_or_else(|| RuntimeError::UndefinedVariable(name.to_string()))?;
+        self.call_function(module, function, args)
+    }
+
+    pub(crate) fn call_function(
+        &mut self,
+        module: &Module,
+        function: u32,
+        args: Vec<Value>,
+    ) -> Result<Value, RuntimeError> {
+        let depth = self.frames.len();
+        if depth == 0 {
+            // Forget the trace of an earlier error
+            self.interpreter.take_stack_trace();
+        }
+        self.interpreter.enter_call()?;
+        let base = self.stack.len();
+        let argc = args.len() as u32;
+        self.stack.extend(args);
+        let result = enter(&mut self.stack, module, function, argc).and_then(|mut frame| {
+            let result = self.execute(module, &mut frame, depth);
+            if result.is_err() {
+                self.record_trace(module, &frame, depth);
+            }
+            result
+        });
+        // Leave this call and those the error escaped from
+        for _ in depth..=self.frames.len() {
+            self.interpreter.leave_call();
+        }
+        if result.is_err() {
+            // Unwind the calls the error escaped from
+            self.frames.truncate(depth);
+            self.stack.truncate(base);
+        }
+        result
+    }
+
+    /// Adds the calls an error escaped from, innermost first, to the
+    /// interpreter's stack trace.
+    fn record_trace(&self, module: &Module, innermost: &Frame, depth: usize) {
+        for frame in std::iter::once(innermost).chain(self.frames[depth..].iter().rev()) {
+            let function = &module.functions[frame.function];
+            self.interpreter.record_frame(StackFrame {
+                // The name as written, like the interpreter's frames
+                function: function
+                    .name
+                    .rsplit('.')
+                    .next()
+                    .unwrap_or_default()
+                    .to_string(),
+                file: function.file.clone(),
+                // `ip` is past the op that failed
+                line: function.line(frame.ip - 1),
+            });
+        }
+    }
+
+    fn name(module: &Module, index: u32) -> &str {
+        &module.names[index as usize]
+    }
+
+    /// Runs until the call `frame` returns to the caller at `depth` frames.
+    fn execute(
+        &mut self,
+        module: &Module,
+        frame: &mut Frame,
+        depth: usize,
+    ) -> Result<Value, RuntimeError> {
+        let Vm {
+            interpreter,
+            stack,
+            frames,
+        } = self;
+        let mut code = &module.functions[frame.function].code;
+        loop {
+            let op = &code[frame.ip];
+            frame.ip += 1;
+            interpreter.tick()?;
+            match op {
+                Op::Constant(index) => {
+                    let value = module.constants[*index as usize].clone();
+                    stack.push(value);
+                }
+                Op::Void => stack.push(Value::Void),
+                Op::Load(Slot::Local(slot)) => {
+                    let value = stack[frame.base + *slot as usize].clone();
+                    stack.push(value);
+                }
+                Op::Load(Slot::Global(name)) => {
+                    let value = interpreter
+                        .globals
+                        .borrow()
+                        .get(Self::name(module, *name))?;
+                    stack.push(value);
+                }
+                Op::Store(Slot::Local(slot)) => {
+                    let value = pop(stack);
+                    stack[frame.base + *slot as usize] = value;
+                }
+                Op::Store(Slot::Global(name)) => {
+                    let value = pop(stack);
+                    interpreter
+                        .globals
+                        .borrow_mut()
+                        .assign(Self::name(module, *name).to_string(), value)?;
+                }
+                Op::Define(name) => {
+                    let value = pop(stack);
+                    interpreter
+                        .globals
+                        .borrow_mut()
+                        .define(Self::name(module, *name).to_string(), value);
+                }
+                Op::Pop => {
+                    pop(stack);
+                }
+                Op::Duplicate(count) => {
+                    let start = stack.len() - *count as usize;
+                    stack.extend_from_within(start..);
+                }
+                Op::LoadElement(slot, count) => {
+                    let keys = stack.len() - *count as usize;
+                    let value = match slot {
+                        Slot::Local(slot) => {
+                            load_element(&stack[frame.base + *slot as usize], &stack[keys..])?
+                        }
+                        Slot::Global(name) => {
+                            let root = interpreter
+                                .globals
+                                .borrow()
+                                .get(Self::name(module, *name))?;
+                            load_element(&root, &stack[keys..])?
+                        }
+                    };
+                    stack.truncate(keys);
+                    stack.push(value);
+                }
+                Op::StoreElement(slot, count) => {
+                    let value = pop(stack);
+                    let keys = stack.len() - *count as usize;
+                    match slot {
+                        Slot::Local(slot) => {
+                            store_element(
+                                interpreter,
+                                &stack[frame.base + *slot as usize],
+                                &stack[keys..],
+                                value,
+                            )?;
+                        }
+                        Slot::Global(name) => {
+                            let root = interpreter
+                                .globals
+                                .borrow()
+                                .get(Self::name(module, *name))?;
+                            store_element(interpreter, &root, &stack[keys..], value)?;
+                        }
+                    }
+                    stack.truncate(keys);
+                }
+                Op::Index => {
+                    let index = pop(stack);
+                    let container = pop(stack);
+                    stack.push(Interpreter::index_value(container, index)?);
+                }
+                Op::Binary(operator, right) => {
+                    let popped = (*right == Operand::Stack) as usize;
+                    let at = stack.len() - 1 - popped;
+                    if let (Value::Integer(l), Some(r)) =
+                        (&stack[at], peek_integer(stack, module, frame.base, *right))
+                    {
+                        if let Some(value) = integer(*l, operator, r) {
+                            stack.truncate(at + 1);
+                            stack[at] = value;
+                            continue;
+                        }
+                    }
+                    let right = operand(stack, module, frame.base, *right);
+                    let left = stack.last_mut().expect("operand stack underflow");
+                    *left = binary(std::mem::replace(left, Value::Void), operator, right)?;
+                    interpreter.check_size(left)?;
+                }
+                Op::Unary(operator) => {
+                    let value = pop(stack);
+                    stack.push(Interpreter::evaluate_unary(operator, value)?);
+                }
+                Op::ShortCircuit(value, target) => {
+                    if matches!(stack.last(), Some(Value::Boolean(b)) if b == value) {
+                        frame.ip = *target as usize;
+                    }
+                }
+                Op::Logical(operator) => {
+                    let right = pop(stack);
+                    let left = pop(stack);
+                    let value = match (left, operator, right) {
+                        (Value::Boolean(l), BinaryOp::And, Value::Boolean(r)) => l && r,
+                        (Value::Boolean(l), BinaryOp::Or, Value::Boolean(r)) => l || r,
+                        (_, BinaryOp::And, _) => {
+                            return Err(RuntimeError::TypeMismatch(
+                                "Both operands of && must be boolean".to_string(),
+                            ))
+                        }
+                        _ => {
+                            return Err(RuntimeError::TypeMismatch(
+                                "Both operands of || must be boolean".to_string(),
+                            ))
+                        }
+                    };
+                    stack.push(Value::Boolean(value));
+                }
+                Op::Jump(target) => frame.ip = *target as usize,
+                Op::JumpIfFalse(target) => match pop(stack) {
+                    Value::Boolean(true) => {}
+                    Value::Boolean(false) => frame.ip = *target as usize,
+                    _ => {
+                        return Err(RuntimeError::TypeMismatch(
+                            "Condition must be boolean".to_string(),
+                        ))
+                    }
+                },
+                Op::JumpUnless(operator, right, target) => {
+                    let popped = (*right == Operand::Stack) as usize;
+                    let at = stack.len() - 1 - popped;
+                    if let (Value::Integer(l), Some(r)) =
+                        (&stack[at], peek_integer(stack, module, frame.base, *right))
+                    {
+                        if let Some(Value::Boolean(holds)) = integer(*l, operator, r) {
+                            stack.truncate(at);
+                            if !holds {
+                                frame.ip = *target as usize;
+                            }
+                            continue;
+                        }
+                    }
+                    let right = operand(stack, module, frame.base, *right);
+                    let left = pop(stack);
+                    match binary(left, operator, right)? {
+                        Value::Boolean(true) => {}
+                        Value::Boolean(false) => frame.ip = *target as usize,
+                        _ => {
+                            return Err(RuntimeError::TypeMismatch(
+                                "Condition must be boolean".to_string(),
+                            ))
+                        }
+                    }
+                }
+                Op::JumpIfNull(target) => {
+                    if matches!(stack.last(), Some(Value::Null)) {
+                        frame.ip = *target as usize;
+                    }
+                }
+                Op::JumpIfNotNull(target) => {
+                    if matches!(stack.last(), Some(Value::Null)) {
+                        pop(stack);
+                    } else {
+                        frame.ip = *target as usize;
+                    }
+                }
+                Op::Call { function, args } => {
+                    let callee = enter(stack, module, *function, *args)?;
+                    interpreter.enter_call()?;
+                    frames.push(std::mem::replace(frame, callee));
+                    code = &module.functions[frame.function].code;
+                }
+                Op::CallNative { native, args } => {
+                    let args = pop_many(stack, *args);
+                    stack.push(module.natives[*native as usize](interpreter, args)?);
+                }
+                Op::CallGlobal { name, args } => {
+                    let args = pop_many(stack, *args);
+                    let callee = interpreter
+                        .globals
+                        .borrow()
+                        .get(Self::name(module, *name))?;
+                    stack.push(interpreter.call_value(callee, args)?);
+                }
+                Op::CallMethod { name, args } => {
+                    let args = pop_many(stack, *args);
+                    let object = pop(stack);
+                    let value = interpreter.call_method(object, Self::name(module, *name), args)?;
+                    interpreter.check_size(&value)?;
+                    stack.push(value);
+                }
+                Op::CallValue { args } => {
+                    let args = pop_many(stack, *args);
+                    let callee = pop(stack);
+                    stack.push(interpreter.call_value(callee, args)?);
+                }
+                Op::Member(name) => {
+                    let value = pop(stack);
+                    stack.push(Interpreter::member(value, Self::name(module, *name))?);
+                }
+                Op::Array(count) => {
+                    let elements = pop_many(stack, *count);
+                    let array = Value::array(elements);
+                    interpreter.check_size(&array)?;
+                    stack.push(array);
+                }
+                Op::Map(count) => {
+                    let mut map = std::collections::HashMap::new();
+                    let mut pairs = pop_many(stack, count * 2).into_iter();
+                    while let (Some(key), Some(value)) = (pairs.next(), pairs.next()) {
+                        map.insert(Interpreter::map_key(key)?, value);
+                    }
+                    let map = Value::map(map);
+                    interpreter.check_size(&map)?;
+                    stack.push(map);
+                }
+                Op::Destructure(count) => {
+                    let value = pop(stack);
+                    let components = Interpreter::destructure(value, *count as usize)?;
+                    stack.extend(components);
+                }
+                Op::Iterate { slot, destructure } => {
+                    let iterable = pop(stack);
+                    stack[frame.base + *slot as usize] = iterator(iterable, *destructure)?;
+                }
+                Op::Next { slot, end } => {
+                    let Value::Iterator(iterator) = &stack[frame.base + *slot as usize] else {
+                        unreachable!("`Iterate` stores an iterator");
+                    };
+                    let next = {
+                        let mut iterator = iterator.borrow_mut();
+                        let next = iterator.items.get(iterator.pos).cloned();
+                        iterator.pos += 1;
+                        next
+                    };
+                    match next {
+                        Some(element) => stack.push(element),
+                        None => frame.ip = *end as usize,
+                    }
+                }
+                Op::Return => {
+                    let value = pop(stack);
+                    stack.truncate(frame.base);
+                    if frames.len() == depth {
+                        return Ok(value);
+                    }
+                    interpreter.leave_call();
+                    *frame = frames.pop().expect("a caller to return to");
+                    code = &module.functions[frame.function].code;
+                    stack.push(value);
+                }
+            }
+        }
+    }
+}
+
+/// Starts a call of `function` with the top `args` values of the stack.
+fn enter(
+    stack: &mut Vec<Value>,
+    module: &Module,
+    function: u32,
+    args: u32,
+) -> Result<Frame, RuntimeError> {
+    let callee = &module.functions[function as usize];
+    if args != callee.params {
+        return Err(RuntimeError::ArgumentCount {
+            expected: callee.params as usize,
+            got: args as usize,
+        });
+    }
+    let base = stack.len() - args as usize;
+    stack.resize(base + callee.locals as usize, Value::Void);
+    Ok(Frame {
+        function: function as usize,
+        ip: 0,
+        base,
+    })
+}
+
+fn pop(stack: &mut Vec<Value>) -> Value {
+    stack.pop().expect("operand stack underflow")
+}
+
+/// Pops the top `count` values, the deepest first.
+fn pop_many(stack: &mut Vec<Value>, count: u32) -> Vec<Value> {
+    stack.split_off(stack.len() - count as usize)
+}
+
+impl Default for Vm {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The right operand of a binary operator.
+fn operand(stack: &mut Vec<Value>, module: &Module, base: usize, operand: Operand) -> Value {
+    match operand {
+        Operand::Stack => pop(stack),
+        Operand::Constant(index) => module.constants[index as usize].clone(),
+        Operand::Local(slot) => stack[base + slot as usize].clone(),
+    }
+}
+
+/// Applies a binary operator, with the `Int` cases inline.
+fn binary(left: Value, operator: &BinaryOp, right: Value) -> Result<Value, RuntimeError> {
+    if let (Value::Integer(l), Value::Integer(r)) = (&left, &right) {
+        if let Some(value) = integer(*l, operator, *r) {
+            return Ok(value);
+        }
+    }
+    Interpreter::evaluate_binary(left, operator, right)
+}
+
+/// The common operators on two `Int`s, leaving division by zero and the
+/// rest to the interpreter.
+#[inline(always)]
+fn integer(l: i32, operator: &BinaryOp, r: i32) -> Option<Value> {
+    Some(match operator {
+        BinaryOp::Add | BinaryOp::PlusEqual => Value::Integer(l.wrapping_add(r)),
+        BinaryOp::Sub | BinaryOp::MinusEqual => Value::Integer(l.wrapping_sub(r)),
+        BinaryOp::Mul | BinaryOp::StarEqual => Value::Integer(l.wrapping_mul(r)),
+        BinaryOp::Div | BinaryOp::SlashEqual if r != 0 => Value::Integer(l.wrapping_div(r)),
+        BinaryOp::Rem if r != 0 => Value::Integer(l.wrapping_rem(r)),
+        BinaryOp::Less => Value::Boolean(l < r),
+        BinaryOp::LessEqual => Value::Boolean(l <= r),
+        BinaryOp::Greater => Value::Boolean(l > r),
+        BinaryOp::GreaterEqual => Value::Boolean(l >= r),
+        BinaryOp::Equal => Value::Boolean(l == r),
+        BinaryOp::NotEqual => Value::Boolean(l != r),
+        _ => return None,
+    })
+}
+
+/// An `Int` operand read without cloning or popping it.
+#[inline(always)]
+fn peek_integer(stack: &[Value], module: &Module, base: usize, operand: Operand) -> Option<i32> {
+    match operand {
+        Operand::Stack => match stack.last() {
+            Some(Value::Integer(n)) => Some(*n),
+            _ => None,
+        },
+        Operand::Constant(index) => match module.constants[index as usize] {
+            Value::Integer(n) => Some(n),
+            _ => None,
+        },
+        Operand::Local(slot) => match stack[base + slot as usize] {
+            Value::Integer(n) => Some(n),
+            _ => None,
+        },
+    }
+}
+
+/// The iterator a `for` loop walks: over the elements of an array, the keys
+/// of a map or, when they are destructured, its entries.
+fn iterator(iterable: Value, destructure: Option<u32>) -> Result<Value, RuntimeError> {
+    let items = match (iterable, destructure) {
+        (Value::Iterator(iterator), _) => return Ok(Value::Iterator(iterator)),
+        (Value::Array(elements), _) => elements.borrow().clone(),
+        (Value::HashMap(map), None) => map.borrow().keys().cloned().map(Value::String).collect(),
+        (Value::HashMap(map), Some(2)) => map
+            .borrow()
+            .iter()
+            .map(|(key, value)| Value::Tuple(vec![Value::String(key.clone()), value.clone()]))
+            .collect(),
+        (Value::HashMap(_), Some(_)) => {
+            return Err(RuntimeError::TypeMismatch(
+                "HashMap entries destructuring requires two variables".to_string(),
+            ))
+        }
+        _ => {
+            return Err(RuntimeError::TypeMismatch(
+                "ForEach iterable must be an array or HashMap".to_string(),
+            ))
+        }
+    };
+    Ok(Value::Iterator(Rc::new(RefCell::new(IteratorState {
+        items,
+        pos: 0,
+    }))))
+}
+
+/// `root[keys[0]][keys[1]]...`.
+fn load_element(root: &Value, keys: &[Value]) -> Result<Value, RuntimeError> {
+    keys.iter().try_fold(root.clone(), |value, key| {
+        Interpreter::index_value(value, key.clone())
+    })
+}
+
+/// Replaces `root[keys[0]][keys[1]]...` with `value` in the array or map that
+/// holds it, which everything sharing that array or map sees.
+fn store_element(
+    interpreter: &Interpreter,
+    root: &Value,
+    keys: &[Value],
+    value: Value,
+) -> Result<(), RuntimeError> {
+    let (key, path) = keys.split_last().expect("an element has at least one key");
+    let container = load_element(root, path)?;
+    Interpreter::set_element(&container, key, value)?;
+    interpreter.check_size(&container)
+}
@@ -1,16 +1,47 @@
 use clap::Parser as ClapParser;
-use dotlin_interpreter::{Interpreter, Value};
+use dotlin_interpreter::{Io, Value};
 use dotlin_parser::{Parser, ReplNode};
+use dotlin_vm::Session;
 use rustyline::error::ReadlineError;
 use rustyline::DefaultEditor;
+use std::io::{IsTerminal, Write};
 
 #[derive(ClapParser)]
 #[command(version, about, long_about = None)]
 struct Cli {}
 
+/// A stream whose text is shown in an ANSI color.
+struct Painted<W: Write> {
+    color: &'static str,
+    inner: W,
+}
+
+impl<W: Write> Write for Painted<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        write!(self.inner, "\x1b[{}m", self.color)?;
+        self.inner.write_all(buf)?;
+        write!(self.inner, "\x1b[0m")?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
 fn main() -> rustyline::Result<()> {
     let _cli = Cli::parse();
-    let interpreter = Interpreter::new();
+    let mut session = Session::new();
+    if std::io::stderr().is_terminal() {
+        // What scripts print to stderr stands out in red
+        session.vm().interpreter().set_io(Io {
+            stderr: Box::new(Painted {
+                color: "31",
+                inner: std::io::stderr(),
+            }),
+            ..Io::std()
+        });
+    }
     let mut rl = DefaultEditor::new()?;
 
     println!("Dotlin REPL v0.1.0");
@@ -33,10 +64,8 @@ fn main() -> rustyline::Result<()> {
                 match parser.parse_repl_input() {
                     Ok(node) => {
                         let res = match node {
-                            ReplNode::Decl(decl) => {
-                                interpreter.interpret_declaration(&decl).map(|_| None)
-                            }
-                            ReplNode::Stmt(stmt) => interpreter.interpret_statement(&stmt),
+                            ReplNode::Decl(decl) => session.declare(&decl).map(|_| None),
+                            ReplNode::Stmt(stmt) => session.execute(&stmt),
                         };
 
                         match res {
@@ -46,7 +75,11 @@ fn main() -> rustyline::Result<()> {
                                 }
                             }
                             Ok(None) => {}
-                            Err(e) => eprintln!("Runtime Error: {}", e),
+                            Err(e) => eprint!(
+                                "Runtime Error: {}\n{}",
+                                e,
+                                session.vm().interpreter().take_stack_trace()
+                            ),
                         }
                     }
                     Err(e) => eprintln!("Parse Error: {}", e),